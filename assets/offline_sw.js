@@ -0,0 +1,40 @@
+"use strict";
+
+// Generated by http for {scope}: the files there are cached on install,
+// and served from the cache when the network isn't available.
+const CACHE_PREFIX = "http-offline-{scope}-";
+const CACHE = CACHE_PREFIX + "{version}";
+const FILES = {files};
+
+self.addEventListener("install", function(ev) {
+  ev.waitUntil(caches.open(CACHE).then(function(cache) {
+    return cache.addAll(FILES);
+  }).then(function() {
+    return self.skipWaiting();
+  }));
+});
+
+self.addEventListener("activate", function(ev) {
+  ev.waitUntil(caches.keys().then(function(keys) {
+    return Promise.all(keys.filter(function(key) {
+      return key.startsWith(CACHE_PREFIX) && key !== CACHE;
+    }).map(function(key) {
+      return caches.delete(key);
+    }));
+  }).then(function() {
+    return self.clients.claim();
+  }));
+});
+
+self.addEventListener("fetch", function(ev) {
+  if(ev.request.method !== "GET")
+    return;
+
+  ev.respondWith(fetch(ev.request).catch(function(err) {
+    return caches.open(CACHE).then(function(cache) {
+      return cache.match(ev.request, {ignoreSearch: true});
+    }).then(function(resp) {
+      return resp || Promise.reject(err);
+    });
+  }));
+});
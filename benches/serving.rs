@@ -0,0 +1,70 @@
+//! Serving benchmarks, run with `cargo bench`.
+//!
+//! The server is started on a free local port, hosting a generated fixture tree, and driven over plain TCP,
+//! so what's measured is the whole request path, as seen by a client:
+//! small-file requests per second, large-file throughput, range requests, and listing generation.
+//!
+//! Pass a filter to only run the benchmarks whose names contain it, like `cargo bench -- range`.
+
+
+#[macro_use]
+extern crate criterion;
+extern crate serde_json;
+extern crate flate2;
+
+#[path = "../tests/util/mod.rs"]
+mod util;
+
+use criterion::{Throughput, Criterion};
+use self::util::Server;
+use std::fs;
+
+
+/// Size of the file served by the large-file benchmarks, 64MiB
+const LARGE_FILE_SIZE: usize = 64 * 1024 * 1024;
+
+/// Amount of files in the listed directory, enough to get listings streamed
+const LISTING_ENTRIES: usize = 2000;
+
+
+fn serving(c: &mut Criterion) {
+    let server = Server::start(&[], |site| {
+        fs::create_dir(site.join("listing")).expect("Failed to create fixture directory");
+        fs::write(site.join("small.txt"), "Abolish the burgeoisie!\n".repeat(40)).expect("Failed to write small file");
+        fs::write(site.join("large.bin"), (0..LARGE_FILE_SIZE).map(|i| (i * 7 % 251) as u8).collect::<Vec<_>>()).expect("Failed to write large file");
+        for i in 0..LISTING_ENTRIES {
+            fs::write(site.join("listing").join(format!("entry number {:05}.txt", i)), i.to_string()).expect("Failed to write listing entry");
+        }
+    });
+
+    let mut group = c.benchmark_group("requests");
+    group.throughput(Throughput::Elements(1));
+    bench(&mut group, &server, "small file", &[], "/small.txt", 200);
+    bench(&mut group, &server, "small file, gzip", &[("Accept-Encoding", "gzip")], "/small.txt", 200);
+    bench(&mut group, &server, "range", &[("Range", "bytes=1048576-1114111")], "/large.bin", 206);
+    bench(&mut group, &server, "suffix range", &[("Range", "bytes=-65536")], "/large.bin", 206);
+    bench(&mut group, &server, "listing", &[], "/listing/", 200);
+    bench(&mut group, &server, "listing, gzip", &[("Accept-Encoding", "gzip")], "/listing/", 200);
+    bench(&mut group, &server, "json listing", &[], "/listing/?format=json", 200);
+    group.finish();
+
+    let mut group = c.benchmark_group("throughput");
+    group.sample_size(10).throughput(Throughput::Bytes(LARGE_FILE_SIZE as u64));
+    bench(&mut group, &server, "large file", &[], "/large.bin", 200);
+    group.finish();
+}
+
+fn bench<M: criterion::measurement::Measurement>(group: &mut criterion::BenchmarkGroup<M>, server: &Server, name: &str, headers: &[(&str, &str)], path: &str,
+                                                 expected: u16) {
+    let request = || headers.iter().fold(server.get(path), |req, &(n, v)| req.header(n, v)).send();
+
+    // Warm up the caches, and make sure it's actually being served
+    let st = request().status;
+    assert_eq!(st, expected, "{}: got status {}", name, st);
+
+    group.bench_function(name, |b| b.iter(|| request().body.len()));
+}
+
+
+criterion_group!(benches, serving);
+criterion_main!(benches);
@@ -0,0 +1,21 @@
+//! Delta signatures are POSTed by clients and size what the server allocates and hashes.
+
+
+#![no_main]
+
+
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate https;
+
+use https::fuzzing::{DeltaSignature, write_delta};
+
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(sig) = DeltaSignature::read(data) {
+        let file = b"Abolish the burgeoisie!\n".repeat(200);
+        let mut delta = vec![];
+        write_delta(&sig, &file[..], &mut delta).unwrap();
+        assert_eq!(delta[delta.len() - 33], b'E');
+    }
+});
@@ -0,0 +1,42 @@
+//! Percent-decoding of request path segments, which is all that stands between a URL and the filesystem,
+//! and the escaping of file names in listings, which has to decode back to the same file.
+
+
+#![no_main]
+
+
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate https;
+
+use https::fuzzing::{percent_decode_non_utf8_path_segment, percent_decode_path_segment, file_name_from_bytes, escape_file_name, file_name_bytes};
+use std::str;
+
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(segment) = str::from_utf8(data) {
+        if let Some(decoded) = percent_decode_path_segment(segment) {
+            assert!(decoded != "." && decoded != "..", "{:?} decoded to {:?}", segment, decoded);
+            assert!(!decoded.contains(|c| c == '/' || c == '\0'), "{:?} decoded to {:?}", segment, decoded);
+            assert!(!cfg!(windows) || !decoded.contains(|c| c == '\\' || c == ':'), "{:?} decoded to {:?}", segment, decoded);
+        }
+        if let Some(decoded) = percent_decode_non_utf8_path_segment(segment) {
+            let bytes = file_name_bytes(&decoded);
+            assert!(str::from_utf8(&bytes).is_err(), "{:?} decoded to UTF-8 {:?}", segment, decoded);
+            assert!(!bytes.contains(&b'/') && !bytes.contains(&b'\0'), "{:?} decoded to {:?}", segment, decoded);
+        }
+    }
+
+    // Anything that can be a file name has to be linked to in a way that leads back to it
+    if data.is_empty() || data == b"." || data == b".." || data.contains(&b'/') || data.contains(&b'\0') {
+        return;
+    }
+    let name = match file_name_from_bytes(data.to_vec()) {
+        Some(name) => name,
+        None => return,
+    };
+    let escaped = escape_file_name(&name);
+    assert!(!escaped.contains(|c| c == '"' || c == '<' || c == '>' || c == '#' || c == '?'), "{:?} escaped to {:?}", name, escaped);
+    let back = percent_decode_path_segment(&escaped).map(|n| n.into_owned().into()).or_else(|| percent_decode_non_utf8_path_segment(&escaped));
+    assert_eq!(back.as_ref(), Some(&name), "{:?} escaped to {:?}", name, escaped);
+});
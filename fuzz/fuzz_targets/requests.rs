@@ -0,0 +1,108 @@
+//! Whole requests, with the path and `Range` taken from the input, against a tree with hostile file names.
+//!
+//! Nothing may answer with a 5xx, echo markup into HTML unescaped, or claim a range the file doesn't have.
+
+
+#![no_main]
+
+
+#[macro_use]
+extern crate libfuzzer_sys;
+#[macro_use]
+extern crate lazy_static;
+extern crate https;
+
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+use https::{TestServerBuilder, TestServer};
+use std::str;
+
+
+/// Served as `application/octet-stream`, so ranges of it are easy to check
+const BIG_LEN: usize = 64 * 1024;
+
+lazy_static! {
+    static ref SERVER: TestServer = start();
+}
+
+fn start() -> TestServer {
+    let server = TestServerBuilder::new()
+        .file("<img src=x onerror=alert(1)>.txt", "Abolish the burgeoisie!\n")
+        .file("dir/\"quoted\" & 'apostrophed'.txt", "Workers of the world, unite!\n")
+        .file("dir/%41 #1?.txt", "Means of production\n")
+        .file("big.bin", vec![0xA5; BIG_LEN])
+        .start();
+    add_non_utf8(&server);
+    server
+}
+
+#[cfg(unix)]
+fn add_non_utf8(server: &TestServer) {
+    use std::os::unix::ffi::OsStrExt;
+    use std::ffi::OsStr;
+    use std::fs;
+
+    fs::write(server.site().join("dir").join(OsStr::from_bytes(b"<script>\xFF\xFE.txt")), "Proletariat\n").unwrap();
+}
+
+#[cfg(not(unix))]
+fn add_non_utf8(_: &TestServer) {}
+
+
+fuzz_target!(|data: &[u8]| {
+    let mut parts = data.splitn(2, |&b| b == b'\n');
+    let path = parts.next().unwrap_or(b"");
+    let range = parts.next();
+
+    // Those would just split the request line or headers, which hyper deals with before us
+    let sane = |b: &u8| *b > b' ' && *b != 0x7F;
+    if !path.iter().all(&sane) || !range.unwrap_or(b"").iter().all(|b| *b == b' ' || sane(b)) {
+        return;
+    }
+    let path = if path.first() == Some(&b'/') { path.to_vec() } else { [&b"/"[..], path].concat() };
+
+    let mut req = [&b"GET "[..], &path, b" HTTP/1.1\r\nHost: 127.0.0.1\r\nConnection: close\r\n"].concat();
+    if let Some(range) = range {
+        req.extend_from_slice(b"Range: ");
+        req.extend_from_slice(range);
+        req.extend_from_slice(b"\r\n");
+    }
+    req.extend_from_slice(b"\r\n");
+
+    let mut conn = TcpStream::connect(SERVER.addr()).unwrap();
+    conn.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+    conn.write_all(&req).unwrap();
+    let mut resp = vec![];
+    conn.read_to_end(&mut resp).unwrap();
+
+    let split = resp.windows(4).position(|w| w == b"\r\n\r\n").expect("No end of headers");
+    let head = str::from_utf8(&resp[..split]).expect("Non-UTF-8 headers");
+    let body = &resp[split + 4..];
+    let status: u16 = head.split(' ').nth(1).and_then(|s| s.parse().ok()).expect("No status");
+    let header = |name: &str| {
+        head.lines()
+            .skip(1)
+            .find(|l| l.len() > name.len() && l[..name.len()].eq_ignore_ascii_case(name) && l.as_bytes()[name.len()] == b':')
+            .map(|l| l[name.len() + 1..].trim())
+    };
+
+    assert!(status < 500, "{:?} answered {}", String::from_utf8_lossy(&req), head);
+
+    if header("Content-Type").map(|ct| ct.starts_with("text/html")).unwrap_or(false) {
+        let body = String::from_utf8_lossy(body);
+        assert!(!body.contains("<img src=x") && !body.contains("<script>\u{FFFD}"),
+                "{:?} echoed markup: {}",
+                String::from_utf8_lossy(&req),
+                body);
+    }
+
+    // Multiple ranges come as multipart/byteranges, with the ranges in the body
+    if let (206, Some(content_range)) = (status, header("Content-Range")) {
+        let len: usize = header("Content-Length").and_then(|l| l.parse().ok()).expect("206 without length");
+        assert!(len == body.len(), "{} for {}", len, head);
+        if path == b"/big.bin" {
+            assert!(len <= BIG_LEN && content_range.ends_with(&format!("/{}", BIG_LEN)), "{}", head);
+        }
+    }
+});
@@ -0,0 +1,36 @@
+//! Deployed archives are unpacked into a staging directory, and nothing in them may land outside of it.
+
+
+#![no_main]
+
+
+#[macro_use]
+extern crate libfuzzer_sys;
+#[macro_use]
+extern crate lazy_static;
+extern crate https;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use https::fuzzing::unpack_tar;
+use std::path::PathBuf;
+use std::{env, fs, process};
+
+
+lazy_static! {
+    static ref ROOT: PathBuf = env::temp_dir().join(format!("http-fuzz-tar-{}", process::id()));
+}
+
+static RUNS: AtomicUsize = AtomicUsize::new(0);
+
+
+fuzz_target!(|data: &[u8]| {
+    let root = ROOT.join(RUNS.fetch_add(1, Ordering::Relaxed).to_string());
+    let staging = root.join("staging");
+    fs::create_dir_all(&staging).unwrap();
+
+    let _ = unpack_tar(data, &staging, false);
+
+    let escaped: Vec<_> = fs::read_dir(&root).unwrap().map(|e| e.unwrap().file_name()).filter(|n| n != "staging").collect();
+    let _ = fs::remove_dir_all(&root);
+    assert!(escaped.is_empty(), "Unpacked outside staging: {:?}", escaped);
+});
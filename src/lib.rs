@@ -0,0 +1,75 @@
+//! The `TestServer` fixture, with the `test-server` feature, for other crates' integration tests.
+//!
+//! The `http` and `httplz` binaries build the server on their own.
+
+
+// Much of the server is only reachable from the binaries
+#![allow(dead_code)]
+
+
+extern crate hyper_native_tls;
+extern crate hyper;
+extern crate percent_encoding;
+extern crate unicode_normalization;
+extern crate trivial_colours;
+#[cfg(not(target_os = "windows"))]
+extern crate os_str_generic;
+#[macro_use]
+extern crate lazy_static;
+#[macro_use]
+extern crate serde_json;
+extern crate mime_guess;
+extern crate itertools;
+extern crate tabwriter;
+extern crate lazysort;
+extern crate unicase;
+extern crate walkdir;
+extern crate base64;
+extern crate blake3;
+extern crate sha1_smol;
+extern crate bcrypt;
+extern crate md5;
+extern crate brotli;
+extern crate flate2;
+extern crate rfsapi;
+#[cfg(target_os = "windows")]
+extern crate winapi;
+extern crate bzip2;
+extern crate zstd;
+extern crate memmap2;
+extern crate socket2;
+extern crate ctrlc;
+extern crate serde;
+extern crate regex;
+extern crate cidr;
+#[macro_use]
+extern crate clap;
+extern crate iron;
+#[cfg(not(any(target_os = "windows", target_os = "macos")))]
+extern crate libc;
+extern crate rand;
+extern crate time;
+extern crate xml;
+
+mod error;
+mod options;
+
+mod ops;
+mod util;
+
+pub use error::Error;
+pub use options::{EncodedStoreKind, SymlinkPolicy, TcpTuning, LogLevel, Options};
+
+#[cfg(feature = "test-server")]
+mod test_server;
+
+#[cfg(feature = "test-server")]
+pub use test_server::{TestServerBuilder, RecordedRequest, TestServer};
+
+/// What the fuzz targets exercise directly, which isn't otherwise part of the API
+#[cfg(feature = "test-server")]
+#[doc(hidden)]
+pub mod fuzzing {
+    pub use util::{percent_decode_non_utf8_path_segment, percent_decode_path_segment, file_name_from_bytes, escape_file_name, file_name_bytes, DeltaSignature,
+                   write_delta, unpack_tar};
+}
@@ -1,10 +1,13 @@
 extern crate hyper_native_tls;
+extern crate hyper;
 extern crate percent_encoding;
+extern crate unicode_normalization;
 extern crate trivial_colours;
 #[cfg(not(target_os = "windows"))]
 extern crate os_str_generic;
 #[macro_use]
 extern crate lazy_static;
+#[macro_use]
 extern crate serde_json;
 extern crate mime_guess;
 extern crate itertools;
@@ -14,12 +17,18 @@ extern crate unicase;
 extern crate walkdir;
 extern crate base64;
 extern crate blake3;
+extern crate sha1_smol;
+extern crate bcrypt;
+extern crate md5;
 extern crate brotli;
 extern crate flate2;
 extern crate rfsapi;
 #[cfg(target_os = "windows")]
 extern crate winapi;
 extern crate bzip2;
+extern crate zstd;
+extern crate memmap2;
+extern crate socket2;
 extern crate ctrlc;
 extern crate serde;
 extern crate regex;
@@ -40,16 +49,19 @@ pub mod ops;
 pub mod util;
 
 pub use error::Error;
-pub use options::{LogLevel, Options};
+pub use options::{EncodedStoreKind, SymlinkPolicy, TcpTuning, LogLevel, Options};
 
+use std::fs;
 use std::mem;
+use std::thread;
+use std::time::Duration;
 use iron::Iron;
-use std::net::IpAddr;
+use std::net::SocketAddr;
 use std::process::exit;
 use tabwriter::TabWriter;
 use std::io::{Write, stdout};
 use std::collections::BTreeSet;
-use std::sync::{Arc, Mutex, Condvar};
+use std::sync::{Arc, Mutex, Condvar, PoisonError};
 use hyper_native_tls::NativeTlsServer;
 
 
@@ -69,6 +81,8 @@ fn actual_main() -> i32 {
 
 fn result_main() -> Result<(), Error> {
     let mut opts = Options::parse();
+    // Uncoloured rather than full of escape sequences on consoles that can't show them
+    opts.log_colour &= util::prepare_console();
     if opts.generate_tls {
         opts.tls_data = Some(ops::generate_tls_data(&opts.temp_directory)?);
     }
@@ -76,11 +90,77 @@ fn result_main() -> Result<(), Error> {
         opts.path_auth_data.insert(path, Some(ops::generate_auth_data()));
     }
 
+    if opts.stdin {
+        fs::create_dir_all(&opts.hosted_directory.1).map_err(|err| {
+                Error {
+                    desc: "stdin buffer directory",
+                    op: "create",
+                    more: err.to_string().into(),
+                }
+            })?;
+    }
+
     let handler = ops::SimpleChain {
         handler: ops::HttpHandler::new(&opts),
         after: opts.request_bandwidth.map(ops::LimitBandwidthMiddleware::new),
     };
-    let mut responder = if let Some(p) = opts.port {
+    let temp_dirs = handler.handler.temp_dirs();
+    if opts.check_cache {
+        handler.handler.check_cache();
+        return Ok(());
+    }
+    for td in temp_dirs.values() {
+        fs::create_dir_all(td).map_err(|err| {
+                Error {
+                    desc: "temp directory",
+                    op: "create",
+                    more: format!("{}: {}", td.display(), err).into(),
+                }
+            })?;
+    }
+    let download_counts = handler.handler.download_counts.clone();
+    if let Some(counts) = download_counts.clone() {
+        thread::spawn(move || counts.save_periodically());
+    }
+    let write_replicator = handler.handler.write_replicator.clone();
+    let serve_limits = handler.handler.serve_limits.clone();
+    if let Some(replicator) = write_replicator.clone() {
+        thread::spawn(move || replicator.run());
+    }
+    if let Some(fds) = handler.handler.fd_cache.clone() {
+        thread::spawn(move || fds.close_idle_periodically());
+    }
+    if let Some(buffer) = handler.handler.stdin.clone() {
+        thread::spawn(move || buffer.pump());
+    }
+    if let Some(interval) = opts.check_cache_interval {
+        let checker = handler.handler.clone();
+        thread::spawn(move || checker.check_cache_periodically(Duration::from_secs(interval)));
+    }
+    if !opts.retention.is_empty() {
+        let retainer = handler.handler.clone();
+        let interval = Duration::from_secs(opts.retention_interval);
+        thread::spawn(move || retainer.enforce_retention_periodically(interval));
+    }
+
+    // Registered before the server starts, so no requests sneak in before the tasks do
+    let mirror_warm_up = opts.mirror.as_ref().map(|_| handler.handler.warm_up.start());
+    if !opts.precompress.is_empty() {
+        let precompress_warm_up = handler.handler.warm_up.start();
+        let precompressor = handler.handler.clone();
+        let globs: Vec<_> = opts.precompress.iter().map(|g| util::glob_regex(g).unwrap()).collect();
+        thread::spawn(move || precompressor.precompress(&globs, precompress_warm_up));
+    }
+    let mut responders = if opts.listeners.is_some() || opts.tcp_tuning != TcpTuning::default() {
+        ops::try_tuned_ports(handler,
+                             opts.bind_address,
+                             opts.port,
+                             util::PORT_SCAN_LOWEST,
+                             util::PORT_SCAN_HIGHEST,
+                             opts.listeners,
+                             &opts.tcp_tuning,
+                             &opts.tls_data)?
+    } else if let Some(p) = opts.port {
         if let Some(&((_, ref id), ref pw)) = opts.tls_data.as_ref() {
                 Iron::new(handler).https((opts.bind_address, p),
                                          NativeTlsServer::new(id, pw).map_err(|err| {
@@ -93,44 +173,63 @@ fn result_main() -> Result<(), Error> {
             } else {
                 Iron::new(handler).http((opts.bind_address, p))
             }
+            .map(|responder| vec![responder])
             .map_err(|_| {
                 Error {
                     desc: "server",
                     op: "start",
                     more: "port taken".into(),
                 }
-            })
+            })?
     } else {
-        ops::try_ports(handler, opts.bind_address, util::PORT_SCAN_LOWEST, util::PORT_SCAN_HIGHEST, &opts.tls_data)
-    }?;
+        vec![ops::try_ports(handler, opts.bind_address, util::PORT_SCAN_LOWEST, util::PORT_SCAN_HIGHEST, &opts.tls_data)?]
+    };
+    // All on the same address
+    let socket = responders[0].socket;
 
-    if opts.loglevel < options::LogLevel::NoStartup {
+    if opts.print_config {
+        println!("{}", opts.effective_config(socket, &temp_dirs));
+    }
+    if opts.loglevel < LogLevel::NoStartup {
         if opts.log_colour {
             print!("{}", trivial_colours::Reset);
         }
-        print!("Hosting \"{}\" on port {}",
-               opts.hosted_directory.0,
-               responder.socket.port());
-        if responder.socket.ip() != IpAddr::from([0, 0, 0, 0]) {
-            print!(" under address {}", responder.socket.ip());
-        }
-        print!(" with");
-        if let Some(&((ref id, _), _)) = opts.tls_data.as_ref() {
-            print!(" TLS certificate from \"{}\"", id);
-        } else {
-            print!("out TLS");
-        }
-        if !opts.path_auth_data.is_empty() {
-            print!(" and basic authentication");
-        } else {
-            print!(" and no authentication");
+        print_banner(&opts, socket);
+
+        if let Some(listeners) = opts.listeners {
+            println!("Accepting connections on {} SO_REUSEPORT sockets.", listeners);
         }
-        println!("...");
 
         if let Some(band) = opts.request_bandwidth {
             println!("Requests limited to {}B/s.", band);
         }
 
+        if let Some(origin) = opts.origin.as_ref() {
+            match opts.origin_ttl {
+                Some(ttl) => println!("Fetching missing files from {}, revalidating after {}s.", origin, ttl),
+                None => println!("Fetching missing files from {}.", origin),
+            }
+        }
+
+        if let Some(origin) = opts.mirror.as_ref() {
+            println!("Mirroring {} every {}s.", origin, opts.mirror_interval);
+        }
+
+        if let Some(until) = opts.until {
+            println!("Stopping after {}s.", until.as_secs());
+        }
+
+        match (opts.max_serves, opts.max_bytes) {
+            (Some(serves), Some(bytes)) => println!("Stopping after {} downloads or {}B sent, whichever's first.", serves, bytes),
+            (Some(serves), None) => println!("Stopping after {} downloads.", serves),
+            (None, Some(bytes)) => println!("Stopping after {}B sent.", bytes),
+            (None, None) => {}
+        }
+
+        if let Some((start, end)) = opts.write_hours {
+            println!("Accepting writes between {:02}:{:02} and {:02}:{:02}.", start / 60, start % 60, end / 60, end % 60);
+        }
+
         for (ext, mime_type) in opts.mime_type_overrides {
             match &ext[..] {
                 "" => println!("Serving files with no extension as {}.", mime_type),
@@ -149,12 +248,16 @@ fn result_main() -> Result<(), Error> {
             out.flush().unwrap();
         }
     }
-    if !opts.path_auth_data.is_empty() && opts.loglevel < options::LogLevel::NoAuth {
+    if (!opts.path_auth_data.is_empty() || opts.auth_file.is_some()) && opts.loglevel < LogLevel::NoAuth {
         println!("Basic authentication credentials:");
 
         let mut out = TabWriter::new(stdout());
         writeln!(out, "Path\tUsername\tPassword").unwrap();
 
+        if let Some((file, _)) = opts.auth_file.as_ref() {
+            writeln!(out, "/\tany in \"{}\"\t", file).unwrap();
+        }
+
         for (path, creds) in &opts.path_auth_data {
             if let Some(ad) = creds {
                 let mut itr = ad.split(':');
@@ -170,23 +273,121 @@ fn result_main() -> Result<(), Error> {
 
         out.flush().unwrap();
     }
-    if opts.loglevel < options::LogLevel::NoStartup {
+    if opts.loglevel < LogLevel::NoStartup {
         println!("Ctrl-C to stop.");
         println!();
     }
 
-    let end_handler = Arc::new(Condvar::new());
+    if let Some(origin) = opts.mirror.clone() {
+        let mirror = ops::MirrorPuller::new(origin,
+                                            opts.hosted_directory.1.clone(),
+                                            &opts.temp_directory,
+                                            (opts.loglevel < LogLevel::NoServeStatus, opts.log_colour));
+        let interval = Duration::from_secs(opts.mirror_interval);
+        thread::spawn(move || mirror.run(interval, mirror_warm_up));
+    }
+
+    // Whether to stop, set on Ctrl-C or once the serving limits were reached
+    let end_handler = Arc::new((Mutex::new(false), Condvar::new()));
+    let stop = |end_handler: &(Mutex<bool>, Condvar)| {
+        *end_handler.0.lock().unwrap_or_else(PoisonError::into_inner) = true;
+        end_handler.1.notify_one();
+    };
     ctrlc::set_handler({
             let r = end_handler.clone();
-            move || r.notify_one()
+            move || stop(&r)
         })
         .unwrap();
-    let mx = Mutex::new(());
-    let _ = end_handler.wait(mx.lock().unwrap()).unwrap();
-    responder.close().unwrap();
+    if let Some(limits) = serve_limits {
+        let r = end_handler.clone();
+        thread::spawn(move || {
+            limits.wait_done();
+            stop(&r)
+        });
+    }
+    let stopped = end_handler.0.lock().unwrap_or_else(PoisonError::into_inner);
+    match opts.until {
+        // Stop when told to or when it's time, whichever's first
+        Some(until) => drop(end_handler.1.wait_timeout_while(stopped, until, |s| !*s).unwrap_or_else(PoisonError::into_inner)),
+        None => drop(end_handler.1.wait_while(stopped, |s| !*s).unwrap_or_else(PoisonError::into_inner)),
+    }
+    for responder in &mut responders {
+        responder.close().unwrap();
+    }
+    if let Some(counts) = download_counts {
+        let _ = counts.save();
+    }
+    if let Some(replicator) = write_replicator {
+        replicator.flush();
+    }
 
     // This is necessary because the server isn't Drop::drop()ped when the responder is
     ops::HttpHandler::clean_temp_dirs(&opts.temp_directory, opts.loglevel, opts.log_colour);
 
     Ok(())
 }
+
+
+/// What's hosted, where, and with what, with `curl` commands to try it with
+fn print_banner(opts: &Options, socket: SocketAddr) {
+    let host = if socket.ip().is_unspecified() {
+        format!("localhost:{}", socket.port())
+    } else {
+        socket.to_string()
+    };
+    let root_url = format!("{}://{}/", if opts.tls_data.is_some() { "https" } else { "http" }, host);
+    let authenticated = opts.authenticated_paths();
+
+    println!("Hosting \"{}\"", opts.hosted_directory.0);
+    let mut out = TabWriter::new(stdout());
+    writeln!(out, "  Root\t{}", opts.hosted_directory.1.display()).unwrap();
+    write!(out, "  URL\t{}", root_url).unwrap();
+    if socket.ip().is_unspecified() {
+        write!(out, " (and every other address of this machine)").unwrap();
+    }
+    writeln!(out).unwrap();
+    writeln!(out,
+             "  Writes\t{}",
+             if opts.inbox {
+                 "upload-only inbox"
+             } else if opts.allow_writes {
+                 "enabled"
+             } else {
+                 "disabled"
+             })
+        .unwrap();
+    writeln!(out, "  Encoding\t{}", if opts.encode_fs { "enabled" } else { "disabled" }).unwrap();
+    match opts.tls_data.as_ref() {
+        Some(&((ref id, _), _)) => writeln!(out, "  TLS\tcertificate from \"{}\"", id).unwrap(),
+        None => writeln!(out, "  TLS\tdisabled").unwrap(),
+    }
+    if authenticated.is_empty() {
+        writeln!(out, "  Authentication\tnone").unwrap();
+    } else {
+        writeln!(out, "  Authentication\tbasic, under {}", authenticated.join(", ")).unwrap();
+    }
+    out.flush().unwrap();
+
+    // Generated certificates aren't trusted by anything, and a root password's asked for by curl, rather than shown here
+    let mut curl = "curl".to_string();
+    if opts.generate_tls {
+        curl.push_str(" -k");
+    }
+    if let Some(Some(creds)) = opts.path_auth_data.get("") {
+        curl.push_str(" -u ");
+        curl.push_str(creds.split(':').next().unwrap());
+    } else if opts.auth_file.is_some() {
+        curl.push_str(" -u USER");
+    }
+    let file = opts.single_file.as_ref().map(|f| &f[..]).unwrap_or("FILE");
+
+    println!("Try:");
+    let mut out = TabWriter::new(stdout());
+    if !opts.inbox {
+        writeln!(out, "  Download\t{} -O {}{}", curl, root_url, file).unwrap();
+    }
+    if opts.allow_writes || opts.inbox {
+        writeln!(out, "  Upload\t{} -T {} {}{}", curl, file, root_url, file).unwrap();
+    }
+    out.flush().unwrap();
+}
@@ -0,0 +1,190 @@
+//! Bandwidth accounting, enabled with `--bandwidth-window`: bytes of response bodies sent, per client address and per
+//! top-level directory, over a sliding window, so heavy users of public instances can be found with the admin API.
+//!
+//! The window is split into `BUCKETS` buckets, the oldest of which is dropped as a new one is started,
+//! so totals cover between the full window and one bucket's worth less.
+
+
+use std::io::{Result as IoResult, Write};
+use std::collections::{BTreeMap, VecDeque};
+use std::time::{Duration, Instant};
+use iron::response::WriteBody;
+use iron::{Response, Request};
+use self::super::HttpHandler;
+use std::sync::{Arc, Mutex, PoisonError};
+use cidr::Cidr;
+
+
+/// How many parts the window is tracked in
+pub const BUCKETS: u32 = 60;
+
+
+/// When it was started, and bytes by client and by top-level directory
+type Bucket = (Instant, BTreeMap<String, u64>, BTreeMap<String, u64>);
+
+/// Bytes sent by client and top-level directory over the last `window`
+#[derive(Debug)]
+pub struct BandwidthAccounts {
+    window: Duration,
+    buckets: Mutex<VecDeque<Bucket>>,
+}
+
+impl BandwidthAccounts {
+    pub fn new(window: Duration) -> BandwidthAccounts {
+        BandwidthAccounts {
+            window,
+            buckets: Default::default(),
+        }
+    }
+
+    pub fn window(&self) -> Duration {
+        self.window
+    }
+
+    fn record(&self, client: &str, dir: &str, bytes: u64) {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap_or_else(PoisonError::into_inner);
+        self.expire(&mut buckets, now);
+        if buckets.back().map(|b| now.duration_since(b.0) >= self.window / BUCKETS).unwrap_or(true) {
+            buckets.push_back((now, BTreeMap::new(), BTreeMap::new()));
+        }
+
+        let bucket = buckets.back_mut().unwrap();
+        match bucket.1.get_mut(client) {
+            Some(b) => *b += bytes,
+            None => {
+                bucket.1.insert(client.to_string(), bytes);
+            }
+        }
+        match bucket.2.get_mut(dir) {
+            Some(b) => *b += bytes,
+            None => {
+                bucket.2.insert(dir.to_string(), bytes);
+            }
+        }
+    }
+
+    /// Bytes by client and by top-level directory, over the window
+    pub(super) fn totals(&self) -> (BTreeMap<String, u64>, BTreeMap<String, u64>) {
+        let mut buckets = self.buckets.lock().unwrap_or_else(PoisonError::into_inner);
+        self.expire(&mut buckets, Instant::now());
+
+        let mut totals = (BTreeMap::new(), BTreeMap::new());
+        for (_, clients, dirs) in buckets.iter() {
+            for (client, bytes) in clients {
+                *totals.0.entry(client.clone()).or_insert(0) += bytes;
+            }
+            for (dir, bytes) in dirs {
+                *totals.1.entry(dir.clone()).or_insert(0) += bytes;
+            }
+        }
+        totals
+    }
+
+    fn expire(&self, buckets: &mut VecDeque<Bucket>, now: Instant) {
+        while buckets.front().map(|b| now.duration_since(b.0) >= self.window).unwrap_or(false) {
+            buckets.pop_front();
+        }
+    }
+}
+
+
+impl HttpHandler {
+    /// Have the bytes of `resp`'s body be accounted to the requesting client and top-level directory as they're sent
+    pub(super) fn account_bandwidth(&self, req: &Request, resp: &mut Response) {
+        if let Some(accounts) = self.bandwidth_accounts.as_ref() {
+            if let Some(body) = resp.body.take() {
+                resp.body = Some(Box::new(AccountedWriteBody {
+                    accounts: accounts.clone(),
+                    client: self.client_address(req),
+                    dir: top_level_directory(req),
+                    underlying: body,
+                }));
+            }
+        }
+    }
+
+    /// The address of the client, as forwarded by a trusted proxy, if any
+    fn client_address(&self, req: &Request) -> String {
+        for (network, header) in &self.proxies {
+            if network.contains(&req.remote_addr.ip()) {
+                if let Some(saddr) = req.headers.get_raw(header).and_then(|saddrs| saddrs.first()) {
+                    // X-Forwarded-For-style lists start with the original client
+                    let saddr = String::from_utf8_lossy(saddr);
+                    return saddr.split(',').next().unwrap_or("").trim().to_string();
+                }
+            }
+        }
+
+        req.remote_addr.ip().to_string()
+    }
+}
+
+/// `/` followed by the first segment of the requested path, if it's a directory, otherwise just `/`
+fn top_level_directory(req: &Request) -> String {
+    let segments: Vec<_> = req.url.as_ref().path_segments().into_iter().flatten().take(2).collect();
+    if segments.len() == 2 {
+        format!("/{}", segments[0])
+    } else {
+        "/".to_string()
+    }
+}
+
+
+struct AccountedWriteBody {
+    accounts: Arc<BandwidthAccounts>,
+    client: String,
+    dir: String,
+    underlying: Box<dyn WriteBody>,
+}
+
+impl WriteBody for AccountedWriteBody {
+    fn write_body(&mut self, res: &mut dyn Write) -> IoResult<()> {
+        let mut writer = AccountedWriter {
+            accounts: &self.accounts,
+            client: &self.client,
+            dir: &self.dir,
+            output: res,
+            unrecorded: 0,
+        };
+        let result = self.underlying.write_body(&mut writer);
+        writer.record();
+        result
+    }
+}
+
+
+/// Records in batches of at least this many bytes, so the accounts aren't locked for every little write
+const RECORD_BATCH: u64 = 64 * 1024;
+
+struct AccountedWriter<'b, 'o> {
+    accounts: &'b BandwidthAccounts,
+    client: &'b str,
+    dir: &'b str,
+    output: &'o mut dyn Write,
+    unrecorded: u64,
+}
+
+impl<'b, 'o> AccountedWriter<'b, 'o> {
+    fn record(&mut self) {
+        if self.unrecorded != 0 {
+            self.accounts.record(self.client, self.dir, self.unrecorded);
+            self.unrecorded = 0;
+        }
+    }
+}
+
+impl<'b, 'o> Write for AccountedWriter<'b, 'o> {
+    fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+        let written = self.output.write(buf)?;
+        self.unrecorded += written as u64;
+        if self.unrecorded >= RECORD_BATCH {
+            self.record();
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> IoResult<()> {
+        self.output.flush()
+    }
+}
@@ -0,0 +1,375 @@
+//! Admin API, enabled with `--admin`, served under `/.http-admin/`, which always needs credentials.
+//!
+//! All responses are JSON:
+//!
+//!   * `GET /.http-admin/` – all of the below at once
+//!   * `GET /.http-admin/caches` – entry counts and sizes of the encoded, generated, and hash caches
+//!   * `DELETE /.http-admin/caches`, `DELETE /.http-admin/caches/{encoded,generated,hashes}` – flush all or one of them
+//!   * `GET /.http-admin/activity` – uploads being received, encodings running, and whether a deploy is in progress
+//!   * `GET /.http-admin/uploads`, `GET /.http-admin/uploads/{id}` – uploads being received, or the ones with that
+//!     `X-Request-Id`, with bytes received, expected, and per second so far
+//!   * `GET /.http-admin/log`, `PUT /.http-admin/log` with `true` or `false` – view or switch serving status logging
+//!   * `GET /.http-admin/log-headers`, `PUT /.http-admin/log-headers` with `true` or `false` – view or switch logging
+//!     request and response headers along with it
+//!   * `GET /.http-admin/downloads` – with `--download-counts`, how many times each file was downloaded, most popular first
+//!   * `GET /.http-admin/bandwidth` – with `--bandwidth-window`, bytes sent to each client and from each top-level directory
+//!     over the window, most first
+//!   * `GET /.http-admin/healthz`, `GET /.http-admin/readyz` – liveness and readiness probes, which don't need credentials;
+//!     see `probes`
+
+
+use self::super::super::util::{percent_decode, url_path, body_framing_error, ADMIN_PREFIX, USER_AGENT};
+use iron::{headers, status, method, mime, IronResult, Response, Request};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{TryLockError, PoisonError, Mutex, Arc};
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+use iron::modifiers::Header;
+use serde_json::{self, Value};
+use self::super::{HttpHandler, LogSwitch};
+use std::path::PathBuf;
+use std::io::{Result as IoResult, Read};
+use std::fs;
+
+
+/// Largest admin request body read, enough for any of them
+const MAX_ADMIN_BODY: u64 = 1024;
+
+
+/// An upload being received, with how much of it's come in so far
+#[derive(Debug)]
+struct UploadInfo {
+    /// The client's `X-Request-Id`, or the upload number if it didn't send one
+    id: String,
+    path: PathBuf,
+    remote: String,
+    start: Instant,
+    /// The body's Content-Length, if not chunked
+    expected: Option<u64>,
+    received: Arc<AtomicU64>,
+}
+
+/// PUT bodies being received, by upload number, and the next upload's number
+#[derive(Debug, Default)]
+pub(super) struct ActiveUploads {
+    uploads: Mutex<(u64, BTreeMap<u64, UploadInfo>)>,
+}
+
+/// Removes the upload from the active ones on drop
+pub(super) struct ActiveUpload<'u> {
+    uploads: &'u ActiveUploads,
+    id: u64,
+    received: Arc<AtomicU64>,
+}
+
+impl ActiveUploads {
+    /// Register an upload into the specified path from the specified remote address until the returned value is dropped
+    pub(super) fn start(&self, path: PathBuf, remote: String, request_id: Option<String>, expected: Option<u64>) -> ActiveUpload<'_> {
+        let mut uploads = self.uploads.lock().unwrap_or_else(PoisonError::into_inner);
+        let id = uploads.0;
+        uploads.0 += 1;
+        let received = Arc::new(AtomicU64::new(0));
+        uploads.1.insert(id,
+                         UploadInfo {
+                             id: request_id.unwrap_or_else(|| id.to_string()),
+                             path,
+                             remote,
+                             start: Instant::now(),
+                             expected,
+                             received: received.clone(),
+                         });
+        ActiveUpload {
+            uploads: self,
+            id,
+            received,
+        }
+    }
+
+    /// `(id, path, remote, elapsed, received, expected)` of every upload, or just the ones with the specified ID
+    fn list(&self, id: Option<&str>) -> Vec<(String, PathBuf, String, Duration, u64, Option<u64>)> {
+        self.uploads
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .1
+            .values()
+            .filter(|u| id.map(|id| u.id == id).unwrap_or(true))
+            .map(|u| (u.id.clone(), u.path.clone(), u.remote.clone(), u.start.elapsed(), u.received.load(Ordering::Relaxed), u.expected))
+            .collect()
+    }
+}
+
+impl<'u> ActiveUpload<'u> {
+    /// Count bytes read from `body` towards the upload's progress
+    pub(super) fn track<R: Read>(&self, body: R) -> ProgressReader<'_, R> {
+        ProgressReader {
+            received: &self.received,
+            body,
+        }
+    }
+}
+
+impl<'u> Drop for ActiveUpload<'u> {
+    fn drop(&mut self) {
+        self.uploads.uploads.lock().unwrap_or_else(PoisonError::into_inner).1.remove(&self.id);
+    }
+}
+
+pub(super) struct ProgressReader<'r, R: Read> {
+    received: &'r AtomicU64,
+    body: R,
+}
+
+impl<'r, R: Read> Read for ProgressReader<'r, R> {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        let read = self.body.read(buf)?;
+        self.received.fetch_add(read as u64, Ordering::Relaxed);
+        Ok(read)
+    }
+}
+
+
+impl HttpHandler {
+    pub(super) fn admin_requested(&self, req: &Request) -> bool {
+        self.admin && req.url.as_ref().path_segments().and_then(|mut s| s.next()) == Some(ADMIN_PREFIX)
+    }
+
+    pub(super) fn handle_admin(&self, req: &mut Request) -> IronResult<Response> {
+        let segments: Vec<String> = req.url.as_ref().path_segments().into_iter().flatten().skip(1).filter(|s| !s.is_empty()).map(str::to_string).collect();
+        let segments: Vec<&str> = segments.iter().map(|s| &s[..]).collect();
+
+        match (&req.method, &segments[..]) {
+            (&method::Get, []) => {
+                self.handle_admin_output(req,
+                                         json!({
+                                             "caches": self.admin_caches(),
+                                             "activity": self.admin_activity(),
+                                             "log": self.log.0.on(),
+                                             "log_headers": self.log_headers.on(),
+                                             "downloads": self.admin_downloads(),
+                                             "bandwidth": self.admin_bandwidth(),
+                                         }))
+            }
+            (_, []) => self.handle_disallowed_method(req, &[&[method::Get]], "admin API overview"),
+
+            (&method::Get, ["caches"]) => self.handle_admin_output(req, self.admin_caches()),
+            (&method::Delete, ["caches"]) => self.handle_admin_flush(req, &["encoded", "generated", "hashes"]),
+            (&method::Delete, ["caches", cache]) if ["encoded", "generated", "hashes"].contains(cache) => {
+                let cache = cache.to_string();
+                self.handle_admin_flush(req, &[&cache])
+            }
+            (_, ["caches"]) => self.handle_disallowed_method(req, &[&[method::Get, method::Delete]], "admin API cache list"),
+            (_, ["caches", "encoded"]) |
+            (_, ["caches", "generated"]) |
+            (_, ["caches", "hashes"]) => self.handle_disallowed_method(req, &[&[method::Delete]], "admin API cache"),
+
+            (&method::Get, ["activity"]) => self.handle_admin_output(req, self.admin_activity()),
+            (_, ["activity"]) => self.handle_disallowed_method(req, &[&[method::Get]], "admin API activity"),
+
+            (&method::Get, ["uploads"]) => self.handle_admin_output(req, self.admin_uploads(None)),
+            (&method::Get, ["uploads", id]) => {
+                let id = percent_decode(id).map(|id| id.into_owned()).unwrap_or_default();
+                match self.admin_uploads(Some(&id)) {
+                    Value::Array(ref uploads) if uploads.is_empty() => self.handle_admin_invalid(req, status::NotFound, "No such upload in progress."),
+                    uploads => self.handle_admin_output(req, uploads),
+                }
+            }
+            (_, ["uploads"]) |
+            (_, ["uploads", _]) => self.handle_disallowed_method(req, &[&[method::Get]], "admin API uploads"),
+
+            (&method::Get, ["log"]) => self.handle_admin_output(req, json!(self.log.0.on())),
+            (&method::Put, ["log"]) => self.handle_admin_set_log(req, &self.log.0, "logging"),
+            (_, ["log"]) => self.handle_disallowed_method(req, &[&[method::Get, method::Put]], "admin API log switch"),
+
+            (&method::Get, ["log-headers"]) => self.handle_admin_output(req, json!(self.log_headers.on())),
+            (&method::Put, ["log-headers"]) => self.handle_admin_set_log(req, &self.log_headers, "header logging"),
+            (_, ["log-headers"]) => self.handle_disallowed_method(req, &[&[method::Get, method::Put]], "admin API header log switch"),
+
+            (&method::Get, ["downloads"]) if self.download_counts.is_some() => self.handle_admin_output(req, self.admin_downloads()),
+            (_, ["downloads"]) if self.download_counts.is_some() => self.handle_disallowed_method(req, &[&[method::Get]], "admin API download counts"),
+
+            (&method::Get, ["bandwidth"]) if self.bandwidth_accounts.is_some() => self.handle_admin_output(req, self.admin_bandwidth()),
+            (_, ["bandwidth"]) if self.bandwidth_accounts.is_some() => self.handle_disallowed_method(req, &[&[method::Get]], "admin API bandwidth accounts"),
+
+            (&method::Get, ["healthz"]) => self.handle_healthz(req),
+            (_, ["healthz"]) => self.handle_disallowed_method(req, &[&[method::Get]], "admin API liveness probe"),
+
+            (&method::Get, ["readyz"]) => self.handle_readyz(req),
+            (_, ["readyz"]) => self.handle_disallowed_method(req, &[&[method::Get]], "admin API readiness probe"),
+
+            _ => self.handle_admin_invalid(req, status::NotFound, "No such admin API endpoint."),
+        }
+    }
+
+    fn admin_caches(&self) -> Value {
+        let encoded_bytes = self.encoded_store
+            .as_ref()
+            .and_then(|s| s.dir().1.read_dir().ok())
+            .map(|rd| rd.flatten().flat_map(|f| f.metadata()).filter(|m| m.is_file()).map(|m| m.len()).sum::<u64>())
+            .unwrap_or(0) + self.encoded_memory.as_ref().map(|m| m.usage().1).unwrap_or(0);
+        let cache_gen = self.cache_gen.read().unwrap_or_else(PoisonError::into_inner);
+
+        json!({
+            "encoded": {
+                "entries": self.cache_fs.len() + self.encoded_memory.as_ref().map(|m| m.usage().0).unwrap_or(0),
+                "bytes": encoded_bytes,
+            },
+            "generated": {
+                "entries": cache_gen.entries.len(),
+                "bytes": cache_gen.size,
+            },
+            "hashes": {
+                "entries": self.cache_hash.len(),
+            },
+        })
+    }
+
+    fn admin_activity(&self) -> Value {
+        json!({
+            "uploads": self.admin_uploads(None),
+            "encodings": self.encoding_slots.in_flight.lock().unwrap_or_else(PoisonError::into_inner).len(),
+            "deploying": matches!(self.deploy_lock.try_lock(), Err(TryLockError::WouldBlock)),
+        })
+    }
+
+    /// Uploads being received, or the ones with the specified ID, with their progress
+    fn admin_uploads(&self, id: Option<&str>) -> Value {
+        let uploads: Vec<_> = self.uploads
+            .list(id)
+            .into_iter()
+            .map(|(id, path, remote, elapsed, received, expected)| {
+                let secs = elapsed.as_secs_f64();
+                json!({
+                    "id": id,
+                    "path": format!("/{}", path.strip_prefix(&self.hosted_directory.1).unwrap_or(&path).display()),
+                    "remote": remote,
+                    "seconds": elapsed.as_secs(),
+                    "bytes": received,
+                    "expected_bytes": expected,
+                    "bytes_per_second": if secs > 0f64 { (received as f64 / secs) as u64 } else { 0 },
+                })
+            })
+            .collect();
+        Value::Array(uploads)
+    }
+
+    /// `null` if downloads aren't counted
+    fn admin_downloads(&self) -> Value {
+        match self.download_counts.as_ref() {
+            Some(counts) => {
+                Value::Array(counts.by_popularity()
+                    .into_iter()
+                    .map(|(path, downloads)| {
+                        json!({
+                            "path": format!("/{}", path),
+                            "downloads": downloads,
+                        })
+                    })
+                    .collect())
+            }
+            None => Value::Null,
+        }
+    }
+
+    /// `null` if bandwidth isn't accounted
+    fn admin_bandwidth(&self) -> Value {
+        fn by_usage(totals: BTreeMap<String, u64>, key: &str) -> Value {
+            let mut totals: Vec<_> = totals.into_iter().collect();
+            totals.sort_by(|lhs, rhs| rhs.1.cmp(&lhs.1).then_with(|| lhs.0.cmp(&rhs.0)));
+            Value::Array(totals.into_iter().map(|(k, bytes)| json!({ key: k, "bytes": bytes })).collect())
+        }
+
+        match self.bandwidth_accounts.as_ref() {
+            Some(accounts) => {
+                let (clients, dirs) = accounts.totals();
+                json!({
+                    "window": accounts.window().as_secs(),
+                    "clients": by_usage(clients, "address"),
+                    "paths": by_usage(dirs, "path"),
+                })
+            }
+            None => Value::Null,
+        }
+    }
+
+    fn handle_admin_flush(&self, req: &mut Request, caches: &[&str]) -> IronResult<Response> {
+        for &cache in caches {
+            match cache {
+                "encoded" => {
+                    self.cache_fs.clear();
+                    if let Some(memory) = self.encoded_memory.as_ref() {
+                        memory.clear();
+                    }
+                    // Encodings in progress create their files anew, so those don't mind
+                    if let Some(rd) = self.encoded_store.as_ref().and_then(|s| s.dir().1.read_dir().ok()) {
+                        for f in rd.flatten() {
+                            let _ = fs::remove_file(f.path());
+                        }
+                    }
+                }
+                "generated" => *self.cache_gen.write().unwrap_or_else(PoisonError::into_inner) = Default::default(),
+                "hashes" => self.cache_hash.clear(),
+                _ => unreachable!(),
+            }
+        }
+
+        log!(self.log,
+             "{} flushed the {} cache{} with the {red}admin API{reset}",
+             self.remote_addresses(req),
+             caches.join(", "),
+             if caches.len() == 1 { "" } else { "s" });
+        Ok(Response::with((status::NoContent, Header(headers::Server(USER_AGENT.to_string())))))
+    }
+
+    fn handle_admin_set_log(&self, req: &mut Request, switch: &LogSwitch, what: &str) -> IronResult<Response> {
+        if let Some((st, cause)) = body_framing_error(&req.headers) {
+            return self.handle_bad_framing(req, st, cause);
+        }
+
+        let mut body = String::new();
+        let on = match (&mut req.body).take(MAX_ADMIN_BODY).read_to_string(&mut body).ok().and_then(|_| serde_json::from_str::<Value>(&body).ok()) {
+            Some(Value::Bool(on)) => on,
+            _ => return self.handle_admin_invalid(req, status::BadRequest, "The body must be true or false."),
+        };
+
+        // Logged while it's on, so both switching it on and switching it off show up
+        if on {
+            switch.set(true);
+        }
+        log!(self.log,
+             "{} switched {} {} with the {red}admin API{reset}",
+             self.remote_addresses(req),
+             what,
+             if on { "on" } else { "off" });
+        switch.set(on);
+
+        Ok(Response::with((status::NoContent, Header(headers::Server(USER_AGENT.to_string())))))
+    }
+
+    pub(super) fn handle_admin_output(&self, req: &mut Request, out: Value) -> IronResult<Response> {
+        self.handle_admin_output_status(req, status::Ok, out)
+    }
+
+    pub(super) fn handle_admin_output_status(&self, req: &mut Request, st: status::Status, out: Value) -> IronResult<Response> {
+        log!(self.log,
+             "{} was served {magenta}{}{reset} from the {red}admin API{reset}",
+             self.remote_addresses(req),
+             url_path(&req.url));
+
+        Ok(Response::with((st,
+                           Header(headers::Server(USER_AGENT.to_string())),
+                           Header(headers::CacheControl(vec![headers::CacheDirective::NoStore])),
+                           "application/json;charset=utf-8".parse::<mime::Mime>().unwrap(),
+                           serde_json::to_string(&out).unwrap())))
+    }
+
+    fn handle_admin_invalid(&self, req: &mut Request, st: status::Status, cause: &str) -> IronResult<Response> {
+        log!(self.log,
+             "{} requested to {red}{}{reset} {yellow}{}{reset} from the admin API -- {}",
+             self.remote_addresses(req),
+             req.method,
+             url_path(&req.url),
+             cause);
+
+        self.handle_generated_error(req, st, &[&st.to_string()[..], cause, ""])
+    }
+}
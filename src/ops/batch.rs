@@ -0,0 +1,231 @@
+//! Batch file operations, submitted as a single JSON document, so that clients don't need a round-trip per operation.
+//!
+//! The request body is a JSON array of operations, each an object with an `"op"` key:
+//!
+//! ```json
+//! [{"op": "mkdir",  "path": "new-dir"},
+//!  {"op": "copy",   "from": "file.txt", "to": "new-dir/file.txt"},
+//!  {"op": "move",   "from": "old-dir",  "to": "new-dir/old-dir", "overwrite": true},
+//!  {"op": "delete", "path": "file.txt"}]
+//! ```
+//!
+//! Paths are resolved relative to the requested directory, and can't lead out of it;
+//! each needs the credentials for where it leads, like requesting it directly would.
+//! Operations are executed in order, stopping at the first failure; the remaining ones are reported as not attempted.
+
+
+use self::super::super::util::{is_actually_file, is_descendant_of, is_nonexistent_descendant_of, copy_dir, body_framing_error, USER_AGENT};
+use std::io::{ErrorKind as IoErrorKind, Error as IoError};
+use iron::{headers, status, mime, IronResult, Response, Request};
+use iron::modifiers::Header;
+use iron::url::Url as GenericUrl;
+use serde_json::{self, Value};
+use self::super::HttpHandler;
+use std::borrow::Cow;
+use std::path::PathBuf;
+use std::fs;
+
+
+type BatchResult = Result<status::Status, (status::Status, Cow<'static, str>)>;
+
+
+impl HttpHandler {
+    pub(super) fn handle_batch(&self, req: &mut Request) -> IronResult<Response> {
+        if self.writes_temp_dir.is_none() {
+            return self.handle_forbidden_method(req, "-w", "write requests");
+        }
+        if let Some((st, cause)) = body_framing_error(&req.headers) {
+            return self.handle_bad_framing(req, st, cause);
+        }
+
+        let ops = match serde_json::from_reader::<_, Value>(&mut req.body) {
+            Ok(Value::Array(ops)) => ops,
+            Ok(_) => return self.handle_invalid_batch(req, "Expected an array of operations.".into()),
+            Err(e) => return self.handle_invalid_batch(req, format!("Invalid JSON: {}", e).into()),
+        };
+
+        let mut base = req.url.as_ref().clone();
+        if !base.path().ends_with('/') {
+            let path = base.path().to_string() + "/";
+            base.set_path(&path);
+        }
+
+        log!(self.log,
+             "{} requested {red}batch{reset} of {} operations in {yellow}{}{reset}",
+             self.remote_addresses(&req),
+             ops.len(),
+             base.path());
+
+        let mut failed = false;
+        let results: Vec<Value> = ops.iter()
+            .map(|op| {
+                let result = if failed {
+                    Err((status::FailedDependency, "Not attempted due to previous failure".into()))
+                } else {
+                    self.handle_batch_op(req, &base, op)
+                };
+                failed |= result.is_err();
+
+                let (st, error) = match result {
+                    Ok(st) => (st, Value::Null),
+                    Err((st, e)) => (st, Value::String(e.into_owned())),
+                };
+                json!({
+                    "op": op.get("op").cloned().unwrap_or(Value::Null),
+                    "status": st.to_u16(),
+                    "error": error,
+                })
+            })
+            .collect();
+
+        Ok(Response::with((if failed {
+                               status::MultiStatus
+                           } else {
+                               status::Ok
+                           },
+                           Header(headers::Server(USER_AGENT.to_string())),
+                           "application/json;charset=utf-8".parse::<mime::Mime>().unwrap(),
+                           serde_json::to_string(&json!({ "results": results })).unwrap())))
+    }
+
+    fn handle_invalid_batch(&self, req: &mut Request, cause: Cow<'static, str>) -> IronResult<Response> {
+        log!(self.log,
+             "{} requested {red}batch{reset} in {yellow}{}{reset} with invalid body -- {}",
+             self.remote_addresses(&req),
+             req.url,
+             cause);
+
+        self.handle_generated_error(req, status::BadRequest, &["400 Bad Request", &cause, ""])
+    }
+
+    fn handle_batch_op(&self, req: &Request, base: &GenericUrl, op: &Value) -> BatchResult {
+        let path_arg = |name| {
+            op.get(name)
+                .and_then(Value::as_str)
+                .ok_or_else(|| (status::BadRequest, Cow::from(format!("Missing \"{}\" string", name))))
+                .and_then(|path| self.resolve_batch_path(req, base, path))
+        };
+        // Like DELETE, MOVE, and the rest would be, ops are refused for paths locked over WebDAV without the token
+        let unlocked = |path: &PathBuf, tree| if self.webdav_write_locked(req, path, tree) {
+            Err((status::Locked, Cow::from("Locked")))
+        } else {
+            Ok(())
+        };
+
+        match op.get("op").and_then(Value::as_str) {
+            Some("mkdir") => {
+                let (path, _) = path_arg("path")?;
+                unlocked(&path, false)?;
+                fs::create_dir(&path).map(|_| status::Created).map_err(batch_io_error)
+            }
+            Some("delete") => {
+                let (path, symlink) = path_arg("path")?;
+                if !path.exists() {
+                    return Err((status::NotFound, "Nonexistent".into()));
+                }
+                unlocked(&path, true)?;
+
+                if is_actually_file(&path.metadata().map_err(batch_io_error)?.file_type(), &path) {
+                    fs::remove_file(&path)
+                } else if symlink {
+                    fs::remove_file(&path).or_else(|_| fs::remove_dir_all(&path))
+                } else {
+                    fs::remove_dir_all(&path)
+                }
+                .map_err(batch_io_error)?;
+                self.written(&path);
+                self.drop_webdav_locks(&path);
+                Ok(status::NoContent)
+            }
+            Some(op_name @ "copy") |
+            Some(op_name @ "move") => {
+                let (from, _) = path_arg("from")?;
+                let (to, _) = path_arg("to")?;
+                let overwrite = op.get("overwrite").and_then(Value::as_bool).unwrap_or(false);
+
+                if !from.exists() {
+                    return Err((status::NotFound, "Source nonexistent".into()));
+                }
+                if from == to {
+                    return Err((status::Forbidden, "Source and destination are the same".into()));
+                }
+                if op_name == "move" {
+                    unlocked(&from, true)?;
+                }
+                unlocked(&to, true)?;
+
+                let overwritten = to.exists();
+                if overwritten {
+                    if !overwrite {
+                        return Err((status::PreconditionFailed, "Destination exists".into()));
+                    }
+
+                    if is_actually_file(&to.metadata().map_err(batch_io_error)?.file_type(), &to) {
+                        fs::remove_file(&to)
+                    } else {
+                        fs::remove_dir_all(&to)
+                    }
+                    .map_err(batch_io_error)?;
+                }
+
+                if op_name == "move" {
+                    fs::rename(&from, &to).map_err(batch_io_error)?;
+                    self.written(&from);
+                    self.drop_webdav_locks(&from);
+                } else if is_actually_file(&from.metadata().map_err(batch_io_error)?.file_type(), &from) {
+                    fs::copy(&from, &to).map_err(batch_io_error)?;
+                } else {
+                    let errors = copy_dir(&from, &to).map_err(batch_io_error)?;
+                    if !errors.is_empty() {
+                        return Err((status::InsufficientStorage,
+                                    format!("Failed to copy {}", errors.iter().map(|(_, p)| &p[..]).collect::<Vec<_>>().join(", ")).into()));
+                    }
+                }
+                self.written(&to);
+
+                Ok(if overwritten {
+                    status::NoContent
+                } else {
+                    status::Created
+                })
+            }
+            Some(other) => Err((status::BadRequest, format!("Unknown operation \"{}\"", other).into())),
+            None => Err((status::BadRequest, "Missing \"op\" string".into())),
+        }
+    }
+
+    /// Resolve `path` relative to `base` into a filesystem path and whether it's a symlink,
+    /// subject to the symlink settings, staying under `base`, and the request having the credentials needed there
+    fn resolve_batch_path(&self, req: &Request, base: &GenericUrl, path: &str) -> Result<(PathBuf, bool), (status::Status, Cow<'static, str>)> {
+        let url = base.join(path).map_err(|e| (status::BadRequest, Cow::from(format!("Invalid path \"{}\": {}", path, e))))?;
+        if !url.path().starts_with(base.path()) {
+            return Err((status::Forbidden, format!("Path \"{}\" outside the requested directory", path).into()));
+        }
+        if !self.authorised_for_path(req, url.path()) {
+            return Err((status::Unauthorized, format!("Path \"{}\" needs other credentials", path).into()));
+        }
+        let (req_p, symlink, url_err) = self.parse_requested_path_custom_symlink(&url, false);
+
+        if url_err {
+            Err((status::BadRequest, format!("Path \"{}\" percent-decoded to invalid UTF-8 or a path separator", path).into()))
+        } else if (symlink && !self.symlink_policy.follow()) ||
+                  (symlink && self.symlink_policy.sandboxed() &&
+                   !(is_descendant_of(&req_p, &self.hosted_directory.1) || is_nonexistent_descendant_of(&req_p, &self.hosted_directory.1))) ||
+                  self.in_temp_dir(&req_p) {
+            Err((status::NotFound, format!("Path \"{}\" nonexistent", path).into()))
+        } else {
+            Ok((req_p, symlink))
+        }
+    }
+}
+
+
+fn batch_io_error(e: IoError) -> (status::Status, Cow<'static, str>) {
+    (match e.kind() {
+         IoErrorKind::NotFound => status::Conflict,
+         IoErrorKind::PermissionDenied => status::Forbidden,
+         IoErrorKind::AlreadyExists => status::MethodNotAllowed,
+         _ => status::InternalServerError,
+     },
+     e.to_string().into())
+}
@@ -0,0 +1,144 @@
+//! Multiple byte ranges in one request, answered with a `multipart/byteranges` body (RFC 7233, appendix A),
+//! with a part per range, in the order requested, unless they overlap, in which case they're coalesced in file order.
+//!
+//! With `--range-window`, small single ranges are widened to the end of their aligned window instead,
+//! and ranges are read a window at a time, for media players scrubbing with lots of tiny ones.
+
+
+use iron::mime::{Mime, Attr as MimeAttr, Value as MimeValue, SubLevel as MimeSubLevel, TopLevel as MimeTopLevel};
+use self::super::super::util::{USER_AGENT, file_length, file_time_modified_p};
+use rand::distributions::Alphanumeric as AlphanumericDistribution;
+use iron::{headers, status, IronResult, Response, Request};
+use std::io::{Result as IoResult, SeekFrom, Write, Seek, Read, copy};
+use iron::modifiers::Header;
+use iron::response::WriteBody;
+use self::super::fd_cache::ServedFile;
+use self::super::HttpHandler;
+use std::path::{PathBuf, Path};
+use rand::{Rng, thread_rng};
+use std::num::NonZeroU64;
+use std::cmp;
+
+
+/// Most ranges served in one request, so a request can't make the server seek around a file forever
+pub const MAX_BYTE_RANGES: usize = 64;
+
+/// Most bytes read from a file at once for ranges, however big `--range-window` is
+pub const MAX_RANGE_READ_SIZE: u64 = 1024 * 1024;
+
+/// How much is read from a file at once for ranges without `--range-window`, like `io::copy()`
+const DEFAULT_RANGE_READ_SIZE: usize = 8 * 1024;
+
+
+impl HttpHandler {
+    /// Where to end the closed range `from`-`to` of a file `flen` bytes long:
+    /// with `--range-window`, the end of the window `from` is in, or of the file, if that's further than `to`
+    pub(super) fn widened_range_end(&self, from: u64, to: u64, flen: u64) -> u64 {
+        match self.range_window.map(NonZeroU64::get) {
+            Some(window) => cmp::max(to, cmp::min((from / window).saturating_add(1).saturating_mul(window) - 1, flen - 1)),
+            None => to,
+        }
+    }
+
+    /// How many bytes to read from a file at once for a range
+    pub(super) fn range_read_size(&self) -> usize {
+        self.range_window.map(|w| cmp::min(w.get(), MAX_RANGE_READ_SIZE) as usize).unwrap_or(DEFAULT_RANGE_READ_SIZE)
+    }
+
+    pub(super) fn handle_get_file_ranges(&self, req: &mut Request, req_p: PathBuf, data_p: &Path, range: &headers::Range, brs: &[headers::ByteRangeSpec])
+                                         -> IronResult<Response> {
+        if brs.len() > MAX_BYTE_RANGES {
+            return self.handle_invalid_range(req, req_p, range, &format!("At most {} ranges can be requested at once.", MAX_BYTE_RANGES));
+        }
+
+        let flen = file_length(&try_fs!(self, req, data_p.metadata(), "getting its metadata"), &data_p);
+        let ranges = coalesce(brs.iter().flat_map(|br| satisfiable(br, flen)).collect());
+        match ranges.len() {
+            0 => return self.handle_get_file_unsatisfiable_range(req, req_p, range, flen),
+            1 => return self.handle_get_file_closed_range(req, req_p, data_p, ranges[0].0, ranges[0].1),
+            _ => {}
+        }
+
+        let mime_type = self.guess_mime_type(&req_p);
+        log!(self.log,
+             "{} was served byte ranges {} of file {magenta}{}{reset} as {blue}{}{reset}",
+             self.remote_addresses(req),
+             ranges.iter().map(|(from, to)| format!("{}-{}", from, to)).collect::<Vec<_>>().join(", "),
+             req_p.display(),
+             mime_type);
+
+        let file = try_fs!(self, req, self.open_served_file(data_p), "opening it");
+        let boundary: String = thread_rng().sample_iter(AlphanumericDistribution).take(32).collect();
+        let parts: Vec<_> = ranges.into_iter()
+            .map(|(from, to)| {
+                (format!("\r\n--{}\r\nContent-Type: {}\r\nContent-Range: bytes {}-{}/{}\r\n\r\n", boundary, mime_type, from, to, flen), from, to)
+            })
+            .collect();
+        let end = format!("\r\n--{}--\r\n", boundary);
+        let clen = parts.iter().map(|(head, from, to)| head.len() as u64 + to + 1 - from).sum::<u64>() + end.len() as u64;
+
+        let mut resp = Response::with((status::PartialContent,
+                                       (Header(headers::Server(USER_AGENT.to_string())),
+                                        Header(headers::LastModified(headers::HttpDate(file_time_modified_p(&req_p)))),
+                                        Header(headers::ContentLength(clen)),
+                                        Header(headers::AcceptRanges(vec![headers::RangeUnit::Bytes]))),
+                                       Mime(MimeTopLevel::Multipart,
+                                            MimeSubLevel::Ext("byteranges".to_string()),
+                                            vec![(MimeAttr::Ext("boundary".to_string()), MimeValue::Ext(boundary))])));
+        resp.body = Some(Box::new(ByteRangesBody {
+            file: file,
+            parts,
+            end,
+        }));
+        Ok(resp)
+    }
+}
+
+
+/// The inclusive range `br` selects from a file `flen` long, if any
+fn satisfiable(br: &headers::ByteRangeSpec, flen: u64) -> Option<(u64, u64)> {
+    match *br {
+        headers::ByteRangeSpec::FromTo(from, to) if from < flen => Some((from, to.min(flen - 1))),
+        headers::ByteRangeSpec::AllFrom(from) if from < flen => Some((from, flen - 1)),
+        // RFC 7233, section 2.1: suffixes longer than the file select all of it
+        headers::ByteRangeSpec::Last(len) if len != 0 && flen != 0 => Some((flen - len.min(flen), flen - 1)),
+        _ => None,
+    }
+}
+
+/// Merge the ranges if any of them overlap, so no byte is sent twice
+fn coalesce(mut ranges: Vec<(u64, u64)>) -> Vec<(u64, u64)> {
+    let mut sorted = ranges.clone();
+    sorted.sort_unstable();
+    if sorted.windows(2).all(|w| w[0].1 < w[1].0) {
+        return ranges;
+    }
+
+    ranges.clear();
+    for (from, to) in sorted {
+        match ranges.last_mut() {
+            Some(last) if from <= last.1 => last.1 = last.1.max(to),
+            _ => ranges.push((from, to)),
+        }
+    }
+    ranges
+}
+
+
+struct ByteRangesBody {
+    file: ServedFile,
+    /// Part header, and the inclusive range that follows it
+    parts: Vec<(String, u64, u64)>,
+    end: String,
+}
+
+impl WriteBody for ByteRangesBody {
+    fn write_body(&mut self, res: &mut dyn Write) -> IoResult<()> {
+        for (head, from, to) in &self.parts {
+            res.write_all(head.as_bytes())?;
+            self.file.seek(SeekFrom::Start(*from))?;
+            copy(&mut (&mut self.file).take(to + 1 - from), res)?;
+        }
+        res.write_all(self.end.as_bytes())
+    }
+}
@@ -0,0 +1,59 @@
+//! Client and intermediary caching of served files, enabled with `--cache-max-age` and `--cache-max-age-for`:
+//! successful file responses, whole or ranged, get `Cache-Control: max-age` and the equivalent `Expires`.
+//!
+//! Versioned ones, see `conditional`, are instead cacheable for a year and `immutable`.
+
+
+use iron::{headers, status, Response};
+use self::super::HttpHandler;
+use std::time::Duration;
+use std::path::Path;
+use std::ffi::OsStr;
+use time;
+
+
+impl HttpHandler {
+    /// How long the file at `req_p` may be cached for, going by its extension, if it should say
+    pub(super) fn cache_max_age_for(&self, req_p: &Path) -> Option<Duration> {
+        if self.cache_max_age_overrides.is_empty() {
+            return self.cache_max_age;
+        }
+
+        let ext = req_p.extension().map(OsStr::to_string_lossy).unwrap_or("".into());
+        self.cache_max_age_overrides.get(&*ext).cloned().or(self.cache_max_age)
+    }
+}
+
+
+/// Add `Cache-Control` and `Expires` allowing reuse for `max_age`, if any, to successful responses
+pub fn with_cache_headers(mut resp: Response, max_age: Option<Duration>) -> Response {
+    match (max_age, resp.status) {
+        (Some(max_age), Some(status::Ok)) |
+        (Some(max_age), Some(status::PartialContent)) => {
+            let secs = max_age.as_secs().min(u32::MAX as u64);
+            resp.headers.set(headers::CacheControl(vec![headers::CacheDirective::MaxAge(secs as u32)]));
+            resp.headers.set(headers::Expires(headers::HttpDate(time::now_utc() + time::Duration::seconds(secs as i64))));
+        }
+        _ => {}
+    }
+    resp
+}
+
+/// Mark successful responses as never changing: publicly cacheable for a year, without revalidation
+pub fn with_immutable_cache_headers(mut resp: Response) -> Response {
+    match resp.status {
+        Some(status::Ok) |
+        Some(status::PartialContent) => {
+            resp.headers.set(headers::CacheControl(vec![headers::CacheDirective::Public,
+                                                        headers::CacheDirective::MaxAge(IMMUTABLE_MAX_AGE as u32),
+                                                        headers::CacheDirective::Extension("immutable".to_string(), None)]));
+            resp.headers.set(headers::Expires(headers::HttpDate(time::now_utc() + time::Duration::seconds(IMMUTABLE_MAX_AGE))));
+        }
+        _ => {}
+    }
+    resp
+}
+
+
+/// How long immutable responses are cacheable for, a year, as RFC 7234 recommends for ones that never expire
+const IMMUTABLE_MAX_AGE: i64 = 365 * 24 * 60 * 60;
@@ -0,0 +1,102 @@
+//! Conditional writes (RFC 7232): `If-Match`, `If-None-Match`, and `If-Unmodified-Since` on PUT and DELETE,
+//! for optimistic concurrency and create-only-if-absent uploads, failing with 412 Precondition Failed.
+//!
+//! Files' entity tags are the same metadata-derived ones as the S3 API's, sent with unencoded GETs and with PUT responses,
+//! or, with `--content-hash-etags`, their content hashes, also sent as `X-Content-Hash`,
+//! and requests with a `?v=` of (at least the first 8 digits of) it are marked cacheable forever.
+
+
+use self::super::super::util::{s3_etag, file_time_modified_p, url_path};
+use iron::{headers, status, IronResult, Response, Request};
+use self::super::cache_control::with_immutable_cache_headers;
+use self::super::HttpHandler;
+use std::path::Path;
+
+
+impl HttpHandler {
+    /// Whether the request's preconditions hold for what's at `req_p` now, if anything
+    pub(super) fn write_preconditions_met(&self, req: &Request, req_p: &Path) -> bool {
+        let exists = req_p.exists();
+        let etag = self.entity_tag(req_p);
+
+        // RFC 7232, section 6: If-Match, or If-Unmodified-Since without it, then If-None-Match
+        match req.headers.get::<headers::IfMatch>() {
+            Some(headers::IfMatch::Any) if !exists => return false,
+            Some(headers::IfMatch::Items(tags)) if !etag.as_ref().map(|e| tags.iter().any(|t| t.strong_eq(e))).unwrap_or(false) => return false,
+            Some(_) => {}
+            None => {
+                if let Some(&headers::IfUnmodifiedSince(headers::HttpDate(since))) = req.headers.get() {
+                    if exists && file_time_modified_p(req_p).to_timespec().sec > since.to_timespec().sec {
+                        return false;
+                    }
+                }
+            }
+        }
+
+        match req.headers.get::<headers::IfNoneMatch>() {
+            Some(headers::IfNoneMatch::Any) => !exists,
+            Some(headers::IfNoneMatch::Items(tags)) => !etag.as_ref().map(|e| tags.iter().any(|t| t.weak_eq(e))).unwrap_or(false),
+            None => true,
+        }
+    }
+
+    pub(super) fn handle_precondition_failed(&self, req: &mut Request, req_p: &Path) -> IronResult<Response> {
+        log!(self.log,
+             "{} tried to {red}{}{reset} {magenta}{}{reset} but its preconditions {red}failed{reset}",
+             self.remote_addresses(req),
+             req.method,
+             req_p.display());
+
+        let cause = format!("The preconditions for \"{}\" weren't met.", url_path(&req.url));
+        self.handle_generated_error(req, status::PreconditionFailed, &["412 Precondition Failed", &cause, ""])
+    }
+
+    /// The entity tag of the file at `p`, if it's one: its content hash with `--content-hash-etags`, metadata-derived otherwise
+    pub(super) fn entity_tag(&self, p: &Path) -> Option<headers::EntityTag> {
+        if !self.content_hash_etags {
+            return file_etag(p);
+        }
+        p.metadata().ok().filter(|m| m.is_file())?;
+        self.cached_file_hash(p).ok().map(|h| headers::EntityTag::strong(h.to_hex().to_string()))
+    }
+
+    /// Add `X-Content-Hash` to successful responses with content-hash entity tags, encoded or not, since it's of the file,
+    /// marking them cacheable forever if they were requested with a `?v=` of it
+    pub(super) fn with_content_hash(&self, req: &Request, mut resp: Response, etag: Option<&headers::EntityTag>) -> Response {
+        let hash = match etag.filter(|_| self.content_hash_etags) {
+            Some(etag) => etag.tag(),
+            None => return resp,
+        };
+        if resp.status != Some(status::Ok) && resp.status != Some(status::PartialContent) {
+            return resp;
+        }
+
+        resp.headers.set_raw("X-Content-Hash", vec![hash.as_bytes().to_vec()]);
+        if req.url.as_ref().query_pairs().any(|(k, v)| k == "v" && v.len() >= MIN_VERSION_LENGTH && hash.starts_with(&*v)) {
+            resp = with_immutable_cache_headers(resp);
+        }
+        resp
+    }
+}
+
+
+/// Shortest `?v=` content hash prefix that marks responses immutable
+const MIN_VERSION_LENGTH: usize = 8;
+
+
+/// The entity tag of the file at `p`, if it's one
+pub fn file_etag(p: &Path) -> Option<headers::EntityTag> {
+    p.metadata().ok().filter(|m| m.is_file()).map(|m| headers::EntityTag::strong(s3_etag(&m)))
+}
+
+/// Add the entity tag to successful unencoded responses; encoded ones are different representations
+pub fn with_etag(mut resp: Response, etag: Option<headers::EntityTag>) -> Response {
+    match (etag, resp.status) {
+        (Some(etag), Some(status::Ok)) |
+        (Some(etag), Some(status::PartialContent)) |
+        (Some(etag), Some(status::Created)) |
+        (Some(etag), Some(status::NoContent)) if !resp.headers.has::<headers::ContentEncoding>() => resp.headers.set(headers::ETag(etag)),
+        _ => {}
+    }
+    resp
+}
@@ -0,0 +1,54 @@
+//! WebDAV dead properties: ones set by clients with PROPPATCH that mean nothing to us, but are returned by PROPFIND as-set.
+//!
+//! They're kept in `.NAME.props/` next to the hosted directory, so they survive restarts, one JSON file per resource,
+//! named after the hash of its path; so they stay with the path, and a resource replaced in place keeps them.
+
+
+use std::io::{ErrorKind as IoErrorKind, Result as IoResult, Error as IoError};
+use std::collections::BTreeMap;
+use self::super::HttpHandler;
+use std::path::{PathBuf, Path};
+use std::fs::{self, File};
+use serde_json;
+
+
+impl HttpHandler {
+    /// Dead properties of the specified resource, keyed by their `{namespace}name`
+    pub(super) fn dead_props(&self, p: &Path) -> BTreeMap<String, String> {
+        self.dead_props_file(p)
+            .and_then(|f| File::open(f).ok())
+            .and_then(|f| serde_json::from_reader(f).ok())
+            .unwrap_or_default()
+    }
+
+    /// Replace the dead properties of the specified resource, removing its file if there aren't any left
+    pub(super) fn set_dead_props(&self, p: &Path, props: &BTreeMap<String, String>) -> IoResult<()> {
+        let props_f = self.dead_props_file(p).ok_or_else(|| IoError::new(IoErrorKind::PermissionDenied, "Resource outside hosted directory"))?;
+        if props.is_empty() {
+            return match fs::remove_file(&props_f) {
+                Err(e) if e.kind() != IoErrorKind::NotFound => Err(e),
+                _ => Ok(()),
+            };
+        }
+
+        fs::create_dir_all(props_f.parent().expect("Dead properties file in root"))?;
+        let temp_f = props_f.with_extension("tmp");
+        serde_json::to_writer(&mut File::create(&temp_f)?, props).map_err(|e| IoError::new(IoErrorKind::Other, e.to_string()))?;
+        fs::rename(temp_f, props_f)
+    }
+
+    fn dead_props_file(&self, p: &Path) -> Option<PathBuf> {
+        let hosted = &self.hosted_directory.1;
+        let subp = p.strip_prefix(hosted).ok()?;
+        let props_dir = hosted.parent().and_then(|p| hosted.file_name().map(|n| p.join(format!(".{}.props", n.to_string_lossy()))))?;
+
+        let mut key = String::new();
+        for (i, segment) in subp.iter().enumerate() {
+            if i != 0 {
+                key.push('/');
+            }
+            key.push_str(&segment.to_string_lossy());
+        }
+        Some(props_dir.join(format!("{}.json", blake3::hash(key.as_bytes()).to_hex())))
+    }
+}
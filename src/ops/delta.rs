@@ -0,0 +1,84 @@
+//! Experimental rsync-style delta sync: a client POSTs the block signature of its copy of a file to `/path/to/file?delta`
+//! and gets back a delta which turns its copy into the current one, transferring only the blocks that changed.
+//!
+//! See `util::DeltaSignature` and `util::write_delta()` for the formats.
+
+
+use self::super::super::util::{DeltaSignature, is_actually_file, is_descendant_of, is_device, write_delta, body_framing_error, USER_AGENT};
+use iron::{headers, status, IronResult, Response, Request};
+use std::io::{Result as IoResult, Error as IoError, Write};
+use iron::response::WriteBody;
+use iron::modifiers::Header;
+use self::super::HttpHandler;
+use std::path::PathBuf;
+use std::fs::File;
+
+
+impl HttpHandler {
+    pub(super) fn handle_delta(&self, req: &mut Request) -> IronResult<Response> {
+        if !self.delta_sync {
+            return self.handle_forbidden_method(req, "--delta-sync", "delta sync");
+        }
+        if let Some((st, cause)) = body_framing_error(&req.headers) {
+            return self.handle_bad_framing(req, st, cause);
+        }
+
+        let (req_p, symlink, url_err) = self.parse_requested_path(req);
+        if url_err {
+            return self.handle_invalid_url(req, "<p>Percent-encoding decoded to invalid UTF-8 or a path separator.</p>");
+        }
+        if !req_p.exists() || (symlink && !self.symlink_policy.follow()) ||
+           (symlink && self.symlink_policy.sandboxed() && !is_descendant_of(&req_p, &self.hosted_directory.1)) ||
+           !req_p.metadata().map(|m| is_actually_file(&m.file_type(), &req_p)).unwrap_or(false) {
+            return self.handle_nonexistent(req, req_p);
+        }
+        if req_p.metadata().map(|m| is_device(&m.file_type())).unwrap_or(false) && !self.serve_devices {
+            return self.handle_get_device(req, req_p);
+        }
+
+        let sig = match DeltaSignature::read(&mut req.body) {
+            Ok(sig) => sig,
+            Err(err) => return self.handle_invalid_signature(req, req_p, err),
+        };
+        let file = match File::open(&req_p) {
+            Ok(f) => f,
+            Err(err) => return self.handle_requested_entity_unopenable(req, err, "file"),
+        };
+
+        log!(self.log,
+             "{} was served delta of {magenta}{}{reset}",
+             self.remote_addresses(&req),
+             req_p.display());
+        Ok(Response::with((status::Ok,
+                           Header(headers::Server(USER_AGENT.to_string())),
+                           "application/octet-stream".parse::<iron::mime::Mime>().unwrap(),
+                           Box::new(DeltaBody {
+                               sig: sig,
+                               file: file,
+                           }) as Box<dyn WriteBody>)))
+    }
+
+    fn handle_invalid_signature(&self, req: &mut Request, req_p: PathBuf, err: IoError) -> IronResult<Response> {
+        log!(self.log,
+             "{} sent invalid delta signature for {magenta}{}{reset}: {}",
+             self.remote_addresses(&req),
+             req_p.display(),
+             err);
+
+        self.handle_generated_error(req,
+                                    status::BadRequest,
+                                    &["400 Bad Request", &format!("Invalid delta signature: {}.", err), ""])
+    }
+}
+
+
+struct DeltaBody {
+    sig: DeltaSignature,
+    file: File,
+}
+
+impl WriteBody for DeltaBody {
+    fn write_body(&mut self, res: &mut dyn Write) -> IoResult<()> {
+        write_delta(&self.sig, &mut self.file, res)
+    }
+}
@@ -0,0 +1,172 @@
+//! Whole-site deploys: a tar archive POSTed to the root is unpacked into a staging directory,
+//! which is then swapped in place of the hosted directory.
+//!
+//! The previous contents of the hosted directory are kept in `.NAME.deploys/` next to it for rollback,
+//! which is requested with a `POST /?rollback`.
+
+
+use self::super::super::util::{exchange_paths, unpack_tar, body_framing_error, USER_AGENT};
+use std::io::{ErrorKind as IoErrorKind, Result as IoResult, Error as IoError};
+use std::time::{SystemTime, UNIX_EPOCH};
+use iron::{headers, status, IronResult, Response, Request};
+use iron::mime::{Mime, SubLevel as MimeSubLevel};
+use iron::modifiers::Header;
+use flate2::read::GzDecoder;
+use bzip2::read::BzDecoder;
+use self::super::HttpHandler;
+use std::sync::PoisonError;
+use std::path::PathBuf;
+use std::fs;
+
+
+impl HttpHandler {
+    pub(super) fn handle_deploy(&self, req: &mut Request) -> IronResult<Response> {
+        let keep = match self.deploy {
+            Some(keep) => keep,
+            None => return self.handle_forbidden_method(req, "--deploy", "site deploys"),
+        };
+        if req.url.path().into_iter().any(|s| !s.is_empty()) {
+            return self.handle_invalid_url(req, "<p>Deploys can only be made to the root.</p>");
+        }
+        // Deploys and rollbacks replace everything
+        if self.webdav_write_locked(req, &self.hosted_directory.1, true) {
+            let root = self.hosted_directory.1.clone();
+            return self.handle_locked(req, root);
+        }
+
+        let _lock = self.deploy_lock.lock().unwrap_or_else(PoisonError::into_inner);
+        let deploys_dir = match self.deploys_dir() {
+            Some(dd) => dd,
+            None => return self.handle_deploy_failed(req, "deploy", IoError::new(IoErrorKind::Other, "hosted directory has no parent")),
+        };
+
+        if req.url.query() == Some("rollback") {
+            return self.handle_deploy_rollback(req, deploys_dir);
+        }
+        if let Some((st, cause)) = body_framing_error(&req.headers) {
+            return self.handle_bad_framing(req, st, cause);
+        }
+
+        let compression = match req.headers.get::<headers::ContentType>() {
+            Some(&headers::ContentType(Mime(_, MimeSubLevel::Ext(ref sub), _))) => sub.clone(),
+            _ => String::new(),
+        };
+        let staging = deploys_dir.join(format!("staging-{}", deploy_timestamp()));
+        let unpacked = fs::create_dir_all(&staging).and_then(|_| match &compression[..] {
+            "gzip" | "x-gzip" => unpack_tar(GzDecoder::new(&mut req.body), &staging, self.xattrs),
+            "x-bzip2" => unpack_tar(BzDecoder::new(&mut req.body), &staging, self.xattrs),
+            _ => unpack_tar(&mut req.body, &staging, self.xattrs),
+        });
+        let entries = match unpacked {
+            Ok(entries) => entries,
+            Err(err) => {
+                let _ = fs::remove_dir_all(&staging);
+                return self.handle_deploy_failed(req, "unpack", err);
+            }
+        };
+
+        let previous = deploys_dir.join(deploy_timestamp());
+        if let Err(err) = self.swap_in(&staging, &previous) {
+            let _ = fs::remove_dir_all(&staging);
+            return self.handle_deploy_failed(req, "swap in", err);
+        }
+        self.drop_webdav_locks(&self.hosted_directory.1);
+
+        log!(self.log,
+             "{} deployed {} entries to {magenta}{}{reset}, previous contents in {magenta}{}{reset}",
+             self.remote_addresses(&req),
+             entries,
+             self.hosted_directory.1.display(),
+             previous.display());
+
+        self.prune_deploys(&deploys_dir, keep);
+        Ok(Response::with((status::Created, Header(headers::Server(USER_AGENT.to_string())))))
+    }
+
+    fn handle_deploy_rollback(&self, req: &mut Request, deploys_dir: PathBuf) -> IronResult<Response> {
+        let previous = match previous_deploys(&deploys_dir).pop() {
+            Some(p) => p,
+            None => return self.handle_nonexistent(req, deploys_dir),
+        };
+
+        let discarded = deploys_dir.join(format!("discarded-{}", deploy_timestamp()));
+        if let Err(err) = self.swap_in(&previous, &discarded) {
+            return self.handle_deploy_failed(req, "roll back", err);
+        }
+        self.drop_webdav_locks(&self.hosted_directory.1);
+        let _ = fs::remove_dir_all(&discarded);
+
+        log!(self.log,
+             "{} rolled {magenta}{}{reset} back to {magenta}{}{reset}",
+             self.remote_addresses(&req),
+             self.hosted_directory.1.display(),
+             previous.display());
+
+        Ok(Response::with((status::Ok, Header(headers::Server(USER_AGENT.to_string())))))
+    }
+
+    fn handle_deploy_failed(&self, req: &mut Request, op: &str, err: IoError) -> IronResult<Response> {
+        log!(self.log,
+             "{} failed to {} deploy to {magenta}{}{reset}: {}",
+             self.remote_addresses(&req),
+             op,
+             self.hosted_directory.1.display(),
+             err);
+
+        let st = if err.kind() == IoErrorKind::InvalidData || err.kind() == IoErrorKind::UnexpectedEof {
+            status::BadRequest
+        } else {
+            status::InternalServerError
+        };
+        self.handle_generated_error(req, st, &[&st.to_string()[..], &format!("Failed to {} deploy: {}.", op, err), ""])
+    }
+
+    /// Move `new` into the hosted directory and the current hosted directory to `old`,
+    /// atomically where supported
+    fn swap_in(&self, new: &PathBuf, old: &PathBuf) -> IoResult<()> {
+        if exchange_paths(new, &self.hosted_directory.1) {
+            fs::rename(new, old)
+        } else {
+            fs::rename(&self.hosted_directory.1, old)?;
+            fs::rename(new, &self.hosted_directory.1).or_else(|err| {
+                let _ = fs::rename(old, &self.hosted_directory.1);
+                Err(err)
+            })
+        }
+    }
+
+    fn prune_deploys(&self, deploys_dir: &PathBuf, keep: usize) {
+        let previous = previous_deploys(deploys_dir);
+        for old in &previous[..previous.len().saturating_sub(keep)] {
+            if fs::remove_dir_all(old).is_ok() {
+                log!(self.log, "Deleted old deploy {magenta}{}{reset}", old.display());
+            }
+        }
+    }
+
+    /// `.NAME.deploys` next to the hosted directory, so that it's on the same filesystem
+    fn deploys_dir(&self) -> Option<PathBuf> {
+        let hosted = &self.hosted_directory.1;
+        hosted.parent().and_then(|p| hosted.file_name().map(|n| p.join(format!(".{}.deploys", n.to_string_lossy()))))
+    }
+}
+
+
+/// Previous deploys, oldest first
+fn previous_deploys(deploys_dir: &PathBuf) -> Vec<PathBuf> {
+    let mut ret: Vec<_> = deploys_dir.read_dir()
+        .map(|rd| {
+            rd.flatten()
+                .filter(|e| e.file_name().to_str().map(|n| n.bytes().all(|b| b.is_ascii_digit())).unwrap_or(false))
+                .map(|e| e.path())
+                .collect()
+        })
+        .unwrap_or_default();
+    ret.sort();
+    ret
+}
+
+/// Fixed-width, so that deploys sort lexicographically
+fn deploy_timestamp() -> String {
+    format!("{:020}", SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos() as u64).unwrap_or(0))
+}
@@ -0,0 +1,118 @@
+//! Per-file download counters, enabled with `--download-counts`.
+//!
+//! Whole-file GETs and ranged ones starting at the beginning of the file each count as one download, keyed by path
+//! relative to the hosted directory. The counts are kept in `counts.json` in the downloads temp dir, which isn't cleaned
+//! up on exit, so they carry over between runs; they're saved every `SAVE_INTERVAL` and on exit.
+
+
+use std::io::{Result as IoResult, Error as IoError, BufReader, BufWriter, Write};
+use std::collections::BTreeMap;
+use self::super::HttpHandler;
+use std::path::{PathBuf, Path};
+use std::time::Duration;
+use std::fs::{self, File};
+use std::sync::{Mutex, PoisonError};
+use iron::headers;
+use std::thread;
+use serde_json;
+
+
+/// How often changed counts are written out
+pub const SAVE_INTERVAL: Duration = Duration::from_secs(60);
+
+
+/// Download counts by path relative to `root`, and whether they changed since they were last saved
+#[derive(Debug)]
+pub struct DownloadCounts {
+    root: PathBuf,
+    file: PathBuf,
+    counts: Mutex<(BTreeMap<String, u64>, bool)>,
+}
+
+impl DownloadCounts {
+    /// Load the counts for files under `root` saved in the specified directory, if any
+    pub fn load(root: PathBuf, dir: &Path) -> DownloadCounts {
+        let file = dir.join("counts.json");
+        let counts = File::open(&file).ok().and_then(|f| serde_json::from_reader(BufReader::new(f)).ok()).unwrap_or_default();
+        DownloadCounts {
+            root,
+            file,
+            counts: Mutex::new((counts, false)),
+        }
+    }
+
+    fn hit(&self, p: &Path) {
+        if let Some(key) = self.key(p) {
+            let mut counts = self.counts.lock().unwrap_or_else(PoisonError::into_inner);
+            *counts.0.entry(key).or_insert(0) += 1;
+            counts.1 = true;
+        }
+    }
+
+    pub(super) fn get(&self, p: &Path) -> u64 {
+        self.key(p).and_then(|key| self.counts.lock().unwrap_or_else(PoisonError::into_inner).0.get(&key).cloned()).unwrap_or(0)
+    }
+
+    /// `p` relative to the root, with forward slashes
+    fn key(&self, p: &Path) -> Option<String> {
+        let rel = p.strip_prefix(&self.root).ok()?;
+        Some(rel.components().map(|c| c.as_os_str().to_string_lossy()).collect::<Vec<_>>().join("/"))
+    }
+
+    /// All counts, most downloaded first
+    pub(super) fn by_popularity(&self) -> Vec<(String, u64)> {
+        let mut counts: Vec<_> = self.counts.lock().unwrap_or_else(PoisonError::into_inner).0.iter().map(|(p, &c)| (p.clone(), c)).collect();
+        counts.sort_by(|lhs, rhs| rhs.1.cmp(&lhs.1).then_with(|| lhs.0.cmp(&rhs.0)));
+        counts
+    }
+
+    /// Write the counts out, if they changed since the last time
+    pub fn save(&self) -> IoResult<()> {
+        let counts = {
+            let mut counts = self.counts.lock().unwrap_or_else(PoisonError::into_inner);
+            if !counts.1 {
+                return Ok(());
+            }
+            counts.1 = false;
+            counts.0.clone()
+        };
+
+        // Written beside and renamed over, so a crash mid-write doesn't lose what was there
+        fs::create_dir_all(self.file.parent().expect("Download counts file has no parent"))?;
+        let temp = self.file.with_extension("json.tmp");
+        {
+            let mut out = BufWriter::new(File::create(&temp)?);
+            serde_json::to_writer(&mut out, &counts).map_err(|e| IoError::other(e.to_string()))?;
+            out.flush()?;
+        }
+        fs::rename(&temp, &self.file)
+    }
+
+    pub fn save_periodically(&self) -> ! {
+        loop {
+            thread::sleep(SAVE_INTERVAL);
+            let _ = self.save();
+        }
+    }
+}
+
+
+impl HttpHandler {
+    /// Count a download of `req_p`, per-file and towards `--max-serves`, if enabled and `range` starts at the beginning of the file
+    pub(super) fn count_download(&self, req_p: &Path, range: Option<&headers::Range>) {
+        let from_start = match range {
+            None => true,
+            Some(headers::Range::Bytes(brs)) => {
+                brs.len() == 1 && matches!(brs[0], headers::ByteRangeSpec::FromTo(0, _) | headers::ByteRangeSpec::AllFrom(0))
+            }
+            Some(headers::Range::Unregistered(..)) => false,
+        };
+
+        if let Some(counts) = self.download_counts.as_ref().filter(|_| from_start) {
+            counts.hit(req_p);
+        }
+        if let Some(limits) = self.serve_limits.as_ref().filter(|_| from_start) {
+            limits.served();
+        }
+    }
+}
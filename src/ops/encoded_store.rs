@@ -0,0 +1,183 @@
+//! Where encoded filesystem files are kept, selected with `--encoded-store`: the temp dir, the temp dir bounded to some size,
+//! or a directory outliving the server, shareable between mirrors of the same files, since encoded files are named by content;
+//! or, for read-only filesystems, not a directory at all, but `EncodedMemory`.
+
+
+use std::path::{PathBuf, Path};
+use self::super::super::EncodedStoreKind;
+use std::collections::HashMap;
+use std::time::SystemTime;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::fs;
+use blake3;
+
+
+/// A directory encoded files are written into, named `<hash>[.<source extension>].<encoding extension>`
+pub trait EncodedStore: Send + Sync {
+    /// Display name and path of the directory
+    fn dir(&self) -> &(String, PathBuf);
+
+    /// Whether it's shared with other servers, so files for ones not hosted here are kept in it
+    fn shared(&self) -> bool {
+        false
+    }
+
+    /// Make room for `encoded`, which was just written into it, returning whether it was kept
+    fn stored(&self, _encoded: &Path) -> bool {
+        true
+    }
+}
+
+/// Create the store specified by `--encoded-store`, with `temp_dir` being the encoded temp dir,
+/// or `None` if it's `EncodedMemory`
+pub fn new_encoded_store(kind: &EncodedStoreKind, temp_dir: (String, PathBuf)) -> Option<Arc<dyn EncodedStore>> {
+    Some(match *kind {
+        EncodedStoreKind::Temp => Arc::new(TempStore { dir: temp_dir }),
+        EncodedStoreKind::Capped(max_size) => {
+            Arc::new(CappedStore {
+                dir: temp_dir,
+                max_size: max_size,
+                evicting: Mutex::new(()),
+            })
+        }
+        EncodedStoreKind::Shared(ref dir) => Arc::new(SharedStore { dir: (dir.display().to_string(), dir.clone()) }),
+        EncodedStoreKind::Memory(_) => return None,
+    })
+}
+
+
+/// The encoded temp dir, removed on exit
+struct TempStore {
+    dir: (String, PathBuf),
+}
+
+impl EncodedStore for TempStore {
+    fn dir(&self) -> &(String, PathBuf) {
+        &self.dir
+    }
+}
+
+
+/// The encoded temp dir, removed on exit, with the least recently written files evicted above `max_size` bytes
+struct CappedStore {
+    dir: (String, PathBuf),
+    max_size: u64,
+    /// Serialises evictions, so concurrent encodings don't evict each other's files for the same space
+    evicting: Mutex<()>,
+}
+
+impl EncodedStore for CappedStore {
+    fn dir(&self) -> &(String, PathBuf) {
+        &self.dir
+    }
+
+    fn stored(&self, encoded: &Path) -> bool {
+        let _evicting = self.evicting.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut files: Vec<(SystemTime, u64, PathBuf)> = match self.dir.1.read_dir() {
+            Ok(rd) => {
+                rd.flatten()
+                    .flat_map(|f| f.metadata().ok().filter(|m| m.is_file()).map(|m| (m.modified().unwrap_or(SystemTime::UNIX_EPOCH), m.len(), f.path())))
+                    .collect()
+            }
+            Err(_) => return true,
+        };
+        let mut size: u64 = files.iter().map(|&(_, len, _)| len).sum();
+        if size <= self.max_size {
+            return true;
+        }
+
+        // The new file goes last, so it's only evicted if it doesn't fit on its own
+        files.sort_by_key(|&(mtime, _, ref p)| (p == encoded, mtime));
+        for (_, len, p) in files {
+            if size <= self.max_size {
+                break;
+            }
+            if fs::remove_file(&p).is_ok() {
+                size -= len;
+            }
+        }
+        encoded.exists()
+    }
+}
+
+
+/// A directory outside the temp dir, kept on exit, which other servers can use too
+struct SharedStore {
+    dir: (String, PathBuf),
+}
+
+impl EncodedStore for SharedStore {
+    fn dir(&self) -> &(String, PathBuf) {
+        &self.dir
+    }
+
+    fn shared(&self) -> bool {
+        true
+    }
+}
+
+
+/// Encoded files kept in memory instead of written out, with the least recently used ones evicted above `max_size` bytes
+///
+/// Keyed by content hash and encoding, like `cache_fs`, so entries for changed files just age out.
+#[derive(Debug)]
+pub struct EncodedMemory {
+    /// (Hash, encoding) -> (last used, encoded data), total size, use counter
+    entries: Mutex<(HashMap<(blake3::Hash, String), (u64, Arc<Vec<u8>>)>, u64, u64)>,
+    max_size: u64,
+}
+
+impl EncodedMemory {
+    pub fn new(max_size: u64) -> EncodedMemory {
+        EncodedMemory {
+            entries: Mutex::new((HashMap::new(), 0, 0)),
+            max_size: max_size,
+        }
+    }
+
+    pub fn get(&self, key: &(blake3::Hash, String)) -> Option<Arc<Vec<u8>>> {
+        let mut entries = self.entries.lock().unwrap_or_else(PoisonError::into_inner);
+        let (ref mut map, _, ref mut counter) = *entries;
+        map.get_mut(key).map(|&mut (ref mut used, ref data)| {
+            *counter += 1;
+            *used = *counter;
+            data.clone()
+        })
+    }
+
+    /// Keep `data`, evicting the least recently used entries to make room for it, returning whether it fit at all
+    pub fn insert(&self, key: (blake3::Hash, String), data: Vec<u8>) -> bool {
+        let len = data.len() as u64;
+        if len > self.max_size {
+            return false;
+        }
+
+        let mut entries = self.entries.lock().unwrap_or_else(PoisonError::into_inner);
+        let (ref mut map, ref mut size, ref mut counter) = *entries;
+        if let Some((_, old)) = map.remove(&key) {
+            *size -= old.len() as u64;
+        }
+        while *size + len > self.max_size {
+            let oldest = map.iter().min_by_key(|&(_, &(used, _))| used).map(|(k, _)| k.clone()).unwrap();
+            let (_, evicted) = map.remove(&oldest).unwrap();
+            *size -= evicted.len() as u64;
+        }
+
+        *counter += 1;
+        *size += len;
+        map.insert(key, (*counter, Arc::new(data)));
+        true
+    }
+
+    /// Amount of encodings and their total size
+    pub fn usage(&self) -> (usize, u64) {
+        let entries = self.entries.lock().unwrap_or_else(PoisonError::into_inner);
+        (entries.0.len(), entries.1)
+    }
+
+    pub fn clear(&self) {
+        let mut entries = self.entries.lock().unwrap_or_else(PoisonError::into_inner);
+        entries.0.clear();
+        entries.1 = 0;
+    }
+}
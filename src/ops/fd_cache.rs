@@ -0,0 +1,194 @@
+//! Open file cache, enabled with `--fd-cache`: files served by GETs, whole, ranged, or encoded, are kept open for a while,
+//! so serving the same big files over and over doesn't reopen them every request, which is especially slow on Windows.
+//!
+//! Cached files are checked against what's at their path now before being reused, by identity, modification time, and size,
+//! and are read positionally, so concurrent responses can share one.
+
+
+use std::io::{Result as IoResult, Error as IoError, ErrorKind as IoErrorKind, SeekFrom, Read, Seek};
+use iron::response::BodyReader;
+use std::time::{SystemTime, Duration, Instant};
+use std::collections::HashMap;
+use iron::modifiers::Header;
+use std::path::{PathBuf, Path};
+use self::super::HttpHandler;
+use std::fs::{self, File};
+use std::sync::{Arc, Mutex, PoisonError};
+use iron::headers;
+use std::thread;
+
+
+/// Cached files not served in this long are closed
+pub const FD_CACHE_IDLE: Duration = Duration::from_secs(10);
+
+
+impl HttpHandler {
+    /// Open the file at `p` for serving, reusing a cached one if it's still what's there
+    pub(super) fn open_served_file(&self, p: &Path) -> IoResult<ServedFile> {
+        match self.fd_cache.as_ref() {
+            Some(cache) => cache.open(p),
+            None => File::open(p).map(|f| ServedFile::new(Arc::new(f))),
+        }
+    }
+
+    /// The file at `p` as a response body, with its length, like `Path`'s modifier
+    pub(super) fn served_file_body(&self, p: &Path) -> IoResult<(BodyReader<ServedFile>, Header<headers::ContentLength>)> {
+        let file = self.open_served_file(p)?;
+        let len = file.metadata()?.len();
+        Ok((BodyReader(file), Header(headers::ContentLength(len))))
+    }
+}
+
+
+/// Files kept open for serving, at most `max_entries`, closing the least recently served ones to make room
+#[derive(Debug)]
+pub struct FdCache {
+    /// Path -> (identity, modification time, size when opened, last served, file)
+    entries: Mutex<HashMap<PathBuf, ((u64, u64), SystemTime, u64, Instant, Arc<File>)>>,
+    max_entries: usize,
+}
+
+impl FdCache {
+    pub fn new(max_entries: usize) -> FdCache {
+        FdCache {
+            entries: Mutex::new(HashMap::new()),
+            max_entries: max_entries,
+        }
+    }
+
+    pub fn open(&self, p: &Path) -> IoResult<ServedFile> {
+        let meta = fs::metadata(p)?;
+        let current = (file_id(&meta), meta.modified()?, meta.len());
+
+        let mut entries = self.entries.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(&mut (id, mtime, len, ref mut used, ref file)) = entries.get_mut(p) {
+            if (id, mtime, len) == current {
+                *used = Instant::now();
+                return Ok(ServedFile::new(file.clone()));
+            }
+        }
+        entries.remove(p);
+        drop(entries);
+
+        // Identified by what was opened, in case it was replaced between the stat and the open
+        let file = File::open(p)?;
+        let meta = file.metadata()?;
+        if !meta.is_file() {
+            return Err(IoError::new(IoErrorKind::Other, "not a file"));
+        }
+        let file = Arc::new(file);
+
+        let mut entries = self.entries.lock().unwrap_or_else(PoisonError::into_inner);
+        while entries.len() >= self.max_entries.max(1) && !entries.is_empty() {
+            let oldest = entries.iter().min_by_key(|&(_, &(_, _, _, used, _))| used).map(|(k, _)| k.clone()).unwrap();
+            entries.remove(&oldest);
+        }
+        entries.insert(p.to_path_buf(), (file_id(&meta), meta.modified()?, meta.len(), Instant::now(), file.clone()));
+        Ok(ServedFile::new(file))
+    }
+
+    /// Close files not served in `FD_CACHE_IDLE`, returning how many were
+    pub fn close_idle(&self) -> usize {
+        let mut entries = self.entries.lock().unwrap_or_else(PoisonError::into_inner);
+        let before = entries.len();
+        entries.retain(|_, &mut (_, _, _, used, _)| used.elapsed() < FD_CACHE_IDLE);
+        before - entries.len()
+    }
+
+    pub fn close_idle_periodically(&self) -> ! {
+        loop {
+            thread::sleep(FD_CACHE_IDLE);
+            self.close_idle();
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap_or_else(PoisonError::into_inner).len()
+    }
+
+    pub fn clear(&self) {
+        self.entries.lock().unwrap_or_else(PoisonError::into_inner).clear();
+    }
+}
+
+
+/// A file being served, possibly shared with other responses, read at its own position
+#[derive(Debug, Clone)]
+pub struct ServedFile {
+    file: Arc<File>,
+    pos: u64,
+}
+
+impl ServedFile {
+    fn new(file: Arc<File>) -> ServedFile {
+        ServedFile {
+            file: file,
+            pos: 0,
+        }
+    }
+
+    pub fn metadata(&self) -> IoResult<fs::Metadata> {
+        self.file.metadata()
+    }
+
+    /// The underlying file, whose own position isn't this one's
+    pub fn file(&self) -> &File {
+        &self.file
+    }
+}
+
+impl Read for ServedFile {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        let read = read_at(&self.file, buf, self.pos)?;
+        self.pos += read as u64;
+        Ok(read)
+    }
+}
+
+impl Seek for ServedFile {
+    fn seek(&mut self, pos: SeekFrom) -> IoResult<u64> {
+        let (base, offset) = match pos {
+            SeekFrom::Start(pos) => {
+                self.pos = pos;
+                return Ok(pos);
+            }
+            SeekFrom::Current(offset) => (self.pos, offset),
+            SeekFrom::End(offset) => (self.file.metadata()?.len(), offset),
+        };
+        match base.checked_add_signed(offset) {
+            Some(pos) => {
+                self.pos = pos;
+                Ok(pos)
+            }
+            None => Err(IoError::new(IoErrorKind::InvalidInput, "invalid seek to a negative or overflowing position")),
+        }
+    }
+}
+
+
+#[cfg(unix)]
+fn read_at(file: &File, buf: &mut [u8], pos: u64) -> IoResult<usize> {
+    use std::os::unix::fs::FileExt;
+    file.read_at(buf, pos)
+}
+
+/// Moves the file's own position, but nothing uses that
+#[cfg(windows)]
+fn read_at(file: &File, buf: &mut [u8], pos: u64) -> IoResult<usize> {
+    use std::os::windows::fs::FileExt;
+    file.seek_read(buf, pos)
+}
+
+/// (Device, inode), so files replaced in one rename are told apart even with the same size and modification time
+#[cfg(unix)]
+fn file_id(meta: &fs::Metadata) -> (u64, u64) {
+    use std::os::unix::fs::MetadataExt;
+    (meta.dev(), meta.ino())
+}
+
+/// File indices are unstable on Windows, so the creation time stands in for them
+#[cfg(windows)]
+fn file_id(meta: &fs::Metadata) -> (u64, u64) {
+    use std::os::windows::fs::MetadataExt;
+    (meta.creation_time(), 0)
+}
@@ -0,0 +1,64 @@
+//! Filesystem errors while handling a request, like a file vanishing between being found and opened, the disk filling up,
+//! or running out of file descriptors, get it an error page, instead of panicking.
+//!
+//! Entities the server isn't allowed to read get a 403, since retrying won't help and it's not the server's fault.
+
+
+use std::io::{ErrorKind as IoErrorKind, Error as IoError};
+use self::super::super::util::{url_path, is_storage_full};
+use iron::{status, IronResult, Response, Request};
+use self::super::HttpHandler;
+
+
+/// How long to tell clients to wait before retrying requests that failed for lack of space or file descriptors, in seconds
+pub const FS_ERROR_RETRY_AFTER: u64 = 30;
+
+/// `ENFILE` and `EMFILE`, the same on all Unices
+#[cfg(unix)]
+const TOO_MANY_OPEN_FILES: &[i32] = &[23, 24];
+/// `ERROR_TOO_MANY_OPEN_FILES`
+#[cfg(windows)]
+const TOO_MANY_OPEN_FILES: &[i32] = &[4];
+
+
+impl HttpHandler {
+    /// Log `err`, which happened `doing` something for the request, and make an error page of it:
+    /// 403 for permission errors, 503 for errors that go away on their own, 500 for the rest
+    pub(super) fn handle_fs_error(&self, req: &mut Request, err: IoError, doing: &str) -> IronResult<Response> {
+        log!(self.log,
+             "{} requested to {red}{}{reset} {yellow}{}{reset} but {} {red}failed{reset}: {}",
+             self.remote_addresses(req),
+             req.method,
+             url_path(&req.url),
+             doing,
+             err);
+
+        if err.kind() == IoErrorKind::PermissionDenied {
+            self.handle_generated_error(req,
+                                        status::Forbidden,
+                                        &["403 Forbidden", &format!("Can't access {}.", url_path(&req.url)), ""])
+        } else if is_transient(&err) {
+            let mut resp = self.handle_generated_error(req,
+                                                       status::ServiceUnavailable,
+                                                       &["503 Service Unavailable",
+                                                         "The server is temporarily unable to handle the request.",
+                                                         &format!("<p>Try again in {} seconds.</p>", FS_ERROR_RETRY_AFTER)])?;
+            resp.headers.set_raw("Retry-After", vec![FS_ERROR_RETRY_AFTER.to_string().into_bytes()]);
+            Ok(resp)
+        } else {
+            self.handle_generated_error(req,
+                                        status::InternalServerError,
+                                        &["500 Internal Server Error", "The server failed to access the requested entity.", ""])
+        }
+    }
+}
+
+
+/// Whether the error is from running out of something that frees up, rather than from what's being accessed
+fn is_transient(err: &IoError) -> bool {
+    is_storage_full(err) || err.raw_os_error().map(|e| TOO_MANY_OPEN_FILES.contains(&e)).unwrap_or(false) ||
+    match err.kind() {
+        IoErrorKind::Interrupted | IoErrorKind::WouldBlock | IoErrorKind::TimedOut => true,
+        _ => false,
+    }
+}
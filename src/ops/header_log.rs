@@ -0,0 +1,66 @@
+//! Header logging, for diagnosing client compatibility: with `--log-headers`, or once switched on with
+//! `PUT /.http-admin/log-headers`, every request's headers, with credentials redacted, and its response's are logged
+//! under its serving status lines.
+
+
+use self::super::super::util::{Spaces, url_path};
+use iron::{IronResult, Response, Request};
+use iron::headers::Headers;
+use self::super::HttpHandler;
+
+
+/// Headers whose values are credentials, logged as just their scheme, if they have one
+const REDACTED_HEADERS: &[&str] = &["Authorization", "Proxy-Authorization"];
+
+/// Headers whose values may be credentials, and have no scheme to keep
+const REDACTED_COOKIE_HEADERS: &[&str] = &["Cookie", "Set-Cookie"];
+
+
+impl HttpHandler {
+    /// Log the request's and response's headers, if header logging's on
+    pub(super) fn log_headers(&self, req: &Request, resp: &IronResult<Response>) {
+        if !self.log.0.on() || !self.log_headers.on() {
+            return;
+        }
+
+        let resp = match *resp {
+            Ok(ref resp) => resp,
+            Err(ref err) => &err.response,
+        };
+        let indent = Spaces(self.remote_addresses(req).width());
+
+        log!(self.log,
+             "{} sent {red}{}{reset} {yellow}{}{reset} with headers",
+             indent,
+             req.method,
+             url_path(&req.url));
+        log_header_lines(self, &indent, &req.headers);
+
+        match resp.status {
+            Some(st) => {
+                log!(self.log, "{} and was answered {green}{}{reset} with headers", indent, st);
+            }
+            None => {
+                log!(self.log, "{} and was answered without a status, with headers", indent);
+            }
+        }
+        log_header_lines(self, &indent, &resp.headers);
+    }
+}
+
+
+fn log_header_lines(handler: &HttpHandler, indent: &Spaces, headers: &Headers) {
+    for header in headers.iter() {
+        let value = header.value_string();
+        if REDACTED_HEADERS.iter().any(|r| r.eq_ignore_ascii_case(header.name())) {
+            match value.trim().find(' ') {
+                Some(space) => log!(handler.log, "{}   {}: {} [redacted]", indent, header.name(), &value.trim()[..space]),
+                None => log!(handler.log, "{}   {}: [redacted]", indent, header.name()),
+            }
+        } else if REDACTED_COOKIE_HEADERS.iter().any(|r| r.eq_ignore_ascii_case(header.name())) {
+            log!(handler.log, "{}   {}: [redacted]", indent, header.name());
+        } else {
+            log!(handler.log, "{}   {}: {}", indent, header.name(), value);
+        }
+    }
+}
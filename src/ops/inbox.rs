@@ -0,0 +1,127 @@
+//! Write-only inbox, enabled with `--inbox`: files can be PUT or POSTed into the hosted directory, but nothing in it can be
+//! read, listed, or removed, and uploads never replace what's already there, getting a free name like `name (1).ext` instead.
+
+
+use self::super::super::util::{detect_file_as_dir, is_nonexistent_descendant_of, body_framing_error, USER_AGENT, FILE_NAME_HEADER,
+                               PATH_SEGMENT_ENCODE_SET};
+use iron::{headers, status, method, IronResult, Response, Request};
+use percent_encoding::utf8_percent_encode;
+use std::io::{ErrorKind as IoErrorKind, Result as IoResult};
+use std::fs::{self, OpenOptions};
+use std::path::{PathBuf, Path};
+use std::iter;
+use iron::modifiers::Header;
+use self::super::HttpHandler;
+
+
+impl HttpHandler {
+    pub(super) fn handle_inbox(&self, req: &mut Request) -> IronResult<Response> {
+        match req.method {
+            method::Post if req.headers.get_raw(FILE_NAME_HEADER).is_some() => {
+                match self.target_upload_file_name(req)? {
+                    Some(resp) => Ok(resp),
+                    None => self.handle_inbox_upload(req),
+                }
+            }
+            method::Put | method::Post => self.handle_inbox_upload(req),
+            method::Options => {
+                log!(self.log, "{} asked for {red}OPTIONS{reset} of the inbox", self.remote_addresses(req));
+                Ok(Response::with((status::NoContent,
+                                   Header(headers::Server(USER_AGENT.to_string())),
+                                   Header(headers::Allow(vec![method::Options, method::Post, method::Put])))))
+            }
+            _ => self.handle_disallowed_method(req, &[&[method::Options, method::Post, method::Put]], "write-only inbox"),
+        }
+    }
+
+    fn handle_inbox_upload(&self, req: &mut Request) -> IronResult<Response> {
+        let (req_p, symlink, url_err) = self.parse_requested_path(req);
+
+        if url_err {
+            return self.handle_invalid_url(req, "<p>Percent-encoding decoded to invalid UTF-8 or a path separator.</p>");
+        } else if req_p == self.hosted_directory.1 {
+            return self.handle_invalid_url(req, "<p>Uploads into the inbox need a file name.</p>");
+        } else if detect_file_as_dir(&req_p) {
+            return self.handle_invalid_url(req, "<p>Attempted to use file as directory.</p>");
+        } else if req.headers.has::<headers::ContentRange>() {
+            return self.handle_put_partial_content(req);
+        } else if let Some((st, cause)) = body_framing_error(&req.headers) {
+            return self.handle_bad_framing(req, st, cause);
+        }
+
+        let legal = !symlink || (self.symlink_policy.follow() && (!self.symlink_policy.sandboxed() || is_nonexistent_descendant_of(&req_p, &self.hosted_directory.1)));
+        let req_p = if legal {
+            // Reserved right away, so concurrent uploads of the same name can't pick the same free one
+            let _ = fs::create_dir_all(req_p.parent().expect("Failed to get requested file's parent directory"));
+            match reserve_free_name(req_p) {
+                Ok(p) => p,
+                Err(err) => return self.handle_fs_error(req, err, "reserving a name for it"),
+            }
+        } else {
+            free_name(req_p)
+        };
+
+        let mut location = req.url.as_ref().path().to_string();
+        location.truncate(location.rfind('/').map(|i| i + 1).unwrap_or(0));
+        location.extend(utf8_percent_encode(&req_p.file_name().expect("Failed to get requested file's filename").to_string_lossy(),
+                                            PATH_SEGMENT_ENCODE_SET));
+
+        self.create_temp_dir(&self.writes_temp_dir);
+        let resp = self.handle_put_file_replacing(req, req_p.clone(), legal, false);
+        match resp {
+            Ok(mut r) => {
+                if r.status == Some(status::Created) {
+                    r.headers.set(headers::Location(location));
+                } else if legal {
+                    let _ = fs::remove_file(&req_p);
+                }
+                Ok(r)
+            }
+            Err(err) => {
+                if legal {
+                    let _ = fs::remove_file(&req_p);
+                }
+                Err(err)
+            }
+        }
+    }
+}
+
+
+/// `p`, then `stem (1).ext`, `stem (2).ext`, &c.
+fn candidate_names(p: PathBuf) -> impl Iterator<Item = PathBuf> {
+    let stem = p.file_stem().expect("Failed to get requested file's filename").to_os_string();
+    let ext = p.extension().map(|e| e.to_os_string());
+    let numbered = (1..).map(move |i| {
+        let mut name = stem.clone();
+        name.push(format!(" ({})", i));
+        if let Some(ext) = ext.as_ref() {
+            name.push(".");
+            name.push(ext);
+        }
+        p.with_file_name(name)
+    });
+    iter::once(p.clone()).chain(numbered)
+}
+
+/// The first of `candidate_names()` that's free, without taking it
+fn free_name(p: PathBuf) -> PathBuf {
+    candidate_names(p).find(|p| !exists(p)).unwrap()
+}
+
+/// The first of `candidate_names()` that's free, created empty, so no other upload can get it
+fn reserve_free_name(p: PathBuf) -> IoResult<PathBuf> {
+    for p in candidate_names(p) {
+        match OpenOptions::new().write(true).create_new(true).open(&p) {
+            Ok(_) => return Ok(p),
+            Err(ref err) if err.kind() == IoErrorKind::AlreadyExists => {}
+            Err(err) => return Err(err),
+        }
+    }
+    unreachable!()
+}
+
+/// Dangling symlinks count, too
+fn exists(p: &Path) -> bool {
+    p.symlink_metadata().is_ok()
+}
@@ -0,0 +1,146 @@
+//! Serving limits, enabled with `--max-serves` and `--max-bytes`: once that many downloads were started, or that many bytes
+//! of response bodies sent, new requests are refused with a 503, and the server stops once the ones in flight finish.
+//!
+//! Downloads count like for `--download-counts`: whole-file GETs and ranged ones starting at the beginning of the file.
+
+
+use std::sync::atomic::{AtomicUsize, AtomicBool, AtomicU64, Ordering};
+use iron::{status, IronResult, Response, Request};
+use std::io::{Result as IoResult, Write};
+use std::sync::{Arc, Condvar, Mutex, PoisonError};
+use iron::response::WriteBody;
+use self::super::HttpHandler;
+
+
+/// Downloads and bytes sent so far, against their limits
+#[derive(Debug)]
+pub struct ServeLimits {
+    max_serves: Option<u64>,
+    max_bytes: Option<u64>,
+    serves: AtomicU64,
+    bytes: AtomicU64,
+    in_flight: AtomicUsize,
+    exhausted: AtomicBool,
+    done: (Mutex<bool>, Condvar),
+}
+
+impl ServeLimits {
+    pub fn new(max_serves: Option<u64>, max_bytes: Option<u64>) -> ServeLimits {
+        ServeLimits {
+            max_serves,
+            max_bytes,
+            serves: AtomicU64::new(0),
+            bytes: AtomicU64::new(0),
+            in_flight: AtomicUsize::new(0),
+            exhausted: AtomicBool::new(false),
+            done: Default::default(),
+        }
+    }
+
+    /// Block until a limit was reached and every request in flight then finished
+    pub fn wait_done(&self) {
+        let mut done = self.done.0.lock().unwrap_or_else(PoisonError::into_inner);
+        while !*done {
+            done = self.done.1.wait(done).unwrap_or_else(PoisonError::into_inner);
+        }
+    }
+
+    pub(super) fn served(&self) {
+        let serves = self.serves.fetch_add(1, Ordering::SeqCst) + 1;
+        if self.max_serves.map(|m| serves >= m).unwrap_or(false) {
+            self.exhausted.store(true, Ordering::SeqCst);
+        }
+    }
+
+    fn sent(&self, bytes: u64) {
+        let sent = self.bytes.fetch_add(bytes, Ordering::SeqCst) + bytes;
+        if self.max_bytes.map(|m| sent >= m).unwrap_or(false) {
+            self.exhausted.store(true, Ordering::SeqCst);
+        }
+    }
+
+    fn exhausted(&self) -> bool {
+        self.exhausted.load(Ordering::SeqCst)
+    }
+}
+
+
+/// A request in flight, until dropped, which is after its response body was written, if any
+pub(super) struct InFlight(Arc<ServeLimits>);
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        if self.0.in_flight.fetch_sub(1, Ordering::SeqCst) == 1 && self.0.exhausted() {
+            *self.0.done.0.lock().unwrap_or_else(PoisonError::into_inner) = true;
+            self.0.done.1.notify_all();
+        }
+    }
+}
+
+
+impl HttpHandler {
+    /// Start tracking the request against the limits, or get it refused if they were reached
+    pub(super) fn enter_serve_limits(&self, req: &mut Request) -> Result<Option<InFlight>, IronResult<Response>> {
+        let limits = match self.serve_limits.as_ref() {
+            Some(limits) => limits,
+            None => return Ok(None),
+        };
+        if limits.exhausted() {
+            log!(self.log,
+                 "{} requested to {red}{}{reset} {yellow}{}{reset} after the serving limits were reached",
+                 self.remote_addresses(req),
+                 req.method,
+                 req.url);
+            return Err(self.handle_generated_error(req,
+                                                   status::ServiceUnavailable,
+                                                   &["503 Service Unavailable", "This server is done serving.", "<p>It's shutting down.</p>"]));
+        }
+
+        limits.in_flight.fetch_add(1, Ordering::SeqCst);
+        Ok(Some(InFlight(limits.clone())))
+    }
+
+    /// Count the bytes of `resp`'s body as they're sent, and keep the request in flight until they are
+    pub(super) fn limit_serving(&self, in_flight: Option<InFlight>, resp: &mut Response) {
+        if let Some(in_flight) = in_flight {
+            if let Some(body) = resp.body.take() {
+                resp.body = Some(Box::new(LimitedWriteBody {
+                    in_flight,
+                    underlying: body,
+                }));
+            }
+        }
+    }
+}
+
+
+struct LimitedWriteBody {
+    in_flight: InFlight,
+    underlying: Box<dyn WriteBody>,
+}
+
+impl WriteBody for LimitedWriteBody {
+    fn write_body(&mut self, res: &mut dyn Write) -> IoResult<()> {
+        self.underlying.write_body(&mut LimitedWriter {
+            limits: &self.in_flight.0,
+            output: res,
+        })
+    }
+}
+
+struct LimitedWriter<'l, 'o> {
+    limits: &'l ServeLimits,
+    output: &'o mut dyn Write,
+}
+
+impl<'l, 'o> Write for LimitedWriter<'l, 'o> {
+    fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+        let written = self.output.write(buf)?;
+        self.limits.sent(written as u64);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> IoResult<()> {
+        self.output.flush()
+    }
+}
@@ -0,0 +1,97 @@
+//! Directory listings with lots of entries are streamed chunked, row by row, and encoded on the fly,
+//! so browsers can start rendering them before the whole directory's been gone through.
+//!
+//! Smaller ones are built in one go, so their encoded forms can be cached.
+
+
+use self::super::super::util::{StreamEncoder, Spaces, html_response, response_encoding, vary_encoding, USER_AGENT};
+use iron::{headers, status, mime, IronResult, Response, Request};
+use std::io::{Result as IoResult, Write};
+use iron::response::WriteBody;
+use iron::modifiers::Header;
+use self::super::HttpHandler;
+
+
+/// Listings of at least this many entries are streamed
+const STREAMED_LISTING_MIN_ENTRIES: usize = 1000;
+
+/// Flush after this many rows, so they get to the client in reasonably-sized chunks
+const STREAMED_LISTING_FLUSH_ROWS: usize = 200;
+
+
+impl HttpHandler {
+    /// Respond with the specified listing template, with the `list_idx`th format string being the `entries` rows
+    pub(super) fn handle_generated_listing<R>(&self, req: &mut Request, entries: usize, rows: R, template: &str, list_idx: usize, format_strings: &[&str])
+                                              -> IronResult<Response>
+        where R: Iterator<Item = String> + Send + 'static
+    {
+        if entries < STREAMED_LISTING_MIN_ENTRIES {
+            let list_s = rows.collect::<String>();
+            let mut format_strings = format_strings.to_vec();
+            format_strings[list_idx] = &list_s;
+            return self.handle_generated_response_encoding(req, status::Ok, html_response(template, &format_strings));
+        }
+
+        let placeholder = format!("{{{}}}", list_idx);
+        let split = template.find(&placeholder).expect("Listing template has no list placeholder");
+        let head = html_response(&template[..split], format_strings);
+        let tail = html_response(&template[split + placeholder.len()..], format_strings);
+
+        let encoding = req.headers.get::<headers::AcceptEncoding>().and_then(|es| response_encoding(es));
+        log!(self.log,
+             "{} streaming {} entries{}",
+             Spaces(self.remote_addresses(req).width()),
+             entries,
+             encoding.as_ref().map(|e| format!(" encoded as {}", e)).unwrap_or_default());
+
+        let mut resp = Response::with((status::Ok,
+                                       Header(headers::Server(USER_AGENT.to_string())),
+                                       "text/html;charset=utf-8".parse::<mime::Mime>().unwrap(),
+                                       Box::new(ListingBody {
+                                           head: head,
+                                           rows: Box::new(rows),
+                                           tail: tail,
+                                           encoding: encoding.clone(),
+                                       }) as Box<dyn WriteBody>));
+        if let Some(encoding) = encoding {
+            resp.headers.set(headers::ContentEncoding(vec![encoding]));
+        }
+        Ok(vary_encoding(resp))
+    }
+}
+
+
+struct ListingBody {
+    head: String,
+    rows: Box<dyn Iterator<Item = String> + Send>,
+    tail: String,
+    encoding: Option<headers::Encoding>,
+}
+
+impl ListingBody {
+    fn write_listing<W: Write + ?Sized>(&mut self, res: &mut W) -> IoResult<()> {
+        res.write_all(self.head.as_bytes())?;
+        res.flush()?;
+        for (i, row) in (&mut self.rows).enumerate() {
+            res.write_all(row.as_bytes())?;
+            if (i + 1) % STREAMED_LISTING_FLUSH_ROWS == 0 {
+                res.flush()?;
+            }
+        }
+        res.write_all(self.tail.as_bytes())
+    }
+}
+
+impl WriteBody for ListingBody {
+    fn write_body(&mut self, res: &mut dyn Write) -> IoResult<()> {
+        match self.encoding.take() {
+            Some(encoding) => {
+                // response_encoding() only picks supported encodings
+                let mut enc = StreamEncoder::new(res, &encoding).expect("Unsupported listing encoding");
+                self.write_listing(&mut enc)?;
+                enc.finish().map(|_| ())
+            }
+            None => self.write_listing(res),
+        }
+    }
+}
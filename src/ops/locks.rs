@@ -0,0 +1,379 @@
+//! In-memory [WebDAV locks](https://tools.ietf.org/html/rfc4918#section-6), for clients (like Microsoft Office and macOS Finder)
+//! that won't write to a server without them.
+//!
+//! Locks live only as long as the server does, and expire after at most `MAX_LOCK_TIMEOUT` unless refreshed.
+//! While one's held, PUTs, DELETEs, and COPY/MOVEs onto what it covers need its token in the If header.
+
+
+use self::super::super::util::{IfLockTokens, LockToken, Timeout, Depth, body_framing_error, is_nonexistent_descendant_of, WEBDAV_XML_NAMESPACE_DAV};
+use self::super::webdav::{DEFAULT_XML_PARSER_CONFIG, intialise_xml_output, webdav_condition_failed};
+use xml::reader::{EventReader as XmlReader, XmlEvent as XmlREvent};
+use xml::writer::{EventWriter as XmlWriter, XmlEvent as XmlWEvent, Error as XmlWError};
+use xml::common::{TextPosition as XmlTextPosition, Position};
+use std::io::{ErrorKind as IoErrorKind, Write};
+use iron::{status, IronResult, Response, Request};
+use std::time::{Duration, Instant};
+use iron::modifiers::Header;
+use std::path::{PathBuf, Path};
+use std::sync::{Arc, Mutex, PoisonError};
+use rand::{Rng, thread_rng};
+use std::fs::OpenOptions;
+use self::super::HttpHandler;
+use iron::mime::Mime;
+
+
+/// All locks currently held, expired ones are pruned whenever they're looked at
+pub(super) type WebdavLocks = Arc<Mutex<Vec<WebdavLock>>>;
+
+/// Locks last at most this long, regardless of the requested timeout
+const MAX_LOCK_TIMEOUT: Duration = Duration::from_secs(60 * 60);
+
+
+#[derive(Debug, Clone, Hash, PartialEq, Eq)]
+pub(super) struct WebdavLock {
+    /// `opaquelocktoken:` URI
+    token: String,
+    root: PathBuf,
+    root_url: String,
+    /// Depth: infinity, as opposed to 0
+    infinite: bool,
+    exclusive: bool,
+    /// Text content of the client-supplied `<owner>`
+    owner: Option<String>,
+    expires: Instant,
+}
+
+impl WebdavLock {
+    /// Whether writing to `p` needs this lock's token
+    ///
+    /// Depth-0 locks on collections also cover their membership
+    fn covers(&self, p: &Path) -> bool {
+        p == self.root || (p.starts_with(&self.root) && (self.infinite || p.parent() == Some(self.root.as_path())))
+    }
+}
+
+
+impl HttpHandler {
+    /// https://tools.ietf.org/html/rfc4918#section-9.10
+    ///
+    /// Nonexistent resources are created empty, an empty body refreshes the lock whose token is in the If header
+    pub(super) fn handle_webdav_lock(&self, req: &mut Request) -> IronResult<Response> {
+        if self.writes_temp_dir.is_none() {
+            return self.handle_forbidden_method(req, "-w", "write requests");
+        }
+
+        let (req_p, symlink, url_err) = self.parse_requested_path(req);
+
+        if url_err {
+            return self.handle_invalid_url(req, "<p>Percent-encoding decoded to invalid UTF-8 or a path separator.</p>");
+        }
+
+        if (symlink && !self.symlink_policy.follow()) ||
+           (symlink && self.symlink_policy.sandboxed() && !is_nonexistent_descendant_of(&req_p, &self.hosted_directory.1)) {
+            return self.handle_nonexistent(req, req_p);
+        }
+
+        // The body's optional, but if there is one it has to be framed unambiguously
+        match body_framing_error(&req.headers) {
+            Some((st, cause)) if st != status::LengthRequired => return self.handle_bad_framing(req, st, cause),
+            _ => {}
+        }
+
+        let lockinfo = match parse_lockinfo(req) {
+            Ok(lockinfo) => lockinfo,
+            Err(e) => {
+                log!(self.log,
+                     "{} tried to {red}LOCK{reset} {yellow}{}{reset} with invalid XML",
+                     self.remote_addresses(&req),
+                     req_p.display());
+                return self.handle_generated_error(req, status::BadRequest, &["400 Bad Request", &format!("Invalid XML: {}", e), ""]);
+            }
+        };
+
+        let depth = req.headers.get::<Depth>().copied().unwrap_or(Depth::Infinity);
+        if depth == Depth::One {
+            return self.handle_generated_error(req, status::BadRequest, &["400 Bad Request", "Invalid depth: 1", ""]);
+        }
+        let timeout = req.headers.get::<Timeout>().and_then(|t| t.0).map_or(MAX_LOCK_TIMEOUT, |t| Duration::from_secs(t).min(MAX_LOCK_TIMEOUT));
+
+        log!(self.log,
+             "{} requested to {red}LOCK{reset} {yellow}{}{reset} at depth {} for {}s",
+             self.remote_addresses(&req),
+             req_p.display(),
+             depth,
+             timeout.as_secs());
+
+        let now = Instant::now();
+        let mut locks = self.webdav_locks.lock().unwrap_or_else(PoisonError::into_inner);
+        locks.retain(|l| l.expires > now);
+
+        let (exclusive, owner) = match lockinfo {
+            Some(lockinfo) => lockinfo,
+            None => {
+                let tokens = req.headers.get::<IfLockTokens>().map(|t| &t.0[..]).unwrap_or(&[]);
+                return match locks.iter_mut().find(|l| tokens.contains(&l.token) && l.covers(&req_p)) {
+                    Some(lock) => {
+                        lock.expires = now + timeout;
+                        lock_response(status::Ok, lock, now)
+                    }
+                    None => webdav_condition_failed(status::PreconditionFailed, "lock-token-matches-request-uri"),
+                };
+            }
+        };
+        let infinite = depth == Depth::Infinity;
+
+        if locks.iter().any(|l| (exclusive || l.exclusive) && (l.covers(&req_p) || (infinite && l.root.starts_with(&req_p)))) {
+            drop(locks);
+            log!(self.log,
+                 "{} tried to {red}LOCK{reset} {yellow}{}{reset}, already locked by another client",
+                 self.remote_addresses(&req),
+                 req_p.display());
+            return webdav_condition_failed(status::Locked, "no-conflicting-lock");
+        }
+
+        let mut created = false;
+        if !req_p.exists() {
+            if !req_p.parent().map(|pp| pp.exists()).unwrap_or(true) {
+                return Ok(Response::with(status::Conflict));
+            }
+            if locked(&locks, req, &req_p, false) {
+                drop(locks);
+                return self.handle_locked(req, req_p);
+            }
+
+            match OpenOptions::new().write(true).create_new(true).open(&req_p) {
+                Ok(_) => created = true,
+                Err(ref e) if e.kind() == IoErrorKind::AlreadyExists => {}
+                Err(e) => {
+                    drop(locks);
+                    return self.handle_fs_error(req, e, "creating it to lock");
+                }
+            }
+            self.written(&req_p);
+        }
+
+        let r = thread_rng().gen::<u128>();
+        locks.push(WebdavLock {
+            token: format!("opaquelocktoken:{:08x}-{:04x}-{:04x}-{:04x}-{:012x}",
+                           r >> 96,
+                           (r >> 80) & 0xFFFF,
+                           (r >> 64) & 0xFFFF,
+                           (r >> 48) & 0xFFFF,
+                           r & 0xFFFF_FFFF_FFFF),
+            root: req_p,
+            root_url: req.url.as_ref().as_str().to_string(),
+            infinite: infinite,
+            exclusive: exclusive,
+            owner: owner,
+            expires: now + timeout,
+        });
+        let lock = locks.last().unwrap();
+        let mut resp = lock_response(if created { status::Created } else { status::Ok }, lock, now)?;
+        resp.headers.set(LockToken(lock.token.clone()));
+        Ok(resp)
+    }
+
+    /// https://tools.ietf.org/html/rfc4918#section-9.11
+    pub(super) fn handle_webdav_unlock(&self, req: &mut Request) -> IronResult<Response> {
+        if self.writes_temp_dir.is_none() {
+            return self.handle_forbidden_method(req, "-w", "write requests");
+        }
+
+        let (req_p, _, url_err) = self.parse_requested_path(req);
+
+        if url_err {
+            return self.handle_invalid_url(req, "<p>Percent-encoding decoded to invalid UTF-8 or a path separator.</p>");
+        }
+
+        let token = match req.headers.get::<LockToken>() {
+            Some(token) => token.0.clone(),
+            None => return self.handle_generated_error(req, status::BadRequest, &["400 Bad Request", "Lock-Token header missing or invalid.", ""]),
+        };
+
+        log!(self.log,
+             "{} requested to {red}UNLOCK{reset} {yellow}{}{reset}",
+             self.remote_addresses(&req),
+             req_p.display());
+
+        let now = Instant::now();
+        let mut locks = self.webdav_locks.lock().unwrap_or_else(PoisonError::into_inner);
+        locks.retain(|l| l.expires > now);
+        match locks.iter().position(|l| l.token == token && l.covers(&req_p)) {
+            Some(idx) => {
+                locks.remove(idx);
+                Ok(Response::with((status::NoContent, Header(LockToken(token)))))
+            }
+            None => webdav_condition_failed(status::Conflict, "lock-token-matches-request-uri"),
+        }
+    }
+
+    /// Whether writing to `p`, or with `tree` anything under it, needs a lock token not submitted with the request
+    pub(super) fn webdav_write_locked(&self, req: &Request, p: &Path, tree: bool) -> bool {
+        if !self.webdav {
+            return false;
+        }
+
+        let now = Instant::now();
+        let mut locks = self.webdav_locks.lock().unwrap_or_else(PoisonError::into_inner);
+        locks.retain(|l| l.expires > now);
+        locked(&locks, req, p, tree)
+    }
+
+    pub(super) fn handle_locked(&self, req: &mut Request, p: PathBuf) -> IronResult<Response> {
+        log!(self.log,
+             "{} tried to write to {magenta}{}{reset}, {red}locked{reset} without its lock token",
+             self.remote_addresses(&req),
+             p.display());
+        webdav_condition_failed(status::Locked, "lock-token-submitted")
+    }
+
+    /// Release the locks on `p` and everything under it, after it's been removed
+    pub(super) fn drop_webdav_locks(&self, p: &Path) {
+        if self.webdav {
+            self.webdav_locks.lock().unwrap_or_else(PoisonError::into_inner).retain(|l| !l.root.starts_with(p));
+        }
+    }
+
+    /// Write the [lockdiscovery](https://tools.ietf.org/html/rfc4918#section-15.8) property's contents for `path`
+    pub(super) fn write_lockdiscovery<W: Write>(&self, out: &mut XmlWriter<W>, path: &Path) -> Result<(), XmlWError> {
+        let now = Instant::now();
+        let locks = self.webdav_locks.lock().unwrap_or_else(PoisonError::into_inner);
+        for lock in locks.iter().filter(|l| l.expires > now && (l.root == path || (l.infinite && path.starts_with(&l.root)))) {
+            write_activelock(out, lock, now)?;
+        }
+        Ok(())
+    }
+}
+
+
+fn locked(locks: &[WebdavLock], req: &Request, p: &Path, tree: bool) -> bool {
+    let tokens = req.headers.get::<IfLockTokens>().map(|t| &t.0[..]).unwrap_or(&[]);
+    locks.iter().any(|l| (l.covers(p) || (tree && l.root.starts_with(p))) && !tokens.contains(&l.token))
+}
+
+/// https://tools.ietf.org/html/rfc4918#section-14.11
+///
+/// `None` for an empty body, otherwise whether the lock's exclusive and its owner
+fn parse_lockinfo(req: &mut Request) -> Result<Option<(bool, Option<String>)>, String> {
+    let mut xml = XmlReader::new_with_config(&mut req.body, DEFAULT_XML_PARSER_CONFIG.clone());
+    // Local names of the elements we're in, outermost first
+    let mut path: Vec<String> = vec![];
+    let mut exclusive = None;
+    let mut write = false;
+    let mut owner: Option<String> = None;
+
+    loop {
+        match xml.next() {
+            Ok(XmlREvent::StartElement { name, .. }) => {
+                match (path.len(), path.last().map(|s| &s[..]), &name.local_name[..]) {
+                    (0, _, "lockinfo") => {}
+                    (0, _, other) => return Err(format!("unexpected root element {}", other)),
+                    (2, Some("lockscope"), "exclusive") => exclusive = Some(true),
+                    (2, Some("lockscope"), "shared") => exclusive = Some(false),
+                    (2, Some("locktype"), "write") => write = true,
+                    _ => {}
+                }
+                path.push(name.local_name);
+            }
+            Ok(XmlREvent::Characters(text)) => {
+                if path.get(1).map(|s| &s[..]) == Some("owner") {
+                    owner.get_or_insert_with(String::new).push_str(&text);
+                }
+            }
+            Ok(XmlREvent::EndElement { .. }) => {
+                path.pop();
+                if path.is_empty() {
+                    break;
+                }
+            }
+            Ok(XmlREvent::EndDocument) => break,
+            Ok(_) => {}
+            Err(e) => {
+                if path.is_empty() && e.position() == XmlTextPosition::new() && e.msg().contains("no root element") {
+                    return Ok(None);
+                } else {
+                    return Err(e.to_string());
+                }
+            }
+        }
+    }
+
+    match (exclusive, write) {
+        (Some(exclusive), true) => Ok(Some((exclusive, owner))),
+        (None, _) => Err("lockinfo without lockscope".to_string()),
+        (_, false) => Err("lockinfo without write locktype".to_string()),
+    }
+}
+
+fn lock_response(st: status::Status, lock: &WebdavLock, now: Instant) -> IronResult<Response> {
+    let mut out = intialise_xml_output().expect("Couldn't write LOCK XML");
+    out.write(XmlWEvent::start_element("D:prop").ns(WEBDAV_XML_NAMESPACE_DAV.0, WEBDAV_XML_NAMESPACE_DAV.1))
+        .and_then(|_| out.write(XmlWEvent::start_element("D:lockdiscovery")))
+        .and_then(|_| write_activelock(&mut out, lock, now))
+        .and_then(|_| out.write(XmlWEvent::end_element()))
+        .and_then(|_| out.write(XmlWEvent::end_element()))
+        .expect("Couldn't write LOCK XML");
+
+    Ok(Response::with((st, out.into_inner(), "text/xml;charset=utf-8".parse::<Mime>().unwrap())))
+}
+
+/// https://tools.ietf.org/html/rfc4918#section-14.1
+fn write_activelock<W: Write>(out: &mut XmlWriter<W>, lock: &WebdavLock, now: Instant) -> Result<(), XmlWError> {
+    out.write(XmlWEvent::start_element("D:activelock"))?;
+    write_lock_kind(out, lock.exclusive)?;
+
+    out.write(XmlWEvent::start_element("D:depth"))?;
+    out.write(XmlWEvent::characters(if lock.infinite { "infinity" } else { "0" }))?;
+    out.write(XmlWEvent::end_element())?;
+
+    if let Some(owner) = lock.owner.as_ref() {
+        out.write(XmlWEvent::start_element("D:owner"))?;
+        out.write(XmlWEvent::start_element("D:href"))?;
+        out.write(XmlWEvent::characters(owner))?;
+        out.write(XmlWEvent::end_element())?;
+        out.write(XmlWEvent::end_element())?;
+    }
+
+    out.write(XmlWEvent::start_element("D:timeout"))?;
+    out.write(XmlWEvent::characters(&Timeout(Some(lock.expires.saturating_duration_since(now).as_secs())).to_string()))?;
+    out.write(XmlWEvent::end_element())?;
+
+    out.write(XmlWEvent::start_element("D:locktoken"))?;
+    out.write(XmlWEvent::start_element("D:href"))?;
+    out.write(XmlWEvent::characters(&lock.token))?;
+    out.write(XmlWEvent::end_element())?;
+    out.write(XmlWEvent::end_element())?;
+
+    out.write(XmlWEvent::start_element("D:lockroot"))?;
+    out.write(XmlWEvent::start_element("D:href"))?;
+    out.write(XmlWEvent::characters(&lock.root_url))?;
+    out.write(XmlWEvent::end_element())?;
+    out.write(XmlWEvent::end_element())?;
+
+    out.write(XmlWEvent::end_element())?; // activelock
+    Ok(())
+}
+
+/// Write the [supportedlock](https://tools.ietf.org/html/rfc4918#section-15.10) property's contents
+pub(super) fn write_supportedlock<W: Write>(out: &mut XmlWriter<W>) -> Result<(), XmlWError> {
+    for &exclusive in &[true, false] {
+        out.write(XmlWEvent::start_element("D:lockentry"))?;
+        write_lock_kind(out, exclusive)?;
+        out.write(XmlWEvent::end_element())?;
+    }
+    Ok(())
+}
+
+/// `<lockscope>` and `<locktype>`
+fn write_lock_kind<W: Write>(out: &mut XmlWriter<W>, exclusive: bool) -> Result<(), XmlWError> {
+    out.write(XmlWEvent::start_element("D:lockscope"))?;
+    out.write(XmlWEvent::start_element(if exclusive { "D:exclusive" } else { "D:shared" }))?;
+    out.write(XmlWEvent::end_element())?;
+    out.write(XmlWEvent::end_element())?;
+
+    out.write(XmlWEvent::start_element("D:locktype"))?;
+    out.write(XmlWEvent::start_element("D:write"))?;
+    out.write(XmlWEvent::end_element())?;
+    out.write(XmlWEvent::end_element())?;
+    Ok(())
+}
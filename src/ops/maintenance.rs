@@ -0,0 +1,136 @@
+//! Temp dir maintenance: the encoded cache is checked against the hosted files, dropping entries whose sources are gone
+//! and ones that don't decode back to their sources, and abandoned leftovers are removed from the other temp dirs.
+//!
+//! Run once with `--check-cache`, or periodically while serving with `--check-cache-interval`.
+
+
+use self::super::super::util::{decoded_file_hash, file_hash};
+use std::time::{SystemTime, Duration};
+use std::collections::HashSet;
+use self::super::HttpHandler;
+use std::path::{PathBuf, Path};
+use walkdir::WalkDir;
+use std::thread;
+use std::fs;
+
+
+/// Temp files not modified in this long are assumed to be abandoned
+const STALE_AGE: Duration = Duration::from_secs(60 * 60);
+
+/// Encoded files modified this recently may still be being written, so they're not checked
+const IN_PROGRESS_AGE: Duration = Duration::from_secs(60);
+
+
+/// What a cache check found, all of which were removed
+#[derive(Debug, Default, Copy, Clone, Hash, PartialEq, Eq)]
+pub struct CacheCheckReport {
+    /// Encoded files checked
+    pub checked: usize,
+    /// Encoded files and torrents whose source files no longer exist or have changed, and encoded files made by other versions
+    pub orphaned: usize,
+    /// Encoded files which don't decode to their source files
+    pub corrupted: usize,
+    /// Abandoned files in the writes, origin, and mirror temp dirs
+    pub stale: usize,
+}
+
+
+impl HttpHandler {
+    /// Check the temp dirs, removing everything that shouldn't be there
+    pub fn check_cache(&self) -> CacheCheckReport {
+        let mut report = CacheCheckReport::default();
+
+        let mut hashes = HashSet::new();
+        let mut torrents = HashSet::new();
+        let check_encoded = self.encoded_store.as_ref().map(|s| s.dir().1.exists()).unwrap_or(false);
+        // Other servers' files are in shared stores, so there's no telling what's orphaned there
+        let check_orphans = check_encoded && !self.encoded_store.as_ref().unwrap().shared();
+        let check_torrents = self.torrent_temp_dir.as_ref().map(|(_, d)| d.exists()).unwrap_or(false);
+        if check_orphans || check_torrents {
+            for e in WalkDir::new(&self.hosted_directory.1)
+                .follow_links(self.symlink_policy.follow())
+                .into_iter()
+                .flatten()
+                .filter(|e| e.file_type().is_file()) {
+                if check_orphans {
+                    if let Ok(hash) = file_hash(e.path()) {
+                        hashes.insert(hash.to_hex().to_string());
+                    }
+                }
+                if check_torrents {
+                    if let Ok(meta) = e.metadata() {
+                        torrents.insert(self.torrent_cache_path(e.path(), &meta));
+                    }
+                }
+            }
+        }
+
+        if check_encoded {
+            for f in temp_files(&self.encoded_store.as_ref().unwrap().dir().1).into_iter().filter(|f| !modified_within(f, IN_PROGRESS_AGE)) {
+                report.checked += 1;
+
+                // <hash>-<fingerprint>[.<source extension>].<encoding extension>
+                let name = f.file_name().unwrap().to_string_lossy().into_owned();
+                let stem = name.split('.').next().unwrap();
+                let (hash, fingerprint) = stem.split_once('-').unwrap_or((stem, ""));
+                let ext = name.rsplit('.').next().unwrap();
+                if check_orphans && (!hashes.contains(hash) || fingerprint != self.cache_fingerprint) {
+                    report.orphaned += 1;
+                    self.remove_temp_file(&f, "orphaned encoded");
+                } else if decoded_file_hash(&f, ext).map(|h| h.to_hex().as_str() != hash).unwrap_or(true) {
+                    report.corrupted += 1;
+                    self.remove_temp_file(&f, "corrupted encoded");
+                }
+            }
+        }
+
+        if check_torrents {
+            for f in temp_files(&self.torrent_temp_dir.as_ref().unwrap().1).into_iter().filter(|f| !torrents.contains(f)) {
+                report.orphaned += 1;
+                self.remove_temp_file(&f, "outdated torrent");
+            }
+        }
+
+        for &(_, ref dir) in self.writes_temp_dir.iter().chain(self.origin_temp_dir.iter()).chain(self.mirror_temp_dir.iter()) {
+            for f in temp_files(dir).into_iter().filter(|f| !modified_within(f, STALE_AGE)) {
+                report.stale += 1;
+                self.remove_temp_file(&f, "stale");
+            }
+        }
+
+        log!(self.log,
+             "Cache check done: {} encoded files checked, removed {} orphaned, {} corrupted, and {} stale files",
+             report.checked,
+             report.orphaned,
+             report.corrupted,
+             report.stale);
+        report
+    }
+
+    /// Check the cache every `interval`, forever
+    pub fn check_cache_periodically(&self, interval: Duration) -> ! {
+        loop {
+            thread::sleep(interval);
+            self.check_cache();
+        }
+    }
+
+    fn remove_temp_file(&self, f: &Path, what: &str) {
+        if fs::remove_file(f).is_ok() {
+            log!(self.log, "Removed {} temp file {magenta}{}{reset}", what, f.display());
+        }
+    }
+}
+
+
+/// All files under the specified directory, empty if it doesn't exist
+fn temp_files(dir: &Path) -> Vec<PathBuf> {
+    WalkDir::new(dir).into_iter().flatten().filter(|e| e.file_type().is_file()).map(|e| e.into_path()).collect()
+}
+
+fn modified_within(f: &Path, age: Duration) -> bool {
+    f.metadata()
+        .and_then(|m| m.modified())
+        .map(|m| SystemTime::now().duration_since(m).map(|d| d < age).unwrap_or(true))
+        .unwrap_or(false)
+}
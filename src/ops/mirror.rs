@@ -0,0 +1,193 @@
+use self::super::super::util::{http_client, file_length, is_actually_file};
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use rfsapi::{RawFsApiHeader, FilesetData, RawFileData};
+use std::io::{self, Error as IoError};
+use std::collections::BTreeSet;
+use iron::url::Url as GenericUrl;
+use std::fs::{self, File};
+use hyper::status;
+use hyper::Client;
+use std::path::{PathBuf, Path};
+use self::super::super::Error;
+use self::super::WarmUpTask;
+use serde_json;
+use std::thread;
+
+
+/// Periodically mirrors another instance into the hosted directory by walking its
+/// [RFSAPI](https://github.com/nabijaczleweli/rfsapi-rs) listings.
+///
+/// Files are (re)downloaded if their size or modification time differ, and local entries absent from the origin are deleted.
+pub struct MirrorPuller {
+    pub origin: GenericUrl,
+    pub hosted_directory: PathBuf,
+    pub temp_directory: PathBuf,
+    /// (at all, log_colour)
+    pub log: (bool, bool),
+    client: Client,
+}
+
+/// What a single mirror pass did
+#[derive(Debug, Copy, Clone, Hash, PartialEq, Eq, Default)]
+pub struct MirrorStats {
+    pub downloaded: usize,
+    pub deleted: usize,
+    pub unchanged: usize,
+}
+
+impl MirrorPuller {
+    pub fn new(origin: GenericUrl, hosted_directory: PathBuf, temp_directory: &(String, PathBuf), log: (bool, bool)) -> MirrorPuller {
+        MirrorPuller {
+            origin: origin,
+            hosted_directory: hosted_directory,
+            temp_directory: temp_directory.1.join("mirror"),
+            log: log,
+            client: http_client(),
+        }
+    }
+
+    /// Mirror the origin, then wait `interval`, forever
+    ///
+    /// The warm-up task, if any, is finished after the first pass.
+    pub fn run(&self, interval: Duration, mut warm_up: Option<WarmUpTask>) -> ! {
+        loop {
+            match self.sync() {
+                Ok(stats) => {
+                    if stats.downloaded != 0 || stats.deleted != 0 {
+                        log!(self.log,
+                             "Mirrored {yellow}{}{reset}: {} downloaded, {} deleted, {} unchanged",
+                             self.origin,
+                             stats.downloaded,
+                             stats.deleted,
+                             stats.unchanged);
+                    }
+                }
+                Err(err) => {
+                    log!(self.log, "Mirroring {yellow}{}{reset} failed: {red}{}{reset}", self.origin, err);
+                }
+            }
+
+            warm_up.take();
+            thread::sleep(interval);
+        }
+    }
+
+    /// Do a single mirror pass
+    pub fn sync(&self) -> Result<MirrorStats, Error> {
+        fs::create_dir_all(&self.temp_directory).map_err(|e| mirror_error("create", e.to_string()))?;
+
+        let mut stats = MirrorStats::default();
+        self.sync_dir(&self.origin, &self.hosted_directory, &mut stats)?;
+        Ok(stats)
+    }
+
+    fn sync_dir(&self, url: &GenericUrl, local: &Path, stats: &mut MirrorStats) -> Result<(), Error> {
+        let listing = self.list(url)?;
+        fs::create_dir_all(local).map_err(|e| mirror_error("create", format!("{}: {}", local.display(), e)))?;
+
+        let mut remote_names = BTreeSet::new();
+        for f in &listing.files {
+            // Don't let the origin escape the hosted directory
+            if f.name.is_empty() || f.name == "." || f.name == ".." || f.name.contains('/') || f.name.contains('\\') {
+                continue;
+            }
+            remote_names.insert(&f.name[..]);
+
+            let local_p = local.join(&f.name);
+            let remote_url = url.join(&format!("{}{}", utf8_percent_encode(&f.name, NON_ALPHANUMERIC), if f.is_file { "" } else { "/" }))
+                .map_err(|e| mirror_error("resolve", e.to_string()))?;
+
+            if f.is_file {
+                if local_up_to_date(&local_p, f) {
+                    stats.unchanged += 1;
+                } else {
+                    if local_p.is_dir() {
+                        fs::remove_dir_all(&local_p).map_err(|e| mirror_error("delete", format!("{}: {}", local_p.display(), e)))?;
+                    }
+                    self.download(&remote_url, &local_p, f)?;
+                    stats.downloaded += 1;
+                }
+            } else {
+                if local_p.exists() && !local_p.is_dir() {
+                    fs::remove_file(&local_p).map_err(|e| mirror_error("delete", format!("{}: {}", local_p.display(), e)))?;
+                }
+                self.sync_dir(&remote_url, &local_p, stats)?;
+            }
+        }
+
+        for entry in local.read_dir().map_err(|e| mirror_error("list", format!("{}: {}", local.display(), e)))?.flatten() {
+            let name = entry.file_name();
+            if name.to_str().map(|n| !remote_names.contains(n)).unwrap_or(true) {
+                let path = entry.path();
+                let removal = match entry.file_type() {
+                    Ok(ft) if !is_actually_file(&ft, &path) => fs::remove_dir_all(&path),
+                    _ => fs::remove_file(&path),
+                };
+                removal.map_err(|e| mirror_error("delete", format!("{}: {}", path.display(), e)))?;
+                stats.deleted += 1;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn list(&self, url: &GenericUrl) -> Result<FilesetData, Error> {
+        let resp = self.client
+            .get(url.clone())
+            .header(RawFsApiHeader(true))
+            .send()
+            .map_err(|e| mirror_error("list", format!("{}: {}", url, e)))?;
+        if resp.status != status::StatusCode::Ok {
+            return Err(mirror_error("list", format!("{}: {}", url, resp.status)));
+        }
+
+        serde_json::from_reader(resp).map_err(|e| mirror_error("parse", format!("{}: {}", url, e)))
+    }
+
+    fn download(&self, url: &GenericUrl, local: &Path, data: &RawFileData) -> Result<(), Error> {
+        let mut resp = self.client.get(url.clone()).send().map_err(|e| mirror_error("download", format!("{}: {}", url, e)))?;
+        if resp.status != status::StatusCode::Ok {
+            return Err(mirror_error("download", format!("{}: {}", url, resp.status)));
+        }
+
+        let temp_p = self.temp_directory.join(local.file_name().expect("Mirrored file has no name"));
+        (|| -> Result<(), IoError> {
+            let mut temp_f = File::create(&temp_p)?;
+            io::copy(&mut resp, &mut temp_f)?;
+            fs::copy(&temp_p, local)?;
+            File::options().write(true).open(local)?.set_modified(remote_mtime(data))?;
+            fs::remove_file(&temp_p)
+        })()
+            .map_err(|e| mirror_error("download", format!("{}: {}", local.display(), e)))
+    }
+}
+
+
+fn local_up_to_date(local: &Path, remote: &RawFileData) -> bool {
+    local.metadata()
+        .map(|m| {
+            // Generated modification times can be off by a bit from the ones on disk
+            is_actually_file(&m.file_type(), local) && file_length(&m, &local) == remote.size &&
+            m.modified()
+                .map(|t| {
+                    let rt = remote_mtime(remote);
+                    t.duration_since(rt).or_else(|_| rt.duration_since(t)).map(|d| d <= Duration::from_secs(1)).unwrap_or(false)
+                })
+                .unwrap_or(false)
+        })
+        .unwrap_or(false)
+}
+
+fn remote_mtime(remote: &RawFileData) -> SystemTime {
+    let ts = remote.last_modified.to_timespec();
+    UNIX_EPOCH + Duration::new(ts.sec.max(0) as u64, ts.nsec.max(0) as u32)
+}
+
+fn mirror_error(op: &'static str, more: String) -> Error {
+    Error {
+        desc: "mirror",
+        op: op,
+        more: more.into(),
+    }
+}
@@ -0,0 +1,74 @@
+//! Memory-mapped serving, enabled with `--mmap-min-size`: big enough files, whole or ranged, are sent straight out of a mapping,
+//! letting the page cache do the reading instead of copying everything through a buffer.
+//!
+//! Mapped files that get truncated fault when the pages past their new end are touched, crashing the server,
+//! so only ones unmodified for `MMAP_SETTLE_TIME`, which are unlikely to be being written, are mapped,
+//! and the file's length is checked before sending each `MMAP_CHUNK_SIZE` chunk, failing the response if it shrank.
+
+
+use std::io::{Result as IoResult, Error as IoError, ErrorKind as IoErrorKind, BufReader, SeekFrom, Write, Read, Seek};
+use iron::response::{BodyReader, WriteBody};
+use self::super::fd_cache::ServedFile;
+use std::time::{SystemTime, Duration};
+use memmap2::{MmapOptions, Mmap};
+use self::super::HttpHandler;
+
+
+/// Files modified this recently might still be being written, so they're read instead
+pub const MMAP_SETTLE_TIME: Duration = Duration::from_secs(60);
+
+/// How much of a mapping is sent between checks of whether the file was truncated
+pub const MMAP_CHUNK_SIZE: usize = 1024 * 1024;
+
+
+impl HttpHandler {
+    /// `len` bytes of `file` from `from` as a response body, mapped if it's at least `--mmap-min-size` and settled,
+    /// read `range_read_size()` at a time otherwise
+    pub(super) fn file_body(&self, mut file: ServedFile, from: u64, len: u64) -> IoResult<Box<dyn WriteBody>> {
+        if let Some(body) = self.mmap_min_size.filter(|min| len >= min.get()).and_then(|_| MappedBody::new(&file, from, len)) {
+            return Ok(Box::new(body));
+        }
+
+        file.seek(SeekFrom::Start(from))?;
+        Ok(Box::new(BodyReader(BufReader::with_capacity(self.range_read_size(), file.take(len)))))
+    }
+}
+
+
+struct MappedBody {
+    map: Mmap,
+    file: ServedFile,
+    from: u64,
+}
+
+impl MappedBody {
+    fn new(file: &ServedFile, from: u64, len: u64) -> Option<MappedBody> {
+        let meta = file.metadata().ok()?;
+        if meta.modified().ok().and_then(|m| SystemTime::now().duration_since(m).ok()).map(|age| age < MMAP_SETTLE_TIME).unwrap_or(true) ||
+           from.checked_add(len).map(|end| end > meta.len()).unwrap_or(true) {
+            return None;
+        }
+
+        // Safety: truncating the file while it's mapped is guarded against as well as it can be, see the module docs
+        let map = unsafe { MmapOptions::new().offset(from).len(len as usize).map(file.file()) }.ok()?;
+        Some(MappedBody {
+            map: map,
+            file: file.clone(),
+            from: from,
+        })
+    }
+}
+
+impl WriteBody for MappedBody {
+    fn write_body(&mut self, res: &mut dyn Write) -> IoResult<()> {
+        let mut end = self.from;
+        for chunk in self.map.chunks(MMAP_CHUNK_SIZE) {
+            end += chunk.len() as u64;
+            if self.file.metadata()?.len() < end {
+                return Err(IoError::new(IoErrorKind::UnexpectedEof, "mapped file truncated while being served"));
+            }
+            res.write_all(chunk)?;
+        }
+        Ok(())
+    }
+}
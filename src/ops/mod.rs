@@ -1,12 +1,18 @@
 use blake3;
 use std::fmt;
+use std::cmp;
 use serde_json;
 use std::ffi::OsStr;
 use std::borrow::Cow;
-use std::net::IpAddr;
+use std::cell::RefCell;
+use std::time::{SystemTime, Duration, Instant};
+use std::net::{SocketAddr, IpAddr};
+use std::num::{NonZeroUsize, NonZeroU64};
 use serde::Serialize;
 use unicase::UniCase;
-use std::sync::RwLock;
+use unicode_normalization::UnicodeNormalization;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering as AtomicOrdering};
+use std::sync::{Arc, Condvar, Mutex, RwLock};
 use lazysort::SortedBy;
 use cidr::{Cidr, IpCidr};
 use std::fs::{self, File};
@@ -17,120 +23,384 @@ use std::path::{PathBuf, Path};
 use iron::url::Url as GenericUrl;
 use mime_guess::get_mime_type_opt;
 use hyper_native_tls::NativeTlsServer;
-use std::collections::{BTreeMap, HashMap};
-use self::super::{LogLevel, Options, Error};
+use hyper::net::{HttpsListener, HttpListener};
+use hyper::Client;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use self::super::{EncodedStoreKind, SymlinkPolicy, TcpTuning, LogLevel, Options, Error};
 use std::process::{ExitStatus, Command, Child, Stdio};
 use rfsapi::{RawFsApiHeader, FilesetData, RawFileData};
 use rand::distributions::uniform::Uniform as UniformDistribution;
 use rand::distributions::Alphanumeric as AlphanumericDistribution;
 use iron::mime::{Mime, SubLevel as MimeSubLevel, TopLevel as MimeTopLevel};
-use std::io::{self, ErrorKind as IoErrorKind, SeekFrom, Write, Error as IoError, Read, Seek};
-use iron::{headers, status, method, mime, IronResult, Listening, Response, TypeMap, Request, Handler, Iron};
-use self::super::util::{WwwAuthenticate, DisplayThree, CommaList, Spaces, Dav, url_path, file_hash, is_symlink, encode_str, encode_file, file_length,
-                        html_response, file_binary, client_mobile, percent_decode, escape_specials, file_icon_suffix, is_actually_file, is_descendant_of,
-                        response_encoding, detect_file_as_dir, encoding_extension, file_time_modified, file_time_modified_p, get_raw_fs_metadata,
-                        human_readable_size, encode_tail_if_trimmed, is_nonexistent_descendant_of, USER_AGENT, ERROR_HTML, MAX_SYMLINKS, INDEX_EXTENSIONS,
-                        MIN_ENCODING_GAIN, MAX_ENCODING_SIZE, MIN_ENCODING_SIZE, DAV_LEVEL_1_METHODS, DIRECTORY_LISTING_HTML, MOBILE_DIRECTORY_LISTING_HTML,
-                        BLACKLISTED_ENCODING_EXTENSIONS};
+use std::io::{self, ErrorKind as IoErrorKind, Write, Error as IoError, Read};
+use iron::{headers, status, method, mime, IronResult, Listening, Response, Protocol, TypeMap, Request, Handler, Iron};
+use self::super::util::{StreamEncoder, WwwAuthenticate, Htpasswd, vary_encoding, ShardedMap, LruMap, DisplayThree, CommaList, Spaces, Dav, url_path, url_path_escaped, push_relative, file_hash, is_symlink, encode_str, encode_file, file_length,
+                        html_response, file_binary, client_mobile, s3_request, http_client, body_framing_error, find_normalised, percent_decode_path_segment, percent_decode_non_utf8_path_segment, escape_specials, escape_file_name, file_icon_suffix, is_actually_file, is_device, is_descendant_of, file_permissions, file_owner,
+                        response_encoding, identity_acceptable, client_prefers_json, request_id, html_to_text, detect_file_as_dir, encoding_extension, file_time_modified, file_time_modified_p, get_raw_fs_metadata,
+                        human_readable_size, encode_tail_if_trimmed, CACHE_FORMAT_VERSION, is_nonexistent_descendant_of, is_storage_full, available_space, bind_listener, USER_AGENT, ERROR_HTML, INDEX_EXTENSIONS, LISTING_HASH_ALGORITHMS, MAX_LISTING_HASH_SIZE,
+                        MAX_GENERATED_CACHE_ENTRY_SIZE, DAV_LEVEL_2_METHODS, DAV_UNSUPPORTED_METHODS, DIRECTORY_LISTING_HTML, MOBILE_DIRECTORY_LISTING_HTML,
+                        BLACKLISTED_ENCODING_EXTENSIONS, SUPPORTED_ENCODINGS, DISK_FULL_ENCODING_PAUSE, METHOD_OVERRIDE_HEADER, FILE_NAME_HEADER};
 
 
 macro_rules! log {
     ($logcfg:expr, $fmt:expr) => {
-        use time::now;
         use trivial_colours::{Reset as CReset, Colour as C};
 
-        if $logcfg.0 {
+        if $crate::ops::LogEnabled::log_enabled(&$logcfg.0) {
             if $logcfg.1 {
-                print!("{}[{}]{} ", C::Cyan, now().strftime("%F %T").unwrap(), CReset);
-                println!(concat!($fmt, "{black:.0}{red:.0}{green:.0}{yellow:.0}{blue:.0}{magenta:.0}{cyan:.0}{white:.0}{reset:.0}"),
-                         black = C::Black,
-                         red = C::Red,
-                         green = C::Green,
-                         yellow = C::Yellow,
-                         blue = C::Blue,
-                         magenta = C::Magenta,
-                         cyan = C::Cyan,
-                         white = C::White,
-                         reset = CReset);
+                $crate::ops::log_coloured(format!(concat!($fmt, "{black:.0}{red:.0}{green:.0}{yellow:.0}{blue:.0}{magenta:.0}{cyan:.0}{white:.0}{reset:.0}"),
+                                                 black = C::Black,
+                                                 red = C::Red,
+                                                 green = C::Green,
+                                                 yellow = C::Yellow,
+                                                 blue = C::Blue,
+                                                 magenta = C::Magenta,
+                                                 cyan = C::Cyan,
+                                                 white = C::White,
+                                                 reset = CReset));
             } else {
-                print!("[{}] ", now().strftime("%F %T").unwrap());
-                println!(concat!($fmt, "{black:.0}{red:.0}{green:.0}{yellow:.0}{blue:.0}{magenta:.0}{cyan:.0}{white:.0}{reset:.0}"),
-                         black = "",
-                         red = "",
-                         green = "",
-                         yellow = "",
-                         blue = "",
-                         magenta = "",
-                         cyan = "",
-                         white = "",
-                         reset = "");
+                $crate::ops::log_plain(format!(concat!($fmt, "{black:.0}{red:.0}{green:.0}{yellow:.0}{blue:.0}{magenta:.0}{cyan:.0}{white:.0}{reset:.0}"),
+                                              black = "",
+                                              red = "",
+                                              green = "",
+                                              yellow = "",
+                                              blue = "",
+                                              magenta = "",
+                                              cyan = "",
+                                              white = "",
+                                              reset = ""));
             }
         }
     };
     ($logcfg:expr, $fmt:expr, $($arg:tt)*) => {
-        use time::now;
         use trivial_colours::{Reset as CReset, Colour as C};
 
-        if $logcfg.0 {
+        if $crate::ops::LogEnabled::log_enabled(&$logcfg.0) {
             if $logcfg.1 {
-                print!("{}[{}]{} ", C::Cyan, now().strftime("%F %T").unwrap(), CReset);
-                println!(concat!($fmt, "{black:.0}{red:.0}{green:.0}{yellow:.0}{blue:.0}{magenta:.0}{cyan:.0}{white:.0}{reset:.0}"),
-                         $($arg)*,
-                         black = C::Black,
-                         red = C::Red,
-                         green = C::Green,
-                         yellow = C::Yellow,
-                         blue = C::Blue,
-                         magenta = C::Magenta,
-                         cyan = C::Cyan,
-                         white = C::White,
-                         reset = CReset);
+                $crate::ops::log_coloured(format!(concat!($fmt, "{black:.0}{red:.0}{green:.0}{yellow:.0}{blue:.0}{magenta:.0}{cyan:.0}{white:.0}{reset:.0}"),
+                                                 $($arg)*,
+                                                 black = C::Black,
+                                                 red = C::Red,
+                                                 green = C::Green,
+                                                 yellow = C::Yellow,
+                                                 blue = C::Blue,
+                                                 magenta = C::Magenta,
+                                                 cyan = C::Cyan,
+                                                 white = C::White,
+                                                 reset = CReset));
             } else {
-                print!("[{}] ", now().strftime("%F %T").unwrap());
-                println!(concat!($fmt, "{black:.0}{red:.0}{green:.0}{yellow:.0}{blue:.0}{magenta:.0}{cyan:.0}{white:.0}{reset:.0}"),
-                         $($arg)*,
-                         black = "",
-                         red = "",
-                         green = "",
-                         yellow = "",
-                         blue = "",
-                         magenta = "",
-                         cyan = "",
-                         white = "",
-                         reset = "");
+                $crate::ops::log_plain(format!(concat!($fmt, "{black:.0}{red:.0}{green:.0}{yellow:.0}{blue:.0}{magenta:.0}{cyan:.0}{white:.0}{reset:.0}"),
+                                              $($arg)*,
+                                              black = "",
+                                              red = "",
+                                              green = "",
+                                              yellow = "",
+                                              blue = "",
+                                              magenta = "",
+                                              cyan = "",
+                                              white = "",
+                                              reset = ""));
             }
         }
     };
 }
 
+/// The result of a filesystem op, or, if it failed, return `handle_fs_error()`'s error page for it from the handler
+macro_rules! try_fs {
+    ($self:ident, $req:expr, $op:expr, $doing:expr) => {
+        match $op {
+            Ok(val) => val,
+            Err(err) => return $self.handle_fs_error($req, err, $doing),
+        }
+    };
+}
+
+mod s3;
+mod batch;
+mod deploy;
+mod delta;
+mod mirror;
+mod origin;
 mod webdav;
+mod warm_up;
+mod precompress;
+mod maintenance;
+mod torrent;
 mod bandwidth;
-
+mod listing;
+mod offline;
+mod dead_props;
+mod admin;
+mod inbox;
+mod downloads;
+mod accounting;
+mod replicate;
+mod retention;
+mod write_hours;
+mod limits;
+mod single_file;
+mod stdin;
+mod panics;
+mod locks;
+mod byteranges;
+mod header_log;
+mod cache_control;
+mod conditional;
+mod encoded_store;
+mod preload;
+mod fd_cache;
+mod mmap;
+mod precompressed;
+mod fs_errors;
+mod unlogged;
+mod probes;
+
+pub use self::mirror::{MirrorPuller, MirrorStats};
+pub use self::warm_up::{WarmUpTask, WarmUp};
+pub use self::maintenance::CacheCheckReport;
+pub use self::downloads::DownloadCounts;
+pub use self::replicate::WriteReplicator;
+pub use self::retention::RetentionReport;
+pub use self::limits::ServeLimits;
+pub use self::stdin::StdinBuffer;
+pub use self::encoded_store::{EncodedMemory, EncodedStore};
+pub use self::fd_cache::FdCache;
+
+use self::origin::OriginValidated;
+use self::webdav::SyncSnapshots;
+use self::locks::WebdavLocks;
+use self::admin::ActiveUploads;
+use self::accounting::BandwidthAccounts;
+use self::cache_control::with_cache_headers;
+use self::conditional::with_etag;
+use self::encoded_store::new_encoded_store;
 pub use self::bandwidth::{LimitBandwidthMiddleware, SimpleChain};
 
 
 // TODO: ideally this String here would be Encoding instead but hyper is bad
-type CacheT<Cnt> = HashMap<(blake3::Hash, String), Cnt>;
+type CacheT<Cnt> = LruMap<(blake3::Hash, String), Cnt>;
+
+/// Encoded files, `None` where encoding wasn't worth it, with the modification time and size of the file they were made for,
+/// so they're checked again once it changes
+///
+/// Bounded to `--encoded-cache-entries`, deleting the encoded files of evicted entries.
+type EncodedCacheT = CacheT<(Option<PathBuf>, SystemTime, u64)>;
+
+/// File hashes, valid for as long as the files' modification time and size stay the same
+type HashCacheT = ShardedMap<PathBuf, (SystemTime, u64, blake3::Hash)>;
+
+/// Encoded generated responses, keyed by the rendered response itself, since those are small
+///
+/// Bounded to `--generated-cache-size` bytes, evicting the least recently used responses first.
+#[derive(Debug, Default)]
+struct GeneratedCache {
+    /// Response -> (last used, encoded forms)
+    entries: HashMap<String, (AtomicU64, Vec<(headers::Encoding, Vec<u8>)>)>,
+    size: usize,
+    /// Bumped on every use, under the read lock too
+    counter: AtomicU64,
+}
+
+impl GeneratedCache {
+    fn get(&self, resp: &str, encoding: &headers::Encoding) -> Option<&[u8]> {
+        let &(ref used, ref encs) = self.entries.get(resp)?;
+        let data = encs.iter().find(|&&(ref e, _)| e == encoding).map(|&(_, ref data)| &data[..])?;
+        used.store(self.counter.fetch_add(1, AtomicOrdering::Relaxed), AtomicOrdering::Relaxed);
+        Some(data)
+    }
+
+    fn insert(&mut self, resp: String, encoding: headers::Encoding, data: Vec<u8>, budget: usize) {
+        if resp.len() > MAX_GENERATED_CACHE_ENTRY_SIZE {
+            return;
+        }
+
+        let added = data.len() + if self.entries.contains_key(&resp) { 0 } else { resp.len() };
+        while self.size + added > budget && !self.entries.is_empty() {
+            let oldest = self.entries.iter().min_by_key(|&(_, &(ref used, _))| used.load(AtomicOrdering::Relaxed)).map(|(k, _)| k.clone()).unwrap();
+            let (_, encs) = self.entries.remove(&oldest).unwrap();
+            self.size -= oldest.len() + encs.iter().map(|&(_, ref d)| d.len()).sum::<usize>();
+        }
+        if self.size + added > budget {
+            return;
+        }
+
+        self.size += added;
+        let used = self.counter.fetch_add(1, AtomicOrdering::Relaxed);
+        let entry = self.entries.entry(resp).or_insert_with(|| (AtomicU64::new(0), vec![]));
+        entry.0.store(used, AtomicOrdering::Relaxed);
+        entry.1.push((encoding, data));
+    }
+}
+
+/// Filesystem encodings currently in progress, limited to some amount at once
+#[derive(Debug, Default)]
+struct EncodingSlots {
+    in_flight: Mutex<HashSet<(blake3::Hash, String)>>,
+    freed: Condvar,
+}
+
+/// Frees the slot on drop
+struct EncodingSlot<'s> {
+    slots: &'s EncodingSlots,
+    key: (blake3::Hash, String),
+}
+
+impl EncodingSlots {
+    /// Wait for one of `limit` slots to free up, or return `None` if the same file is already being encoded into the same encoding
+    fn acquire(&self, key: &(blake3::Hash, String), limit: usize) -> Option<EncodingSlot> {
+        let mut in_flight = self.in_flight.lock().unwrap_or_else(PoisonError::into_inner);
+        loop {
+            if in_flight.contains(key) {
+                return None;
+            }
+            if in_flight.len() < limit {
+                in_flight.insert(key.clone());
+                return Some(EncodingSlot {
+                    slots: self,
+                    key: key.clone(),
+                });
+            }
+            in_flight = self.freed.wait(in_flight).unwrap_or_else(PoisonError::into_inner);
+        }
+    }
+}
+
+impl<'s> Drop for EncodingSlot<'s> {
+    fn drop(&mut self) {
+        self.slots.in_flight.lock().unwrap_or_else(PoisonError::into_inner).remove(&self.key);
+        self.slots.freed.notify_all();
+    }
+}
+
+/// What's accepted as credentials under a path
+#[derive(Debug, Clone)]
+pub enum Credentials {
+    /// The `--auth` or `--path-auth` username and, optionally, password
+    Single(String, Option<String>),
+    /// Any user in the `--auth-file`
+    Htpasswd(Arc<Htpasswd>),
+}
+
+impl Credentials {
+    /// Whether these are the supplied basic credentials, with an empty password the same as none
+    pub fn accept(&self, username: &str, password: &Option<String>) -> bool {
+        let password = password.as_ref().filter(|p| !p.is_empty());
+        match *self {
+            Credentials::Single(ref user, ref pwd) => user == username && pwd.as_ref() == password,
+            Credentials::Htpasswd(ref users) => users.verify(username, password.map(|p| &p[..]).unwrap_or("")),
+        }
+    }
+}
 
 pub struct HttpHandler {
     pub hosted_directory: (String, PathBuf),
-    pub follow_symlinks: bool,
-    pub sandbox_symlinks: bool,
+    /// Name of the only file in `hosted_directory` to serve, if a file was given to host
+    pub single_file: Option<String>,
+    pub symlink_policy: SymlinkPolicy,
+    pub max_symlink_depth: usize,
     pub generate_listings: bool,
+    pub listing_permissions: bool,
+    pub listing_downloads: bool,
     pub check_indices: bool,
     pub strip_extensions: bool,
+    pub normalise_unicode: bool,
+    pub serve_devices: bool,
     /// (at all, log_colour)
-    pub log: (bool, bool),
+    pub log: (LogSwitch, bool),
+    /// Whether to tag request log lines with `[METHOD STATUS]`
+    pub log_tags: bool,
+    /// Paths, without the leading slash, successful requests to which aren't logged or counted
+    pub unlogged_paths: BTreeSet<String>,
+    /// Whether to log request and response headers, along with serving status
+    pub log_headers: LogSwitch,
     pub webdav: bool,
-    pub global_auth_data: Option<(String, Option<String>)>,
-    pub path_auth_data: BTreeMap<String, Option<(String, Option<String>)>>,
+    pub s3: bool,
+    /// How many previous deploys to keep, if deploys are enabled
+    pub deploy: Option<usize>,
+    pub delta_sync: bool,
+    /// Smallest file to serve `.torrent`s for, if enabled
+    pub torrent_min_size: Option<u64>,
+    pub offline_bundle: bool,
+    pub xattrs: bool,
+    pub admin: bool,
+    pub inbox: bool,
+    pub method_override: bool,
+    /// Where to fetch missing files from, if anywhere
+    pub origin: Option<GenericUrl>,
+    /// How long fetched files are fresh for before being revalidated against the origin
+    pub origin_ttl: Option<Duration>,
+    pub global_auth_data: Option<Credentials>,
+    pub public_files: bool,
+    pub path_auth_data: BTreeMap<String, Option<Credentials>>,
     pub writes_temp_dir: Option<(String, PathBuf)>,
-    pub encoded_temp_dir: Option<(String, PathBuf)>,
+    /// The temp directory, if it's inside the hosted one, so its contents are never served
+    pub hosted_temp_dir: Option<PathBuf>,
+    /// Largest file that can be PUT
+    pub max_upload_size: Option<u64>,
+    /// Minutes since midnight, local time, between which writes are accepted, if restricted
+    pub write_hours: Option<(u16, u16)>,
+    /// Paths relative to the hosted directory -> age after which files under them are deleted
+    pub retention: BTreeMap<String, Duration>,
+    pub retention_dry_run: bool,
+    /// What replicates writes to the backup directory, if they're mirrored
+    pub write_replicator: Option<Arc<WriteReplicator>>,
+    /// Where encoded files are kept, if filesystem files are encoded
+    pub encoded_store: Option<Arc<dyn EncodedStore>>,
+    /// Where encoded files are kept instead, with `--encoded-store memory:BYTES`
+    pub encoded_memory: Option<Arc<EncodedMemory>>,
+    /// (min size, max size, min gain) for encoding filesystem files
+    pub encoding_thresholds: (u64, u64, f64),
+    /// How many files can be encoded at once
+    pub max_encodings: usize,
+    pub origin_temp_dir: Option<(String, PathBuf)>,
+    pub torrent_temp_dir: Option<(String, PathBuf)>,
+    pub mirror_temp_dir: Option<(String, PathBuf)>,
+    pub downloads_temp_dir: Option<(String, PathBuf)>,
+    /// How many times each file was downloaded, if counted
+    pub download_counts: Option<Arc<DownloadCounts>>,
+    /// Files kept open for serving, if they are
+    pub fd_cache: Option<Arc<FdCache>>,
+    /// Bytes sent by client and top-level directory, if accounted
+    bandwidth_accounts: Option<Arc<BandwidthAccounts>>,
+    /// Downloads and bytes sent against `--max-serves` and `--max-bytes`, if limited
+    pub serve_limits: Option<Arc<ServeLimits>>,
+    /// Stdin being buffered to the single file, if that's what's served
+    pub stdin: Option<Arc<StdinBuffer>>,
+    pub allowed_hosts: BTreeSet<String>,
+    /// Other hosts allowed to embed media files, if hotlinking is disallowed
+    pub hotlink_protection: Option<BTreeSet<String>>,
     pub proxies: BTreeMap<IpCidr, String>,
     pub mime_type_overrides: BTreeMap<String, Mime>,
+    pub cache_max_age: Option<Duration>,
+    pub cache_max_age_overrides: BTreeMap<String, Duration>,
+    pub range_window: Option<NonZeroU64>,
+    pub mmap_min_size: Option<NonZeroU64>,
+    pub serve_precompressed: bool,
+    /// Whether files' entity tags are their content hashes, also sent as `X-Content-Hash`
+    pub content_hash_etags: bool,
+    pub preloads: Vec<String>,
+    pub compression_policy: BTreeMap<String, bool>,
     pub additional_headers: Vec<(String, Vec<u8>)>,
-    cache_gen: RwLock<CacheT<Vec<u8>>>,
-    cache_fs: RwLock<CacheT<(PathBuf, bool)>>,
+    pub warm_up: WarmUp,
+    /// The TLS identity file, checked by readiness probes
+    pub tls_identity: Option<PathBuf>,
+    // Caches and locks are shared with clones, so all listeners and background threads hit the same ones
+    /// Largest total size of `cache_gen`
+    generated_cache_size: usize,
+    cache_gen: Arc<RwLock<GeneratedCache>>,
+    cache_fs: Arc<EncodedCacheT>,
+    cache_hash: Arc<HashCacheT>,
+    /// Part of the names of encoded files and the keys of torrent info, see `cache_fingerprint()`
+    cache_fingerprint: String,
+    encoding_slots: Arc<EncodingSlots>,
+    /// Encoding is paused until then after running out of space
+    encoding_paused_until: Arc<Mutex<Option<Instant>>>,
+    deploy_lock: Arc<Mutex<()>>,
+    sync_snapshots: SyncSnapshots,
+    webdav_locks: WebdavLocks,
+    uploads: Arc<ActiveUploads>,
+    origin_client: Option<Arc<Client>>,
+    origin_validated: OriginValidated,
 }
 
 impl HttpHandler {
@@ -142,7 +412,7 @@ impl HttpHandler {
             let creds = creds.as_ref()
                 .map(|auth| {
                     let mut itr = auth.split_terminator(':');
-                    (itr.next().unwrap().to_string(), itr.next().map(str::to_string))
+                    Credentials::Single(itr.next().unwrap().to_string(), itr.next().map(str::to_string))
                 });
 
             if path == "" {
@@ -151,30 +421,122 @@ impl HttpHandler {
                 path_auth_data.insert(path.to_string(), creds);
             }
         }
+        if let Some((_, users)) = opts.auth_file.as_ref() {
+            global_auth_data = Some(Credentials::Htpasswd(Arc::new(users.clone())));
+        }
 
+        let downloads_temp_dir = HttpHandler::temp_subdir(&opts.temp_directory, opts.download_counts, "downloads");
         HttpHandler {
             hosted_directory: opts.hosted_directory.clone(),
-            follow_symlinks: opts.follow_symlinks,
-            sandbox_symlinks: opts.sandbox_symlinks,
+            single_file: opts.single_file.clone(),
+            symlink_policy: opts.symlink_policy,
+            max_symlink_depth: opts.max_symlink_depth,
             generate_listings: opts.generate_listings,
+            listing_permissions: opts.listing_permissions,
+            listing_downloads: opts.listing_downloads,
             check_indices: opts.check_indices,
             strip_extensions: opts.strip_extensions,
-            log: (opts.loglevel < LogLevel::NoServeStatus, opts.log_colour),
+            normalise_unicode: opts.normalise_unicode,
+            serve_devices: opts.serve_devices,
+            log: (LogSwitch::new(opts.loglevel < LogLevel::NoServeStatus), opts.log_colour),
+            log_tags: opts.log_tags,
+            unlogged_paths: opts.unlogged_paths.clone(),
+            log_headers: LogSwitch::new(opts.log_headers),
             webdav: opts.webdav,
+            s3: opts.s3,
+            deploy: if opts.deploy { Some(opts.deploy_keep) } else { None },
+            delta_sync: opts.delta_sync,
+            torrent_min_size: opts.torrent_min_size,
+            offline_bundle: opts.offline_bundle,
+            xattrs: opts.xattrs,
+            admin: opts.admin,
+            inbox: opts.inbox,
+            method_override: opts.method_override,
+            origin: opts.origin.clone(),
+            origin_ttl: opts.origin_ttl.map(Duration::from_secs),
             global_auth_data: global_auth_data,
+            public_files: opts.public_files,
             path_auth_data: path_auth_data,
             writes_temp_dir: HttpHandler::temp_subdir(&opts.temp_directory, opts.allow_writes, "writes"),
-            encoded_temp_dir: HttpHandler::temp_subdir(&opts.temp_directory, opts.encode_fs, "encoded"),
+            hosted_temp_dir: HttpHandler::hosted_temp_dir(&opts.temp_directory.1, &opts.hosted_directory.1),
+            max_upload_size: opts.max_upload_size,
+            write_hours: opts.write_hours,
+            retention: opts.retention.clone(),
+            retention_dry_run: opts.retention_dry_run,
+            write_replicator: opts.mirror_writes.as_ref().map(|backup| {
+                Arc::new(WriteReplicator::new(opts.hosted_directory.1.clone(),
+                                              backup.clone(),
+                                              (opts.loglevel < LogLevel::NoServeStatus, opts.log_colour)))
+            }),
+            encoded_store: HttpHandler::temp_subdir(&opts.temp_directory, opts.encode_fs, "encoded").and_then(|td| new_encoded_store(&opts.encoded_store, td)),
+            encoded_memory: match opts.encoded_store {
+                EncodedStoreKind::Memory(max_size) if opts.encode_fs => Some(Arc::new(EncodedMemory::new(max_size))),
+                _ => None,
+            },
+            encoding_thresholds: (opts.min_encoding_size, opts.max_encoding_size, opts.min_encoding_gain as f64 / 100f64),
+            max_encodings: opts.max_encodings,
+            origin_temp_dir: HttpHandler::temp_subdir(&opts.temp_directory, opts.origin.is_some(), "origin"),
+            torrent_temp_dir: HttpHandler::temp_subdir(&opts.temp_directory, opts.torrent_min_size.is_some(), "torrent"),
+            mirror_temp_dir: HttpHandler::temp_subdir(&opts.temp_directory, opts.mirror.is_some(), "mirror"),
+            download_counts: downloads_temp_dir.as_ref().map(|(_, d)| Arc::new(DownloadCounts::load(opts.hosted_directory.1.clone(), d))),
+            fd_cache: opts.fd_cache.map(|n| Arc::new(FdCache::new(n.get()))),
+            downloads_temp_dir,
+            serve_limits: if opts.max_serves.is_some() || opts.max_bytes.is_some() {
+                Some(Arc::new(ServeLimits::new(opts.max_serves, opts.max_bytes)))
+            } else {
+                None
+            },
+            stdin: opts.single_file.as_ref().filter(|_| opts.stdin).map(|name| {
+                Arc::new(StdinBuffer::new(opts.hosted_directory.1.join(name), (opts.loglevel < LogLevel::NoServeStatus, opts.log_colour)))
+            }),
+            bandwidth_accounts: opts.bandwidth_window.map(|w| Arc::new(BandwidthAccounts::new(Duration::from_secs(w.get())))),
+            generated_cache_size: opts.generated_cache_size,
             cache_gen: Default::default(),
-            cache_fs: Default::default(),
+            cache_fs: Arc::new(LruMap::new(opts.encoded_cache_entries)),
+            cache_hash: Default::default(),
+            cache_fingerprint: HttpHandler::cache_fingerprint(),
+            encoding_slots: Default::default(),
+            encoding_paused_until: Default::default(),
+            deploy_lock: Default::default(),
+            sync_snapshots: Default::default(),
+            webdav_locks: Default::default(),
+            uploads: Default::default(),
+            origin_client: opts.origin.as_ref().map(|_| Arc::new(http_client())),
+            origin_validated: Default::default(),
+            allowed_hosts: opts.allowed_hosts.clone(),
+            hotlink_protection: opts.hotlink_protection.clone(),
             proxies: opts.proxies.clone(),
             mime_type_overrides: opts.mime_type_overrides.clone(),
+            cache_max_age: opts.cache_max_age,
+            cache_max_age_overrides: opts.cache_max_age_overrides.clone(),
+            range_window: opts.range_window,
+            mmap_min_size: opts.mmap_min_size,
+            serve_precompressed: opts.serve_precompressed,
+            content_hash_etags: opts.content_hash_etags,
+            preloads: opts.preloads.clone(),
+            compression_policy: opts.compression_policy.clone(),
             additional_headers: opts.additional_headers.clone(),
+            warm_up: Default::default(),
+            tls_identity: opts.tls_data.as_ref().map(|&((_, ref id), _)| id.clone()),
         }
     }
 
+    /// The temp subdirectories used with the current configuration, by purpose
+    pub fn temp_dirs(&self) -> BTreeMap<&'static str, PathBuf> {
+        let encoded_dir = self.encoded_store.as_ref().map(|s| s.dir().clone());
+        [("writes", &self.writes_temp_dir),
+         ("encoded", &encoded_dir),
+         ("origin", &self.origin_temp_dir),
+         ("torrent", &self.torrent_temp_dir),
+         ("mirror", &self.mirror_temp_dir),
+         ("downloads", &self.downloads_temp_dir)]
+            .iter()
+            .flat_map(|&(name, td)| td.as_ref().map(|td| (name, td.1.clone())))
+            .collect()
+    }
+
     pub fn clean_temp_dirs(temp_dir: &(String, PathBuf), loglevel: LogLevel, log_colour: bool) {
-        for (temp_name, temp_dir) in ["writes", "encoded", "tls"].iter().flat_map(|tn| HttpHandler::temp_subdir(temp_dir, true, tn)) {
+        for (temp_name, temp_dir) in ["writes", "encoded", "tls", "mirror", "origin", "torrent", "stdin"].iter().flat_map(|tn| HttpHandler::temp_subdir(temp_dir, true, tn)) {
             if temp_dir.exists() && fs::remove_dir_all(&temp_dir).is_ok() {
                 log!((loglevel < LogLevel::NoServeStatus, log_colour),
                      "Deleted temp dir {magenta}{}{reset}",
@@ -183,6 +545,15 @@ impl HttpHandler {
         }
     }
 
+    /// What of the temp directory is inside the hosted one: all of `--temp-dir`, unless that's the hosted directory itself
+    fn hosted_temp_dir(temp_dir: &Path, hosted_dir: &Path) -> Option<PathBuf> {
+        match temp_dir.parent() {
+            Some(parent) if parent != hosted_dir && parent.starts_with(hosted_dir) => Some(parent.to_path_buf()),
+            _ if temp_dir.starts_with(hosted_dir) => Some(temp_dir.to_path_buf()),
+            _ => None,
+        }
+    }
+
     fn temp_subdir(&(ref temp_name, ref temp_dir): &(String, PathBuf), flag: bool, name: &str) -> Option<(String, PathBuf)> {
         if flag {
             Some((format!("{}{}{}",
@@ -202,15 +573,124 @@ impl HttpHandler {
 
 impl Handler for HttpHandler {
     fn handle(&self, req: &mut Request) -> IronResult<Response> {
-        if self.global_auth_data.is_some() || !self.path_auth_data.is_empty() {
+        let unlogged = self.unlogged_requested(req);
+        if !self.log_tags && !unlogged {
+            let resp = self.handle_isolated(req);
+            self.log_headers(req, &resp);
+            return resp;
+        }
+
+        REQUEST_LOG.with(|held| *held.borrow_mut() = Some(vec![]));
+        let resp = self.handle_isolated(req);
+        self.log_headers(req, &resp);
+        let st = match resp {
+            Ok(ref resp) => resp.status,
+            Err(ref err) => err.response.status,
+        };
+        let held = REQUEST_LOG.with(|held| held.borrow_mut().take()).unwrap_or_default();
+        if unlogged && st.map(|s| !s.is_client_error() && !s.is_server_error()).unwrap_or(false) {
+            return resp;
+        }
+
+        let tag = format!("[{} {}]", req.method, st.map(|s| s.to_u16().to_string()).unwrap_or_else(|| "-".to_string()));
+        for (stamp, line) in held {
+            if self.log_tags {
+                println!("[{}] {} {}", stamp, tag, line);
+            } else {
+                print_log_line(&stamp, &line, self.log.1);
+            }
+        }
+        resp
+    }
+}
+
+impl HttpHandler {
+    fn handle_request(&self, req: &mut Request) -> IronResult<Response> {
+        let in_flight = match self.enter_serve_limits(req) {
+            Ok(in_flight) => in_flight,
+            Err(resp) => return resp,
+        };
+
+        if !self.allowed_hosts.is_empty() && !self.host_allowed(req) {
+            return self.handle_misdirected(req);
+        }
+
+        if self.method_override && req.method == method::Post && req.headers.get_raw(METHOD_OVERRIDE_HEADER).is_some() {
+            if let Some(resp) = self.override_method(req)? {
+                return Ok(resp);
+            }
+        }
+
+        let single_file_root = match self.single_file.as_ref() {
+            Some(file) if !self.admin_requested(req) => {
+                match self.route_single_file(req, file) {
+                    Ok(root) => root,
+                    Err(resp) => return resp,
+                }
+            }
+            _ => false,
+        };
+
+        if self.hosted_temp_dir.is_some() && !self.admin_requested(req) {
+            let (req_p, _, _) = self.parse_requested_path(req);
+            if self.in_temp_dir(&req_p) {
+                return self.handle_nonexistent(req, req_p);
+            }
+        }
+
+        if (self.global_auth_data.is_some() || !self.path_auth_data.is_empty()) && !(self.public_files && self.public_file_requested(req)) &&
+           !self.probe_requested(req) {
             if let Some(resp) = self.verify_auth(req)? {
                 return Ok(resp);
             }
         }
 
-        let mut resp = match req.method {
+        if self.outside_write_hours(req) && !self.admin_requested(req) {
+            return self.handle_outside_write_hours(req);
+        }
+
+        // Available while warming up, too
+        let mut resp = if self.admin_requested(req) {
+            self.handle_admin(req)
+        } else if !self.warm_up.done() {
+            self.handle_warming_up(req)
+        } else if self.stdin_pending() {
+            self.handle_stdin_pending(req)
+        } else if self.inbox {
+            self.handle_inbox(req)
+        } else if self.s3 && s3_request(&req.headers, req.url.query()) {
+            self.handle_s3(req)
+        } else {
+            self.handle_method(req)
+        }?;
+        if self.webdav {
+            resp.headers.set(Dav::LEVEL_2);
+        }
+        for (h, v) in &self.additional_headers {
+            resp.headers.append_raw(h.clone(), v.clone());
+        }
+        if resp.status.map(|s| s.is_success()).unwrap_or(false) && (resp.body.is_some() || req.method == method::Head) &&
+           !resp.headers.has::<headers::ContentEncoding>() &&
+           req.headers.get::<headers::AcceptEncoding>().map(|es| !identity_acceptable(es)).unwrap_or(false) {
+            resp = self.handle_not_acceptable(req)?;
+        }
+        if let Some(file) = self.single_file.as_ref().filter(|_| single_file_root && resp.status.map(|s| s.is_success()).unwrap_or(false)) {
+            self.name_single_file(&mut resp, file);
+        }
+        if !self.unlogged_requested(req) {
+            self.account_bandwidth(req, &mut resp);
+        }
+        self.limit_serving(in_flight, &mut resp);
+        Ok(resp)
+    }
+}
+
+impl HttpHandler {
+    fn handle_method(&self, req: &mut Request) -> IronResult<Response> {
+        match req.method {
             method::Options => self.handle_options(req),
             method::Get => self.handle_get(req),
+            method::Post => self.handle_post(req),
             method::Put => self.handle_put(req),
             method::Delete => self.handle_delete(req),
             method::Head => {
@@ -224,10 +704,13 @@ impl Handler for HttpHandler {
                 if self.webdav {
                     match &ext[..] {
                         "COPY" => self.handle_webdav_copy(req),
+                        "LOCK" => self.handle_webdav_lock(req),
                         "MKCOL" => self.handle_webdav_mkcol(req),
                         "MOVE" => self.handle_webdav_move(req),
                         "PROPFIND" => self.handle_webdav_propfind(req),
                         "PROPPATCH" => self.handle_webdav_proppatch(req),
+                        "REPORT" => self.handle_webdav_report(req),
+                        "UNLOCK" => self.handle_webdav_unlock(req),
 
                         _ => self.handle_bad_method(req),
                     }
@@ -236,23 +719,50 @@ impl Handler for HttpHandler {
                 }
             }
             _ => self.handle_bad_method(req),
-        }?;
-        if self.webdav {
-            resp.headers.set(Dav::LEVEL_1);
         }
-        for (h, v) in &self.additional_headers {
-            resp.headers.append_raw(h.clone(), v.clone());
+    }
+
+    /// Whether metadata's requested instead of content, with the raw filesystem API header or `?format=json`
+    fn raw_fs_requested(&self, req: &Request) -> bool {
+        req.headers.get().map(|r: &RawFsApiHeader| r.0).unwrap_or(false) || req.url.as_ref().query_pairs().any(|(k, v)| k == "format" && v == "json")
+    }
+
+    /// Whether this is a GET or HEAD that `handle_get()` would answer with a file or a directory's index file, not a listing
+    ///
+    /// Raw filesystem API and S3 requests get metadata or listings, even for files, so never are.
+    fn public_file_requested(&self, req: &Request) -> bool {
+        if !(req.method == method::Get || req.method == method::Head) || self.admin_requested(req) || self.raw_fs_requested(req) ||
+           (self.s3 && s3_request(&req.headers, req.url.query())) {
+            return false;
+        }
+
+        let (mut req_p, symlink, url_err) = self.parse_requested_path(req);
+        if url_err || (symlink && !self.symlink_policy.follow()) ||
+           (symlink && self.symlink_policy.sandboxed() && !is_descendant_of(&req_p, &self.hosted_directory.1)) {
+            return false;
+        }
+        if !req_p.exists() && req_p.extension().is_none() && self.strip_extensions {
+            if let Some(rp) = INDEX_EXTENSIONS.iter().map(|ext| req_p.with_extension(ext)).find(|rp| rp.exists()) {
+                req_p = rp;
+            }
+        }
+
+        match req_p.metadata() {
+            Ok(ref m) if is_actually_file(&m.file_type(), &req_p) => true,
+            Ok(ref m) if m.is_dir() => {
+                self.check_indices && req.url.as_ref().path_segments().and_then(|mut s| s.next_back()) == Some("") &&
+                self.offline_bundle_requested(req).is_none() &&
+                INDEX_EXTENSIONS.iter().any(|e| req_p.join("index").with_extension(e).is_file())
+            }
+            _ => false,
         }
-        Ok(resp)
     }
-}
 
-impl HttpHandler {
-    fn verify_auth(&self, req: &mut Request) -> IronResult<Option<Response>> {
+    /// The credentials needed under the specified URL path: the most specific `--path-auth` ones, or else the global ones
+    fn auth_for_path(&self, mut path: &str) -> Option<&Credentials> {
         let mut auth = self.global_auth_data.as_ref();
 
         if !self.path_auth_data.is_empty() {
-            let mut path = req.url.as_ref().path();
             if path.starts_with('/') {
                 path = &path[1..];
             }
@@ -270,7 +780,24 @@ impl HttpHandler {
             }
         }
 
-        let auth = if let Some(auth) = auth {
+        auth
+    }
+
+    /// Whether the request carries the credentials needed under the specified URL path, if any are
+    fn authorised_for_path(&self, req: &Request, path: &str) -> bool {
+        match self.auth_for_path(path) {
+            Some(auth) => {
+                match req.headers.get() {
+                    Some(headers::Authorization(headers::Basic { username, password })) => auth.accept(username, password),
+                    None => false,
+                }
+            }
+            None => true,
+        }
+    }
+
+    fn verify_auth(&self, req: &mut Request) -> IronResult<Option<Response>> {
+        let auth = if let Some(auth) = self.auth_for_path(req.url.as_ref().path()) {
             auth
         } else {
             return Ok(None);
@@ -278,13 +805,7 @@ impl HttpHandler {
 
         match req.headers.get() {
             Some(headers::Authorization(headers::Basic { username, password })) => {
-                let pwd = if password == &Some(String::new()) {
-                    &None
-                } else {
-                    password
-                };
-
-                if &auth.0 == username && &auth.1 == pwd {
+                if auth.accept(username, password) {
                     log!(self.log,
                          "{} correctly authorised to {red}{}{reset} {yellow}{}{reset}",
                          self.remote_addresses(&req),
@@ -320,25 +841,34 @@ impl HttpHandler {
     fn handle_options(&self, req: &mut Request) -> IronResult<Response> {
         log!(self.log, "{} asked for {red}OPTIONS{reset}", self.remote_addresses(&req));
 
-        let mut allowed_methods = Vec::with_capacity(6 +
+        let mut allowed_methods = Vec::with_capacity(7 +
                                                      if self.webdav {
-            DAV_LEVEL_1_METHODS.len()
+            DAV_LEVEL_2_METHODS.len()
         } else {
             0
         });
-        allowed_methods.extend_from_slice(&[method::Options, method::Get, method::Put, method::Delete, method::Head, method::Trace]);
+        allowed_methods.extend_from_slice(&[method::Options, method::Get, method::Post, method::Put, method::Delete, method::Head, method::Trace]);
         if self.webdav {
-            allowed_methods.extend_from_slice(&DAV_LEVEL_1_METHODS);
+            allowed_methods.extend_from_slice(&DAV_LEVEL_2_METHODS);
         }
 
-        Ok(Response::with((status::NoContent, Header(headers::Server(USER_AGENT.to_string())), Header(headers::Allow(allowed_methods)))))
+        let mut resp = Response::with((status::NoContent, Header(headers::Server(USER_AGENT.to_string())), Header(headers::Allow(allowed_methods))));
+        if self.webdav {
+            // Windows' WebDAV redirector only tries authoring after seeing this
+            resp.headers.set_raw("MS-Author-Via", vec![b"DAV".to_vec()]);
+        }
+        let (req_p, _, url_err) = self.parse_requested_path(req);
+        if !url_err {
+            self.set_available_space(&mut resp, &req_p);
+        }
+        Ok(resp)
     }
 
     fn handle_get(&self, req: &mut Request) -> IronResult<Response> {
         let (mut req_p, symlink, url_err) = self.parse_requested_path(req);
 
         if url_err {
-            return self.handle_invalid_url(req, "<p>Percent-encoding decoded to invalid UTF-8.</p>");
+            return self.handle_invalid_url(req, "<p>Percent-encoding decoded to invalid UTF-8 or a path separator.</p>");
         }
 
         if !req_p.exists() && req_p.extension().is_none() && self.strip_extensions {
@@ -347,25 +877,56 @@ impl HttpHandler {
             }
         }
 
-        if !req_p.exists() || (symlink && !self.follow_symlinks) ||
-           (symlink && self.follow_symlinks && self.sandbox_symlinks && !is_descendant_of(&req_p, &self.hosted_directory.1)) {
+        if !symlink && self.origin.is_some() {
+            if !req_p.exists() {
+                self.fetch_from_origin(req, &req_p);
+            } else if req_p.is_file() {
+                self.revalidate_from_origin(req, &req_p);
+            }
+        }
+
+        if !req_p.exists() || (symlink && !self.symlink_policy.follow()) ||
+           (symlink && self.symlink_policy.sandboxed() && !is_descendant_of(&req_p, &self.hosted_directory.1)) {
             return self.handle_nonexistent(req, req_p);
         }
 
-        let is_file = is_actually_file(&req_p.metadata().expect("Failed to get file metadata").file_type(), &req_p);
+        let file_type = try_fs!(self, req, req_p.metadata(), "getting its metadata").file_type();
+        if is_device(&file_type) && !self.serve_devices {
+            return self.handle_get_device(req, req_p);
+        }
+
+        let is_file = is_actually_file(&file_type, &req_p);
+        if is_file && self.hotlink_protection.is_some() && self.hotlinked(req, &req_p) {
+            return self.handle_hotlinked(req, req_p);
+        }
+
         let range = req.headers.get().map(|r: &headers::Range| (*r).clone());
-        let raw_fs = req.headers.get().map(|r: &RawFsApiHeader| r.0).unwrap_or(false);
+        let raw_fs = self.raw_fs_requested(req);
         if is_file {
+            if !raw_fs && req.method == method::Get && !self.torrent_requested(req, &req_p) && !self.unlogged_requested(req) {
+                self.count_download(&req_p, range.as_ref());
+            }
+
             if raw_fs {
                 self.handle_get_raw_fs_file(req, req_p)
-            } else if range.is_some() {
-                self.handle_get_file_range(req, req_p, range.unwrap())
+            } else if self.torrent_requested(req, &req_p) {
+                self.handle_get_file_torrent(req, req_p)
             } else {
-                self.handle_get_file(req, req_p)
+                let max_age = self.cache_max_age_for(&req_p);
+                let etag = self.entity_tag(&req_p);
+                let resp = if range.is_some() {
+                    self.handle_get_file_range(req, req_p, range.unwrap())
+                } else {
+                    self.handle_get_file(req, req_p)
+                };
+                resp.map(|r| self.with_content_hash(req, with_etag(with_cache_headers(r, max_age), etag.clone()), etag.as_ref()))
+                    .map(|r| self.with_preloads(r))
             }
         } else {
             if raw_fs {
                 self.handle_get_raw_fs_dir(req, req_p)
+            } else if let Some(part) = self.offline_bundle_requested(req) {
+                self.handle_get_offline_bundle(req, req_p, part)
             } else {
                 self.handle_get_dir(req, req_p)
             }
@@ -380,9 +941,9 @@ impl HttpHandler {
              req.url,
              &cause[3..cause.len() - 4]); // Strip <p> tags
 
-        self.handle_generated_response_encoding(req,
-                                                status::BadRequest,
-                                                html_response(ERROR_HTML, &["400 Bad Request", "The request URL was invalid.", cause]))
+        self.handle_generated_error(req,
+                                    status::BadRequest,
+                                    &["400 Bad Request", "The request URL was invalid.", cause])
     }
 
     #[inline(always)]
@@ -398,10 +959,9 @@ impl HttpHandler {
              req_p.display());
 
         let url_p = url_path(&req.url);
-        self.handle_generated_response_encoding(req,
-                                                status,
-                                                html_response(ERROR_HTML,
-                                                              &[&status.to_string()[..], &format!("The requested entity \"{}\" doesn't exist.", url_p), ""]))
+        self.handle_generated_error(req,
+                                    status,
+                                    &[&status.to_string()[..], &format!("The requested entity \"{}\" doesn't exist.", url_p), ""])
     }
 
     fn handle_get_raw_fs_file(&self, req: &mut Request, req_p: PathBuf) -> IronResult<Response> {
@@ -414,42 +974,105 @@ impl HttpHandler {
                                             writes_supported: self.writes_temp_dir.is_some(),
                                             is_root: false,
                                             is_file: true,
-                                            files: vec![get_raw_fs_metadata(&req_p)],
+                                            files: vec![try_fs!(self, req, get_raw_fs_metadata(&req_p), "getting its metadata")],
                                         })
     }
 
     fn handle_get_file_range(&self, req: &mut Request, req_p: PathBuf, range: headers::Range) -> IronResult<Response> {
-        match range {
+        // Resumed compressed downloads want the rest of the same encoded representation
+        let encoded = self.cached_encoded_file(req, &req_p);
+        let data_p = encoded.as_ref().map(|e| e.0.clone()).unwrap_or_else(|| req_p.clone());
+        // Ones that aren't encoded yet would be later
+        let negotiated = encoded.is_some() ||
+                         self.should_encode(&req_p,
+                                            &self.guess_mime_type(&req_p),
+                                            file_length(&try_fs!(self, req, req_p.metadata(), "getting its metadata"), &req_p));
+
+        let resp = match range {
             headers::Range::Bytes(ref brs) => {
                 if brs.len() == 1 {
-                    let flen = file_length(&req_p.metadata().expect("Failed to get requested file metadata"), &req_p);
+                    let flen = file_length(&try_fs!(self, req, data_p.metadata(), "getting its metadata"), &data_p);
                     match brs[0] {
+                        // Nothing can be satisfied from an empty file, and neither can anything starting past the end
+                        headers::ByteRangeSpec::FromTo(from, _) |
+                        headers::ByteRangeSpec::AllFrom(from) if from >= flen => self.handle_get_file_unsatisfiable_range(req, req_p, &range, flen),
+                        headers::ByteRangeSpec::Last(0) => self.handle_get_file_unsatisfiable_range(req, req_p, &range, flen),
+                        headers::ByteRangeSpec::Last(_) if flen == 0 => self.handle_get_file_unsatisfiable_range(req, req_p, &range, flen),
+
                         // Cases where from is bigger than to are filtered out by iron so can never happen
-                        headers::ByteRangeSpec::FromTo(from, to) => self.handle_get_file_closed_range(req, req_p, from, to),
-                        headers::ByteRangeSpec::AllFrom(from) => {
-                            if flen < from {
-                                self.handle_get_file_empty_range(req, req_p, from, flen)
-                            } else {
-                                self.handle_get_file_right_opened_range(req, req_p, from)
-                            }
-                        }
-                        headers::ByteRangeSpec::Last(from) => {
-                            if flen < from {
-                                self.handle_get_file_empty_range(req, req_p, from, flen)
-                            } else {
-                                self.handle_get_file_left_opened_range(req, req_p, from)
-                            }
+                        headers::ByteRangeSpec::FromTo(from, to) => {
+                            let to = self.widened_range_end(from, cmp::min(to, flen - 1), flen);
+                            self.handle_get_file_closed_range(req, req_p, &data_p, from, to)
                         }
+                        headers::ByteRangeSpec::AllFrom(from) => self.handle_get_file_right_opened_range(req, req_p, &data_p, from),
+                        // RFC 7233, section 2.1: suffixes longer than the file select all of it
+                        headers::ByteRangeSpec::Last(from) => self.handle_get_file_left_opened_range(req, req_p, &data_p, cmp::min(from, flen)),
                     }
                 } else {
-                    self.handle_invalid_range(req, req_p, &range, "More than one range is unsupported.")
+                    self.handle_get_file_ranges(req, req_p, &data_p, &range, brs)
                 }
             }
             headers::Range::Unregistered(..) => self.handle_invalid_range(req, req_p, &range, "Custom ranges are unsupported."),
+        };
+
+        match encoded {
+            Some((_, encoding)) => {
+                resp.map(|mut r| {
+                    if r.status == Some(status::PartialContent) {
+                        log!(self.log,
+                             "{} from the {} encoded file",
+                             Spaces(self.remote_addresses(req).width()),
+                             encoding);
+                        r.headers.set(headers::ContentEncoding(vec![encoding]));
+                    }
+                    vary_encoding(r)
+                })
+            }
+            None if negotiated => resp.map(vary_encoding),
+            None => resp,
+        }
+    }
+
+    /// Get the already-encoded version of the specified file and its encoding, if the client accepts it:
+    /// its precompressed sibling, or its cached encoding
+    ///
+    /// Files that weren't hashed yet can't have been encoded either, so they aren't read just to find that out.
+    fn cached_encoded_file(&self, req: &Request, req_p: &Path) -> Option<(PathBuf, headers::Encoding)> {
+        if let Some(sibling) = self.precompressed_sibling(req, req_p) {
+            return Some(sibling);
+        }
+        let encoding = req.headers.get::<headers::AcceptEncoding>().and_then(|es| response_encoding(es))?;
+        let cache_key = (self.known_file_hash(req_p)?, encoding.to_string());
+        self.cached_encoding(req_p, &cache_key).and_then(|e| e).map(|resp_p| (resp_p, encoding))
+    }
+
+    /// The `cache_fs` entry for the specified file, unless it changed since it was made or its encoded file is gone
+    fn cached_encoding(&self, req_p: &Path, cache_key: &(blake3::Hash, String)) -> Option<Option<PathBuf>> {
+        let (resp_p, mtime, len) = self.cache_fs.get(cache_key)?;
+        let meta = req_p.metadata().ok()?;
+        if meta.modified().ok()? != mtime || meta.len() != len {
+            return None;
+        }
+        match resp_p {
+            // Encoded files can be removed by cache checks
+            Some(resp_p) if !resp_p.exists() => None,
+            resp_p => Some(resp_p),
+        }
+    }
+
+    /// Remember the encoded version of the specified file, or `None` if it's not worth encoding, as of its current state
+    ///
+    /// The encoded files of the entries this evicts are deleted, unless other servers might be using them.
+    fn cache_encoding(&self, req_p: &Path, cache_key: (blake3::Hash, String), resp_p: Option<PathBuf>) {
+        if let Some((mtime, len)) = req_p.metadata().ok().and_then(|m| m.modified().ok().map(|mt| (mt, m.len()))) {
+            let shared = self.encoded_store.as_ref().map(|s| s.shared()).unwrap_or(false);
+            for evicted in self.cache_fs.insert(cache_key, (resp_p, mtime, len)).into_iter().flat_map(|(p, _, _)| p).filter(|_| !shared) {
+                let _ = fs::remove_file(evicted);
+            }
         }
     }
 
-    fn handle_get_file_closed_range(&self, req: &mut Request, req_p: PathBuf, from: u64, to: u64) -> IronResult<Response> {
+    fn handle_get_file_closed_range(&self, req: &mut Request, req_p: PathBuf, data_p: &Path, from: u64, to: u64) -> IronResult<Response> {
         let mime_type = self.guess_mime_type(&req_p);
         log!(self.log,
              "{} was served byte range {}-{} of file {magenta}{}{reset} as {blue}{}{reset}",
@@ -459,24 +1082,25 @@ impl HttpHandler {
              req_p.display(),
              mime_type);
 
-        let mut buf = vec![0; (to + 1 - from) as usize];
-        let mut f = File::open(&req_p).expect("Failed to open requested file");
-        f.seek(SeekFrom::Start(from)).expect("Failed to seek requested file");
-        f.read_exact(&mut buf).expect("Failed to read requested file");
+        // Streamed, not buffered, since the range can be as big as the file
+        let f = try_fs!(self, req, self.open_served_file(data_p), "opening it");
+        let flen = file_length(&try_fs!(self, req, f.metadata(), "getting its metadata"), &data_p);
+        let body = try_fs!(self, req, self.file_body(f, from, to + 1 - from), "seeking in it");
 
         Ok(Response::with((status::PartialContent,
                            (Header(headers::Server(USER_AGENT.to_string())),
                             Header(headers::LastModified(headers::HttpDate(file_time_modified_p(&req_p)))),
                             Header(headers::ContentRange(headers::ContentRangeSpec::Bytes {
                                 range: Some((from, to)),
-                                instance_length: Some(file_length(&f.metadata().expect("Failed to get requested file metadata"), &req_p)),
+                                instance_length: Some(flen),
                             })),
+                            Header(headers::ContentLength(to + 1 - from)),
                             Header(headers::AcceptRanges(vec![headers::RangeUnit::Bytes]))),
-                           buf,
+                           body,
                            mime_type)))
     }
 
-    fn handle_get_file_right_opened_range(&self, req: &mut Request, req_p: PathBuf, from: u64) -> IronResult<Response> {
+    fn handle_get_file_right_opened_range(&self, req: &mut Request, req_p: PathBuf, data_p: &Path, from: u64) -> IronResult<Response> {
         let mime_type = self.guess_mime_type(&req_p);
         log!(self.log,
              "{} was served file {magenta}{}{reset} from byte {} as {blue}{}{reset}",
@@ -485,11 +1109,11 @@ impl HttpHandler {
              from,
              mime_type);
 
-        let flen = file_length(&req_p.metadata().expect("Failed to get requested file metadata"), &req_p);
-        self.handle_get_file_opened_range(req_p, SeekFrom::Start(from), from, flen - from, mime_type)
+        let flen = file_length(&try_fs!(self, req, data_p.metadata(), "getting its metadata"), &data_p);
+        self.handle_get_file_opened_range(req, req_p, data_p, from, flen - from, mime_type)
     }
 
-    fn handle_get_file_left_opened_range(&self, req: &mut Request, req_p: PathBuf, from: u64) -> IronResult<Response> {
+    fn handle_get_file_left_opened_range(&self, req: &mut Request, req_p: PathBuf, data_p: &Path, from: u64) -> IronResult<Response> {
         let mime_type = self.guess_mime_type(&req_p);
         log!(self.log,
              "{} was served last {} bytes of file {magenta}{}{reset} as {blue}{}{reset}",
@@ -498,20 +1122,20 @@ impl HttpHandler {
              req_p.display(),
              mime_type);
 
-        let flen = file_length(&req_p.metadata().expect("Failed to get requested file metadata"), &req_p);
-        self.handle_get_file_opened_range(req_p, SeekFrom::End(-(from as i64)), flen - from, from, mime_type)
+        let flen = file_length(&try_fs!(self, req, data_p.metadata(), "getting its metadata"), &data_p);
+        self.handle_get_file_opened_range(req, req_p, data_p, flen - from, from, mime_type)
     }
 
-    fn handle_get_file_opened_range(&self, req_p: PathBuf, s: SeekFrom, b_from: u64, clen: u64, mt: Mime) -> IronResult<Response> {
-        let mut f = File::open(&req_p).expect("Failed to open requested file");
-        let fmeta = f.metadata().expect("Failed to get requested file metadata");
-        let flen = file_length(&fmeta, &req_p);
-        f.seek(s).expect("Failed to seek requested file");
+    fn handle_get_file_opened_range(&self, req: &mut Request, req_p: PathBuf, data_p: &Path, b_from: u64, clen: u64, mt: Mime) -> IronResult<Response> {
+        let f = try_fs!(self, req, self.open_served_file(data_p), "opening it");
+        let fmeta = try_fs!(self, req, f.metadata(), "getting its metadata");
+        let flen = file_length(&fmeta, &data_p);
+        let body = try_fs!(self, req, self.file_body(f, b_from, flen - b_from), "seeking in it");
 
         Ok(Response::with((status::PartialContent,
-                           f,
+                           body,
                            (Header(headers::Server(USER_AGENT.to_string())),
-                            Header(headers::LastModified(headers::HttpDate(file_time_modified(&fmeta)))),
+                            Header(headers::LastModified(headers::HttpDate(file_time_modified_p(&req_p)))),
                             Header(headers::ContentRange(headers::ContentRangeSpec::Bytes {
                                 range: Some((b_from, flen - 1)),
                                 instance_length: Some(flen),
@@ -522,33 +1146,29 @@ impl HttpHandler {
     }
 
     fn handle_invalid_range(&self, req: &mut Request, req_p: PathBuf, range: &headers::Range, reason: &str) -> IronResult<Response> {
-        self.handle_generated_response_encoding(req,
-                                                status::RangeNotSatisfiable,
-                                                html_response(ERROR_HTML,
-                                                              &["416 Range Not Satisfiable",
-                                                                &format!("Requested range <samp>{}</samp> could not be fulfilled for file {}.",
-                                                                         range,
-                                                                         req_p.display()),
-                                                                reason]))
+        self.handle_generated_error(req,
+                                    status::RangeNotSatisfiable,
+                                    &["416 Range Not Satisfiable",
+                                      &format!("Requested range <samp>{}</samp> could not be fulfilled for file {}.",
+                                               range,
+                                               req_p.display()),
+                                      reason])
     }
 
-    fn handle_get_file_empty_range(&self, req: &mut Request, req_p: PathBuf, from: u64, to: u64) -> IronResult<Response> {
-        let mime_type = self.guess_mime_type(&req_p);
+    fn handle_get_file_unsatisfiable_range(&self, req: &mut Request, req_p: PathBuf, range: &headers::Range, flen: u64) -> IronResult<Response> {
         log!(self.log,
-             "{} was served an empty range from file {magenta}{}{reset} as {blue}{}{reset}",
+             "{} requested unsatisfiable range {} of {}B file {magenta}{}{reset}",
              self.remote_addresses(&req),
-             req_p.display(),
-             mime_type);
+             range,
+             flen,
+             req_p.display());
 
-        Ok(Response::with((status::NoContent,
-                           Header(headers::Server(USER_AGENT.to_string())),
-                           Header(headers::LastModified(headers::HttpDate(file_time_modified_p(&req_p)))),
-                           Header(headers::ContentRange(headers::ContentRangeSpec::Bytes {
-                               range: Some((from, to)),
-                               instance_length: Some(file_length(&req_p.metadata().expect("Failed to get requested file metadata"), &req_p)),
-                           })),
-                           Header(headers::AcceptRanges(vec![headers::RangeUnit::Bytes])),
-                           mime_type)))
+        let mut resp = self.handle_invalid_range(req, req_p, range, "")?;
+        resp.headers.set(headers::ContentRange(headers::ContentRangeSpec::Bytes {
+            range: None,
+            instance_length: Some(flen),
+        }));
+        Ok(resp)
     }
 
     fn handle_get_file(&self, req: &mut Request, req_p: PathBuf) -> IronResult<Response> {
@@ -559,14 +1179,15 @@ impl HttpHandler {
              req_p.display(),
              mime_type);
 
-        let metadata = req_p.metadata().expect("Failed to get requested file metadata");
+        let metadata = try_fs!(self, req, req_p.metadata(), "getting its metadata");
         let flen = file_length(&metadata, &req_p);
-        if self.encoded_temp_dir.is_some() && flen > MIN_ENCODING_SIZE && flen < MAX_ENCODING_SIZE &&
-           req_p.extension().and_then(|s| s.to_str()).map(|s| !BLACKLISTED_ENCODING_EXTENSIONS.contains(&UniCase::new(s))).unwrap_or(true) {
-            self.handle_get_file_encoded(req, req_p, mime_type)
+        if let Some((sibling, encoding)) = self.precompressed_sibling(req, &req_p) {
+            self.handle_get_file_precompressed(req, req_p, sibling, encoding, mime_type).map(vary_encoding)
+        } else if self.should_encode(&req_p, &mime_type, flen) {
+            self.handle_get_file_encoded(req, req_p, mime_type).map(vary_encoding)
         } else {
-            let file = match File::open(&req_p) {
-                Ok(file) => file,
+            let file = match self.open_served_file(&req_p) {
+                Ok(file) => try_fs!(self, req, self.file_body(file, 0, flen), "opening it"),
                 Err(err) => return self.handle_requested_entity_unopenable(req, err, "file"),
             };
             Ok(Response::with((status::Ok,
@@ -574,138 +1195,392 @@ impl HttpHandler {
                                 Header(headers::LastModified(headers::HttpDate(file_time_modified(&metadata)))),
                                 Header(headers::AcceptRanges(vec![headers::RangeUnit::Bytes]))),
                                file,
-                               Header(headers::ContentLength(file_length(&metadata, &req_p))),
+                               Header(headers::ContentLength(flen)),
                                mime_type)))
         }
     }
 
     fn handle_get_file_encoded(&self, req: &mut Request, req_p: PathBuf, mt: Mime) -> IronResult<Response> {
-        if let Some(encoding) = req.headers.get_mut::<headers::AcceptEncoding>().and_then(|es| response_encoding(&mut **es)) {
-            self.create_temp_dir(&self.encoded_temp_dir);
+        if let Some(encoding) = req.headers.get::<headers::AcceptEncoding>().and_then(|es| response_encoding(&es)) {
+            if let Some(memory) = self.encoded_memory.as_ref() {
+                return self.handle_get_file_encoded_in_memory(req, req_p, mt, encoding, memory);
+            }
+            self.create_temp_dir(&self.encoded_store.as_ref().map(|s| s.dir().clone()));
 
-            let cache_key = match file_hash(&req_p) {
+            let cache_key = match self.cached_file_hash(&req_p) {
                 Ok(h) => (h, encoding.to_string()),
                 Err(err) => return self.handle_requested_entity_unopenable(req, err, "file"),
             };
 
             {
-                match self.cache_fs.read().expect("Filesystem cache read lock poisoned").get(&cache_key) {
-                    Some(&(ref resp_p, true)) => {
+                match self.cached_encoding(&req_p, &cache_key) {
+                    Some(Some(resp_p)) => {
+                        let flen = file_length(&try_fs!(self, req, req_p.metadata(), "getting its metadata"), &req_p);
+                        let body = try_fs!(self, req, self.served_file_body(&resp_p), "opening its encoded file");
                         log!(self.log,
                              "{} encoded as {} for {:.1}% ratio (cached)",
-                             Spaces(self.remote_addresses(req).to_string().len()),
+                             Spaces(self.remote_addresses(req).width()),
                              encoding,
-                             ((file_length(&req_p.metadata().expect("Failed to get requested file metadata"), &req_p) as f64) /
-                              (file_length(&resp_p.metadata().expect("Failed to get encoded file metadata"), &resp_p) as f64)) *
-                             100f64);
+                             (flen as f64) / (((body.1).0).0 as f64) * 100f64);
 
                         return Ok(Response::with((status::Ok,
                                                   Header(headers::Server(USER_AGENT.to_string())),
                                                   Header(headers::ContentEncoding(vec![encoding])),
                                                   Header(headers::AcceptRanges(vec![headers::RangeUnit::Bytes])),
-                                                  resp_p.as_path(),
-                                                  mt)));
-                    }
-                    Some(&(ref resp_p, false)) => {
-                        return Ok(Response::with((status::Ok,
-                                                  Header(headers::Server(USER_AGENT.to_string())),
-                                                  Header(headers::LastModified(headers::HttpDate(file_time_modified_p(resp_p)))),
-                                                  Header(headers::AcceptRanges(vec![headers::RangeUnit::Bytes])),
-                                                  resp_p.as_path(),
+                                                  body,
                                                   mt)));
                     }
+                    Some(None) => return self.handle_get_file_identity(req, req_p, mt),
                     None => (),
                 }
             }
 
-            let mut resp_p = self.encoded_temp_dir.as_ref().unwrap().1.join(cache_key.0.to_hex().as_str());
-            match (req_p.extension(), encoding_extension(&encoding)) {
-                (Some(ext), Some(enc)) => resp_p.set_extension(format!("{}.{}", ext.to_str().unwrap_or("ext"), enc)),
-                (Some(ext), None) => resp_p.set_extension(format!("{}.{}", ext.to_str().unwrap_or("ext"), encoding)),
-                (None, Some(enc)) => resp_p.set_extension(enc),
-                (None, None) => resp_p.set_extension(format!("{}", encoding)),
+            // Someone else is already encoding this file, so it'll be cached for the next request
+            let _slot = match self.encoding_slots.acquire(&cache_key, self.max_encodings) {
+                Some(slot) => slot,
+                None => {
+                    log!(self.log,
+                         "{} already being encoded as {}, sending identity",
+                         Spaces(self.remote_addresses(req).width()),
+                         encoding);
+                    return self.handle_get_file_identity(req, req_p, mt);
+                }
             };
 
-            if encode_file(&req_p, &resp_p, &encoding) {
-                let gain = (file_length(&req_p.metadata().expect("Failed to get requested file metadata"), &req_p) as f64) /
-                           (file_length(&resp_p.metadata().expect("Failed to get encoded file metadata"), &resp_p) as f64);
-                if gain < MIN_ENCODING_GAIN {
-                    let mut cache = self.cache_fs.write().expect("Filesystem cache write lock poisoned");
-                    cache.insert(cache_key, (req_p.clone(), false));
-                    fs::remove_file(resp_p).expect("Failed to remove too big encoded file");
+            // Someone else might've finished encoding it while we waited for a slot
+            if let Some(Some(resp_p)) = self.cached_encoding(&req_p, &cache_key) {
+                return Ok(Response::with((status::Ok,
+                                          Header(headers::Server(USER_AGENT.to_string())),
+                                          Header(headers::ContentEncoding(vec![encoding])),
+                                          Header(headers::AcceptRanges(vec![headers::RangeUnit::Bytes])),
+                                          try_fs!(self, req, self.served_file_body(&resp_p), "opening its encoded file"),
+                                          mt)));
+            }
+
+            // Precompressed files are already there
+            let resp_p = self.encoded_path(&req_p, &cache_key.0, &encoding);
+            let precompressed = resp_p.exists();
+            let encoded = if precompressed {
+                Ok(())
+            } else {
+                encode_file(&req_p, &resp_p, &encoding)
+            };
+            if encoded.is_ok() {
+                let gain = (file_length(&try_fs!(self, req, req_p.metadata(), "getting its metadata"), &req_p) as f64) /
+                           (file_length(&try_fs!(self, req, resp_p.metadata(), "getting its encoded file's metadata"), &resp_p) as f64);
+                if gain < self.encoding_thresholds.2 {
+                    self.cache_encoding(&req_p, cache_key, None);
+                    // Only wastes space if it stays
+                    let _ = fs::remove_file(resp_p);
+                } else if !precompressed && !self.encoded_store.as_ref().unwrap().stored(&resp_p) {
+                    log!(self.log,
+                         "{} encoded as {} too big for the encoded store, sending identity",
+                         Spaces(self.remote_addresses(req).width()),
+                         encoding);
+                    self.cache_encoding(&req_p, cache_key, None);
                 } else {
                     log!(self.log,
                          "{} encoded as {} for {:.1}% ratio",
-                         Spaces(self.remote_addresses(req).to_string().len()),
+                         Spaces(self.remote_addresses(req).width()),
                          encoding,
                          gain * 100f64);
 
-                    let mut cache = self.cache_fs.write().expect("Filesystem cache write lock poisoned");
-                    cache.insert(cache_key, (resp_p.clone(), true));
+                    self.cache_encoding(&req_p, cache_key, Some(resp_p.clone()));
 
                     return Ok(Response::with((status::Ok,
                                               Header(headers::Server(USER_AGENT.to_string())),
                                               Header(headers::ContentEncoding(vec![encoding])),
                                               Header(headers::AcceptRanges(vec![headers::RangeUnit::Bytes])),
-                                              resp_p.as_path(),
+                                              try_fs!(self, req, self.served_file_body(&resp_p), "opening its encoded file"),
                                               mt)));
                 }
+            } else if encoded.as_ref().err().map(is_storage_full).unwrap_or(false) {
+                log!(self.log,
+                     "{} ran out of space encoding as {}, sending identity",
+                     Spaces(self.remote_addresses(req).width()),
+                     encoding);
+                self.pause_encoding();
             } else {
                 log!(self.log,
                      "{} failed to encode as {}, sending identity",
-                     Spaces(self.remote_addresses(req).to_string().len()),
+                     Spaces(self.remote_addresses(req).width()),
                      encoding);
             }
         }
 
+        self.handle_get_file_identity(req, req_p, mt)
+    }
+
+    fn handle_get_file_encoded_in_memory(&self, req: &mut Request, req_p: PathBuf, mt: Mime, encoding: headers::Encoding, memory: &EncodedMemory)
+                                         -> IronResult<Response> {
+        let cache_key = match self.cached_file_hash(&req_p) {
+            Ok(h) => (h, encoding.to_string()),
+            Err(err) => return self.handle_requested_entity_unopenable(req, err, "file"),
+        };
+        let flen = file_length(&try_fs!(self, req, req_p.metadata(), "getting its metadata"), &req_p);
+
+        if let Some(data) = memory.get(&cache_key) {
+            log!(self.log,
+                 "{} encoded as {} for {:.1}% ratio (cached in memory)",
+                 Spaces(self.remote_addresses(req).width()),
+                 encoding,
+                 (flen as f64) / (data.len() as f64) * 100f64);
+            return Ok(Response::with((status::Ok,
+                                      Header(headers::Server(USER_AGENT.to_string())),
+                                      Header(headers::ContentEncoding(vec![encoding])),
+                                      Header(headers::AcceptRanges(vec![headers::RangeUnit::Bytes])),
+                                      (*data).clone(),
+                                      mt)));
+        }
+        if let Some(None) = self.cached_encoding(&req_p, &cache_key) {
+            return self.handle_get_file_identity(req, req_p, mt);
+        }
+
+        let _slot = match self.encoding_slots.acquire(&cache_key, self.max_encodings) {
+            Some(slot) => slot,
+            None => {
+                log!(self.log,
+                     "{} already being encoded as {}, sending identity",
+                     Spaces(self.remote_addresses(req).width()),
+                     encoding);
+                return self.handle_get_file_identity(req, req_p, mt);
+            }
+        };
+
+        let encoded = File::open(&req_p).and_then(|mut f| {
+            let mut enc = StreamEncoder::new(vec![], &encoding).ok_or_else(|| IoError::new(IoErrorKind::InvalidInput, "unknown encoding"))?;
+            io::copy(&mut f, &mut enc)?;
+            enc.finish()
+        });
+        match encoded {
+            Ok(data) => {
+                let gain = (flen as f64) / (data.len() as f64);
+                if gain < self.encoding_thresholds.2 {
+                    self.cache_encoding(&req_p, cache_key, None);
+                } else if !memory.insert(cache_key.clone(), data.clone()) {
+                    log!(self.log,
+                         "{} encoded as {} too big for the encoded store, sending identity",
+                         Spaces(self.remote_addresses(req).width()),
+                         encoding);
+                    self.cache_encoding(&req_p, cache_key, None);
+                } else {
+                    log!(self.log,
+                         "{} encoded as {} for {:.1}% ratio",
+                         Spaces(self.remote_addresses(req).width()),
+                         encoding,
+                         gain * 100f64);
+
+                    return Ok(Response::with((status::Ok,
+                                              Header(headers::Server(USER_AGENT.to_string())),
+                                              Header(headers::ContentEncoding(vec![encoding])),
+                                              Header(headers::AcceptRanges(vec![headers::RangeUnit::Bytes])),
+                                              data,
+                                              mt)));
+                }
+            }
+            Err(_) => {
+                log!(self.log,
+                     "{} failed to encode as {}, sending identity",
+                     Spaces(self.remote_addresses(req).width()),
+                     encoding);
+            }
+        }
+
+        self.handle_get_file_identity(req, req_p, mt)
+    }
+
+    /// `<hash>-<fingerprint>[.<source extension>].<encoding extension>` in the encoded store
+    fn encoded_path(&self, req_p: &Path, hash: &blake3::Hash, encoding: &headers::Encoding) -> PathBuf {
+        let mut resp_p = self.encoded_store.as_ref().unwrap().dir().1.join(format!("{}-{}", hash.to_hex(), self.cache_fingerprint));
+        match (req_p.extension(), encoding_extension(&encoding)) {
+            (Some(ext), Some(enc)) => resp_p.set_extension(format!("{}.{}", ext.to_str().unwrap_or("ext"), enc)),
+            (Some(ext), None) => resp_p.set_extension(format!("{}.{}", ext.to_str().unwrap_or("ext"), encoding)),
+            (None, Some(enc)) => resp_p.set_extension(enc),
+            (None, None) => resp_p.set_extension(format!("{}", encoding)),
+        };
+        resp_p
+    }
+
+    fn handle_get_file_identity(&self, req: &mut Request, req_p: PathBuf, mt: Mime) -> IronResult<Response> {
+        let body = try_fs!(self, req, self.served_file_body(&req_p), "opening it");
+        let meta = try_fs!(self, req, req_p.metadata(), "getting its metadata");
         Ok(Response::with((status::Ok,
                            (Header(headers::Server(USER_AGENT.to_string())),
-                            Header(headers::LastModified(headers::HttpDate(file_time_modified_p(&req_p)))),
+                            Header(headers::LastModified(headers::HttpDate(file_time_modified(&meta)))),
                             Header(headers::AcceptRanges(vec![headers::RangeUnit::Bytes]))),
-                           req_p.as_path(),
-                           Header(headers::ContentLength(file_length(&req_p.metadata().expect("Failed to get requested file metadata"), &req_p))),
+                           body,
+                           Header(headers::ContentLength(file_length(&meta, &req_p))),
                            mt)))
     }
 
     fn handle_get_raw_fs_dir(&self, req: &mut Request, req_p: PathBuf) -> IronResult<Response> {
+        let hashes = req.url.as_ref().query_pairs().find(|&(ref k, _)| k == "hashes").map(|(_, v)| v.into_owned());
+        if let Some(alg) = hashes.as_ref().filter(|alg| !LISTING_HASH_ALGORITHMS.contains(&&alg[..])) {
+            return self.handle_invalid_url(req,
+                                           &format!("<p>Unsupported hash algorithm {}, available: {}.</p>",
+                                                    escape_specials(alg),
+                                                    CommaList(LISTING_HASH_ALGORITHMS.iter())));
+        }
+
+        let rd = match req_p.read_dir() {
+            Ok(rd) => rd,
+            Err(err) => return self.handle_requested_entity_unopenable(req, err, "directory"),
+        };
+
         log!(self.log,
              "{} was served metadata for directory {magenta}{}{reset}",
              self.remote_addresses(&req),
              req_p.display());
-        self.handle_raw_fs_api_response(status::Ok,
-                                        &FilesetData {
-                                            writes_supported: self.writes_temp_dir.is_some(),
-                                            is_root: req.url.as_ref().path_segments().unwrap().count() + !req.url.as_ref().as_str().ends_with('/') as usize ==
-                                                     1,
-                                            is_file: false,
-                                            files: req_p.read_dir()
-                                                .expect("Failed to read requested directory")
-                                                .map(|p| p.expect("Failed to iterate over requested directory"))
-                                                .filter(|f| {
-                    let fp = f.path();
-                    let mut symlink = false;
-                    !((!self.follow_symlinks &&
-                       {
-                        symlink = is_symlink(&fp);
-                        symlink
-                    }) || (self.follow_symlinks && self.sandbox_symlinks && symlink && !is_descendant_of(fp, &self.hosted_directory.1)))
-                })
-                                                .map(|f| {
-                    let is_file = is_actually_file(&f.file_type().expect("Failed to get file type"), &f.path());
+        let data = FilesetData {
+            writes_supported: self.writes_temp_dir.is_some(),
+            is_root: req.url.as_ref().path_segments().unwrap().count() + !req.url.as_ref().path().ends_with('/') as usize == 1,
+            is_file: false,
+            // Entries removed while being listed are left out
+            files: rd.flatten()
+                .filter(|f| self.listed(&f.path()))
+                .flat_map(|f| {
+                    let is_file = is_actually_file(&f.file_type().ok()?, &f.path());
                     if is_file {
-                        get_raw_fs_metadata(f.path())
+                        get_raw_fs_metadata(f.path()).ok()
                     } else {
-                        RawFileData {
+                        Some(RawFileData {
                             mime_type: "text/directory".parse().unwrap(),
-                            name: f.file_name().into_string().expect("Failed to get file name"),
-                            last_modified: file_time_modified_p(&f.path()),
+                            name: f.file_name().to_string_lossy().into_owned(),
+                            last_modified: file_time_modified(&f.metadata().ok()?),
                             size: 0,
                             is_file: false,
-                        }
+                        })
                     }
                 })
-                                                .collect(),
-                                        })
+                .collect(),
+        };
+
+        let available_space = self.available_space(&req_p);
+        if self.listing_permissions || hashes.is_some() || available_space.is_some() || self.download_counts.is_some() {
+            // Per-file data doesn't allow unknown fields, so these go alongside it instead
+            let mut extended = serde_json::to_value(&data).expect("Failed to serialise fileset data");
+            if let Some(space) = available_space {
+                extended.as_object_mut().unwrap().insert("available_space".to_string(), json!(space));
+            }
+            if self.listing_permissions {
+                let permissions: BTreeMap<_, _> = data.files
+                    .iter()
+                    .flat_map(|f| req_p.join(&f.name).metadata().map(|m| (f.name.clone(), json!({"mode": file_permissions(&m), "owner": file_owner(&m)}))))
+                    .collect();
+                extended.as_object_mut().unwrap().insert("permissions".to_string(), json!(permissions));
+            }
+            if let Some(counts) = self.download_counts.as_ref() {
+                let downloads: BTreeMap<_, _> = data.files
+                    .iter()
+                    .filter(|f| f.is_file)
+                    .map(|f| (f.name.clone(), counts.get(&req_p.join(&f.name))))
+                    .collect();
+                extended.as_object_mut().unwrap().insert("downloads".to_string(), json!(downloads));
+            }
+            if let Some(alg) = hashes {
+                // Reading a FIFO or device would block or never end
+                let hashes: BTreeMap<_, _> = data.files
+                    .iter()
+                    .filter(|f| f.is_file && !req_p.join(&f.name).metadata().map(|m| is_device(&m.file_type())).unwrap_or(true))
+                    .map(|f| {
+                        let hash = if f.size <= MAX_LISTING_HASH_SIZE {
+                            self.cached_file_hash(&req_p.join(&f.name)).ok().map(|h| h.to_hex().to_string())
+                        } else {
+                            None
+                        };
+                        (f.name.clone(), hash)
+                    })
+                    .collect();
+                extended.as_object_mut().unwrap().insert("hashes".to_string(), json!({ alg: hashes }));
+            }
+            self.handle_raw_fs_api_response(status::Ok, &extended)
+        } else {
+            self.handle_raw_fs_api_response(status::Ok, &data)
+        }
+    }
+
+    /// Whether the specified directory entry should show up in listings, i.e. isn't a symlink that mustn't be followed or the temp directory
+    pub(super) fn listed(&self, p: &Path) -> bool {
+        let symlink = is_symlink(p);
+        !((symlink && !self.symlink_policy.follow()) || (symlink && self.symlink_policy.sandboxed() && !is_descendant_of(p, &self.hosted_directory.1)) ||
+          self.in_temp_dir(p))
+    }
+
+    /// Whether the specified path is in the temp directory, which isn't served even if it's inside the hosted one
+    pub(super) fn in_temp_dir(&self, p: &Path) -> bool {
+        self.hosted_temp_dir.as_ref().map(|t| p.starts_with(t)).unwrap_or(false)
+    }
+
+    /// How many bytes can still be uploaded into the specified directory, if writes are allowed and it's known
+    fn available_space(&self, req_p: &Path) -> Option<u64> {
+        if self.writes_temp_dir.is_some() && req_p.is_dir() {
+            available_space(req_p)
+        } else {
+            None
+        }
+    }
+
+    /// Advertise `available_space()` in the `X-Available-Space` header, so upload clients can warn about uploads that won't fit
+    pub(super) fn set_available_space(&self, resp: &mut Response, req_p: &Path) {
+        if let Some(space) = self.available_space(req_p) {
+            resp.headers.set_raw("X-Available-Space", vec![space.to_string().into_bytes()]);
+        }
+    }
+
+    /// Hash the specified file, reusing the previous hash if it wasn't modified since
+    fn cached_file_hash(&self, p: &Path) -> Result<blake3::Hash, IoError> {
+        let meta = p.metadata()?;
+        let (mtime, len) = (meta.modified()?, meta.len());
+        if let Some(hash) = self.fresh_file_hash(p, mtime, len) {
+            return Ok(hash);
+        }
+
+        let hash = file_hash(p)?;
+        self.cache_hash.insert(p.to_path_buf(), (mtime, len, hash));
+        Ok(hash)
+    }
+
+    /// Hash of what cached encoded files and torrent info depend on, other than the files they're of
+    ///
+    /// No options change them, so that's the format they're stored in and the encoders, which come with the version;
+    /// ones persisted by a server with a different fingerprint are never used.
+    fn cache_fingerprint() -> String {
+        let mut fingerprint = blake3::Hasher::new();
+        fingerprint.update(&CACHE_FORMAT_VERSION.to_le_bytes());
+        fingerprint.update(USER_AGENT.as_bytes());
+        fingerprint.finalize().to_hex()[..16].to_string()
+    }
+
+    /// The previous hash of the specified file, if it was hashed and wasn't modified since, without reading it
+    fn known_file_hash(&self, p: &Path) -> Option<blake3::Hash> {
+        let meta = p.metadata().ok()?;
+        self.fresh_file_hash(p, meta.modified().ok()?, meta.len())
+    }
+
+    fn fresh_file_hash(&self, p: &Path, mtime: SystemTime, len: u64) -> Option<blake3::Hash> {
+        self.cache_hash.get(p).filter(|&(cached_mtime, cached_len, _)| cached_mtime == mtime && cached_len == len).map(|(_, _, hash)| hash)
+    }
+
+    /// Forget what's cached about `p` and everything under it, and bump the modification time of its parent directory,
+    /// so listings and downloads right after a write see the new state, even within the filesystem's timestamp granularity,
+    /// then queue it for replication, if writes are mirrored
+    pub(super) fn written(&self, p: &Path) {
+        if p.is_dir() || !p.exists() {
+            self.cache_hash.retain(|cp, _| !cp.starts_with(p));
+            self.origin_validated.write().unwrap_or_else(PoisonError::into_inner).retain(|cp, _| !cp.starts_with(p));
+        } else {
+            self.cache_hash.remove(p);
+            self.origin_validated.write().unwrap_or_else(PoisonError::into_inner).remove(p);
+        }
+
+        // Replacing a file in-place doesn't touch its directory; Windows can't open directories like this, so it's best-effort
+        if let Some(parent) = p.parent() {
+            let _ = File::open(parent).and_then(|d| d.set_modified(SystemTime::now()));
+        }
+
+        if let Some(replicator) = self.write_replicator.as_ref() {
+            replicator.queue(p);
+        }
     }
 
     fn handle_get_dir(&self, req: &mut Request, req_p: PathBuf) -> IronResult<Response> {
@@ -714,15 +1589,14 @@ impl HttpHandler {
             if let Some(e) = INDEX_EXTENSIONS.iter()
                 .find(|e| {
                     idx.set_extension(e);
-                    idx.exists() &&
-                    ((!self.follow_symlinks || !self.sandbox_symlinks) ||
-                     (self.follow_symlinks && self.sandbox_symlinks && is_descendant_of(&req_p, &self.hosted_directory.1)))
+                    idx.exists() && (!self.symlink_policy.sandboxed() || is_descendant_of(&req_p, &self.hosted_directory.1))
                 }) {
                 if req.url.as_ref().path_segments().unwrap().next_back() == Some("") {
-                    let r = self.handle_get_file(req, idx);
+                    let max_age = self.cache_max_age_for(&idx);
+                    let r = self.handle_get_file(req, idx).map(|r| self.with_preloads(with_cache_headers(r, max_age)));
                     log!(self.log,
                          "{} found index file for directory {magenta}{}{reset}",
-                         Spaces(self.remote_addresses(req).to_string().len()),
+                         Spaces(self.remote_addresses(req).width()),
                          req_p.display());
                     return r;
                 } else {
@@ -761,6 +1635,7 @@ impl HttpHandler {
 
     fn handle_get_mobile_dir_listing(&self, req: &mut Request, req_p: PathBuf) -> IronResult<Response> {
         let relpath = (url_path(&req.url) + "/").replace("//", "/");
+        let link_relpath = (url_path_escaped(&req.url) + "/").replace("//", "/");
         let is_root = req.url.as_ref().path_segments().unwrap().count() + !req.url.as_ref().as_str().ends_with('/') as usize == 1;
         let show_file_management_controls = self.writes_temp_dir.is_some();
         log!(self.log,
@@ -771,7 +1646,7 @@ impl HttpHandler {
         let parent_s = if is_root {
             String::new()
         } else {
-            let rel_noslash = &relpath[0..relpath.len() - 1];
+            let rel_noslash = &link_relpath[0..link_relpath.len() - 1];
             let slash_idx = rel_noslash.rfind('/');
             format!("<a href=\"/{up_path}{up_path_slash}\" class=\"list entry top\"><span class=\"back_arrow_icon\">Parent directory</span></a> \
                      <a href=\"/{up_path}{up_path_slash}\" class=\"list entry bottom\"><span class=\"marker\">@</span>\
@@ -779,91 +1654,98 @@ impl HttpHandler {
                     file_time_modified_p(req_p.parent().unwrap_or(&req_p))
                         .strftime("%F %T")
                         .unwrap(),
-                    up_path = escape_specials(slash_idx.map(|i| &rel_noslash[0..i]).unwrap_or("")),
+                    up_path = slash_idx.map(|i| &rel_noslash[0..i]).unwrap_or(""),
                     up_path_slash = if slash_idx.is_some() { "/" } else { "" })
         };
-        let list_s = req_p.read_dir()
-            .expect("Failed to read requested directory")
-            .map(|p| p.expect("Failed to iterate over requested directory"))
-            .filter(|f| {
-                let fp = f.path();
-                let mut symlink = false;
-                !((!self.follow_symlinks &&
-                   {
-                    symlink = is_symlink(&fp);
-                    symlink
-                }) || (self.follow_symlinks && self.sandbox_symlinks && symlink && !is_descendant_of(fp, &self.hosted_directory.1)))
+        let rd = match req_p.read_dir() {
+            Ok(rd) => rd,
+            Err(err) => return self.handle_requested_entity_unopenable(req, err, "directory"),
+        };
+        // Entries removed while being listed are left out
+        let list = rd.flatten()
+            .filter(|f| self.listed(&f.path()))
+            .flat_map(|f| {
+                let is_file = is_actually_file(&f.file_type().ok()?, &f.path());
+                let fmeta = f.metadata().ok()?;
+                Some((f, is_file, fmeta))
             })
-            .sorted_by(|lhs, rhs| {
-                (is_actually_file(&lhs.file_type().expect("Failed to get file type"), &lhs.path()),
-                 lhs.file_name().to_str().expect("Failed to get file name").to_lowercase())
-                    .cmp(&(is_actually_file(&rhs.file_type().expect("Failed to get file type"), &rhs.path()),
-                           rhs.file_name().to_str().expect("Failed to get file name").to_lowercase()))
+            .sorted_by(|&(ref lhs, lhs_file, _), &(ref rhs, rhs_file, _)| {
+                (lhs_file, lhs.file_name().to_string_lossy().to_lowercase())
+                    .cmp(&(rhs_file, rhs.file_name().to_string_lossy().to_lowercase()))
             })
-            .fold("".to_string(), |cur, f| {
-                let is_file = is_actually_file(&f.file_type().expect("Failed to get file type"), &f.path());
-                let fmeta = f.metadata().expect("Failed to get requested file metadata");
-                let fname = f.file_name().into_string().expect("Failed to get file name");
-                let path = f.path();
-
-                format!("{}<a href=\"{path}{fname}\" class=\"list entry top\"><span class=\"{}{}_icon\" id=\"{}\">{}{}</span>{}</a> \
-                           <a href=\"{path}{fname}\" class=\"list entry bottom\"><span class=\"marker\">@</span><span class=\"datetime\">{} UTC</span>{}</a>\n",
-                        cur,
-                        if is_file { "file" } else { "dir" },
-                        file_icon_suffix(&path, is_file),
-                        path.file_name().map(|p| p.to_str().expect("Filename not UTF-8").replace('.', "_")).as_ref().unwrap_or(&fname),
-                        fname.replace('&', "&amp;").replace('<', "&lt;"),
-                        if is_file { "" } else { "/" },
-                        if show_file_management_controls {
-                            DisplayThree("<span class=\"manage\"><span class=\"delete_file_icon\">Delete</span>",
-                                         if self.webdav {
-                                             " <span class=\"rename_icon\">Rename</span>"
-                                         } else {
-                                             ""
-                                         },
-                                         "</span>")
-                        } else {
-                            DisplayThree("", "", "")
-                        },
-                        file_time_modified(&fmeta).strftime("%F %T").unwrap(),
-                        if is_file {
-                            DisplayThree("<span class=\"size\">", human_readable_size(file_length(&fmeta, &path)), "</span>")
-                        } else {
-                            DisplayThree("", String::new(), "")
-                        },
-                        path = escape_specials(format!("/{}", relpath).replace("//", "/")),
-                        fname = encode_tail_if_trimmed(escape_specials(&fname)))
+            .collect::<Vec<_>>();
+        let entries = list.len();
+        let (normalise_unicode, webdav, list_relpath) = (self.normalise_unicode, self.webdav, link_relpath);
+        let list = list.into_iter().map(move |(f, is_file, fmeta)| {
+            // Names that aren't UTF-8 are shown lossily, but linked to byte for byte
+            let name = f.file_name();
+            let fname = name.to_string_lossy().into_owned();
+            let link_fname = encode_tail_if_trimmed(match name.to_str() {
+                Some(_) if normalise_unicode => escape_specials(fname.nfc().collect::<String>()),
+                _ => escape_file_name(&name),
             });
+            let path = f.path();
+
+            format!("<a href=\"{path}{fname}\" class=\"list entry top\"><span class=\"{}{}_icon\" id=\"{}\">{}{}</span>{}</a> \
+                       <a href=\"{path}{fname}\" class=\"list entry bottom\"><span class=\"marker\">@</span><span class=\"datetime\">{} UTC</span>{}</a>\n",
+                    if is_file { "file" } else { "dir" },
+                    file_icon_suffix(&path, is_file),
+                    fname.replace('.', "_").replace('&', "&amp;").replace('"', "&quot;"),
+                    fname.replace('&', "&amp;").replace('<', "&lt;"),
+                    if is_file { "" } else { "/" },
+                    if show_file_management_controls {
+                        DisplayThree("<span class=\"manage\"><span class=\"delete_file_icon\">Delete</span>",
+                                     if webdav {
+                                         " <span class=\"rename_icon\">Rename</span>"
+                                     } else {
+                                         ""
+                                     },
+                                     "</span>")
+                    } else {
+                        DisplayThree("", "", "")
+                    },
+                    file_time_modified(&fmeta).strftime("%F %T").unwrap(),
+                    if is_file {
+                        DisplayThree("<span class=\"size\">", human_readable_size(file_length(&fmeta, &path)), "</span>")
+                    } else {
+                        DisplayThree("", String::new(), "")
+                    },
+                    path = format!("/{}", list_relpath).replace("//", "/"),
+                    fname = link_fname)
+        });
 
-        self.handle_generated_response_encoding(req,
-                                                status::Ok,
-                                                html_response(MOBILE_DIRECTORY_LISTING_HTML,
-                                                              &[&relpath[..],
-                                                                if is_root { "" } else { "/" },
-                                                                if show_file_management_controls {
-                                                                    r#"<script type="text/javascript">{upload}{manage_mobile}{manage}</script>"#
-                                                                } else {
-                                                                    ""
-                                                                },
-                                                                &parent_s[..],
-                                                                &list_s[..],
-                                                                if show_file_management_controls {
-                                                                    "<span class=\"list heading top top-border bottom\"> \
-                                                                       Upload files: <input id=\"file_upload\" type=\"file\" multiple /> \
-                                                                     </span>"
-                                                                } else {
-                                                                    ""
-                                                                },
-                                                                if show_file_management_controls && self.webdav {
-                                                                    "<a id=\"new_directory\" href=\"#new_directory\" class=\"list entry top bottom\">
-                                                                         <span class=\"new_dir_icon\">Create directory</span></a>"
-                                                                } else {
-                                                                    ""
-                                                                }]))
+        self.handle_generated_listing(req,
+                                      entries,
+                                      list,
+                                      MOBILE_DIRECTORY_LISTING_HTML,
+                                      4,
+                                      &[&relpath[..],
+                                        if is_root { "" } else { "/" },
+                                        if show_file_management_controls {
+                                            r#"<script type="text/javascript">{upload}{manage_mobile}{manage}</script>"#
+                                        } else {
+                                            ""
+                                        },
+                                        &parent_s[..],
+                                        "",
+                                        if show_file_management_controls {
+                                            "<span class=\"list heading top top-border bottom\"> \
+                                               Upload files: <input id=\"file_upload\" type=\"file\" multiple /> \
+                                             </span>"
+                                        } else {
+                                            ""
+                                        },
+                                        if show_file_management_controls && self.webdav {
+                                            "<a id=\"new_directory\" href=\"#new_directory\" class=\"list entry top bottom\">
+                                                 <span class=\"new_dir_icon\">Create directory</span></a>"
+                                        } else {
+                                            ""
+                                        }])
     }
 
     fn handle_get_dir_listing(&self, req: &mut Request, req_p: PathBuf) -> IronResult<Response> {
         let relpath = (url_path(&req.url) + "/").replace("//", "/");
+        let link_relpath = (url_path_escaped(&req.url) + "/").replace("//", "/");
         let is_root = req.url.as_ref().path_segments().unwrap().count() + !req.url.as_ref().as_str().ends_with('/') as usize == 1;
         let show_file_management_controls = self.writes_temp_dir.is_some();
         log!(self.log,
@@ -874,7 +1756,7 @@ impl HttpHandler {
         let parent_s = if is_root {
             String::new()
         } else {
-            let rel_noslash = &relpath[0..relpath.len() - 1];
+            let rel_noslash = &link_relpath[0..link_relpath.len() - 1];
             let slash_idx = rel_noslash.rfind('/');
             format!("<tr><td><a href=\"/{up_path}{up_path_slash}\" id=\"parent_dir\" class=\"back_arrow_icon\"></a></td> \
                          <td><a href=\"/{up_path}{up_path_slash}\">Parent directory</a></td> \
@@ -882,7 +1764,7 @@ impl HttpHandler {
                          <td><a href=\"/{up_path}{up_path_slash}\">&nbsp;</a></td> \
                          <td><a href=\"/{up_path}{up_path_slash}\">&nbsp;</a></td></tr>",
                     file_time_modified_p(req_p.parent().unwrap_or(&req_p)).strftime("%F %T").unwrap(),
-                    up_path = escape_specials(slash_idx.map(|i| &rel_noslash[0..i]).unwrap_or("")),
+                    up_path = slash_idx.map(|i| &rel_noslash[0..i]).unwrap_or(""),
                     up_path_slash = if slash_idx.is_some() { "/" } else { "" })
         };
 
@@ -891,98 +1773,199 @@ impl HttpHandler {
             Ok(rd) => rd,
             Err(err) => return self.handle_requested_entity_unopenable(req, err, "directory"),
         };
-        let list_s = rd.map(|p| p.expect("Failed to iterate over requested directory"))
-            .filter(|f| {
-                let fp = f.path();
-                let mut symlink = false;
-                !((!self.follow_symlinks &&
-                   {
-                    symlink = is_symlink(&fp);
-                    symlink
-                }) || (self.follow_symlinks && self.sandbox_symlinks && symlink && !is_descendant_of(fp, &self.hosted_directory.1)))
+        // Entries removed while being listed are left out
+        let list = rd.flatten()
+            .filter(|f| self.listed(&f.path()))
+            .flat_map(|f| {
+                let is_file = is_actually_file(&f.file_type().ok()?, &f.path());
+                let fmeta = f.metadata().ok()?;
+                Some((f, is_file, fmeta))
             })
-            .sorted_by(|lhs, rhs| {
-                (is_actually_file(&lhs.file_type().expect("Failed to get file type"), &lhs.path()),
-                 lhs.file_name().to_str().expect("Failed to get file name").to_lowercase())
-                    .cmp(&(is_actually_file(&rhs.file_type().expect("Failed to get file type"), &rhs.path()),
-                           rhs.file_name().to_str().expect("Failed to get file name").to_lowercase()))
+            .sorted_by(|&(ref lhs, lhs_file, _), &(ref rhs, rhs_file, _)| {
+                (lhs_file, lhs.file_name().to_string_lossy().to_lowercase())
+                    .cmp(&(rhs_file, rhs.file_name().to_string_lossy().to_lowercase()))
             })
-            .fold("".to_string(), |cur, f| {
-                let is_file = is_actually_file(&f.file_type().expect("Failed to get file type"), &f.path());
-                let fmeta = f.metadata().expect("Failed to get requested file metadata");
-                let fname = f.file_name().into_string().expect("Failed to get file name");
-                let path = f.path();
-                let len = file_length(&fmeta, &path);
-
-                format!("{}<tr><td><a href=\"{path}{fname}\" id=\"{}\" class=\"{}{}_icon\"></a></td> \
-                               <td><a href=\"{path}{fname}\">{}{}</a></td> <td><a href=\"{path}{fname}\" class=\"datetime\">{}</a></td> \
-                               <td><a href=\"{path}{fname}\">{}{}{}</a></td> {}</tr>\n",
-                        cur,
-                        path.file_name().map(|p| p.to_str().expect("Filename not UTF-8").replace('.', "_")).as_ref().unwrap_or(&fname),
-                        if is_file { "file" } else { "dir" },
-                        file_icon_suffix(&path, is_file),
-                        fname.replace('&', "&amp;").replace('<', "&lt;"),
-                        if is_file { "" } else { "/" },
-                        file_time_modified(&fmeta).strftime("%F %T").unwrap(),
-                        if is_file {
-                            DisplayThree("<abbr title=\"", len.to_string(), " B\">")
-                        } else {
-                            DisplayThree("&nbsp;", String::new(), "")
-                        },
-                        if is_file {
-                            human_readable_size(len)
-                        } else {
-                            String::new()
-                        },
-                        if is_file { "</abbr>" } else { "" },
-                        if show_file_management_controls {
-                            DisplayThree("<td><a href=\"#delete_file\" class=\"delete_file_icon\">Delete</a>",
-                                         if self.webdav {
-                                             " <a href=\"#rename\" class=\"rename_icon\">Rename</a>"
-                                         } else {
-                                             ""
-                                         },
-                                         "</td>")
-                        } else {
-                            DisplayThree("", "", "")
-                        },
-                        path = escape_specials(format!("/{}", relpath).replace("//", "/")),
-                        fname = encode_tail_if_trimmed(escape_specials(&fname)))
+            .collect::<Vec<_>>();
+        let entries = list.len();
+        let (normalise_unicode, listing_permissions, webdav, list_relpath) = (self.normalise_unicode, self.listing_permissions, self.webdav, link_relpath);
+        let download_counts = self.download_counts.clone().filter(|_| self.listing_downloads);
+        let list = list.into_iter().map(move |(f, is_file, fmeta)| {
+            // Names that aren't UTF-8 are shown lossily, but linked to byte for byte
+            let name = f.file_name();
+            let fname = name.to_string_lossy().into_owned();
+            let link_fname = encode_tail_if_trimmed(match name.to_str() {
+                Some(_) if normalise_unicode => escape_specials(fname.nfc().collect::<String>()),
+                _ => escape_file_name(&name),
             });
+            let path = f.path();
+            let len = file_length(&fmeta, &path);
+
+            format!("<tr><td><a href=\"{path}{fname}\" id=\"{}\" class=\"{}{}_icon\"></a></td> \
+                           <td><a href=\"{path}{fname}\">{}{}</a></td> <td><a href=\"{path}{fname}\" class=\"datetime\">{}</a></td> \
+                           <td><a href=\"{path}{fname}\">{}{}{}</a></td> {}{}{}</tr>\n",
+                    fname.replace('.', "_").replace('&', "&amp;").replace('"', "&quot;"),
+                    if is_file { "file" } else { "dir" },
+                    file_icon_suffix(&path, is_file),
+                    fname.replace('&', "&amp;").replace('<', "&lt;"),
+                    if is_file { "" } else { "/" },
+                    file_time_modified(&fmeta).strftime("%F %T").unwrap(),
+                    if is_file {
+                        DisplayThree("<abbr title=\"", len.to_string(), " B\">")
+                    } else {
+                        DisplayThree("&nbsp;", String::new(), "")
+                    },
+                    if is_file {
+                        human_readable_size(len)
+                    } else {
+                        String::new()
+                    },
+                    if is_file { "</abbr>" } else { "" },
+                    if listing_permissions {
+                        format!("<td><a href=\"{path}{fname}\"><samp>{}</samp> {}</a></td> ",
+                                file_permissions(&fmeta),
+                                file_owner(&fmeta).unwrap_or_default(),
+                                path = format!("/{}", list_relpath).replace("//", "/"),
+                                fname = link_fname)
+                    } else {
+                        String::new()
+                    },
+                    if let Some(counts) = download_counts.as_ref() {
+                        format!("<td><a href=\"{path}{fname}\">{}</a></td> ",
+                                if is_file {
+                                    counts.get(&path).to_string()
+                                } else {
+                                    "&nbsp;".to_string()
+                                },
+                                path = format!("/{}", list_relpath).replace("//", "/"),
+                                fname = link_fname)
+                    } else {
+                        String::new()
+                    },
+                    if show_file_management_controls {
+                        DisplayThree("<td><a href=\"#delete_file\" class=\"delete_file_icon\">Delete</a>",
+                                     if webdav {
+                                         " <a href=\"#rename\" class=\"rename_icon\">Rename</a>"
+                                     } else {
+                                         ""
+                                     },
+                                     "</td>")
+                    } else {
+                        DisplayThree("", "", "")
+                    },
+                    path = format!("/{}", list_relpath).replace("//", "/"),
+                    fname = link_fname)
+        });
+
+        self.handle_generated_listing(req,
+                                      entries,
+                                      list,
+                                      DIRECTORY_LISTING_HTML,
+                                      3,
+                                      &[&relpath[..],
+                                        if show_file_management_controls {
+                                            r#"<script type="text/javascript">{upload}{manage_desktop}{manage}</script>"#
+                                        } else {
+                                            ""
+                                        },
+                                        &parent_s[..],
+                                        "",
+                                        if show_file_management_controls {
+                                            "<hr /> \
+                                             <p> \
+                                               Drag&amp;Drop to upload or <input id=\"file_upload\" type=\"file\" multiple />. \
+                                             </p>"
+                                        } else {
+                                            ""
+                                        },
+                                        if show_file_management_controls {
+                                            "<th>Manage</th>"
+                                        } else {
+                                            ""
+                                        },
+                                        if show_file_management_controls && self.webdav {
+                                            "<tr id=\"new_directory\"><td><a href=\"#new_directory\" class=\"new_dir_icon\"></a></td> \
+                                                                      <td><a href=\"#new_directory\">Create directory</a></td> \
+                                                                      <td><a href=\"#new_directory\">&nbsp;</a></td> \
+                                                                      <td><a href=\"#new_directory\">&nbsp;</a></td> \
+                                                                      <td><a href=\"#new_directory\">&nbsp;</a></td></tr>"
+                                        } else {
+                                            ""
+                                        },
+                                        &format!("{}{}",
+                                                 if self.listing_permissions {
+                                                     "<th>Permissions</th> "
+                                                 } else {
+                                                     ""
+                                                 },
+                                                 if self.download_counts.is_some() && self.listing_downloads {
+                                                     "<th>Downloads</th> "
+                                                 } else {
+                                                     ""
+                                                 })])
+    }
+
+    fn handle_post(&self, req: &mut Request) -> IronResult<Response> {
+        // Uploaded files can be JSON or archives, too, and their type shouldn't turn them into batches or deploys
+        if req.headers.get_raw(FILE_NAME_HEADER).is_some() {
+            return self.handle_post_upload(req);
+        }
+
+        match req.headers.get::<headers::ContentType>() {
+            Some(&headers::ContentType(Mime(MimeTopLevel::Application, MimeSubLevel::Json, _))) => self.handle_batch(req),
+            Some(&headers::ContentType(Mime(MimeTopLevel::Application, MimeSubLevel::Ext(ref sub), _)))
+                if ["x-tar", "gzip", "x-gzip", "x-bzip2"].contains(&&sub[..]) => self.handle_deploy(req),
+            _ if req.url.query() == Some("rollback") => self.handle_deploy(req),
+            _ if req.url.query() == Some("delta") => self.handle_delta(req),
+            _ => self.handle_bad_method(req),
+        }
+    }
+
+    /// `POST /dir/` with the raw file in the body and its name in `X-File-Name`, handled as a PUT to `/dir/name`
+    fn handle_post_upload(&self, req: &mut Request) -> IronResult<Response> {
+        if self.writes_temp_dir.is_none() {
+            return self.handle_forbidden_method(req, "-w", "write requests");
+        }
+        if let Some(resp) = self.target_upload_file_name(req)? {
+            return Ok(resp);
+        }
+
+        let location = req.url.as_ref().path().to_string();
+        self.handle_put(req).map(|mut r| {
+            if r.status == Some(status::Created) {
+                r.headers.set(headers::Location(location));
+            }
+            r
+        })
+    }
 
-        self.handle_generated_response_encoding(req,
-                                                status::Ok,
-                                                html_response(DIRECTORY_LISTING_HTML,
-                                                              &[&relpath[..],
-                                                                if show_file_management_controls {
-                                                                    r#"<script type="text/javascript">{upload}{manage_desktop}{manage}</script>"#
-                                                                } else {
-                                                                    ""
-                                                                },
-                                                                &parent_s[..],
-                                                                &list_s[..],
-                                                                if show_file_management_controls {
-                                                                    "<hr /> \
-                                                                     <p> \
-                                                                       Drag&amp;Drop to upload or <input id=\"file_upload\" type=\"file\" multiple />. \
-                                                                     </p>"
-                                                                } else {
-                                                                    ""
-                                                                },
-                                                                if show_file_management_controls {
-                                                                    "<th>Manage</th>"
-                                                                } else {
-                                                                    ""
-                                                                },
-                                                                if show_file_management_controls && self.webdav {
-                                                                    "<tr id=\"new_directory\"><td><a href=\"#new_directory\" class=\"new_dir_icon\"></a></td> \
-                                                                                              <td><a href=\"#new_directory\">Create directory</a></td> \
-                                                                                              <td><a href=\"#new_directory\">&nbsp;</a></td> \
-                                                                                              <td><a href=\"#new_directory\">&nbsp;</a></td> \
-                                                                                              <td><a href=\"#new_directory\">&nbsp;</a></td></tr>"
-                                                                } else {
-                                                                    ""
-                                                                }]))
+    /// Point the request URL at the file named by `X-File-Name` in the requested directory,
+    /// or respond with an error if the URL isn't to a directory or the name isn't a valid one
+    fn target_upload_file_name(&self, req: &mut Request) -> IronResult<Option<Response>> {
+        let name = req.headers
+            .get_raw(FILE_NAME_HEADER)
+            .filter(|vals| vals.len() == 1)
+            .and_then(|vals| str::from_utf8(&vals[0]).ok())
+            .and_then(|name| percent_decode_path_segment(name.trim()))
+            .filter(|name| !name.is_empty())
+            .map(|name| name.into_owned());
+
+        if !req.url.as_ref().path().ends_with('/') {
+            self.handle_invalid_url(req, "<p>Uploads with X-File-Name need to be POSTed to a directory.</p>").map(Some)
+        } else if let Some(name) = name {
+            req.url.as_mut().path_segments_mut().expect("Request URL can't be a base").pop_if_empty().push(&name);
+            Ok(None)
+        } else {
+            log!(self.log,
+                 "{} tried to {red}POST{reset} to {yellow}{}{reset} with an invalid file name",
+                 self.remote_addresses(req),
+                 url_path(&req.url));
+            self.handle_generated_error(req,
+                                        status::BadRequest,
+                                        &["400 Bad Request",
+                                          "The file name was invalid.",
+                                          "<p>X-File-Name needs to be a single, non-empty, percent-encoded UTF-8 file name without path separators.</p>"])
+                .map(Some)
+        }
     }
 
     fn handle_put(&self, req: &mut Request) -> IronResult<Response> {
@@ -993,22 +1976,28 @@ impl HttpHandler {
         let (req_p, symlink, url_err) = self.parse_requested_path(req);
 
         if url_err {
-            self.handle_invalid_url(req, "<p>Percent-encoding decoded to invalid UTF-8.</p>")
+            self.handle_invalid_url(req, "<p>Percent-encoding decoded to invalid UTF-8 or a path separator.</p>")
         } else if req_p.is_dir() {
             self.handle_disallowed_method(req,
                                           &[&[method::Options, method::Get, method::Delete, method::Head, method::Trace],
                                             if self.webdav {
-                                                &DAV_LEVEL_1_METHODS[..]
+                                                &DAV_LEVEL_2_METHODS[..]
                                             } else {
                                                 &[]
                                             }],
                                           "directory")
         } else if detect_file_as_dir(&req_p) {
             self.handle_invalid_url(req, "<p>Attempted to use file as directory.</p>")
+        } else if self.webdav_write_locked(req, &req_p, false) {
+            self.handle_locked(req, req_p)
         } else if req.headers.has::<headers::ContentRange>() {
             self.handle_put_partial_content(req)
-        } else if (symlink && !self.follow_symlinks) ||
-                  (symlink && self.follow_symlinks && self.sandbox_symlinks && !is_nonexistent_descendant_of(&req_p, &self.hosted_directory.1)) {
+        } else if let Some((st, cause)) = body_framing_error(&req.headers) {
+            self.handle_bad_framing(req, st, cause)
+        } else if !self.write_preconditions_met(req, &req_p) {
+            self.handle_precondition_failed(req, &req_p)
+        } else if (symlink && !self.symlink_policy.follow()) ||
+                  (symlink && self.symlink_policy.sandboxed() && !is_nonexistent_descendant_of(&req_p, &self.hosted_directory.1)) {
             self.create_temp_dir(&self.writes_temp_dir);
             self.handle_put_file(req, req_p, false)
         } else {
@@ -1018,14 +2007,15 @@ impl HttpHandler {
     }
 
     fn handle_disallowed_method(&self, req: &mut Request, allowed: &[&[method::Method]], tpe: &str) -> IronResult<Response> {
+        let allowed_count = allowed.iter().map(|mms| mms.len()).sum::<usize>();
         let allowed_s = allowed.iter()
             .flat_map(|mms| mms.iter())
             .enumerate()
             .fold("".to_string(), |cur, (i, m)| {
                 cur + &m.to_string() +
-                if i == allowed.len() - 2 {
+                if i + 2 == allowed_count {
                     ", and "
-                } else if i == allowed.len() - 1 {
+                } else if i + 1 == allowed_count {
                     ""
                 } else {
                     ", "
@@ -1041,10 +2031,8 @@ impl HttpHandler {
              tpe,
              allowed_s);
 
-        let resp_text =
-            html_response(ERROR_HTML,
-                          &["405 Method Not Allowed", &format!("Can't {} on a {}.", req.method, tpe), &format!("<p>Allowed methods: {}</p>", allowed_s)]);
-        self.handle_generated_response_encoding(req, status::MethodNotAllowed, resp_text)
+        let cause = format!("Can't {} on a {}.", req.method, tpe);
+        self.handle_generated_error(req, status::MethodNotAllowed, &["405 Method Not Allowed", &cause, &format!("<p>Allowed methods: {}</p>", allowed_s)])
             .map(|mut r| {
                 r.headers.set(headers::Allow(allowed.iter().flat_map(|mms| mms.iter()).cloned().collect()));
                 r
@@ -1057,17 +2045,80 @@ impl HttpHandler {
              self.remote_addresses(&req),
              url_path(&req.url));
 
-        self.handle_generated_response_encoding(req,
-                                                status::BadRequest,
-                                                html_response(ERROR_HTML,
-                                                              &["400 Bad Request",
-                                                                "<a href=\"https://tools.ietf.org/html/rfc7231#section-4.3.3\">RFC7231 forbids \
-                                                                 partial-content PUT requests.</a>",
-                                                                ""]))
+        self.handle_generated_error(req,
+                                    status::BadRequest,
+                                    &["400 Bad Request",
+                                      "<a href=\"https://tools.ietf.org/html/rfc7231#section-4.3.3\">RFC7231 forbids \
+                                       partial-content PUT requests.</a>",
+                                      ""])
+    }
+
+    pub(super) fn handle_bad_framing(&self, req: &mut Request, st: status::Status, cause: &str) -> IronResult<Response> {
+        log!(self.log,
+             "{} tried to {red}{}{reset} to {yellow}{}{reset} with bad body framing -- {}",
+             self.remote_addresses(&req),
+             req.method,
+             url_path(&req.url),
+             cause);
+
+        self.handle_generated_error(req,
+                                    st,
+                                    &[&st.to_string()[..], "The request body was framed incorrectly.", &format!("<p>{}.</p>", cause)])
     }
 
     fn handle_put_file(&self, req: &mut Request, req_p: PathBuf, legal: bool) -> IronResult<Response> {
         let existant = !legal || req_p.exists();
+        self.handle_put_file_replacing(req, req_p, legal, existant)
+    }
+
+    /// `handle_put_file()`, with whether it's replacing something decided by the caller,
+    /// for when what's there is a placeholder, like the inbox's reserved names
+    pub(super) fn handle_put_file_replacing(&self, req: &mut Request, req_p: PathBuf, legal: bool, existant: bool) -> IronResult<Response> {
+        if let Some(max) = self.max_upload_size {
+            if req.headers.get::<headers::ContentLength>().map(|l| l.0 > max).unwrap_or(false) {
+                return self.handle_put_too_large(req, req_p, max);
+            }
+        }
+
+        let &(_, ref temp_dir) = self.writes_temp_dir.as_ref().unwrap();
+        let temp_file_p = temp_dir.join(req_p.file_name().expect("Failed to get requested file's filename"));
+
+        // Chunked bodies don't say how long they are, so stop reading right after they've gone over the limit
+        let upload = self.uploads.start(req_p.clone(), req.remote_addr.to_string(), request_id(&req.headers), req.headers.get::<headers::ContentLength>().map(|l| l.0));
+        let written = match File::create(&temp_file_p)
+            .and_then(|mut f| io::copy(&mut upload.track(&mut req.body).take(self.max_upload_size.map(|m| m + 1).unwrap_or(u64::MAX)), &mut f)) {
+            Ok(written) => written,
+            Err(ref err) if is_storage_full(err) => {
+                let _ = fs::remove_file(&temp_file_p);
+                return self.handle_put_storage_full(req, req_p, err);
+            }
+            Err(err) => {
+                let _ = fs::remove_file(&temp_file_p);
+                return self.handle_fs_error(req, err, "writing it to the temp dir");
+            }
+        };
+        drop(upload);
+        if let Some(max) = self.max_upload_size.filter(|&m| written > m) {
+            let _ = fs::remove_file(&temp_file_p);
+            return self.handle_put_too_large(req, req_p, max);
+        }
+
+        if legal {
+            let _ = fs::create_dir_all(req_p.parent().expect("Failed to get requested file's parent directory"));
+            let copied = fs::copy(&temp_file_p, &req_p);
+            let _ = fs::remove_file(&temp_file_p);
+            match copied {
+                Ok(_) => (),
+                Err(ref err) if is_storage_full(err) => {
+                    // Whatever was there before was already truncated, so don't leave a partial file in its place
+                    let _ = fs::remove_file(&req_p);
+                    return self.handle_put_storage_full(req, req_p, err);
+                }
+                Err(err) => return self.handle_fs_error(req, err, "writing it"),
+            }
+            self.written(&req_p);
+        }
+
         log!(self.log,
              "{} {} {magenta}{}{reset}, size: {}B",
              self.remote_addresses(&req),
@@ -1079,24 +2130,41 @@ impl HttpHandler {
                  "created"
              },
              req_p.display(),
-             *req.headers.get::<headers::ContentLength>().expect("No Content-Length header"));
+             written);
+
+        Ok(with_etag(Response::with((if !legal || !existant {
+                                         status::Created
+                                     } else {
+                                         status::NoContent
+                                     },
+                                     Header(headers::Server(USER_AGENT.to_string())))),
+                     if legal { self.entity_tag(&req_p) } else { None }))
+    }
 
-        let &(_, ref temp_dir) = self.writes_temp_dir.as_ref().unwrap();
-        let temp_file_p = temp_dir.join(req_p.file_name().expect("Failed to get requested file's filename"));
+    fn handle_put_too_large(&self, req: &mut Request, req_p: PathBuf, max: u64) -> IronResult<Response> {
+        log!(self.log,
+             "{} tried to {red}PUT{reset} more than {}B to {magenta}{}{reset}",
+             self.remote_addresses(&req),
+             max,
+             req_p.display());
 
-        io::copy(&mut req.body, &mut File::create(&temp_file_p).expect("Failed to create temp file"))
-            .expect("Failed to write requested data to requested file");
-        if legal {
-            let _ = fs::create_dir_all(req_p.parent().expect("Failed to get requested file's parent directory"));
-            fs::copy(&temp_file_p, req_p).expect("Failed to copy temp file to requested file");
-        }
+        self.handle_generated_error(req,
+                                    status::PayloadTooLarge,
+                                    &["413 Payload Too Large",
+                                      &format!("Uploads are limited to {}.", human_readable_size(max)),
+                                      ""])
+    }
+
+    fn handle_put_storage_full(&self, req: &mut Request, req_p: PathBuf, err: &IoError) -> IronResult<Response> {
+        log!(self.log,
+             "{} tried to {red}PUT{reset} to {magenta}{}{reset} but {red}ran out of space{reset}: {}",
+             self.remote_addresses(&req),
+             req_p.display(),
+             err);
 
-        Ok(Response::with((if !legal || !existant {
-                               status::Created
-                           } else {
-                               status::NoContent
-                           },
-                           Header(headers::Server(USER_AGENT.to_string())))))
+        self.handle_generated_error(req,
+                                    status::InsufficientStorage,
+                                    &["507 Insufficient Storage", "The server ran out of space.", ""])
     }
 
     fn handle_delete(&self, req: &mut Request) -> IronResult<Response> {
@@ -1107,18 +2175,30 @@ impl HttpHandler {
         let (req_p, symlink, url_err) = self.parse_requested_path_custom_symlink(req.url.as_ref(), false);
 
         if url_err {
-            self.handle_invalid_url(req, "<p>Percent-encoding decoded to invalid UTF-8.</p>")
-        } else if !req_p.exists() || (symlink && !self.follow_symlinks) ||
-                  (symlink && self.follow_symlinks && self.sandbox_symlinks && !is_descendant_of(&req_p, &self.hosted_directory.1)) {
+            self.handle_invalid_url(req, "<p>Percent-encoding decoded to invalid UTF-8 or a path separator.</p>")
+        } else if !req_p.exists() || (symlink && !self.symlink_policy.follow()) ||
+                  (symlink && self.symlink_policy.sandboxed() && !is_descendant_of(&req_p, &self.hosted_directory.1)) {
             self.handle_nonexistent(req, req_p)
+        } else if self.webdav_write_locked(req, &req_p, true) {
+            self.handle_locked(req, req_p)
+        } else if !self.write_preconditions_met(req, &req_p) {
+            self.handle_precondition_failed(req, &req_p)
         } else {
             self.handle_delete_path(req, req_p, symlink)
         }
     }
 
     fn handle_delete_path(&self, req: &mut Request, req_p: PathBuf, symlink: bool) -> IronResult<Response> {
-        let ft = req_p.metadata().expect("failed to get file metadata").file_type();
+        let ft = try_fs!(self, req, req_p.metadata(), "getting its metadata").file_type();
         let is_file = is_actually_file(&ft, &req_p);
+        if is_file {
+            try_fs!(self, req, fs::remove_file(&req_p), "removing it");
+        } else {
+            try_fs!(self, req, fs::remove_dir_all(&req_p), "removing it");
+        }
+        self.written(&req_p);
+        self.drop_webdav_locks(&req_p);
+
         log!(self.log,
              "{} deleted {blue}{} {magenta}{}{reset}",
              self.remote_addresses(&req),
@@ -1131,16 +2211,6 @@ impl HttpHandler {
              },
              req_p.display());
 
-        if is_file {
-            fs::remove_file(req_p).expect("Failed to remove requested file");
-        } else {
-            fs::remove_dir_all(req_p).expect(if symlink {
-                "Failed to remove requested symlink"
-            } else {
-                "Failed to remove requested directory"
-            });
-        }
-
         Ok(Response::with((status::NoContent, Header(headers::Server(USER_AGENT.to_string())))))
     }
 
@@ -1161,6 +2231,111 @@ impl HttpHandler {
         })
     }
 
+    /// Check the Host header against the allowed hosts, to protect from DNS rebinding.
+    ///
+    /// IP addresses are always allowed, since those can't be rebound.
+    fn host_allowed(&self, req: &Request) -> bool {
+        let host = match req.headers.get::<headers::Host>() {
+            Some(h) => h,
+            None => return false,
+        };
+        let hostname = host.hostname.to_lowercase();
+
+        hostname.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>().is_ok() || self.allowed_hosts.contains(&hostname) ||
+        host.port.map(|p| self.allowed_hosts.contains(&format!("{}:{}", hostname, p))).unwrap_or(false)
+    }
+
+    fn handle_misdirected(&self, req: &mut Request) -> IronResult<Response> {
+        log!(self.log,
+             "{} requested to {red}{}{reset} {yellow}{}{reset} for disallowed host {red}{}{reset}",
+             self.remote_addresses(&req),
+             req.method,
+             req.url,
+             req.headers.get::<headers::Host>().map(|h| h.hostname.clone()).unwrap_or_default());
+
+        self.handle_generated_error(req,
+                                    status::Unregistered(421),
+                                    &["421 Misdirected Request", "This server doesn't serve this host.", ""])
+    }
+
+    /// Replace the POST's method with the one from `X-HTTP-Method-Override`, or refuse the request if it's not a valid one
+    fn override_method(&self, req: &mut Request) -> IronResult<Option<Response>> {
+        let overridden = req.headers
+            .get_raw(METHOD_OVERRIDE_HEADER)
+            .filter(|vals| vals.len() == 1)
+            .and_then(|vals| str::from_utf8(&vals[0]).ok())
+            .map(|m| m.trim().to_ascii_uppercase())
+            .filter(|m| !m.is_empty() && m.bytes().all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b)))
+            .and_then(|m| m.parse::<method::Method>().ok())
+            .filter(|m| *m != method::Post && *m != method::Connect);
+        let overridden = match overridden {
+            Some(m) => m,
+            None => {
+                log!(self.log,
+                     "{} tried to override {red}POST{reset} to {yellow}{}{reset} with an invalid method",
+                     self.remote_addresses(req),
+                     url_path(&req.url));
+                return self.handle_generated_error(req,
+                                                   status::BadRequest,
+                                                   &["400 Bad Request",
+                                                     "The method override was invalid.",
+                                                     "<p>X-HTTP-Method-Override needs to be a single method other than POST or CONNECT.</p>"])
+                    .map(Some);
+            }
+        };
+
+        log!(self.log,
+             "{} overrode {red}POST{reset} to {red}{}{reset} for {yellow}{}{reset}",
+             self.remote_addresses(req),
+             overridden,
+             url_path(&req.url));
+        req.method = overridden;
+        Ok(None)
+    }
+
+    /// Check if the request is for a media file and was referred from a site other than this one or the allowed ones
+    fn hotlinked(&self, req: &Request, req_p: &Path) -> bool {
+        let referrer_host = match req.headers.get::<headers::Referer>().and_then(|r| GenericUrl::parse(r).ok()).and_then(|r| r.host_str().map(str::to_lowercase)) {
+            Some(rh) => rh,
+            None => return false,
+        };
+        if req.headers.get::<headers::Host>().map(|h| h.hostname.eq_ignore_ascii_case(&referrer_host)).unwrap_or(false) ||
+           self.hotlink_protection.as_ref().unwrap().contains(&referrer_host) {
+            return false;
+        }
+
+        match self.guess_mime_type(req_p).0 {
+            MimeTopLevel::Image | MimeTopLevel::Video | MimeTopLevel::Audio => true,
+            _ => false,
+        }
+    }
+
+    fn handle_hotlinked(&self, req: &mut Request, req_p: PathBuf) -> IronResult<Response> {
+        log!(self.log,
+             "{} was refused hotlinked {magenta}{}{reset} from {red}{}{reset}",
+             self.remote_addresses(&req),
+             req_p.display(),
+             req.headers.get::<headers::Referer>().map(|r| &r[..]).unwrap_or(""));
+
+        self.handle_generated_error(req,
+                                    status::Forbidden,
+                                    &["403 Forbidden", "This file can't be embedded on other sites.", ""])
+    }
+
+    pub(super) fn handle_get_device(&self, req: &mut Request, req_p: PathBuf) -> IronResult<Response> {
+        log!(self.log,
+             "{} was refused device file {magenta}{}{reset}",
+             self.remote_addresses(&req),
+             req_p.display());
+
+        self.handle_generated_error(req,
+                                    status::Forbidden,
+                                    &["403 Forbidden",
+                                      "This is a device file, not a regular one.",
+                                      "<p>Ask the server administrator to pass <samp>--serve-devices</samp> to the executable \
+                                       to enable serving it.</p>"])
+    }
+
     fn handle_forbidden_method(&self, req: &mut Request, switch: &str, desc: &str) -> IronResult<Response> {
         log!(self.log,
              "{} used disabled request method {red}{}{reset} grouped under {}",
@@ -1168,93 +2343,138 @@ impl HttpHandler {
              req.method,
              desc);
 
-        self.handle_generated_response_encoding(req,
-                                                status::Forbidden,
-                                                html_response(ERROR_HTML,
-                                                              &["403 Forbidden",
-                                                                "This feature is currently disabled.",
-                                                                &format!("<p>Ask the server administrator to pass <samp>{}</samp> to the executable to \
-                                                                          enable support for {}.</p>",
-                                                                         switch,
-                                                                         desc)]))
+        self.handle_generated_error(req,
+                                    status::Forbidden,
+                                    &["403 Forbidden",
+                                      "This feature is currently disabled.",
+                                      &format!("<p>Ask the server administrator to pass <samp>{}</samp> to the executable to \
+                                                enable support for {}.</p>",
+                                               switch,
+                                               desc)])
     }
 
     fn handle_bad_method(&self, req: &mut Request) -> IronResult<Response> {
+        if DAV_LEVEL_2_METHODS.contains(&req.method) || DAV_UNSUPPORTED_METHODS.contains(&req.method) {
+            return self.handle_disallowed_method(req,
+                                                 &[&[method::Options, method::Get, method::Post, method::Put, method::Delete, method::Head, method::Trace],
+                                                   if self.webdav {
+                                                       &DAV_LEVEL_2_METHODS[..]
+                                                   } else {
+                                                       &[]
+                                                   }],
+                                                 if self.webdav {
+                                                     "WebDAV server without this extension"
+                                                 } else {
+                                                     "server without WebDAV"
+                                                 });
+        }
+
         log!(self.log,
              "{} used invalid request method {red}{}{reset}",
              self.remote_addresses(&req),
              req.method);
 
-        let last_p = format!("<p>Unsupported request method: {}.<br />\nSupported methods: {}{}OPTIONS, GET, PUT, DELETE, HEAD, and TRACE.</p>",
+        let last_p = format!("<p>Unsupported request method: {}.<br />\nSupported methods: {}{}OPTIONS, GET, POST, PUT, DELETE, HEAD, and TRACE.</p>",
                              req.method,
                              CommaList(if self.webdav {
-                                     &DAV_LEVEL_1_METHODS[..]
+                                     &DAV_LEVEL_2_METHODS[..]
                                  } else {
                                      &[][..]
                                  }
                                  .iter()),
                              if self.webdav { ", " } else { "" });
-        self.handle_generated_response_encoding(req,
-                                                status::NotImplemented,
-                                                html_response(ERROR_HTML, &["501 Not Implemented", "This operation was not implemented.", &last_p]))
+        self.handle_generated_error(req,
+                                    status::NotImplemented,
+                                    &["501 Not Implemented", "This operation was not implemented.", &last_p])
     }
 
-    fn handle_generated_response_encoding(&self, req: &mut Request, st: status::Status, resp: String) -> IronResult<Response> {
-        if let Some(encoding) = req.headers.get_mut::<headers::AcceptEncoding>().and_then(|es| response_encoding(&mut **es)) {
-            let cache_key = (blake3::hash(resp.as_bytes()), encoding.to_string());
+    /// An error page, or, for clients preferring JSON, `{"status": 404, "error": "404 Not Found", "message": "...", "detail": "..."}`,
+    /// with the HTML of `parts` (title, message, and detail, as for `ERROR_HTML`) rendered as text, and an empty detail as `null`
+    fn handle_generated_error(&self, req: &mut Request, st: status::Status, parts: &[&str]) -> IronResult<Response> {
+        if !client_prefers_json(&req.headers) {
+            return self.handle_generated_response_encoding(req, st, html_response(ERROR_HTML, parts));
+        }
+
+        let detail = html_to_text(parts[2]);
+        Ok(Response::with((st,
+                           Header(headers::Server(USER_AGENT.to_string())),
+                           "application/json;charset=utf-8".parse::<mime::Mime>().unwrap(),
+                           serde_json::to_string(&json!({
+                                   "status": st.to_u16(),
+                                   "error": parts[0],
+                                   "message": html_to_text(parts[1]),
+                                   "detail": if detail.is_empty() { None } else { Some(detail) },
+                               }))
+                               .unwrap())))
+    }
 
+    fn handle_generated_response_encoding(&self, req: &mut Request, st: status::Status, resp: String) -> IronResult<Response> {
+        // Small responses aren't worth encoding, unless the client won't take them as-is
+        if let Some(encoding) = req.headers
+            .get::<headers::AcceptEncoding>()
+            .filter(|es| resp.len() as u64 > self.encoding_thresholds.0 || !identity_acceptable(es))
+            .and_then(|es| response_encoding(es)) {
             {
-                if let Some(enc_resp) = self.cache_gen.read().expect("Generated file cache read lock poisoned").get(&cache_key) {
+                if let Some(enc_resp) = self.cache_gen.read().unwrap_or_else(PoisonError::into_inner).get(&resp, &encoding) {
                     log!(self.log,
                          "{} encoded as {} for {:.1}% ratio (cached)",
-                         Spaces(self.remote_addresses(req).to_string().len()),
+                         Spaces(self.remote_addresses(req).width()),
                          encoding,
                          ((resp.len() as f64) / (enc_resp.len() as f64)) * 100f64);
 
-                    return Ok(Response::with((st,
-                                              Header(headers::Server(USER_AGENT.to_string())),
-                                              Header(headers::ContentEncoding(vec![encoding])),
-                                              "text/html;charset=utf-8".parse::<mime::Mime>().unwrap(),
-                                              &enc_resp[..])));
+                    return Ok(vary_encoding(Response::with((st,
+                                                            Header(headers::Server(USER_AGENT.to_string())),
+                                                            Header(headers::ContentEncoding(vec![encoding])),
+                                                            "text/html;charset=utf-8".parse::<mime::Mime>().unwrap(),
+                                                            enc_resp))));
                 }
             }
 
             if let Some(enc_resp) = encode_str(&resp, &encoding) {
                 log!(self.log,
                      "{} encoded as {} for {:.1}% ratio",
-                     Spaces(self.remote_addresses(req).to_string().len()),
+                     Spaces(self.remote_addresses(req).width()),
                      encoding,
                      ((resp.len() as f64) / (enc_resp.len() as f64)) * 100f64);
 
-                let mut cache = self.cache_gen.write().expect("Generated file cache read lock poisoned");
-                cache.insert(cache_key.clone(), enc_resp);
-
-                return Ok(Response::with((st,
-                                          Header(headers::Server(USER_AGENT.to_string())),
-                                          Header(headers::ContentEncoding(vec![encoding])),
-                                          "text/html;charset=utf-8".parse::<mime::Mime>().unwrap(),
-                                          &cache[&cache_key][..])));
+                let response = Response::with((st,
+                                               Header(headers::Server(USER_AGENT.to_string())),
+                                               Header(headers::ContentEncoding(vec![encoding.clone()])),
+                                               "text/html;charset=utf-8".parse::<mime::Mime>().unwrap(),
+                                               &enc_resp[..]));
+                self.cache_gen.write().unwrap_or_else(PoisonError::into_inner).insert(resp, encoding, enc_resp, self.generated_cache_size);
+                return Ok(vary_encoding(response));
             } else {
                 log!(self.log,
                      "{} failed to encode as {}, sending identity",
-                     Spaces(self.remote_addresses(req).to_string().len()),
+                     Spaces(self.remote_addresses(req).width()),
                      encoding);
             }
         }
 
-        Ok(Response::with((st, Header(headers::Server(USER_AGENT.to_string())), "text/html;charset=utf-8".parse::<mime::Mime>().unwrap(), resp)))
+        Ok(vary_encoding(Response::with((st, Header(headers::Server(USER_AGENT.to_string())), "text/html;charset=utf-8".parse::<mime::Mime>().unwrap(), resp))))
     }
 
-    fn handle_requested_entity_unopenable(&self, req: &mut Request, e: IoError, entity_type: &str) -> IronResult<Response> {
-        if e.kind() == IoErrorKind::PermissionDenied {
-            self.handle_generated_response_encoding(req,
-                                                    status::Forbidden,
-                                                    html_response(ERROR_HTML, &["403 Forbidden", &format!("Can't access {}.", url_path(&req.url)), ""]))
-        } else {
-            // The ops that get here (File::open(), fs::read_dir()) can't return any other errors by the time they're run
-            // (and even if it could, there isn't much we can do about them)
-            panic!("Failed to read requested {}: {:?}", entity_type, e)
+    fn handle_not_acceptable(&self, req: &mut Request) -> IronResult<Response> {
+        log!(self.log,
+             "{} requested {yellow}{}{reset} but accepts none of its content encodings",
+             self.remote_addresses(req),
+             url_path(&req.url));
+
+        let mut resp = self.handle_generated_error(req,
+                                                   status::NotAcceptable,
+                                                   &["406 Not Acceptable",
+                                                     "This can't be sent in any content encoding you accept.",
+                                                     &format!("<p>Supported content encodings: identity, {}.</p>",
+                                                              CommaList(SUPPORTED_ENCODINGS.iter()))])?;
+        if req.method == method::Head {
+            resp.body = None;
         }
+        Ok(resp)
+    }
+
+    fn handle_requested_entity_unopenable(&self, req: &mut Request, e: IoError, entity_type: &str) -> IronResult<Response> {
+        self.handle_fs_error(req, e, &format!("opening the {}", entity_type))
     }
 
     fn handle_raw_fs_api_response<R: Serialize>(&self, st: status::Status, resp: &R) -> IronResult<Response> {
@@ -1270,14 +2490,21 @@ impl HttpHandler {
     }
 
     fn parse_requested_path_custom_symlink(&self, req_url: &GenericUrl, follow_symlinks: bool) -> (PathBuf, bool, bool) {
-        let mut depth_left = MAX_SYMLINKS;
+        let mut depth_left = self.max_symlink_depth;
         let (mut cur, sk, err, abs) = req_url.path_segments()
             .unwrap()
             .filter(|p| !p.is_empty())
             .fold((self.hosted_directory.1.clone(), false, false, true),
                   |(mut cur, mut sk, mut err, mut abs), pp| {
-                if let Some(pp) = percent_decode(pp) {
+                if let Some(pp) = percent_decode_path_segment(pp) {
                     cur.push(&*pp);
+                    if self.normalise_unicode && !cur.exists() {
+                        if let Some(actual) = cur.parent().and_then(|p| find_normalised(p, &pp)) {
+                            cur.set_file_name(actual);
+                        }
+                    }
+                } else if let Some(pp) = percent_decode_non_utf8_path_segment(pp) {
+                    cur.push(pp);
                 } else {
                     err = true;
                 }
@@ -1289,7 +2516,7 @@ impl HttpHandler {
                         } else {
                             abs = false;
                             cur.pop();
-                            cur.push(newlink);
+                            push_relative(&mut cur, &newlink);
                         }
                         depth_left -= 1;
                     } else {
@@ -1320,8 +2547,49 @@ impl HttpHandler {
         AddressWriter {
             request: req,
             proxies: &self.proxies,
-            log: self.log,
+            log: (self.log.0.on(), self.log.1),
+        }
+    }
+
+    /// Whether the specified file should be served encoded, if possible
+    fn should_encode(&self, req_p: &Path, mime_type: &Mime, flen: u64) -> bool {
+        let compressible = self.mime_compression_policy(mime_type)
+            .unwrap_or_else(|| req_p.extension().and_then(|s| s.to_str()).map(|s| !BLACKLISTED_ENCODING_EXTENSIONS.contains(&UniCase::new(s))).unwrap_or(true));
+        (self.encoded_store.is_some() || self.encoded_memory.is_some()) && flen > self.encoding_thresholds.0 && flen < self.encoding_thresholds.1 && compressible && !self.encoding_paused()
+    }
+
+    /// Stop encoding files for a while, after running out of space
+    pub(super) fn pause_encoding(&self) {
+        *self.encoding_paused_until.lock().unwrap_or_else(PoisonError::into_inner) = Some(Instant::now() + DISK_FULL_ENCODING_PAUSE);
+        log!(self.log,
+             "{red}Out of space{reset}, not encoding files for {}s",
+             DISK_FULL_ENCODING_PAUSE.as_secs());
+    }
+
+    fn encoding_paused(&self) -> bool {
+        let mut until = self.encoding_paused_until.lock().unwrap_or_else(PoisonError::into_inner);
+        match *until {
+            Some(u) if Instant::now() < u => true,
+            Some(_) => {
+                *until = None;
+                log!(self.log, "Resuming encoding files");
+                false
+            }
+            None => false,
+        }
+    }
+
+    /// Whether files of the specified MIME type should always or never be encoded, with `TYPE/SUBTYPE` taking precedence over `TYPE/*`,
+    /// or `None` to decide based on the extension
+    fn mime_compression_policy(&self, mt: &Mime) -> Option<bool> {
+        if self.compression_policy.is_empty() {
+            return None;
         }
+
+        let top = mt.0.to_string().to_lowercase();
+        (self.compression_policy.get(&format!("{}/{}", top, mt.1.to_string().to_lowercase())))
+            .or_else(|| self.compression_policy.get(&format!("{}/*", top)))
+            .cloned()
     }
 
     fn guess_mime_type(&self, req_p: &Path) -> Mime {
@@ -1342,27 +2610,157 @@ impl Clone for HttpHandler {
     fn clone(&self) -> HttpHandler {
         HttpHandler {
             hosted_directory: self.hosted_directory.clone(),
-            follow_symlinks: self.follow_symlinks,
-            sandbox_symlinks: self.sandbox_symlinks,
+            single_file: self.single_file.clone(),
+            symlink_policy: self.symlink_policy,
+            max_symlink_depth: self.max_symlink_depth,
             generate_listings: self.generate_listings,
+            listing_permissions: self.listing_permissions,
+            listing_downloads: self.listing_downloads,
             check_indices: self.check_indices,
             strip_extensions: self.strip_extensions,
-            log: self.log,
+            normalise_unicode: self.normalise_unicode,
+            serve_devices: self.serve_devices,
+            log: self.log.clone(),
+            log_tags: self.log_tags,
+            unlogged_paths: self.unlogged_paths.clone(),
+            log_headers: self.log_headers.clone(),
             webdav: self.webdav,
+            s3: self.s3,
+            deploy: self.deploy,
+            delta_sync: self.delta_sync,
+            torrent_min_size: self.torrent_min_size,
+            offline_bundle: self.offline_bundle,
+            xattrs: self.xattrs,
+            admin: self.admin,
+            inbox: self.inbox,
+            method_override: self.method_override,
+            origin: self.origin.clone(),
+            origin_ttl: self.origin_ttl,
             global_auth_data: self.global_auth_data.clone(),
+            public_files: self.public_files,
             path_auth_data: self.path_auth_data.clone(),
             writes_temp_dir: self.writes_temp_dir.clone(),
-            encoded_temp_dir: self.encoded_temp_dir.clone(),
+            hosted_temp_dir: self.hosted_temp_dir.clone(),
+            max_upload_size: self.max_upload_size,
+            write_hours: self.write_hours,
+            retention: self.retention.clone(),
+            retention_dry_run: self.retention_dry_run,
+            write_replicator: self.write_replicator.clone(),
+            encoded_store: self.encoded_store.clone(),
+            encoded_memory: self.encoded_memory.clone(),
+            encoding_thresholds: self.encoding_thresholds,
+            max_encodings: self.max_encodings,
+            origin_temp_dir: self.origin_temp_dir.clone(),
+            torrent_temp_dir: self.torrent_temp_dir.clone(),
+            mirror_temp_dir: self.mirror_temp_dir.clone(),
+            downloads_temp_dir: self.downloads_temp_dir.clone(),
+            download_counts: self.download_counts.clone(),
+            fd_cache: self.fd_cache.clone(),
+            bandwidth_accounts: self.bandwidth_accounts.clone(),
+            serve_limits: self.serve_limits.clone(),
+            stdin: self.stdin.clone(),
+            allowed_hosts: self.allowed_hosts.clone(),
+            hotlink_protection: self.hotlink_protection.clone(),
             proxies: self.proxies.clone(),
             mime_type_overrides: self.mime_type_overrides.clone(),
+            cache_max_age: self.cache_max_age,
+            cache_max_age_overrides: self.cache_max_age_overrides.clone(),
+            range_window: self.range_window,
+            mmap_min_size: self.mmap_min_size,
+            serve_precompressed: self.serve_precompressed,
+            content_hash_etags: self.content_hash_etags,
+            preloads: self.preloads.clone(),
+            compression_policy: self.compression_policy.clone(),
             additional_headers: self.additional_headers.clone(),
-            cache_gen: Default::default(),
-            cache_fs: Default::default(),
+            warm_up: self.warm_up.clone(),
+            tls_identity: self.tls_identity.clone(),
+            generated_cache_size: self.generated_cache_size,
+            cache_gen: self.cache_gen.clone(),
+            cache_fs: self.cache_fs.clone(),
+            cache_hash: self.cache_hash.clone(),
+            cache_fingerprint: self.cache_fingerprint.clone(),
+            encoding_slots: self.encoding_slots.clone(),
+            encoding_paused_until: self.encoding_paused_until.clone(),
+            deploy_lock: self.deploy_lock.clone(),
+            sync_snapshots: self.sync_snapshots.clone(),
+            webdav_locks: self.webdav_locks.clone(),
+            uploads: self.uploads.clone(),
+            origin_client: self.origin_client.clone(),
+            origin_validated: self.origin_validated.clone(),
         }
     }
 }
 
 
+/// Whether to log serving status, shared with clones so that it can be switched while running
+#[derive(Debug, Clone)]
+pub struct LogSwitch(Arc<AtomicBool>);
+
+impl LogSwitch {
+    pub fn new(on: bool) -> LogSwitch {
+        LogSwitch(Arc::new(AtomicBool::new(on)))
+    }
+
+    pub fn on(&self) -> bool {
+        self.0.load(AtomicOrdering::Relaxed)
+    }
+
+    pub fn set(&self, on: bool) {
+        self.0.store(on, AtomicOrdering::Relaxed)
+    }
+}
+
+thread_local! {
+    /// Lines logged while handling a request with `--log-tags` or to an `--unlogged` path, with when, held until its status is known
+    static REQUEST_LOG: RefCell<Option<Vec<(String, String)>>> = const { RefCell::new(None) };
+}
+
+/// Print an uncoloured `log!()` line, or hold it if a request's being handled with held lines on this thread
+pub fn log_plain(line: String) {
+    log_line(line, false)
+}
+
+/// Print a coloured `log!()` line, or hold it if a request's being handled with held lines on this thread
+pub fn log_coloured(line: String) {
+    log_line(line, true)
+}
+
+fn log_line(line: String, colour: bool) {
+    let stamp = time::now().strftime("%F %T").unwrap().to_string();
+    REQUEST_LOG.with(|held| match *held.borrow_mut() {
+        Some(ref mut held) => held.push((stamp, line)),
+        None => print_log_line(&stamp, &line, colour),
+    })
+}
+
+fn print_log_line(stamp: &str, line: &str, colour: bool) {
+    use trivial_colours::{Reset as CReset, Colour as C};
+
+    if colour {
+        println!("{}[{}]{} {}", C::Cyan, stamp, CReset, line);
+    } else {
+        println!("[{}] {}", stamp, line);
+    }
+}
+
+/// Whether to log at all, as used by `log!()`, for both fixed and switchable configurations
+pub trait LogEnabled {
+    fn log_enabled(&self) -> bool;
+}
+
+impl LogEnabled for bool {
+    fn log_enabled(&self) -> bool {
+        *self
+    }
+}
+
+impl LogEnabled for LogSwitch {
+    fn log_enabled(&self) -> bool {
+        self.on()
+    }
+}
+
+
 pub struct AddressWriter<'r, 'p, 'ra, 'rb: 'ra> {
     pub request: &'r Request<'ra, 'rb>,
     pub proxies: &'p BTreeMap<IpCidr, String>,
@@ -1370,6 +2768,23 @@ pub struct AddressWriter<'r, 'p, 'ra, 'rb: 'ra> {
     pub log: (bool, bool),
 }
 
+impl<'r, 'p, 'ra, 'rb: 'ra> AddressWriter<'r, 'p, 'ra, 'rb> {
+    /// How long the formatted addresses are, for indenting continuation log lines, without allocating them
+    pub fn width(&self) -> usize {
+        struct Counter(usize);
+        impl fmt::Write for Counter {
+            fn write_str(&mut self, s: &str) -> fmt::Result {
+                self.0 += s.len();
+                Ok(())
+            }
+        }
+
+        let mut counter = Counter(0);
+        let _ = fmt::write(&mut counter, format_args!("{}", self));
+        counter.0
+    }
+}
+
 impl<'r, 'p, 'ra, 'rb: 'ra> fmt::Display for AddressWriter<'r, 'p, 'ra, 'rb> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         use trivial_colours::{Reset as CReset, Colour as C};
@@ -1452,6 +2867,81 @@ pub fn try_ports<H: Handler + Clone>(hndlr: H, addr: IpAddr, from: u16, up_to: u
     })
 }
 
+/// Start servers accepting connections on sockets tuned as specified, on the first free port in the specified range
+/// if not on the specified one: one on an ordinary socket, or `listeners` on the same port,
+/// each on its own `SO_REUSEPORT` socket with its own accept loop
+pub fn try_tuned_ports<H: Handler + Clone>(hndlr: H, addr: IpAddr, port: Option<u16>, from: u16, up_to: u16, listeners: Option<NonZeroUsize>,
+                                           tuning: &TcpTuning, tls_data: &Option<((String, PathBuf), String)>)
+                                           -> Result<Vec<Listening>, Error> {
+    let reuseport = listeners.is_some();
+    let start_error = |err: IoError| {
+        Error {
+            desc: "server",
+            op: "start",
+            more: err.to_string().into(),
+        }
+    };
+    let tls = match tls_data.as_ref() {
+        Some(&((_, ref id), ref pw)) => {
+            Some(NativeTlsServer::new(id, pw).map_err(|err| {
+                    Error {
+                        desc: "TLS certificate",
+                        op: "open",
+                        more: err.to_string().into(),
+                    }
+                })?)
+        }
+        None => None,
+    };
+
+    let first = match port {
+        Some(port) => bind_listener(SocketAddr::new(addr, port), reuseport, tuning).map_err(start_error)?,
+        None => {
+            let mut first = None;
+            for port in from..up_to + 1 {
+                match bind_listener(SocketAddr::new(addr, port), reuseport, tuning) {
+                    Ok(socket) => {
+                        first = Some(socket);
+                        break;
+                    }
+                    Err(ref err) if err.kind() == IoErrorKind::AddrInUse => {}
+                    Err(err) => return Err(start_error(err)),
+                }
+            }
+            first.ok_or_else(|| {
+                    Error {
+                        desc: "server",
+                        op: "start",
+                        more: "no free ports".into(),
+                    }
+                })?
+        }
+    };
+    // Port 0 got one picked by the OS, which the rest have to share
+    let addr = first.local_addr().map_err(start_error)?;
+    let mut sockets = vec![first];
+    for _ in 1..listeners.map(NonZeroUsize::get).unwrap_or(1) {
+        sockets.push(bind_listener(addr, reuseport, tuning).map_err(start_error)?);
+    }
+
+    sockets.into_iter()
+        .map(|socket| {
+            let ir = Iron::new(hndlr.clone());
+            match tls.clone() {
+                    Some(tls) => ir.listen(HttpsListener::with_listener(HttpListener::from(socket), tls), Protocol::https()),
+                    None => ir.listen(HttpListener::from(socket), Protocol::http()),
+                }
+                .map_err(|err| {
+                    Error {
+                        desc: "server",
+                        op: "start",
+                        more: err.to_string().into(),
+                    }
+                })
+        })
+        .collect()
+}
+
 /// Generate a passwordless self-signed certificate in the `"tls"` subdirectory of the specified directory
 /// with the filenames `"tls.*"`.
 ///
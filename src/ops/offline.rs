@@ -0,0 +1,98 @@
+//! Offline bundles for small static sites: `/dir/?offline=manifest` lists everything under the directory,
+//! and `/dir/?offline=sw` is a service worker caching all of it, so the site keeps working without a network.
+//!
+//! Both are generated from a walk skipping the same entries listings do, versioned by the files' paths, sizes, and
+//! modification times, so the service worker gets updated and re-caches whenever anything changes.
+
+
+use self::super::super::util::{file_time_modified, file_name_bytes, USER_AGENT, PATH_SEGMENT_ENCODE_SET, OFFLINE_SERVICE_WORKER_JS};
+use percent_encoding::percent_encode;
+use iron::{headers, status, IronResult, Response, Request};
+use iron::modifiers::Header;
+use self::super::HttpHandler;
+use std::path::PathBuf;
+use walkdir::WalkDir;
+use serde_json;
+
+
+impl HttpHandler {
+    /// Which part of the offline bundle was requested with `?offline=`, if any
+    pub(super) fn offline_bundle_requested(&self, req: &Request) -> Option<String> {
+        req.url.as_ref().query_pairs().find(|(k, _)| k == "offline").map(|(_, v)| v.into_owned())
+    }
+
+    pub(super) fn handle_get_offline_bundle(&self, req: &mut Request, req_p: PathBuf, part: String) -> IronResult<Response> {
+        if !self.offline_bundle {
+            return self.handle_forbidden_method(req, "--offline-bundle", "offline bundles");
+        }
+        if part != "manifest" && part != "sw" {
+            return self.handle_invalid_url(req, "<p>Offline bundle part must be either manifest or sw.</p>");
+        }
+
+        let mut scope = req.url.as_ref().path().to_string();
+        if !scope.ends_with('/') {
+            scope.push('/');
+        }
+
+        // Entries are visited in a stable order, so the version only changes with the files
+        let mut version = blake3::Hasher::new();
+        let mut files = vec![scope.clone()];
+        for e in WalkDir::new(&req_p)
+            .min_depth(1)
+            .follow_links(self.symlink_policy.follow())
+            .sort_by(|lhs, rhs| lhs.file_name().cmp(rhs.file_name()))
+            .into_iter()
+            .filter_entry(|e| self.listed(e.path()))
+            .flatten() {
+            let meta = match e.metadata() {
+                Ok(meta) => meta,
+                Err(_) => continue,
+            };
+            let mut url = scope.clone();
+            for (i, segment) in e.path().strip_prefix(&req_p).expect("Walked outside requested directory").iter().enumerate() {
+                if i != 0 {
+                    url.push('/');
+                }
+                url.extend(percent_encode(&file_name_bytes(segment), PATH_SEGMENT_ENCODE_SET));
+            }
+            if meta.is_dir() {
+                url.push('/');
+            }
+
+            version.update(url.as_bytes());
+            version.update(&meta.len().to_le_bytes());
+            version.update(file_time_modified(&meta).rfc3339().to_string().as_bytes());
+            files.push(url);
+        }
+        let version = version.finalize().to_hex()[..16].to_string();
+
+        log!(self.log,
+             "{} was served offline bundle {} of {magenta}{}{reset} with {} entries",
+             self.remote_addresses(req),
+             part,
+             req_p.display(),
+             files.len());
+
+        if part == "manifest" {
+            Ok(Response::with((status::Ok,
+                               Header(headers::Server(USER_AGENT.to_string())),
+                               Header(headers::CacheControl(vec![headers::CacheDirective::NoCache])),
+                               "application/json;charset=utf-8".parse::<iron::mime::Mime>().unwrap(),
+                               serde_json::to_string(&json!({
+                                       "version": version,
+                                       "scope": scope,
+                                       "files": files,
+                                   }))
+                                   .expect("Failed to serialise offline manifest"))))
+        } else {
+            let sw = OFFLINE_SERVICE_WORKER_JS.replace("{files}", &serde_json::to_string(&files).expect("Failed to serialise offline files"))
+                .replace("{version}", &version)
+                .replace("{scope}", &scope);
+            Ok(Response::with((status::Ok,
+                               Header(headers::Server(USER_AGENT.to_string())),
+                               Header(headers::CacheControl(vec![headers::CacheDirective::NoCache])),
+                               "application/javascript;charset=utf-8".parse::<iron::mime::Mime>().unwrap(),
+                               sw)))
+        }
+    }
+}
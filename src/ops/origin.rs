@@ -0,0 +1,170 @@
+//! Read-through origin: GETs for files missing from the hosted directory are fetched from the origin,
+//! stored in place, and served as if they'd always been there.
+//!
+//! With a TTL, files older than it are served as-is while being revalidated against the origin in the background.
+
+
+use std::time::{Duration, SystemTime, Instant, UNIX_EPOCH};
+use iron::headers::{IfModifiedSince, LastModified, IfNoneMatch, EntityTag, HttpDate, ETag};
+use self::super::super::util::is_nonexistent_descendant_of;
+use std::io::{self, Error as IoError};
+use std::path::{PathBuf, Path};
+use iron::url::Url as GenericUrl;
+use std::collections::HashMap;
+use self::super::HttpHandler;
+use std::sync::{Arc, PoisonError, RwLock};
+use std::fs::{self, File};
+use hyper::status;
+use hyper::Client;
+use iron::Request;
+use std::thread;
+use time;
+
+
+/// When each file was last validated against the origin, and the ETag it had
+pub(super) type OriginValidated = Arc<RwLock<HashMap<PathBuf, (Instant, Option<EntityTag>)>>>;
+
+
+/// Result of asking the origin for a file
+enum OriginFetch {
+    Fetched(Option<EntityTag>),
+    NotModified,
+    Missing,
+}
+
+
+impl HttpHandler {
+    /// Try to fetch the file at the request URL from the origin into `req_p`, returning whether it now exists
+    pub(super) fn fetch_from_origin(&self, req: &Request, req_p: &Path) -> bool {
+        let (url, client) = match self.origin_url(req, req_p) {
+            Some(uc) => uc,
+            None => return false,
+        };
+
+        self.create_temp_dir(&self.origin_temp_dir);
+        match fetch_origin_file(client, &url, req_p, &self.origin_temp_dir.as_ref().unwrap().1, None) {
+            Ok(OriginFetch::Fetched(etag)) => {
+                self.origin_validated.write().unwrap_or_else(PoisonError::into_inner).insert(req_p.to_path_buf(), (Instant::now(), etag));
+                log!(self.log,
+                     "{} fetched {magenta}{}{reset} from origin {yellow}{}{reset}",
+                     self.remote_addresses(&req),
+                     req_p.display(),
+                     url);
+                true
+            }
+            Ok(_) => false,
+            Err(err) => {
+                log!(self.log,
+                     "{} failed to fetch {yellow}{}{reset} from origin: {red}{}{reset}",
+                     self.remote_addresses(&req),
+                     url,
+                     err);
+                false
+            }
+        }
+    }
+
+    /// If `req_p` hasn't been validated against the origin in the last TTL, do so in the background;
+    /// the current version is served in the meantime
+    pub(super) fn revalidate_from_origin(&self, req: &Request, req_p: &Path) {
+        let ttl = match self.origin_ttl {
+            Some(ttl) => ttl,
+            None => return,
+        };
+        let (url, client) = match self.origin_url(req, req_p) {
+            Some((url, client)) => (url, client.clone()),
+            None => return,
+        };
+        let etag = {
+            let mut validated = self.origin_validated.write().unwrap_or_else(PoisonError::into_inner);
+            let now = Instant::now();
+            let entry = validated.entry(req_p.to_path_buf()).or_insert((now.checked_sub(ttl + Duration::from_secs(1)).unwrap_or(now), None));
+            if entry.0 != now && entry.0.elapsed() <= ttl {
+                return;
+            }
+            // Claim the revalidation, so concurrent requests don't repeat it
+            entry.0 = now;
+            entry.1.clone()
+        };
+
+        self.create_temp_dir(&self.origin_temp_dir);
+        let temp_dir = self.origin_temp_dir.as_ref().unwrap().1.clone();
+        let validated = self.origin_validated.clone();
+        let req_p = req_p.to_path_buf();
+        let log = self.log.clone();
+        thread::spawn(move || {
+            let mtime = req_p.metadata().and_then(|m| m.modified()).ok();
+            match fetch_origin_file(&client, &url, &req_p, &temp_dir, Some((etag, mtime))) {
+                Ok(OriginFetch::Fetched(etag)) => {
+                    validated.write().unwrap_or_else(PoisonError::into_inner).insert(req_p.clone(), (Instant::now(), etag));
+                    log!(log, "Refreshed {magenta}{}{reset} from origin {yellow}{}{reset}", req_p.display(), url);
+                }
+                Ok(_) => {}
+                Err(err) => {
+                    log!(log, "Failed to revalidate {yellow}{}{reset} against origin: {red}{}{reset}", url, err);
+                }
+            }
+        });
+    }
+
+    fn origin_url(&self, req: &Request, req_p: &Path) -> Option<(GenericUrl, &Arc<Client>)> {
+        let (origin, client) = match (self.origin.as_ref(), self.origin_client.as_ref()) {
+            (Some(o), Some(c)) => (o, c),
+            _ => return None,
+        };
+        let segments = req.url.path();
+        if segments.last().map(|s| s.is_empty()).unwrap_or(true) || !is_nonexistent_descendant_of(req_p, &self.hosted_directory.1) {
+            return None;
+        }
+
+        origin.join(&segments.join("/")).ok().map(|url| (url, client))
+    }
+}
+
+
+/// GET `url` into `req_p` via a file in `temp_dir`, conditionally on the specified ETag and modification time, if any.
+///
+/// The fetched file's modification time is set to the origin's `Last-Modified`, so it can be used for revalidation later.
+fn fetch_origin_file(client: &Client, url: &GenericUrl, req_p: &Path, temp_dir: &Path, validators: Option<(Option<EntityTag>, Option<SystemTime>)>)
+                     -> Result<OriginFetch, IoError> {
+    let mut req = client.get(url.clone());
+    if let Some((etag, mtime)) = validators {
+        if let Some(etag) = etag {
+            req = req.header(IfNoneMatch::Items(vec![etag]));
+        }
+        if let Some(mtime) = mtime.and_then(|m| m.duration_since(UNIX_EPOCH).ok()) {
+            req = req.header(IfModifiedSince(HttpDate(time::at_utc(time::Timespec::new(mtime.as_secs() as i64, 0)))));
+        }
+    }
+
+    let mut resp = req.send().map_err(|e| IoError::new(io::ErrorKind::Other, e.to_string()))?;
+    match resp.status {
+        status::StatusCode::Ok => {}
+        status::StatusCode::NotModified => return Ok(OriginFetch::NotModified),
+        _ => return Ok(OriginFetch::Missing),
+    }
+
+    // Download next to nothing that's served, so that a half-fetched file is never visible
+    let temp_p = temp_dir.join(format!("{}-{}",
+                                       SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos()).unwrap_or(0),
+                                       req_p.file_name().and_then(|n| n.to_str()).unwrap_or("origin")));
+    let last_modified = resp.headers.get::<LastModified>().map(|lm| {
+        let ts = (lm.0).0.to_timespec();
+        UNIX_EPOCH + Duration::from_secs(ts.sec.max(0) as u64)
+    });
+    let fetched = (|| {
+        {
+            let mut temp_f = File::create(&temp_p)?;
+            io::copy(&mut resp, &mut temp_f)?;
+            if let Some(lm) = last_modified {
+                temp_f.set_modified(lm)?;
+            }
+        }
+        if let Some(parent) = req_p.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::rename(&temp_p, req_p).or_else(|_| fs::copy(&temp_p, req_p).map(|_| ()))
+    })();
+    let _ = fs::remove_file(&temp_p);
+    fetched.map(|_| OriginFetch::Fetched(resp.headers.get::<ETag>().map(|e| e.0.clone())))
+}
@@ -0,0 +1,55 @@
+//! Panic isolation: a panic while handling one request gets it a 500, instead of taking the connection down with it,
+//! and the caches it may have poisoned are flushed, so they don't fail every request after.
+
+
+use std::panic::{self, AssertUnwindSafe};
+use iron::{status, IronResult, Response, Request};
+use self::super::super::util::url_path;
+use std::sync::PoisonError;
+use self::super::HttpHandler;
+use std::any::Any;
+
+
+impl HttpHandler {
+    /// Handle the request, catching any panic in doing so
+    pub(super) fn handle_isolated(&self, req: &mut Request) -> IronResult<Response> {
+        match panic::catch_unwind(AssertUnwindSafe(|| self.handle_request(req))) {
+            Ok(resp) => resp,
+            Err(payload) => {
+                self.recover_from_panic();
+                log!(self.log,
+                     "{} requested to {red}{}{reset} {yellow}{}{reset} but handling it {red}panicked{reset}: {}",
+                     self.remote_addresses(req),
+                     req.method,
+                     url_path(&req.url),
+                     panic_message(&*payload));
+
+                self.handle_generated_error(req,
+                                            status::InternalServerError,
+                                            &["500 Internal Server Error", "The server failed to handle the request.", ""])
+            }
+        }
+    }
+
+    /// Flush poisoned caches and unpoison the locks a panic may have been holding; what they guard stays consistent
+    fn recover_from_panic(&self) {
+        if self.cache_gen.is_poisoned() {
+            *self.cache_gen.write().unwrap_or_else(PoisonError::into_inner) = Default::default();
+            self.cache_gen.clear_poison();
+        }
+        self.cache_fs.recover();
+        self.cache_hash.recover();
+
+        self.encoding_slots.in_flight.clear_poison();
+        self.encoding_paused_until.clear_poison();
+        self.deploy_lock.clear_poison();
+    }
+}
+
+
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    payload.downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(|s| &s[..]))
+        .unwrap_or("unknown cause")
+}
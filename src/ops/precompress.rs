@@ -0,0 +1,103 @@
+use self::super::super::util::{file_length, encode_file, is_storage_full, SUPPORTED_ENCODINGS};
+use self::super::{HttpHandler, WarmUpTask};
+use std::path::PathBuf;
+use walkdir::WalkDir;
+use std::sync::{Mutex, PoisonError};
+use regex::Regex;
+use std::thread;
+use std::fs;
+
+
+impl HttpHandler {
+    /// Encode all files in the hosted directory whose relative paths match any of the specified globs
+    /// into the encoded store, `max_encodings` at a time, returning how many encoded files were kept.
+    ///
+    /// Requests then pick the encoded files up as if they'd encoded them themselves.
+    pub fn precompress(&self, globs: &[Regex], warm_up: WarmUpTask) -> usize {
+        if self.encoded_store.is_none() {
+            return 0;
+        }
+        self.create_temp_dir(&self.encoded_store.as_ref().map(|s| s.dir().clone()));
+
+        let queue = Mutex::new(WalkDir::new(&self.hosted_directory.1)
+            .follow_links(self.symlink_policy.follow())
+            .into_iter()
+            .filter_entry(|e| !self.in_temp_dir(e.path()))
+            .flatten()
+            .filter(|e| e.file_type().is_file())
+            .filter(|e| {
+                e.path()
+                    .strip_prefix(&self.hosted_directory.1)
+                    .map(|rp| {
+                        let rp = rp.to_string_lossy().replace('\\', "/");
+                        globs.iter().any(|g| g.is_match(&rp))
+                    })
+                    .unwrap_or(false)
+            })
+            .map(|e| e.into_path())
+            .collect::<Vec<_>>());
+        let kept = Mutex::new(0);
+
+        thread::scope(|s| for _ in 0..self.max_encodings {
+            s.spawn(|| loop {
+                let next = queue.lock().unwrap_or_else(PoisonError::into_inner).pop();
+                match next {
+                    Some(p) => *kept.lock().unwrap_or_else(PoisonError::into_inner) += self.precompress_file(p),
+                    None => break,
+                }
+            });
+        });
+
+        drop(warm_up);
+        let kept = kept.into_inner().unwrap_or_else(PoisonError::into_inner);
+        log!(self.log, "Precompression done, kept {} encoded files", kept);
+        kept
+    }
+
+    fn precompress_file(&self, req_p: PathBuf) -> usize {
+        let flen = match req_p.metadata() {
+            Ok(m) => file_length(&m, &req_p),
+            Err(_) => return 0,
+        };
+        if !self.should_encode(&req_p, &self.guess_mime_type(&req_p), flen) {
+            return 0;
+        }
+        // Remembered, so serving it doesn't hash it again
+        let hash = match self.cached_file_hash(&req_p) {
+            Ok(h) => h,
+            Err(_) => return 0,
+        };
+
+        // The x-* aliases are encoded in the same way
+        let mut kept = 0;
+        for encoding in SUPPORTED_ENCODINGS.iter().filter(|e| !e.to_string().starts_with("x-")) {
+            let resp_p = self.encoded_path(&req_p, &hash, encoding);
+            if resp_p.exists() {
+                continue;
+            }
+            match encode_file(&req_p, &resp_p, encoding) {
+                Ok(()) => (),
+                Err(ref err) if is_storage_full(err) => {
+                    self.pause_encoding();
+                    return kept;
+                }
+                Err(_) => continue,
+            }
+
+            let gain = flen as f64 / resp_p.metadata().map(|m| file_length(&m, &resp_p)).unwrap_or(flen) as f64;
+            if gain < self.encoding_thresholds.2 {
+                let _ = fs::remove_file(&resp_p);
+            } else if !self.encoded_store.as_ref().unwrap().stored(&resp_p) {
+                continue;
+            } else {
+                log!(self.log,
+                     "Precompressed {magenta}{}{reset} as {} for {:.1}% ratio",
+                     req_p.display(),
+                     encoding,
+                     gain * 100f64);
+                kept += 1;
+            }
+        }
+        kept
+    }
+}
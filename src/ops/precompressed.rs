@@ -0,0 +1,58 @@
+//! Precompressed siblings, enabled with `--serve-precompressed`: `foo.js.br`, `foo.js.gz`, or `foo.js.zst` next to `foo.js`
+//! are sent as `foo.js`, encoded, to clients accepting their encoding, like nginx's `gzip_static` and Caddy's `precompressed`,
+//! so build pipelines' compressed outputs are used instead of encoding the files again.
+
+
+use self::super::super::util::{USER_AGENT, Spaces, file_time_modified_p, response_encoding_of};
+use iron::{headers, status, IronResult, Response, Request};
+use iron::modifiers::Header;
+use self::super::HttpHandler;
+use std::path::{PathBuf, Path};
+use iron::mime::Mime;
+use std::ffi::OsString;
+
+
+/// Sibling extensions and the encodings they're in
+const PRECOMPRESSED_EXTENSIONS: &[(&str, &str)] = &[("br", "br"), ("gz", "gzip"), ("zst", "zstd")];
+
+
+impl HttpHandler {
+    /// The precompressed sibling of `req_p` in the encoding the client likes best, if any, with `--serve-precompressed`
+    pub(super) fn precompressed_sibling(&self, req: &Request, req_p: &Path) -> Option<(PathBuf, headers::Encoding)> {
+        if !self.serve_precompressed {
+            return None;
+        }
+        let accepted = req.headers.get::<headers::AcceptEncoding>()?;
+
+        let siblings: Vec<_> = PRECOMPRESSED_EXTENSIONS.iter()
+            .map(|&(ext, enc)| {
+                let mut sibling = OsString::from(req_p.as_os_str());
+                sibling.push(".");
+                sibling.push(ext);
+                (PathBuf::from(sibling), enc.parse::<headers::Encoding>().unwrap())
+            })
+            .filter(|(sibling, _)| sibling.metadata().map(|m| m.is_file()).unwrap_or(false))
+            .collect();
+        let available: Vec<_> = siblings.iter().map(|(_, enc)| enc.clone()).collect();
+        let encoding = response_encoding_of(accepted, &available)?;
+        siblings.into_iter().find(|(_, enc)| *enc == encoding)
+    }
+
+    pub(super) fn handle_get_file_precompressed(&self, req: &mut Request, req_p: PathBuf, sibling: PathBuf, encoding: headers::Encoding, mt: Mime)
+                                                -> IronResult<Response> {
+        log!(self.log,
+             "{} precompressed as {} in {magenta}{}{reset}",
+             Spaces(self.remote_addresses(req).width()),
+             encoding,
+             sibling.display());
+
+        let body = try_fs!(self, req, self.served_file_body(&sibling), "opening its precompressed file");
+        Ok(Response::with((status::Ok,
+                           (Header(headers::Server(USER_AGENT.to_string())),
+                            Header(headers::LastModified(headers::HttpDate(file_time_modified_p(&req_p)))),
+                            Header(headers::ContentEncoding(vec![encoding])),
+                            Header(headers::AcceptRanges(vec![headers::RangeUnit::Bytes]))),
+                           body,
+                           mt)))
+    }
+}
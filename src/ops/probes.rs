@@ -0,0 +1,56 @@
+//! Liveness and readiness probes, served with the admin API at `/.http-admin/healthz` and `/.http-admin/readyz`,
+//! for orchestrators (Kubernetes, Nomad) which can't send credentials.
+//!
+//! Liveness is just having answered. Readiness is warm-up being over, the temp dirs the configuration uses,
+//! created at startup, still being there and writable, and the TLS identity, if any, still being there.
+
+
+use iron::{status, method, IronResult, Response, Request};
+use self::super::HttpHandler;
+use std::fs;
+
+
+/// How long to tell probes to wait before asking for readiness again, in seconds
+const READY_RETRY_AFTER: u64 = 5;
+
+/// Admin API endpoints reachable without credentials
+const PROBES: &[&str] = &["healthz", "readyz"];
+
+
+impl HttpHandler {
+    /// Whether the request is a GET of one of the probes, which skips authentication
+    pub(super) fn probe_requested(&self, req: &Request) -> bool {
+        if req.method != method::Get || !self.admin_requested(req) {
+            return false;
+        }
+
+        // Parsed like handle_admin() does, so this is only true for what it'll answer as a probe
+        let mut segments = req.url.as_ref().path_segments().into_iter().flatten().skip(1).filter(|s| !s.is_empty());
+        segments.next().map(|s| PROBES.contains(&s)).unwrap_or(false) && segments.next().is_none()
+    }
+
+    pub(super) fn handle_healthz(&self, req: &mut Request) -> IronResult<Response> {
+        self.handle_admin_output(req, json!({ "healthy": true }))
+    }
+
+    pub(super) fn handle_readyz(&self, req: &mut Request) -> IronResult<Response> {
+        let warm_up = self.warm_up.done();
+        let temp_dirs = self.temp_dirs().values().all(|td| fs::metadata(td).map(|m| m.is_dir() && !m.permissions().readonly()).unwrap_or(false));
+        let tls = self.tls_identity.as_ref().map(|id| id.is_file()).unwrap_or(true);
+        let ready = warm_up && temp_dirs && tls;
+
+        let out = json!({
+            "ready": ready,
+            "warm_up": warm_up,
+            "temp_dirs": temp_dirs,
+            "tls": tls,
+        });
+        if ready {
+            self.handle_admin_output(req, out)
+        } else {
+            let mut resp = self.handle_admin_output_status(req, status::ServiceUnavailable, out)?;
+            resp.headers.set_raw("Retry-After", vec![READY_RETRY_AFTER.to_string().into_bytes()]);
+            Ok(resp)
+        }
+    }
+}
@@ -0,0 +1,153 @@
+//! Write-through to a backup directory, enabled with `--mirror-writes`: every path written to, moved, or deleted
+//! is queued, and a background thread makes the backup match the hosted directory there.
+//!
+//! Paths that fail to replicate are retried `MAX_ATTEMPTS` times, backing off exponentially from `RETRY_DELAY`.
+
+
+use self::super::super::util::{copy_dir, is_actually_file};
+use std::io::{Result as IoResult, Error as IoError};
+use std::sync::{Condvar, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+use std::path::{PathBuf, Path};
+use std::collections::VecDeque;
+use std::fs;
+
+
+/// How many times replicating a path is attempted before giving up on it
+pub const MAX_ATTEMPTS: u32 = 5;
+
+/// How long to wait before the first retry, doubled for each one after
+pub const RETRY_DELAY: Duration = Duration::from_secs(1);
+
+
+/// Path relative to the hosted directory, how many times it failed to replicate, and when to next try it
+type Job = (PathBuf, u32, Instant);
+
+/// Replicates the hosted directory's changed paths into `backup`
+#[derive(Debug)]
+pub struct WriteReplicator {
+    pub hosted_directory: PathBuf,
+    pub backup: PathBuf,
+    /// (at all, log_colour)
+    pub log: (bool, bool),
+    queue: Mutex<VecDeque<Job>>,
+    queued: Condvar,
+}
+
+impl WriteReplicator {
+    pub fn new(hosted_directory: PathBuf, backup: PathBuf, log: (bool, bool)) -> WriteReplicator {
+        WriteReplicator {
+            hosted_directory,
+            backup,
+            log,
+            queue: Default::default(),
+            queued: Condvar::new(),
+        }
+    }
+
+    /// Replicate `p`, which is under the hosted directory, as soon as possible
+    pub fn queue(&self, p: &Path) {
+        if let Ok(rel) = p.strip_prefix(&self.hosted_directory) {
+            let mut queue = self.queue.lock().unwrap_or_else(PoisonError::into_inner);
+            // Replication copies the path's state at the time, so a queued one will pick this write up, too
+            if !queue.iter().any(|j| j.0 == rel && j.1 == 0) {
+                queue.push_back((rel.to_path_buf(), 0, Instant::now()));
+                self.queued.notify_one();
+            }
+        }
+    }
+
+    /// Replicate queued paths as they come in, forever
+    pub fn run(&self) -> ! {
+        loop {
+            let job = {
+                let mut queue = self.queue.lock().unwrap_or_else(PoisonError::into_inner);
+                loop {
+                    let now = Instant::now();
+                    match queue.iter().position(|j| j.2 <= now) {
+                        Some(i) => break queue.remove(i).unwrap(),
+                        None => {
+                            let wait = queue.iter().map(|j| j.2 - now).min().unwrap_or(Duration::from_secs(60));
+                            queue = self.queued.wait_timeout(queue, wait).unwrap_or_else(PoisonError::into_inner).0;
+                        }
+                    }
+                }
+            };
+            self.attempt(job);
+        }
+    }
+
+    /// Replicate everything still queued once, retries included, for shutdown
+    pub fn flush(&self) {
+        let jobs: Vec<_> = self.queue.lock().unwrap_or_else(PoisonError::into_inner).drain(..).collect();
+        for (rel, _, _) in jobs {
+            if let Err(err) = self.replicate(&rel) {
+                log!(self.log, "Failed to replicate {magenta}{}{reset} to {magenta}{}{reset}: {}", rel.display(), self.backup.display(), err);
+            }
+        }
+    }
+
+    fn attempt(&self, (rel, attempts, _): Job) {
+        match self.replicate(&rel) {
+            Ok(()) => {}
+            Err(err) if attempts + 1 < MAX_ATTEMPTS => {
+                let delay = RETRY_DELAY * 2u32.pow(attempts);
+                log!(self.log,
+                     "Failed to replicate {magenta}{}{reset} to {magenta}{}{reset}, retrying in {}s: {}",
+                     rel.display(),
+                     self.backup.display(),
+                     delay.as_secs(),
+                     err);
+                self.queue.lock().unwrap_or_else(PoisonError::into_inner).push_back((rel, attempts + 1, Instant::now() + delay));
+            }
+            Err(err) => {
+                log!(self.log,
+                     "Gave up replicating {magenta}{}{reset} to {magenta}{}{reset} after {} attempts: {}",
+                     rel.display(),
+                     self.backup.display(),
+                     MAX_ATTEMPTS,
+                     err);
+            }
+        }
+    }
+
+    /// Make the backup at `rel` match the hosted directory: remove it if it's gone, otherwise copy it over
+    fn replicate(&self, rel: &Path) -> IoResult<()> {
+        let (from, to) = (self.hosted_directory.join(rel), self.backup.join(rel));
+        let meta = match from.metadata() {
+            Ok(meta) => meta,
+            Err(_) => return remove(&to),
+        };
+        if let Some(parent) = to.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        if is_actually_file(&meta.file_type(), &from) {
+            if to.symlink_metadata().map(|m| m.is_dir()).unwrap_or(false) {
+                fs::remove_dir_all(&to)?;
+            }
+
+            // Copied beside and renamed over, so the backup never has a partial file
+            let mut temp = to.clone().into_os_string();
+            temp.push(".http-replica");
+            fs::copy(&from, &temp).and_then(|_| fs::rename(&temp, &to)).inspect_err(|_| {
+                let _ = fs::remove_file(&temp);
+            })
+        } else {
+            remove(&to)?;
+            match copy_dir(&from, &to)?.into_iter().next() {
+                Some((err, p)) => Err(IoError::new(err.kind(), format!("{}: {}", p, err))),
+                None => Ok(()),
+            }
+        }
+    }
+}
+
+/// Remove whatever's at `p`, if anything
+fn remove(p: &Path) -> IoResult<()> {
+    match p.symlink_metadata() {
+        Ok(meta) if meta.is_dir() => fs::remove_dir_all(p),
+        Ok(_) => fs::remove_file(p),
+        Err(_) => Ok(()),
+    }
+}
@@ -0,0 +1,91 @@
+//! Retention policies, enabled with `--retention PATH=AGE`: files under PATH not modified in AGE are deleted
+//! every `--retention-interval`, along with the directories that leaves empty, so public upload areas don't grow forever.
+//!
+//! With `--retention-dry-run`, what would be deleted is only logged.
+
+
+use std::time::{SystemTime, Duration};
+use std::collections::BTreeSet;
+use self::super::HttpHandler;
+use std::path::Path;
+use walkdir::WalkDir;
+use std::thread;
+use std::fs;
+
+
+/// What a retention pass deleted, or would've
+#[derive(Debug, Default, Copy, Clone, Hash, PartialEq, Eq)]
+pub struct RetentionReport {
+    pub files: usize,
+    pub bytes: u64,
+    pub directories: usize,
+}
+
+
+impl HttpHandler {
+    /// Delete everything past its retention age, or just log it for a dry run
+    pub fn enforce_retention(&self) -> RetentionReport {
+        let mut report = RetentionReport::default();
+        let now = SystemTime::now();
+
+        for (path, &age) in &self.retention {
+            let root = self.hosted_directory.1.join(path);
+            let mut emptied = BTreeSet::new();
+            // Symlinks aren't followed, so nothing outside the rule's directory is ever touched
+            for entry in WalkDir::new(&root).min_depth(1).into_iter().filter_entry(|e| !self.in_temp_dir(e.path())).flatten().filter(|e| e.file_type().is_file()) {
+                let meta = match entry.metadata() {
+                    Ok(meta) => meta,
+                    Err(_) => continue,
+                };
+                if meta.modified().ok().and_then(|m| now.duration_since(m).ok()).map(|d| d < age).unwrap_or(true) {
+                    continue;
+                }
+
+                if self.retention_dry_run {
+                    log!(self.log, "Would delete {magenta}{}{reset} past its retention", entry.path().display());
+                } else if fs::remove_file(entry.path()).is_ok() {
+                    log!(self.log, "Deleted {magenta}{}{reset} past its retention", entry.path().display());
+                    self.written(entry.path());
+                } else {
+                    continue;
+                }
+                report.files += 1;
+                report.bytes += meta.len();
+                emptied.extend(entry.path().ancestors().skip(1).take_while(|p| *p != root).map(Path::to_path_buf));
+            }
+
+            if !self.retention_dry_run {
+                // Deepest first, so parents emptied by their children going are caught too
+                for dir in emptied.iter().rev().filter(|d| is_empty_dir(d)) {
+                    if fs::remove_dir(dir).is_ok() {
+                        self.written(dir);
+                        report.directories += 1;
+                    }
+                }
+            }
+        }
+
+        if report.files != 0 {
+            log!(self.log,
+                 "Retention {}: {} files ({}B), {} directories",
+                 if self.retention_dry_run { "dry run" } else { "pass" },
+                 report.files,
+                 report.bytes,
+                 report.directories);
+        }
+        report
+    }
+
+    /// Enforce retention every `interval`, forever
+    pub fn enforce_retention_periodically(&self, interval: Duration) -> ! {
+        loop {
+            self.enforce_retention();
+            thread::sleep(interval);
+        }
+    }
+}
+
+
+fn is_empty_dir(p: &Path) -> bool {
+    fs::read_dir(p).map(|mut d| d.next().is_none()).unwrap_or(false)
+}
@@ -0,0 +1,358 @@
+//! A subset of the [S3 REST API](https://docs.aws.amazon.com/AmazonS3/latest/API/Welcome.html), in path-style addressing,
+//! where buckets are the top-level directories of the hosted directory and objects are the files therein.
+//!
+//! Supported are ListBuckets, CreateBucket, DeleteBucket, ListObjects (V1 and V2), and Get/Head/Put/DeleteObject.
+//! Request signatures are not checked.
+
+
+use self::super::super::util::{is_actually_file, is_descendant_of, file_time_modified, s3_etag, USER_AGENT, S3_XML_NAMESPACE, S3_MAX_KEYS};
+use xml::writer::{EventWriter as XmlWriter, XmlEvent as XmlWEvent, Error as XmlWError};
+use iron::{headers, status, method, IronResult, Response, Request};
+use self::super::webdav::intialise_xml_output;
+use std::collections::BTreeSet;
+use iron::modifiers::Header;
+use self::super::HttpHandler;
+use std::path::{PathBuf, Path};
+use iron::mime::Mime;
+use walkdir::WalkDir;
+use std::io::Write;
+use std::fs;
+
+
+impl HttpHandler {
+    pub(super) fn handle_s3(&self, req: &mut Request) -> IronResult<Response> {
+        let segments = req.url.path().into_iter().filter(|s| !s.is_empty()).count();
+
+        match (&req.method, segments) {
+            (&method::Get, 0) => self.handle_s3_list_buckets(req),
+            (&method::Head, 0) => self.handle_s3_list_buckets(req).map(strip_body),
+            (&method::Get, 1) => self.handle_s3_list_objects(req),
+            (&method::Head, 1) => self.handle_s3_head_bucket(req),
+            (&method::Put, 1) => self.handle_s3_create_bucket(req),
+            (&method::Delete, 1) => self.handle_s3_delete_bucket(req),
+            (&method::Get, _) => self.handle_s3_get_object(req),
+            (&method::Head, _) => self.handle_s3_get_object(req).map(strip_body),
+            (&method::Put, _) => self.handle_s3_put_object(req),
+            (&method::Delete, _) => {
+                // Keys are only ever files, and directories are never removed whole from under them
+                if self.parse_requested_path(req).0.is_dir() {
+                    return s3_error(status::NotFound, "NoSuchKey", "The specified key does not exist.");
+                }
+
+                // S3 reports success for deleting nonexistent objects
+                self.handle_delete(req).map(|mut r| {
+                    if r.status == Some(status::NotFound) {
+                        r = Response::with((status::NoContent, Header(headers::Server(USER_AGENT.to_string()))));
+                    }
+                    r
+                })
+            }
+            _ => self.handle_bad_method(req),
+        }
+    }
+
+    fn handle_s3_list_buckets(&self, req: &mut Request) -> IronResult<Response> {
+        log!(self.log, "{} was served {red}S3{reset} bucket list", self.remote_addresses(&req));
+
+        let buckets = self.hosted_directory
+            .1
+            .read_dir()
+            .map(|rd| {
+                rd.flatten()
+                    .filter(|f| f.file_type().map(|ft| !is_actually_file(&ft, f.path())).unwrap_or(false))
+                    .flat_map(|f| f.file_name().into_string().ok().map(|n| (n, f.path())))
+                    .filter(|(_, p)| self.s3_path_allowed(p))
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+
+        s3_xml_response(status::Ok, |out| {
+            out.write(XmlWEvent::start_element("ListAllMyBucketsResult").default_ns(S3_XML_NAMESPACE))?;
+            out.write(XmlWEvent::start_element("Owner"))?;
+            write_text_element(out, "ID", "http")?;
+            write_text_element(out, "DisplayName", "http")?;
+            out.write(XmlWEvent::end_element())?;
+
+            out.write(XmlWEvent::start_element("Buckets"))?;
+            for (name, path) in &buckets {
+                out.write(XmlWEvent::start_element("Bucket"))?;
+                write_text_element(out, "Name", name)?;
+                if let Ok(meta) = path.metadata() {
+                    write_text_element(out, "CreationDate", &file_time_modified(&meta).rfc3339().to_string())?;
+                }
+                out.write(XmlWEvent::end_element())?;
+            }
+            out.write(XmlWEvent::end_element())?;
+
+            out.write(XmlWEvent::end_element())
+        })
+    }
+
+    fn handle_s3_head_bucket(&self, req: &mut Request) -> IronResult<Response> {
+        let (req_p, bucket) = match self.s3_bucket_path(req) {
+            Ok(b) => b,
+            Err(resp) => return resp.map(strip_body),
+        };
+
+        log!(self.log,
+             "{} asked for {red}S3{reset} bucket {magenta}{}{reset} at {yellow}{}{reset}",
+             self.remote_addresses(&req),
+             bucket,
+             req_p.display());
+        Ok(Response::with((status::Ok, Header(headers::Server(USER_AGENT.to_string())))))
+    }
+
+    fn handle_s3_list_objects(&self, req: &mut Request) -> IronResult<Response> {
+        let (req_p, bucket) = match self.s3_bucket_path(req) {
+            Ok(b) => b,
+            Err(resp) => return resp,
+        };
+
+        let mut v2 = false;
+        let mut prefix = String::new();
+        let mut delimiter = None;
+        let mut start_after: Option<String> = None;
+        let mut max_keys = S3_MAX_KEYS;
+        for (k, v) in req.url.as_ref().query_pairs() {
+            match &k[..] {
+                "list-type" => v2 = v == "2",
+                "prefix" => prefix = v.into_owned(),
+                "delimiter" if !v.is_empty() => delimiter = Some(v.into_owned()),
+                "max-keys" => max_keys = v.parse().unwrap_or(S3_MAX_KEYS).min(S3_MAX_KEYS),
+                "marker" | "start-after" | "continuation-token" => {
+                    if start_after.as_ref().map(|sa| sa[..] < v[..]).unwrap_or(true) {
+                        start_after = Some(v.into_owned())
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        log!(self.log,
+             "{} was served {red}S3{reset} object list of bucket {magenta}{}{reset} with prefix {yellow}{}{reset}",
+             self.remote_addresses(&req),
+             bucket,
+             prefix);
+
+        let mut keys = WalkDir::new(&req_p)
+            .min_depth(1)
+            .follow_links(self.symlink_policy.follow())
+            .into_iter()
+            .flatten()
+            .filter(|e| is_actually_file(&e.file_type(), e.path()) && self.s3_path_allowed(e.path()))
+            .flat_map(|e| {
+                let key = e.path().strip_prefix(&req_p).ok()?.to_str()?.replace('\\', "/");
+                Some((key, e.into_path()))
+            })
+            .filter(|(key, _)| {
+                // Objects are listed only if they could be gotten with the same credentials
+                let mut url = req.url.as_ref().clone();
+                url.path_segments_mut().expect("Request URL can't be a base").pop_if_empty().extend(key.split('/'));
+                self.authorised_for_path(req, url.path()) && key.starts_with(&prefix) &&
+                start_after.as_ref()
+                    .map(|sa| key > sa && !(delimiter.as_ref().map(|d| sa.ends_with(&d[..])).unwrap_or(false) && key.starts_with(&sa[..])))
+                    .unwrap_or(true)
+            })
+            .collect::<Vec<_>>();
+        keys.sort_by(|lhs, rhs| lhs.0.cmp(&rhs.0));
+
+        let mut contents = vec![];
+        let mut common_prefixes = BTreeSet::new();
+        let mut truncated = false;
+        let mut last_key = None;
+        for (key, path) in keys {
+            if let Some(common) = delimiter.as_ref().and_then(|d| key[prefix.len()..].find(&d[..]).map(|i| key[..prefix.len() + i + d.len()].to_string())) {
+                if common_prefixes.contains(&common) {
+                    continue;
+                }
+                if contents.len() + common_prefixes.len() == max_keys {
+                    truncated = true;
+                    break;
+                }
+                last_key = Some(common.clone());
+                common_prefixes.insert(common);
+            } else {
+                if contents.len() + common_prefixes.len() == max_keys {
+                    truncated = true;
+                    break;
+                }
+                last_key = Some(key.clone());
+                contents.push((key, path));
+            }
+        }
+
+        s3_xml_response(status::Ok, |out| {
+            out.write(XmlWEvent::start_element("ListBucketResult").default_ns(S3_XML_NAMESPACE))?;
+            write_text_element(out, "Name", &bucket)?;
+            write_text_element(out, "Prefix", &prefix)?;
+            if let Some(d) = delimiter.as_ref() {
+                write_text_element(out, "Delimiter", d)?;
+            }
+            write_text_element(out, "MaxKeys", &max_keys.to_string())?;
+            write_text_element(out, "IsTruncated", if truncated { "true" } else { "false" })?;
+            if v2 {
+                write_text_element(out, "KeyCount", &(contents.len() + common_prefixes.len()).to_string())?;
+                if let Some(sa) = start_after.as_ref() {
+                    write_text_element(out, "ContinuationToken", sa)?;
+                }
+                if let Some(lk) = last_key.as_ref().filter(|_| truncated) {
+                    write_text_element(out, "NextContinuationToken", lk)?;
+                }
+            } else {
+                write_text_element(out, "Marker", start_after.as_ref().map(|s| &s[..]).unwrap_or(""))?;
+                if let Some(lk) = last_key.as_ref().filter(|_| truncated) {
+                    write_text_element(out, "NextMarker", lk)?;
+                }
+            }
+
+            for (key, path) in &contents {
+                if let Ok(meta) = path.metadata() {
+                    out.write(XmlWEvent::start_element("Contents"))?;
+                    write_text_element(out, "Key", key)?;
+                    write_text_element(out, "LastModified", &file_time_modified(&meta).rfc3339().to_string())?;
+                    write_text_element(out, "ETag", &format!("\"{}\"", s3_etag(&meta)))?;
+                    write_text_element(out, "Size", &meta.len().to_string())?;
+                    write_text_element(out, "StorageClass", "STANDARD")?;
+                    out.write(XmlWEvent::end_element())?;
+                }
+            }
+            for cp in &common_prefixes {
+                out.write(XmlWEvent::start_element("CommonPrefixes"))?;
+                write_text_element(out, "Prefix", cp)?;
+                out.write(XmlWEvent::end_element())?;
+            }
+
+            out.write(XmlWEvent::end_element())
+        })
+    }
+
+    fn handle_s3_create_bucket(&self, req: &mut Request) -> IronResult<Response> {
+        if self.writes_temp_dir.is_none() {
+            return self.handle_forbidden_method(req, "-w", "write requests");
+        }
+
+        let (req_p, _, url_err) = self.parse_requested_path(req);
+        if url_err {
+            return self.handle_invalid_url(req, "<p>Percent-encoding decoded to invalid UTF-8 or a path separator.</p>");
+        }
+        if self.webdav_write_locked(req, &req_p, false) {
+            return self.handle_locked(req, req_p);
+        }
+
+        log!(self.log,
+             "{} created {red}S3{reset} bucket {magenta}{}{reset}",
+             self.remote_addresses(&req),
+             req_p.display());
+
+        match fs::create_dir(&req_p) {
+            Ok(()) => Ok(Response::with((status::Ok, Header(headers::Server(USER_AGENT.to_string())), Header(headers::Location(req.url.as_ref().path().to_string()))))),
+            Err(_) if req_p.is_dir() => s3_error(status::Conflict, "BucketAlreadyOwnedByYou", "Bucket already exists"),
+            Err(e) => s3_error(status::Forbidden, "AccessDenied", &e.to_string()),
+        }
+    }
+
+    fn handle_s3_delete_bucket(&self, req: &mut Request) -> IronResult<Response> {
+        if self.writes_temp_dir.is_none() {
+            return self.handle_forbidden_method(req, "-w", "write requests");
+        }
+
+        let (req_p, bucket) = match self.s3_bucket_path(req) {
+            Ok(b) => b,
+            Err(resp) => return resp,
+        };
+        if self.webdav_write_locked(req, &req_p, true) {
+            return self.handle_locked(req, req_p);
+        }
+
+        log!(self.log,
+             "{} deleted {red}S3{reset} bucket {magenta}{}{reset}",
+             self.remote_addresses(&req),
+             bucket);
+
+        match fs::remove_dir(&req_p) {
+            Ok(()) => {
+                self.drop_webdav_locks(&req_p);
+                Ok(Response::with((status::NoContent, Header(headers::Server(USER_AGENT.to_string())))))
+            }
+            Err(_) => s3_error(status::Conflict, "BucketNotEmpty", "The bucket you tried to delete is not empty"),
+        }
+    }
+
+    fn handle_s3_get_object(&self, req: &mut Request) -> IronResult<Response> {
+        let (req_p, _, _) = self.parse_requested_path(req);
+        if req_p.is_dir() {
+            return s3_error(status::NotFound, "NoSuchKey", "The specified key does not exist.");
+        }
+
+        let mut resp = self.handle_get(req)?;
+        if resp.status == Some(status::NotFound) {
+            return s3_error(status::NotFound, "NoSuchKey", "The specified key does not exist.");
+        }
+        if let Ok(meta) = req_p.metadata() {
+            resp.headers.set(headers::ETag(headers::EntityTag::strong(s3_etag(&meta))));
+        }
+        Ok(resp)
+    }
+
+    fn handle_s3_put_object(&self, req: &mut Request) -> IronResult<Response> {
+        let mut resp = self.handle_put(req)?;
+        if resp.status == Some(status::Created) || resp.status == Some(status::NoContent) {
+            let (req_p, _, _) = self.parse_requested_path(req);
+            resp.status = Some(status::Ok);
+            if let Ok(meta) = req_p.metadata() {
+                resp.headers.set(headers::ETag(headers::EntityTag::strong(s3_etag(&meta))));
+            }
+        }
+        Ok(resp)
+    }
+
+    /// Get the path to and name of the requested bucket, or an S3 error response if it doesn't exist
+    fn s3_bucket_path(&self, req: &mut Request) -> Result<(PathBuf, String), IronResult<Response>> {
+        let (req_p, _, url_err) = self.parse_requested_path(req);
+        let bucket = req_p.file_name().and_then(|n| n.to_str()).unwrap_or("").to_string();
+
+        if url_err || !req_p.is_dir() || !self.s3_path_allowed(&req_p) {
+            Err(s3_error(status::NotFound, "NoSuchBucket", "The specified bucket does not exist"))
+        } else {
+            Ok((req_p, bucket))
+        }
+    }
+
+    /// Whether `path` can be listed, taking into account that what's under a followed symlinked directory isn't a symlink itself
+    fn s3_path_allowed(&self, path: &Path) -> bool {
+        let symlink = path.read_link().is_ok();
+        !((symlink && !self.symlink_policy.follow()) || (self.symlink_policy.sandboxed() && !is_descendant_of(path, &self.hosted_directory.1)) ||
+          self.in_temp_dir(path))
+    }
+}
+
+
+fn s3_xml_response<F: FnOnce(&mut XmlWriter<Vec<u8>>) -> Result<(), XmlWError>>(st: status::Status, f: F) -> IronResult<Response> {
+    let mut out = intialise_xml_output().expect("Couldn't write S3 XML");
+    f(&mut out).expect("Couldn't write S3 XML");
+
+    Ok(Response::with((st,
+                       Header(headers::Server(USER_AGENT.to_string())),
+                       out.into_inner(),
+                       "application/xml;charset=utf-8".parse::<Mime>().unwrap())))
+}
+
+fn s3_error(st: status::Status, code: &str, message: &str) -> IronResult<Response> {
+    s3_xml_response(st, |out| {
+        out.write(XmlWEvent::start_element("Error"))?;
+        write_text_element(out, "Code", code)?;
+        write_text_element(out, "Message", message)?;
+        out.write(XmlWEvent::end_element())
+    })
+}
+
+fn write_text_element<W: Write>(out: &mut XmlWriter<W>, name: &str, text: &str) -> Result<(), XmlWError> {
+    out.write(XmlWEvent::start_element(name))?;
+    out.write(XmlWEvent::characters(text))?;
+    out.write(XmlWEvent::end_element())
+}
+
+fn strip_body(mut r: Response) -> Response {
+    r.body = None;
+    r
+}
@@ -0,0 +1,35 @@
+//! Single-file mode, when pointed at a file instead of a directory: the file's served at `/` and at its name,
+//! with the containing directory hosted otherwise as usual, but everything else in it 404ing.
+
+
+use self::super::super::util::{percent_decode, PATH_SEGMENT_ENCODE_SET};
+use percent_encoding::utf8_percent_encode;
+use iron::{headers, IronResult, Response, Request};
+use self::super::HttpHandler;
+
+
+impl HttpHandler {
+    /// Point requests for `/` at the file, returning whether it was one, or get the ones for anything else 404ed
+    pub(super) fn route_single_file(&self, req: &mut Request, file: &str) -> Result<bool, IronResult<Response>> {
+        let path = percent_decode(&req.url.as_ref().path()[1..]).map(|p| p.into_owned());
+        match path.as_deref() {
+            Some("") => {
+                req.url.as_mut().set_path(&format!("/{}", utf8_percent_encode(file, PATH_SEGMENT_ENCODE_SET)));
+                Ok(true)
+            }
+            Some(p) if p == file => Ok(false),
+            _ => {
+                let req_p = self.hosted_directory.1.join(path.unwrap_or_default());
+                Err(self.handle_nonexistent(req, req_p))
+            }
+        }
+    }
+
+    /// Name the file for responses served at `/`, which'd otherwise get saved under the server's name
+    pub(super) fn name_single_file(&self, resp: &mut Response, file: &str) {
+        resp.headers.set(headers::ContentDisposition {
+            disposition: headers::DispositionType::Inline,
+            parameters: vec![headers::DispositionParam::Filename(headers::Charset::Ext("UTF-8".to_string()), None, file.as_bytes().to_vec())],
+        });
+    }
+}
@@ -0,0 +1,144 @@
+//! Serving stdin, enabled with `--stdin NAME`: what's piped in is buffered into the temp dir as NAME and hosted
+//! like a single file, so `tar c dir | http --stdin dir.tar` shares a directory without making an archive first.
+//!
+//! Requests made while stdin's still being read are streamed what's come in, and then the rest as it arrives, without
+//! a length; after it's closed, the file is served as usual, with ranges and encoding.
+
+
+use std::io::{Result as IoResult, Write, Read, stdin, copy};
+use iron::{headers, status, method, IronResult, Response, Request};
+use self::super::super::util::{USER_AGENT, url_path};
+use std::sync::{Arc, Condvar, Mutex, PoisonError};
+use iron::modifiers::Header;
+use iron::response::WriteBody;
+use self::super::HttpHandler;
+use std::path::PathBuf;
+use std::fs::File;
+
+
+/// Where stdin's being buffered to, and how far along it is
+#[derive(Debug)]
+pub struct StdinBuffer {
+    pub file: PathBuf,
+    /// (at all, log_colour)
+    pub log: (bool, bool),
+    /// (bytes received, whether stdin's closed)
+    received: Mutex<(u64, bool)>,
+    progressed: Condvar,
+}
+
+impl StdinBuffer {
+    pub fn new(file: PathBuf, log: (bool, bool)) -> StdinBuffer {
+        StdinBuffer {
+            file,
+            log,
+            received: Default::default(),
+            progressed: Condvar::new(),
+        }
+    }
+
+    /// Copy stdin to the buffer file until it's closed
+    pub fn pump(&self) {
+        let result = File::create(&self.file).and_then(|mut out| {
+            let mut input = stdin();
+            let mut buf = vec![0; 64 * 1024];
+            loop {
+                let read = input.read(&mut buf)?;
+                if read == 0 {
+                    return Ok(());
+                }
+                out.write_all(&buf[..read])?;
+                self.received.lock().unwrap_or_else(PoisonError::into_inner).0 += read as u64;
+                self.progressed.notify_all();
+            }
+        });
+
+        let mut received = self.received.lock().unwrap_or_else(PoisonError::into_inner);
+        match result {
+            Ok(()) => {
+                log!(self.log, "Received {}B from stdin into {magenta}{}{reset}", received.0, self.file.display());
+            }
+            Err(err) => {
+                log!(self.log, "Failed to buffer stdin into {magenta}{}{reset} after {}B: {}", self.file.display(), received.0, err);
+            }
+        }
+        received.1 = true;
+        self.progressed.notify_all();
+    }
+
+    /// Whether stdin was closed, so the buffer file's complete
+    pub fn done(&self) -> bool {
+        self.received.lock().unwrap_or_else(PoisonError::into_inner).1
+    }
+
+    /// Block until more than `sent` bytes were received or stdin was closed
+    fn wait_past(&self, sent: u64) -> (u64, bool) {
+        let mut received = self.received.lock().unwrap_or_else(PoisonError::into_inner);
+        while received.0 <= sent && !received.1 {
+            received = self.progressed.wait(received).unwrap_or_else(PoisonError::into_inner);
+        }
+        *received
+    }
+}
+
+
+impl HttpHandler {
+    /// Whether stdin's being served and is still being read
+    pub(super) fn stdin_pending(&self) -> bool {
+        self.stdin.as_ref().map(|s| !s.done()).unwrap_or(false)
+    }
+
+    /// Stream the file as it's received; `route_single_file()` already 404ed requests for anything else
+    pub(super) fn handle_stdin_pending(&self, req: &mut Request) -> IronResult<Response> {
+        let buffer = self.stdin.clone().expect("Stdin not served");
+        match req.method {
+            method::Options => return self.handle_options(req),
+            method::Get | method::Head => {}
+            _ => return self.handle_disallowed_method(req, &[&[method::Options, method::Get, method::Head]], "file still being received"),
+        }
+
+        let mime_type = self.guess_mime_type(&buffer.file);
+        log!(self.log,
+             "{} was served {magenta}{}{reset} as {blue}{}{reset} while it's received from stdin",
+             self.remote_addresses(req),
+             url_path(&req.url),
+             mime_type);
+
+        let mut resp = Response::with((status::Ok, Header(headers::Server(USER_AGENT.to_string())), mime_type));
+        if req.method == method::Get {
+            self.count_download(&buffer.file, None);
+            resp.body = Some(Box::new(StdinWriteBody {
+                buffer,
+                file: None,
+                sent: 0,
+            }));
+        }
+        Ok(resp)
+    }
+}
+
+
+struct StdinWriteBody {
+    buffer: Arc<StdinBuffer>,
+    file: Option<File>,
+    sent: u64,
+}
+
+impl WriteBody for StdinWriteBody {
+    fn write_body(&mut self, res: &mut dyn Write) -> IoResult<()> {
+        loop {
+            let (received, done) = self.buffer.wait_past(self.sent);
+            if received > self.sent {
+                // Only opened once something was received, by which point the buffer file exists
+                if self.file.is_none() {
+                    self.file = Some(File::open(&self.buffer.file)?);
+                }
+                let file = self.file.as_mut().unwrap();
+                self.sent += copy(&mut file.take(received - self.sent), res)?;
+                res.flush()?;
+            } else if done {
+                return Ok(());
+            }
+        }
+    }
+}
@@ -0,0 +1,139 @@
+//! `.torrent`s for big files, requested with `?torrent`, with the server itself as the web seed (BEP 19),
+//! so downloaders can share the load over BitTorrent.
+//!
+//! The info dictionaries are cached in the temp dir by the file's path, modification time, size, and the cache fingerprint,
+//! since hashing the pieces means reading the whole file.
+
+
+use std::io::{Result as IoResult, Write, Read};
+use std::time::UNIX_EPOCH;
+use iron::{headers, status, IronResult, Response, Request};
+use iron::modifiers::Header;
+use self::super::super::util::USER_AGENT;
+use self::super::HttpHandler;
+use std::path::{PathBuf, Path};
+use std::fs::{self, Metadata, File};
+use std::cmp;
+
+
+/// Smallest piece length, 256KiB
+const MIN_PIECE_LENGTH: u64 = 256 * 1024;
+
+/// Biggest piece length, 16MiB
+const MAX_PIECE_LENGTH: u64 = 16 * 1024 * 1024;
+
+/// How many pieces to aim for, keeping the `.torrent` small
+const TARGET_PIECES: u64 = 1500;
+
+
+impl HttpHandler {
+    /// Whether `?torrent` was requested for a file big enough to have one
+    pub(super) fn torrent_requested(&self, req: &Request, req_p: &Path) -> bool {
+        match self.torrent_min_size {
+            Some(min) => {
+                req.url.as_ref().query_pairs().any(|(k, _)| k == "torrent") && req_p.metadata().map(|m| m.len() >= min).unwrap_or(false)
+            }
+            None => false,
+        }
+    }
+
+    pub(super) fn handle_get_file_torrent(&self, req: &mut Request, req_p: PathBuf) -> IronResult<Response> {
+        let info = match self.torrent_info(&req_p) {
+            Ok(info) => info,
+            Err(err) => return self.handle_requested_entity_unopenable(req, err, "file"),
+        };
+
+        let mut seed = req.url.as_ref().clone();
+        seed.set_query(None);
+        seed.set_fragment(None);
+
+        // Keys in lexicographical order, as bencoding requires
+        let mut torrent = b"d10:created by".to_vec();
+        bencode_str(&mut torrent, USER_AGENT.as_bytes());
+        torrent.extend_from_slice(b"4:info");
+        torrent.extend_from_slice(&info);
+        torrent.extend_from_slice(b"8:url-list");
+        bencode_str(&mut torrent, seed.as_str().as_bytes());
+        torrent.push(b'e');
+
+        log!(self.log,
+             "{} was served torrent for {magenta}{}{reset}",
+             self.remote_addresses(&req),
+             req_p.display());
+        let fname = req_p.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+        Ok(Response::with((status::Ok,
+                           Header(headers::Server(USER_AGENT.to_string())),
+                           Header(headers::ContentDisposition {
+                               disposition: headers::DispositionType::Attachment,
+                               parameters: vec![headers::DispositionParam::Filename(headers::Charset::Ext("UTF-8".to_string()),
+                                                                                    None,
+                                                                                    format!("{}.torrent", fname).into_bytes())],
+                           }),
+                           "application/x-bittorrent".parse::<iron::mime::Mime>().unwrap(),
+                           torrent)))
+    }
+
+    /// Get the bencoded info dictionary of the specified file, from the cache if it's there
+    fn torrent_info(&self, req_p: &Path) -> IoResult<Vec<u8>> {
+        let meta = req_p.metadata()?;
+        let cache_p = self.torrent_cache_path(req_p, &meta);
+        if let Ok(info) = fs::read(&cache_p) {
+            return Ok(info);
+        }
+
+        let piece_length = cmp::min(cmp::max((meta.len() / TARGET_PIECES).next_power_of_two(), MIN_PIECE_LENGTH), MAX_PIECE_LENGTH);
+        let mut pieces = Vec::new();
+        let mut file = File::open(req_p)?;
+        let mut buf = vec![0; piece_length as usize];
+        loop {
+            let read = read_piece(&mut file, &mut buf)?;
+            if read == 0 {
+                break;
+            }
+            pieces.extend_from_slice(&sha1_smol::Sha1::from(&buf[..read]).digest().bytes());
+        }
+
+        let mut info = format!("d6:lengthi{}e4:name", meta.len()).into_bytes();
+        bencode_str(&mut info, req_p.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default().as_bytes());
+        write!(info, "12:piece lengthi{}e6:pieces", piece_length)?;
+        bencode_str(&mut info, &pieces);
+        info.push(b'e');
+
+        self.create_temp_dir(&self.torrent_temp_dir);
+        if fs::write(&cache_p, &info).is_ok() {
+            log!(self.log, "Hashed {magenta}{}{reset} into {} torrent pieces", req_p.display(), pieces.len() / 20);
+        }
+        Ok(info)
+    }
+
+    /// Where the info dictionary of the specified file is cached
+    pub(super) fn torrent_cache_path(&self, req_p: &Path, meta: &Metadata) -> PathBuf {
+        let modified = meta.modified().ok().and_then(|m| m.duration_since(UNIX_EPOCH).ok()).map(|d| d.as_nanos()).unwrap_or(0);
+
+        let mut key = blake3::Hasher::new();
+        key.update(req_p.to_string_lossy().as_bytes());
+        key.update(&modified.to_le_bytes());
+        key.update(&meta.len().to_le_bytes());
+        key.update(self.cache_fingerprint.as_bytes());
+        self.torrent_temp_dir.as_ref().unwrap().1.join(format!("{}.info", key.finalize().to_hex()))
+    }
+}
+
+
+fn bencode_str(out: &mut Vec<u8>, s: &[u8]) {
+    out.extend_from_slice(s.len().to_string().as_bytes());
+    out.push(b':');
+    out.extend_from_slice(s);
+}
+
+/// Fill as much of the buffer as possible, short only at EOF
+fn read_piece(file: &mut File, buf: &mut [u8]) -> IoResult<usize> {
+    let mut read = 0;
+    while read < buf.len() {
+        match file.read(&mut buf[read..])? {
+            0 => break,
+            r => read += r,
+        }
+    }
+    Ok(read)
+}
@@ -0,0 +1,14 @@
+//! Requests to `--unlogged` paths, like load balancer health checks, which are logged and counted only if they fail.
+
+
+use self::super::super::util::url_path;
+use self::super::HttpHandler;
+use iron::Request;
+
+
+impl HttpHandler {
+    /// Whether the request is to one of the `--unlogged` paths
+    pub(super) fn unlogged_requested(&self, req: &Request) -> bool {
+        !self.unlogged_paths.is_empty() && self.unlogged_paths.contains(url_path(&req.url).trim_start_matches('/'))
+    }
+}
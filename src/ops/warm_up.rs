@@ -0,0 +1,57 @@
+use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+use iron::{status, IronResult, Response, Request};
+use self::super::HttpHandler;
+use std::sync::Arc;
+
+
+/// How long to tell clients to wait before retrying during warm-up, in seconds
+const WARM_UP_RETRY_AFTER: u64 = 5;
+
+
+/// Startup tasks still in progress, shared between all clones.
+///
+/// All requests are refused with a 503 while any are.
+#[derive(Debug, Clone, Default)]
+pub struct WarmUp(Arc<AtomicUsize>);
+
+/// A single startup task, finished on drop
+#[derive(Debug)]
+pub struct WarmUpTask(WarmUp);
+
+impl WarmUp {
+    /// Start a task, which needs to be done before the warm-up is over
+    pub fn start(&self) -> WarmUpTask {
+        self.0.fetch_add(1, AtomicOrdering::SeqCst);
+        WarmUpTask(self.clone())
+    }
+
+    /// Check if all tasks have finished
+    pub fn done(&self) -> bool {
+        self.0.load(AtomicOrdering::SeqCst) == 0
+    }
+}
+
+impl Drop for WarmUpTask {
+    fn drop(&mut self) {
+        (self.0).0.fetch_sub(1, AtomicOrdering::SeqCst);
+    }
+}
+
+
+impl HttpHandler {
+    pub(super) fn handle_warming_up(&self, req: &mut Request) -> IronResult<Response> {
+        log!(self.log,
+             "{} requested to {red}{}{reset} {yellow}{}{reset} while warming up",
+             self.remote_addresses(&req),
+             req.method,
+             req.url);
+
+        let mut resp = self.handle_generated_error(req,
+                                                   status::ServiceUnavailable,
+                                                   &["503 Service Unavailable",
+                                                     "The server is still starting up.",
+                                                     "<p>Try again in a few seconds.</p>"])?;
+        resp.headers.set_raw("Retry-After", vec![WARM_UP_RETRY_AFTER.to_string().into_bytes()]);
+        Ok(resp)
+    }
+}
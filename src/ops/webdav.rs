@@ -6,11 +6,10 @@
 //! https://tools.ietf.org/html/rfc2518
 
 
-use self::super::super::util::{BorrowXmlName, Destination, CommaList, Overwrite, Depth, win32_file_attributes, file_time_accessed, file_time_modified,
-                               file_time_created, client_microsoft, is_actually_file, is_descendant_of, file_executable, html_response, file_length, copy_dir,
-                               WEBDAV_ALLPROP_PROPERTIES_NON_WINDOWS, WEBDAV_ALLPROP_PROPERTIES_WINDOWS, WEBDAV_XML_NAMESPACE_MICROSOFT,
-                               WEBDAV_XML_NAMESPACE_APACHE, WEBDAV_PROPNAME_PROPERTIES, WEBDAV_XML_NAMESPACE_DAV, WEBDAV_XML_NAMESPACES, MAX_SYMLINKS,
-                               ERROR_HTML};
+use self::super::super::util::{BorrowXmlName, Destination, CommaList, Spaces, Overwrite, Depth, win32_file_attributes, file_time_accessed, file_time_modified,
+                               file_time_created, client_microsoft, is_actually_file, is_descendant_of, file_executable, file_length, copy_dir,
+                               is_storage_full, file_xattrs, file_name_bytes, escape_file_name, set_file_xattr, copy_xattrs, body_framing_error, PATH_SEGMENT_ENCODE_SET, WEBDAV_XML_NAMESPACE_XATTR, WEBDAV_ALLPROP_PROPERTIES_NON_WINDOWS, WEBDAV_ALLPROP_PROPERTIES_WINDOWS, WEBDAV_XML_NAMESPACE_MICROSOFT,
+                               WEBDAV_XML_NAMESPACE_APACHE, WEBDAV_PROPNAME_PROPERTIES, WEBDAV_XML_NAMESPACE_DAV, WEBDAV_XML_NAMESPACES};
 use std::io::{ErrorKind as IoErrorKind, Result as IoResult, Error as IoError, Write, Read};
 use xml::reader::{EventReader as XmlReader, XmlEvent as XmlREvent, Error as XmlRError};
 use xml::writer::{EventWriter as XmlWriter, XmlEvent as XmlWEvent, Error as XmlWError};
@@ -18,10 +17,17 @@ use xml::{EmitterConfig as XmlEmitterConfig, ParserConfig as XmlParserConfig};
 use xml::writer::events::StartElementBuilder as XmlWEventStartElementBuilder;
 use xml::common::{TextPosition as XmlTextPosition, XmlVersion, Position};
 use xml::name::{OwnedName as OwnedXmlName, Name as XmlName};
+use std::collections::{BTreeMap, VecDeque};
+use percent_encoding::percent_encode;
 use iron::{status, IronResult, Response, Request};
 use iron::url::Url as GenericUrl;
 use std::path::{PathBuf, Path};
-use std::fs::{self, Metadata};
+use std::sync::{Arc, PoisonError, RwLock};
+use std::time::{SystemTime, Duration, UNIX_EPOCH};
+use std::fs::{self, File, FileTimes, Metadata, OpenOptions};
+use walkdir::WalkDir;
+use time;
+use self::super::locks::write_supportedlock;
 use self::super::HttpHandler;
 use itertools::Itertools;
 use std::borrow::Borrow;
@@ -29,8 +35,19 @@ use iron::mime::Mime;
 use std::fmt;
 
 
+/// Collection snapshots handed out with [sync-collection](https://tools.ietf.org/html/rfc6578) REPORTs, oldest first,
+/// as (sync token, collection, snapshot)
+pub(super) type SyncSnapshots = Arc<RwLock<VecDeque<(String, PathBuf, SyncSnapshot)>>>;
+
+/// Members' paths relative to the collection, with their modification time, size, and whether they're directories
+type SyncSnapshot = BTreeMap<PathBuf, (SystemTime, u64, bool)>;
+
+/// How many snapshots are kept, across all collections; syncing from an older token than that starts over
+const SYNC_SNAPSHOTS_KEPT: usize = 32;
+
+
 lazy_static! {
-    static ref DEFAULT_XML_PARSER_CONFIG: XmlParserConfig = XmlParserConfig { trim_whitespace: true, ..Default::default() };
+    pub(super) static ref DEFAULT_XML_PARSER_CONFIG: XmlParserConfig = XmlParserConfig { trim_whitespace: true, ..Default::default() };
     static ref DEFAULT_XML_EMITTER_CONFIG: XmlEmitterConfig = XmlEmitterConfig { perform_indent: cfg!(debug_assertions), ..Default::default() };
 }
 
@@ -40,15 +57,21 @@ impl HttpHandler {
         let (req_p, symlink, url_err) = self.parse_requested_path(req);
 
         if url_err {
-            return self.handle_invalid_url(req, "<p>Percent-encoding decoded to invalid UTF-8.</p>");
+            return self.handle_invalid_url(req, "<p>Percent-encoding decoded to invalid UTF-8 or a path separator.</p>");
         }
 
-        if !req_p.exists() || (symlink && !self.follow_symlinks) ||
-           (symlink && self.follow_symlinks && self.sandbox_symlinks && !is_descendant_of(&req_p, &self.hosted_directory.1)) {
+        if !req_p.exists() || (symlink && !self.symlink_policy.follow()) ||
+           (symlink && self.symlink_policy.sandboxed() && !is_descendant_of(&req_p, &self.hosted_directory.1)) {
             return self.handle_nonexistent(req, req_p);
         }
 
 
+        // The body's optional, but if there is one it has to be framed unambiguously
+        match body_framing_error(&req.headers) {
+            Some((st, cause)) if st != status::LengthRequired => return self.handle_bad_framing(req, st, cause),
+            _ => {}
+        }
+
         let depth = req.headers.get::<Depth>().copied().unwrap_or(Depth::Zero);
 
         let props = match parse_propfind(req) {
@@ -69,9 +92,9 @@ impl HttpHandler {
                              "{} tried to {red}PROPFIND{reset} {yellow}{}{reset} with invalid XML",
                              self.remote_addresses(&req),
                              req_p.display());
-                        return self.handle_generated_response_encoding(req,
-                                                                       status::BadRequest,
-                                                                       html_response(ERROR_HTML, &["400 Bad Request", &format!("Invalid XML: {}", e), ""]));
+                        return self.handle_generated_error(req,
+                                                           status::BadRequest,
+                                                           &["400 Bad Request", &format!("Invalid XML: {}", e), ""]);
                     }
                     Err(props) => props,
                 }
@@ -104,7 +127,11 @@ impl HttpHandler {
         };
 
         match resp.expect("Couldn't write PROPFIND XML") {
-            Ok(xml_resp) => Ok(Response::with((status::MultiStatus, xml_resp, "text/xml;charset=utf-8".parse::<Mime>().unwrap()))),
+            Ok(xml_resp) => {
+                let mut resp = Response::with((status::MultiStatus, xml_resp, "text/xml;charset=utf-8".parse::<Mime>().unwrap()));
+                self.set_available_space(&mut resp, &req_p);
+                Ok(resp)
+            }
             Err(resp) => resp,
         }
     }
@@ -117,7 +144,10 @@ impl HttpHandler {
         let mut out = intialise_xml_output()?;
         out.write(namespaces_for_props("D:multistatus", props.iter().flat_map(|pp| pp.iter())))?;
 
-        let meta = path.metadata().expect("Failed to get requested file metadata");
+        let meta = match path.metadata() {
+            Ok(meta) => meta,
+            Err(err) => return Ok(Err(self.handle_fs_error(req, err, "getting its metadata"))),
+        };
         self.handle_propfind_path(&mut out, &url, &path, &meta, props, just_names)?;
 
         if meta.is_dir() {
@@ -134,14 +164,24 @@ impl HttpHandler {
     fn handle_webdav_propfind_path_recursive<'n, W: Write, N: BorrowXmlName<'n>>(&self, req: &mut Request, out: &mut XmlWriter<W>, root_url: String,
                                                                                  root_path: &Path, props: &[&'n [N]], just_names: bool, depth: Depth)
                                                                                  -> Result<Option<IronResult<Response>>, XmlWError> {
-        let mut links_left = MAX_SYMLINKS;
+        let mut links_left = self.max_symlink_depth;
         if let Some(next_depth) = depth.lower() {
-            for f in root_path.read_dir().expect("Failed to read requested directory").map(|p| p.expect("Failed to iterate over requested directory")) {
+            let rd = match root_path.read_dir() {
+                Ok(rd) => rd,
+                Err(err) => return Ok(Some(self.handle_fs_error(req, err, "reading it"))),
+            };
+            // Entries removed while being listed are left out
+            for f in rd.flatten() {
+                let fname = f.file_name();
                 let mut url = root_url.clone();
                 if !url.ends_with('/') {
                     url.push('/');
                 }
-                url.push_str(f.file_name().to_str().expect("Filename not UTF-8"));
+                match fname.to_str() {
+                    Some(fname) => url.push_str(fname),
+                    // Linked to byte for byte, like in listings
+                    None => url.push_str(&escape_file_name(&fname)),
+                }
 
                 let mut path = f.path();
                 let mut symlink = false;
@@ -160,14 +200,13 @@ impl HttpHandler {
                     }
                 }
 
-                if !(!path.exists() || (symlink && !self.follow_symlinks) ||
-                     (symlink && self.follow_symlinks && self.sandbox_symlinks && !is_descendant_of(&path, &self.hosted_directory.1))) {
-                    self.handle_propfind_path(out,
-                                              &url,
-                                              &path,
-                                              &path.metadata().expect("Failed to get requested file metadata"),
-                                              props,
-                                              just_names)?;
+                if !(!path.exists() || (symlink && !self.symlink_policy.follow()) ||
+                     (symlink && self.symlink_policy.sandboxed() && !is_descendant_of(&path, &self.hosted_directory.1)) || self.in_temp_dir(&path)) {
+                    let meta = match path.metadata() {
+                        Ok(meta) => meta,
+                        Err(_) => continue,
+                    };
+                    self.handle_propfind_path(out, &url, &path, &meta, props, just_names)?;
                     self.handle_webdav_propfind_path_recursive(req, out, url, &path, props, just_names, next_depth)?;
                 }
             }
@@ -176,22 +215,30 @@ impl HttpHandler {
         Ok(None)
     }
 
-    /// NB: we don't allow modifying any properties, so we 409 Conflict all of them
+    /// Clients can set modification and access times, and any properties that aren't live, which are stored as dead properties;
+    /// other live properties are protected
     pub(super) fn handle_webdav_proppatch(&self, req: &mut Request) -> IronResult<Response> {
         let (req_p, symlink, url_err) = self.parse_requested_path(req);
 
         if url_err {
-            return self.handle_invalid_url(req, "<p>Percent-encoding decoded to invalid UTF-8.</p>");
+            return self.handle_invalid_url(req, "<p>Percent-encoding decoded to invalid UTF-8 or a path separator.</p>");
         }
 
         if self.writes_temp_dir.is_none() {
             return self.handle_forbidden_method(req, "-w", "write requests");
         }
 
-        if !req_p.exists() || (symlink && !self.follow_symlinks) ||
-           (symlink && self.follow_symlinks && self.sandbox_symlinks && !is_descendant_of(&req_p, &self.hosted_directory.1)) {
+        if !req_p.exists() || (symlink && !self.symlink_policy.follow()) ||
+           (symlink && self.symlink_policy.sandboxed() && !is_descendant_of(&req_p, &self.hosted_directory.1)) {
             return self.handle_nonexistent(req, req_p);
         }
+        if self.webdav_write_locked(req, &req_p, false) {
+            return self.handle_locked(req, req_p);
+        }
+
+        if let Some((st, cause)) = body_framing_error(&req.headers) {
+            return self.handle_bad_framing(req, st, cause);
+        }
 
         let props = match parse_proppatch(req) {
             Ok(props) => props,
@@ -200,9 +247,9 @@ impl HttpHandler {
                      "{} tried to {red}PROPPATCH{reset} {yellow}{}{reset} with invalid XML",
                      self.remote_addresses(&req),
                      req_p.display());
-                return self.handle_generated_response_encoding(req,
-                                                               status::BadRequest,
-                                                               html_response(ERROR_HTML, &["400 Bad Request", &format!("Invalid XML: {}", e), ""]));
+                return self.handle_generated_error(req,
+                                                   status::BadRequest,
+                                                   &["400 Bad Request", &format!("Invalid XML: {}", e), ""]);
             }
         };
 
@@ -212,7 +259,90 @@ impl HttpHandler {
              CommaList(props.iter().map(|p| &p.0.local_name)),
              req_p.display());
 
-        match write_proppatch_output(&props, req.url.as_ref()).expect("Couldn't write PROPPATCH XML") {
+        // Either all of the changes are made, or none are
+        let mut dead = self.dead_props(&req_p);
+        let mut times = FileTimes::new();
+        let mut times_set = false;
+        let mut xattrs = vec![];
+        let mut results = Vec::with_capacity(props.len());
+        for (name, is_remove, value) in &props {
+            let st = if self.xattrs && name.namespace.as_ref().map(|ns| &ns[..]) == Some(WEBDAV_XML_NAMESPACE_XATTR.1) {
+                if *is_remove {
+                    xattrs.push((&name.local_name, None));
+                    "200 OK"
+                } else {
+                    match base64::decode(value.trim()) {
+                        Ok(val) => {
+                            xattrs.push((&name.local_name, Some(val)));
+                            "200 OK"
+                        }
+                        Err(_) => "409 Conflict",
+                    }
+                }
+            } else if webdav_live_prop(name.borrow()) {
+                match (is_remove, webdav_settable_time_prop(name.borrow())) {
+                    (false, Some(accessed)) => {
+                        match parse_webdav_time(value) {
+                            Some(t) => {
+                                times = if accessed { times.set_accessed(t) } else { times.set_modified(t) };
+                                times_set = true;
+                                "200 OK"
+                            }
+                            None => "409 Conflict",
+                        }
+                    }
+                    _ => "403 Forbidden",
+                }
+            } else {
+                let key = dead_prop_key(name.borrow());
+                if *is_remove {
+                    dead.remove(&key);
+                } else {
+                    dead.insert(key, value.clone());
+                }
+                "200 OK"
+            };
+            results.push((name, st));
+        }
+
+        if results.iter().all(|(_, st)| *st == "200 OK") {
+            let applied = (|| {
+                if times_set {
+                    if req_p.is_dir() {
+                            File::open(&req_p)
+                        } else {
+                            OpenOptions::new().write(true).open(&req_p)
+                        }?
+                        .set_times(times)?;
+                }
+                for (name, val) in &xattrs {
+                    set_file_xattr(&req_p, name, val.as_ref().map(|v| &v[..]))?;
+                }
+                self.set_dead_props(&req_p, &dead)
+            })();
+            if let Err(e) = applied {
+                log!(self.log,
+                     "{} Failed to apply properties: {}",
+                     Spaces(self.remote_addresses(req).width()),
+                     e);
+                let st = if is_storage_full(&e) {
+                    "507 Insufficient Storage"
+                } else {
+                    "403 Forbidden"
+                };
+                for (_, res) in &mut results {
+                    *res = st;
+                }
+            }
+        } else {
+            for (_, res) in &mut results {
+                if *res == "200 OK" {
+                    *res = "424 Failed Dependency";
+                }
+            }
+        }
+
+        match write_proppatch_output(&results, req.url.as_ref()).expect("Couldn't write PROPPATCH XML") {
             Ok(xml_resp) => Ok(Response::with((status::MultiStatus, xml_resp, "text/xml;charset=utf-8".parse::<Mime>().unwrap()))),
             Err(resp) => resp,
         }
@@ -227,18 +357,25 @@ impl HttpHandler {
              req_p.display());
 
         if url_err {
-            return self.handle_invalid_url(req, "<p>Percent-encoding decoded to invalid UTF-8.</p>");
+            return self.handle_invalid_url(req, "<p>Percent-encoding decoded to invalid UTF-8 or a path separator.</p>");
         }
 
         if self.writes_temp_dir.is_none() {
             return self.handle_forbidden_method(req, "-w", "write requests");
         }
 
-        if !req_p.parent().map(|pp| pp.exists()).unwrap_or(true) || (symlink && !self.follow_symlinks) ||
-           (symlink && self.follow_symlinks && self.sandbox_symlinks && !is_descendant_of(&req_p, &self.hosted_directory.1)) {
+        if !req_p.parent().map(|pp| pp.exists()).unwrap_or(true) || (symlink && !self.symlink_policy.follow()) ||
+           (symlink && self.symlink_policy.sandboxed() && !is_descendant_of(&req_p, &self.hosted_directory.1)) {
             return self.handle_nonexistent_status(req, req_p, status::Conflict);
         }
+        if self.webdav_write_locked(req, &req_p, false) {
+            return self.handle_locked(req, req_p);
+        }
 
+        match body_framing_error(&req.headers) {
+            Some((st, cause)) if st != status::LengthRequired => return self.handle_bad_framing(req, st, cause),
+            _ => {}
+        }
         if req.body.read_exact(&mut [0]).is_ok() {
             return Ok(Response::with(status::UnsupportedMediaType));
         }
@@ -269,13 +406,15 @@ impl HttpHandler {
             let (req_p, is_file) = sp;
 
             let removal = if is_file {
-                fs::remove_file(req_p)
+                fs::remove_file(&req_p)
             } else {
-                fs::remove_dir_all(req_p)
+                fs::remove_dir_all(&req_p)
             };
+            self.written(&req_p);
             if removal.is_err() {
                 return Ok(Response::with(status::Locked));
             }
+            self.drop_webdav_locks(&req_p);
         }
 
         Ok(resp)
@@ -285,7 +424,7 @@ impl HttpHandler {
         let (req_p, symlink, url_err) = self.parse_requested_path(req);
 
         if url_err {
-            return self.handle_invalid_url(req, "<p>Percent-encoding decoded to invalid UTF-8.</p>");
+            return self.handle_invalid_url(req, "<p>Percent-encoding decoded to invalid UTF-8 or a path separator.</p>");
         }
         let (dest_p, dest_symlink) = match req.headers.get::<Destination>() {
             Some(dest) => {
@@ -294,6 +433,9 @@ impl HttpHandler {
                 if dest_url_err {
                     return self.handle_invalid_url(req, "<p>Percent-encoding decoded destination to invalid UTF-8.</p>");
                 }
+                if self.in_temp_dir(&dest_p) {
+                    return self.handle_nonexistent(req, dest_p);
+                }
 
                 (dest_p, dest_symlink)
             }
@@ -320,23 +462,30 @@ impl HttpHandler {
             return Ok(Response::with(status::Forbidden));
         }
 
-        if !req_p.exists() || (symlink && !self.follow_symlinks) ||
-           (symlink && self.follow_symlinks && self.sandbox_symlinks && !is_descendant_of(&req_p, &self.hosted_directory.1)) {
+        if !req_p.exists() || (symlink && !self.symlink_policy.follow()) ||
+           (symlink && self.symlink_policy.sandboxed() && !is_descendant_of(&req_p, &self.hosted_directory.1)) {
             return self.handle_nonexistent(req, req_p);
         }
 
-        if !dest_p.parent().map(|pp| pp.exists()).unwrap_or(true) || (dest_symlink && !self.follow_symlinks) ||
-           (dest_symlink && self.follow_symlinks && self.sandbox_symlinks && !is_descendant_of(&dest_p, &self.hosted_directory.1)) {
+        if !dest_p.parent().map(|pp| pp.exists()).unwrap_or(true) || (dest_symlink && !self.symlink_policy.follow()) ||
+           (dest_symlink && self.symlink_policy.sandboxed() && !is_descendant_of(&dest_p, &self.hosted_directory.1)) {
             return Ok(Response::with(status::Conflict));
         }
 
+        if is_move && self.webdav_write_locked(req, &req_p, true) {
+            return self.handle_locked(req, req_p);
+        }
+        if self.webdav_write_locked(req, &dest_p, true) {
+            return self.handle_locked(req, dest_p);
+        }
+
         let mut overwritten = false;
         if dest_p.exists() {
             if !overwrite {
                 return Ok(Response::with(status::PreconditionFailed));
             }
 
-            if !is_actually_file(&dest_p.metadata().expect("Failed to get destination file metadata").file_type(), &dest_p) {
+            if !is_actually_file(&try_fs!(self, req, dest_p.metadata(), "getting its destination's metadata").file_type(), &dest_p) {
                 // NB: this disallows overwriting non-empty directories
                 if fs::remove_dir(&dest_p).is_err() {
                     return Ok(Response::with(status::Locked));
@@ -346,18 +495,25 @@ impl HttpHandler {
             overwritten = true;
         }
 
-        let source_file = is_actually_file(&req_p.metadata().expect("Failed to get requested file metadata").file_type(), &dest_p);
+        let source_file = is_actually_file(&try_fs!(self, req, req_p.metadata(), "getting its metadata").file_type(), &dest_p);
         if let Some(sp) = source_path {
             *sp = (req_p.clone(), source_file);
         }
-        if source_file {
-            copy_response(fs::copy(req_p, dest_p).map(|_| ()), overwritten)
+        let resp = if source_file {
+            let copied = fs::copy(&req_p, &dest_p).map(|_| ());
+            if copied.is_ok() && self.xattrs {
+                copy_xattrs(&req_p, &dest_p);
+            }
+            copy_response(copied, overwritten)
         } else {
             match depth {
-                Depth::Zero if !is_move => copy_response(fs::create_dir(dest_p), overwritten),
+                Depth::Zero if !is_move => copy_response(fs::create_dir(&dest_p), overwritten),
                 Depth::Infinity => {
                     match copy_dir(&req_p, &dest_p) {
                         Ok(errors) => {
+                            if self.xattrs {
+                                copy_xattrs(&req_p, &dest_p);
+                            }
                             if errors.is_empty() {
                                 copy_response(Ok(()), overwritten)
                             } else {
@@ -369,12 +525,169 @@ impl HttpHandler {
                     }
                 }
                 _ => {
-                    self.handle_generated_response_encoding(req,
-                                                            status::BadRequest,
-                                                            html_response(ERROR_HTML, &["400 Bad Request", &format!("Invalid depth: {}", depth), ""]))
+                    self.handle_generated_error(req,
+                                                status::BadRequest,
+                                                &["400 Bad Request", &format!("Invalid depth: {}", depth), ""])
                 }
             }
+        };
+        self.written(&dest_p);
+        resp
+    }
+
+    /// Only [sync-collection](https://tools.ietf.org/html/rfc6578#section-3.2) REPORTs are supported,
+    /// answered with the members changed or removed since the snapshot named by the sync token
+    pub(super) fn handle_webdav_report(&self, req: &mut Request) -> IronResult<Response> {
+        let (req_p, symlink, url_err) = self.parse_requested_path(req);
+
+        if url_err {
+            return self.handle_invalid_url(req, "<p>Percent-encoding decoded to invalid UTF-8 or a path separator.</p>");
+        }
+
+        if !req_p.exists() || (symlink && !self.symlink_policy.follow()) ||
+           (symlink && self.symlink_policy.sandboxed() && !is_descendant_of(&req_p, &self.hosted_directory.1)) {
+            return self.handle_nonexistent(req, req_p);
+        }
+
+        if let Some((st, cause)) = body_framing_error(&req.headers) {
+            return self.handle_bad_framing(req, st, cause);
+        }
+
+        let (token, infinite, props) = match parse_sync_collection(req) {
+            Ok(Some(sc)) if req_p.is_dir() => sc,
+            Ok(_) => {
+                log!(self.log,
+                     "{} requested unsupported {red}REPORT{reset} on {yellow}{}{reset}",
+                     self.remote_addresses(req),
+                     req_p.display());
+                return webdav_condition_failed(status::Forbidden, "supported-report");
+            }
+            Err(e) => {
+                log!(self.log,
+                     "{} tried to {red}REPORT{reset} {yellow}{}{reset} with invalid XML",
+                     self.remote_addresses(req),
+                     req_p.display());
+                return self.handle_generated_error(req,
+                                                   status::BadRequest,
+                                                   &["400 Bad Request", &format!("Invalid XML: {}", e), ""]);
+            }
+        };
+
+        let previous = if token.is_empty() {
+            Some(SyncSnapshot::new())
+        } else {
+            self.sync_snapshots
+                .read()
+                .unwrap_or_else(PoisonError::into_inner)
+                .iter()
+                .find(|(tok, p, _)| *tok == token && *p == req_p)
+                .map(|(_, _, snap)| snap.clone())
+        };
+        let previous = match previous {
+            Some(previous) if token.is_empty() || token.starts_with(sync_token_prefix(infinite)) => previous,
+            _ => {
+                log!(self.log,
+                     "{} requested {red}REPORT{reset} sync-collection of {yellow}{}{reset} with unknown sync token {}",
+                     self.remote_addresses(req),
+                     req_p.display(),
+                     token);
+                return webdav_condition_failed(status::Forbidden, "valid-sync-token");
+            }
+        };
+
+        let current = self.sync_snapshot(&req_p, infinite);
+        let new_token = sync_token(&req_p, infinite, &current);
+
+        let mut url = req.url.as_ref().as_str().to_string();
+        if !url.ends_with('/') {
+            url.push('/');
+        }
+        let (xml_resp, changed, removed) = self.handle_webdav_report_write_output(&url, &req_p, &props, &previous, &current, &new_token)
+            .expect("Couldn't write REPORT XML");
+
+        log!(self.log,
+             "{} requested {red}REPORT{reset} sync-collection of {} on {yellow}{}{reset} at depth {} since {}: {} changed, {} removed",
+             self.remote_addresses(req),
+             PropfindVariant::Props(props),
+             req_p.display(),
+             if infinite { "infinity" } else { "1" },
+             if token.is_empty() { "the start" } else { &token },
+             changed,
+             removed);
+
+        {
+            let mut snapshots = self.sync_snapshots.write().unwrap_or_else(PoisonError::into_inner);
+            if !snapshots.iter().any(|(tok, p, _)| *tok == new_token && *p == req_p) {
+                snapshots.push_back((new_token, req_p, current));
+                while snapshots.len() > SYNC_SNAPSHOTS_KEPT {
+                    snapshots.pop_front();
+                }
+            }
+        }
+
+        Ok(Response::with((status::MultiStatus, xml_resp, "text/xml;charset=utf-8".parse::<Mime>().unwrap())))
+    }
+
+    fn handle_webdav_report_write_output(&self, url: &str, root: &Path, props: &[OwnedXmlName], previous: &SyncSnapshot, current: &SyncSnapshot,
+                                         token: &str)
+                                         -> Result<(Vec<u8>, usize, usize), XmlWError> {
+        let mut out = intialise_xml_output()?;
+        out.write(namespaces_for_props("D:multistatus", props.iter()))?;
+
+        let mut changed = 0;
+        for (subp, state) in current {
+            if previous.get(subp) == Some(state) {
+                continue;
+            }
+
+            let path = root.join(subp);
+            if let Ok(meta) = path.metadata() {
+                self.handle_propfind_path(&mut out, &sync_member_url(url, subp, state.2), &path, &meta, &[props], false)?;
+                changed += 1;
+            }
+        }
+
+        let mut removed = 0;
+        for (subp, state) in previous {
+            if current.contains_key(subp) {
+                continue;
+            }
+
+            out.write(XmlWEvent::start_element("D:response"))?;
+            out.write(XmlWEvent::start_element("D:href"))?;
+            out.write(XmlWEvent::characters(&sync_member_url(url, subp, state.2)))?;
+            out.write(XmlWEvent::end_element())?;
+            out.write(XmlWEvent::start_element("D:status"))?;
+            out.write(XmlWEvent::characters("HTTP/1.1 404 Not Found"))?;
+            out.write(XmlWEvent::end_element())?;
+            out.write(XmlWEvent::end_element())?;
+            removed += 1;
         }
+
+        out.write(XmlWEvent::start_element("D:sync-token"))?;
+        out.write(XmlWEvent::characters(token))?;
+        out.write(XmlWEvent::end_element())?;
+
+        out.write(XmlWEvent::end_element())?;
+
+        Ok((out.into_inner(), changed, removed))
+    }
+
+    /// Snapshot the members of the specified collection, or all of its descendants, skipping the same ones listings do
+    fn sync_snapshot(&self, root: &Path, infinite: bool) -> SyncSnapshot {
+        WalkDir::new(root)
+            .min_depth(1)
+            .max_depth(if infinite { usize::MAX } else { 1 })
+            .follow_links(self.symlink_policy.follow())
+            .into_iter()
+            .filter_entry(|e| self.listed(e.path()))
+            .flatten()
+            .flat_map(|e| {
+                let meta = e.metadata().ok()?;
+                Some((e.path().strip_prefix(root).expect("Walked outside requested directory").to_path_buf(),
+                      (meta.modified().unwrap_or(SystemTime::UNIX_EPOCH), meta.len(), meta.is_dir())))
+            })
+            .collect()
     }
 
     /// Adapted from
@@ -390,6 +703,7 @@ impl HttpHandler {
 
         let prop_count = props.iter().map(|pp| pp.len()).sum();
         let mut failed_props = Vec::with_capacity(prop_count);
+        let mut dead_props = None;
         out.write(XmlWEvent::start_element("D:propstat"))?;
         out.write(XmlWEvent::start_element("D:prop"))?;
         for prop in props.iter().flat_map(|pp| pp.iter()) {
@@ -397,8 +711,17 @@ impl HttpHandler {
 
             let mut write_name = false;
             if !just_names && !self.handle_prop_path(out, path, meta, prop)? {
-                failed_props.push(prop);
-                write_name = true;
+                match dead_props.get_or_insert_with(|| self.dead_props(path)).get(&dead_prop_key(prop)) {
+                    Some(val) => {
+                        start_client_prop_element(out, prop)?;
+                        out.write(XmlWEvent::characters(val))?;
+                        out.write(XmlWEvent::end_element())?;
+                    }
+                    None => {
+                        failed_props.push(prop);
+                        write_name = true;
+                    }
+                }
             }
 
             if just_names || write_name {
@@ -476,6 +799,16 @@ impl HttpHandler {
                     }
                 }
 
+                "lockdiscovery" => {
+                    out.write(XmlWEvent::start_element((WEBDAV_XML_NAMESPACE_DAV.0, "lockdiscovery")))?;
+                    self.write_lockdiscovery(out, path)?;
+                }
+
+                "supportedlock" => {
+                    out.write(XmlWEvent::start_element((WEBDAV_XML_NAMESPACE_DAV.0, "supportedlock")))?;
+                    write_supportedlock(out)?;
+                }
+
                 _ => return Ok(false),
             }
         } else if prop.namespace == Some(WEBDAV_XML_NAMESPACE_MICROSOFT.1) {
@@ -513,6 +846,14 @@ impl HttpHandler {
 
                 _ => return Ok(false),
             }
+        } else if self.xattrs && prop.namespace == Some(WEBDAV_XML_NAMESPACE_XATTR.1) {
+            match file_xattrs(path).into_iter().find(|(name, _)| name == prop.local_name) {
+                Some((_, val)) => {
+                    out.write(XmlWEvent::start_element(XmlName { prefix: Some(WEBDAV_XML_NAMESPACE_XATTR.0), ..prop }))?;
+                    out.write(XmlWEvent::characters(&base64::encode(&val)))?;
+                }
+                None => return Ok(false),
+            }
         } else {
             return Ok(false);
         }
@@ -606,6 +947,8 @@ fn start_client_prop_element<W: Write>(out: &mut XmlWriter<W>, prop: XmlName) ->
 
         if prop.prefix.map(|prop_prefix| WEBDAV_XML_NAMESPACES.iter().any(|(pf, _)| *pf == prop_prefix)).unwrap_or(true) {
             return out.write(XmlWEvent::start_element(XmlName { prefix: Some("U"), ..prop }).ns("U", prop_namespace));
+        } else if let Some(prop_prefix) = prop.prefix {
+            return out.write(XmlWEvent::start_element(prop).ns(prop_prefix, prop_namespace));
         }
     }
 
@@ -613,7 +956,9 @@ fn start_client_prop_element<W: Write>(out: &mut XmlWriter<W>, prop: XmlName) ->
 }
 
 /// https://tools.ietf.org/html/rfc2518#section-12.13
-fn parse_proppatch(req: &mut Request) -> Result<Vec<(OwnedXmlName, bool)>, String> {
+///
+/// Returns the properties with whether they're to be removed, and their text values
+fn parse_proppatch(req: &mut Request) -> Result<Vec<(OwnedXmlName, bool, String)>, String> {
     #[derive(Debug, Copy, Clone, Hash, PartialOrd, Ord, PartialEq, Eq)]
     enum State {
         Start,
@@ -653,7 +998,7 @@ fn parse_proppatch(req: &mut Request) -> Result<Vec<(OwnedXmlName, bool)>, Strin
             (State::Prop, XmlREvent::StartElement { name, .. }) => {
                 state = State::InProp;
                 propname = Some(name.clone());
-                props.push((name, is_remove));
+                props.push((name, is_remove, String::new()));
             }
             (State::Prop, XmlREvent::EndElement { .. }) => state = State::Action,
 
@@ -662,6 +1007,7 @@ fn parse_proppatch(req: &mut Request) -> Result<Vec<(OwnedXmlName, bool)>, Strin
                     state = State::Prop;
                 }
             }
+            (State::InProp, XmlREvent::Characters(val)) => props.last_mut().expect("Property value without property").2.push_str(&val),
             (State::InProp, _) => {}
 
             (st, ev) => return Err(format!("Unexpected event {:?} during state {:?}", ev, st)),
@@ -669,36 +1015,188 @@ fn parse_proppatch(req: &mut Request) -> Result<Vec<(OwnedXmlName, bool)>, Strin
     }
 }
 
-fn write_proppatch_output(props: &[(OwnedXmlName, bool)], req_url: &GenericUrl) -> Result<Result<Vec<u8>, IronResult<Response>>, XmlWError> {
+/// https://tools.ietf.org/html/rfc6578#section-6.1
+///
+/// Returns the sync token, whether the sync level is infinite, and the requested properties,
+/// or `None` if this is a different kind of report
+fn parse_sync_collection(req: &mut Request) -> Result<Option<(String, bool, Vec<OwnedXmlName>)>, String> {
+    #[derive(Debug, Copy, Clone, Hash, PartialOrd, Ord, PartialEq, Eq)]
+    enum State {
+        Start,
+        SyncCollection,
+        SyncToken,
+        SyncLevel,
+        Prop,
+        InProp,
+        Skip,
+    }
+
+
+    let mut xml = XmlReader::new_with_config(&mut req.body, DEFAULT_XML_PARSER_CONFIG.clone());
+    let mut state = State::Start;
+    let mut token = String::new();
+    let mut level = None;
+    let mut props = vec![];
+    let mut skipped = None;
+
+    loop {
+        let event = xml.next().map_err(|e| e.to_string())?;
+
+        match (state, event) {
+            (State::Start, XmlREvent::StartDocument { .. }) => (),
+            (State::Start, XmlREvent::StartElement { ref name, .. }) if name.local_name == "sync-collection" => state = State::SyncCollection,
+            (State::Start, XmlREvent::StartElement { .. }) => return Ok(None),
+
+            (State::SyncCollection, XmlREvent::StartElement { ref name, .. }) if name.local_name == "sync-token" => state = State::SyncToken,
+            (State::SyncCollection, XmlREvent::StartElement { ref name, .. }) if name.local_name == "sync-level" => state = State::SyncLevel,
+            (State::SyncCollection, XmlREvent::StartElement { ref name, .. }) if name.local_name == "prop" => state = State::Prop,
+            (State::SyncCollection, XmlREvent::StartElement { name, .. }) => {
+                // <limit> and unknown extensions
+                state = State::Skip;
+                skipped = Some(name);
+            }
+            (State::SyncCollection, XmlREvent::EndElement { .. }) => break,
+
+            (State::SyncToken, XmlREvent::Characters(tok)) => token = tok,
+            (State::SyncToken, XmlREvent::EndElement { .. }) => state = State::SyncCollection,
+
+            (State::SyncLevel, XmlREvent::Characters(lvl)) => level = Some(lvl),
+            (State::SyncLevel, XmlREvent::EndElement { .. }) => state = State::SyncCollection,
+
+            (State::Prop, XmlREvent::StartElement { name, .. }) => {
+                state = State::InProp;
+                props.push(name);
+            }
+            (State::Prop, XmlREvent::EndElement { .. }) => state = State::SyncCollection,
+
+            (State::InProp, XmlREvent::EndElement { .. }) => state = State::Prop,
+
+            (State::Skip, XmlREvent::EndElement { name, .. }) => {
+                if Some(name) == skipped {
+                    state = State::SyncCollection;
+                }
+            }
+            (State::Skip, _) => {}
+
+            (st, ev) => return Err(format!("Unexpected event {:?} during state {:?}", ev, st)),
+        }
+    }
+
+    match level.as_ref().map(|l| &l[..]) {
+        Some("1") => Ok(Some((token, false, props))),
+        Some("infinite") => Ok(Some((token, true, props))),
+        Some(l) => Err(format!("Unsupported sync-level {}", l)),
+        None => Err("Missing sync-level".to_string()),
+    }
+}
+
+/// Sync tokens are [`data:` URIs](https://tools.ietf.org/html/rfc2397) naming the sync level and the snapshot's contents
+fn sync_token_prefix(infinite: bool) -> &'static str {
+    if infinite {
+        "data:,infinite-"
+    } else {
+        "data:,1-"
+    }
+}
+
+fn sync_token(root: &Path, infinite: bool, snapshot: &SyncSnapshot) -> String {
+    let mut hash = blake3::Hasher::new();
+    hash.update(root.to_string_lossy().as_bytes());
+    for (subp, (modified, len, is_dir)) in snapshot {
+        hash.update(subp.to_string_lossy().as_bytes());
+        hash.update(&modified.duration_since(SystemTime::UNIX_EPOCH).map(|d| d.as_nanos()).unwrap_or(0).to_le_bytes());
+        hash.update(&len.to_le_bytes());
+        hash.update(&[*is_dir as u8]);
+    }
+    format!("{}{}", sync_token_prefix(infinite), &hash.finalize().to_hex()[..32])
+}
+
+fn sync_member_url(collection_url: &str, subp: &Path, is_dir: bool) -> String {
+    let mut url = collection_url.to_string();
+    for (i, segment) in subp.iter().enumerate() {
+        if i != 0 {
+            url.push('/');
+        }
+        url.extend(percent_encode(&file_name_bytes(segment), PATH_SEGMENT_ENCODE_SET));
+    }
+    if is_dir {
+        url.push('/');
+    }
+    url
+}
+
+/// https://tools.ietf.org/html/rfc4918#section-16
+pub(super) fn webdav_condition_failed(st: status::Status, condition: &str) -> IronResult<Response> {
+    let mut out = intialise_xml_output().expect("Couldn't write WebDAV error XML");
+    out.write(XmlWEvent::start_element("D:error").ns(WEBDAV_XML_NAMESPACE_DAV.0, WEBDAV_XML_NAMESPACE_DAV.1))
+        .and_then(|_| out.write(XmlWEvent::start_element((WEBDAV_XML_NAMESPACE_DAV.0, condition))))
+        .and_then(|_| out.write(XmlWEvent::end_element()))
+        .and_then(|_| out.write(XmlWEvent::end_element()))
+        .expect("Couldn't write WebDAV error XML");
+
+    Ok(Response::with((st, out.into_inner(), "text/xml;charset=utf-8".parse::<Mime>().unwrap())))
+}
+
+fn write_proppatch_output(results: &[(&OwnedXmlName, &str)], req_url: &GenericUrl) -> Result<Result<Vec<u8>, IronResult<Response>>, XmlWError> {
     let mut out = intialise_xml_output()?;
-    out.write(namespaces_for_props("D:multistatus", props.iter().map(|pp| &pp.0)))?;
+    out.write(namespaces_for_props("D:multistatus", results.iter().map(|pp| pp.0)))?;
+    out.write(XmlWEvent::start_element("D:response"))?;
 
     out.write(XmlWEvent::start_element("D:href"))?;
     out.write(XmlWEvent::characters(req_url.as_str()))?;
     out.write(XmlWEvent::end_element())?;
 
-    out.write(XmlWEvent::start_element("D:propstat"))?;
-
-    for (name, _) in props {
+    for st in results.iter().map(|pp| pp.1).unique() {
+        out.write(XmlWEvent::start_element("D:propstat"))?;
         out.write(XmlWEvent::start_element("D:prop"))?;
-
-        start_client_prop_element(&mut out, name.borrow())?;
+        for (name, _) in results.iter().filter(|pp| pp.1 == st) {
+            start_client_prop_element(&mut out, (*name).borrow())?;
+            out.write(XmlWEvent::end_element())?;
+        }
         out.write(XmlWEvent::end_element())?;
 
+        out.write(XmlWEvent::start_element("D:status"))?;
+        out.write(XmlWEvent::characters(&format!("HTTP/1.1 {}", st)))?;
+        out.write(XmlWEvent::end_element())?;
         out.write(XmlWEvent::end_element())?;
     }
 
-    out.write(XmlWEvent::start_element("D:status"))?;
-    out.write(XmlWEvent::characters("HTTP/1.1 409 Conflict"))?;
     out.write(XmlWEvent::end_element())?;
-
-    out.write(XmlWEvent::end_element())?;
-
     out.write(XmlWEvent::end_element())?;
 
     Ok(Ok(out.into_inner()))
 }
 
+/// Whether this is a property we compute, as opposed to a dead one
+fn webdav_live_prop(prop: XmlName) -> bool {
+    WEBDAV_ALLPROP_PROPERTIES_WINDOWS.iter()
+        .chain(WEBDAV_PROPNAME_PROPERTIES.iter())
+        .flat_map(|pp| pp.iter())
+        .any(|lp| lp.namespace == prop.namespace && lp.local_name == prop.local_name)
+}
+
+/// Whether this live property can be set, and if so, whether it's the access time (or modification time)
+fn webdav_settable_time_prop(prop: XmlName) -> Option<bool> {
+    match (prop.namespace, prop.local_name) {
+        (Some(ns), "getlastmodified") if ns == WEBDAV_XML_NAMESPACE_DAV.1 => Some(false),
+        (Some(ns), "Win32LastModifiedTime") if ns == WEBDAV_XML_NAMESPACE_MICROSOFT.1 => Some(false),
+        (Some(ns), "Win32LastAccessTime") if ns == WEBDAV_XML_NAMESPACE_MICROSOFT.1 => Some(true),
+        _ => None,
+    }
+}
+
+/// Clients send [RFC 1123](https://tools.ietf.org/html/rfc1123#page-55) dates, but we return RFC 3339 ones, so accept both
+fn parse_webdav_time(val: &str) -> Option<SystemTime> {
+    let tm = time::strptime(val.trim(), "%a, %d %b %Y %T GMT").or_else(|_| time::strptime(val.trim(), "%Y-%m-%dT%H:%M:%SZ")).ok()?;
+    let ts = tm.to_timespec();
+    Some(UNIX_EPOCH + Duration::from_secs(ts.sec.max(0) as u64))
+}
+
+/// Dead properties are stored by their [Clark notation](http://www.jclark.com/xml/xmlns.htm) names
+fn dead_prop_key(prop: XmlName) -> String {
+    format!("{{{}}}{}", prop.namespace.unwrap_or(""), prop.local_name)
+}
+
 fn copy_response(op_result: IoResult<()>, overwritten: bool) -> IronResult<Response> {
     match op_result {
         Ok(_) => {
@@ -734,7 +1232,7 @@ fn copy_response_multierror(errors: &[(IoError, String)], req_url: &GenericUrl)
     Ok(out.into_inner())
 }
 
-fn intialise_xml_output() -> Result<XmlWriter<Vec<u8>>, XmlWError> {
+pub(super) fn intialise_xml_output() -> Result<XmlWriter<Vec<u8>>, XmlWError> {
     let mut out = XmlWriter::new_with_config(vec![], DEFAULT_XML_EMITTER_CONFIG.clone());
 
     out.write(XmlWEvent::StartDocument {
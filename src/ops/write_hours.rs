@@ -0,0 +1,59 @@
+//! Writes restricted to a daily window, enabled with `--write-hours HH:MM-HH:MM`: outside it, requests that'd change
+//! the hosted directory are refused with a 403, while reading goes on as usual.
+
+
+use iron::{status, method, IronResult, Response, Request};
+use self::super::super::util::url_path;
+use self::super::HttpHandler;
+use time;
+
+
+impl HttpHandler {
+    /// Whether the request would write and writes are restricted to a window that's currently closed
+    pub(super) fn outside_write_hours(&self, req: &Request) -> bool {
+        match self.write_hours {
+            Some(hours) => is_write(&req.method) && !within(hours, minute_of_day()),
+            None => false,
+        }
+    }
+
+    pub(super) fn handle_outside_write_hours(&self, req: &mut Request) -> IronResult<Response> {
+        let (start, end) = self.write_hours.expect("Write hours not set");
+        let window = format!("{:02}:{:02} and {:02}:{:02}", start / 60, start % 60, end / 60, end % 60);
+        log!(self.log,
+             "{} tried to {red}{}{reset} {yellow}{}{reset} outside write hours",
+             self.remote_addresses(req),
+             req.method,
+             url_path(&req.url));
+
+        self.handle_generated_error(req,
+                                    status::Forbidden,
+                                    &["403 Forbidden",
+                                      "Writes aren't accepted right now.",
+                                      &format!("<p>They're only accepted between {} (server time).</p>", window)])
+    }
+}
+
+
+/// Current local time as minutes since midnight
+fn minute_of_day() -> u16 {
+    let now = time::now();
+    (now.tm_hour * 60 + now.tm_min) as u16
+}
+
+/// Whether `minute` is in `[start, end)`, which wraps around midnight if `end < start`
+fn within((start, end): (u16, u16), minute: u16) -> bool {
+    if start < end {
+        start <= minute && minute < end
+    } else {
+        start <= minute || minute < end
+    }
+}
+
+fn is_write(m: &method::Method) -> bool {
+    match *m {
+        method::Put | method::Post | method::Delete | method::Patch => true,
+        method::Extension(ref ext) => ["MKCOL", "COPY", "MOVE", "PROPPATCH"].contains(&&ext[..]),
+        _ => false,
+    }
+}
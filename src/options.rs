@@ -15,12 +15,20 @@ use clap::{AppSettings, ErrorKind as ClapErrorKind, Error as ClapError, Arg, App
 use std::collections::btree_map::{BTreeMap, Entry as BTreeMapEntry};
 use std::collections::BTreeSet;
 use std::env::{self, temp_dir};
-use std::num::NonZeroU64;
-use std::path::PathBuf;
+use std::num::{NonZeroUsize, NonZeroU64, NonZeroU32};
+use iron::url::Url as GenericUrl;
+use std::path::{PathBuf, Path};
 use std::str::FromStr;
+use std::time::Duration;
+use std::thread;
 use std::borrow::Cow;
+use std::ffi::OsString;
 use iron::mime::Mime;
-use std::net::IpAddr;
+use std::net::{SocketAddr, IpAddr};
+use serde_json;
+use std::fmt;
+use self::super::util::{verbatim_path, glob_regex, Htpasswd, ADMIN_PREFIX, MAX_SYMLINKS, MIN_ENCODING_GAIN, MAX_ENCODING_SIZE, MIN_ENCODING_SIZE, GENERATED_CACHE_BUDGET,
+                        ENCODED_CACHE_ENTRIES};
 use regex::Regex;
 use cidr::IpCidr;
 use std::fs;
@@ -56,37 +64,180 @@ impl From<u64> for LogLevel {
     }
 }
 
+/// Where symlinks in the hosted directory can be followed to
+#[derive(Debug, Copy, Clone, Hash, PartialEq, Eq)]
+pub enum SymlinkPolicy {
+    /// Don't follow symlinks
+    Never,
+    /// Only follow symlinks leading to descendants of the hosted directory
+    WithinRoot,
+    /// Follow all symlinks
+    Always,
+}
+
+impl SymlinkPolicy {
+    /// Whether symlinks are followed at all
+    pub fn follow(self) -> bool {
+        self != SymlinkPolicy::Never
+    }
+
+    /// Whether followed symlinks must stay within the hosted directory
+    pub fn sandboxed(self) -> bool {
+        self == SymlinkPolicy::WithinRoot
+    }
+}
+
+impl FromStr for SymlinkPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<SymlinkPolicy, String> {
+        match s {
+            "never" => Ok(SymlinkPolicy::Never),
+            "within-root" => Ok(SymlinkPolicy::WithinRoot),
+            "always" => Ok(SymlinkPolicy::Always),
+            _ => Err(format!("{} is not a valid symlink policy", s)),
+        }
+    }
+}
+
+impl fmt::Display for SymlinkPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match *self {
+            SymlinkPolicy::Never => "never",
+            SymlinkPolicy::WithinRoot => "within-root",
+            SymlinkPolicy::Always => "always",
+        })
+    }
+}
+
+
+/// Where encoded filesystem files are kept
+#[derive(Debug, Clone, Hash, PartialEq, Eq)]
+pub enum EncodedStoreKind {
+    /// In the temp dir, removed on exit
+    Temp,
+    /// In the temp dir, removed on exit, evicting the least recently written ones above this many bytes
+    Capped(u64),
+    /// In this directory, kept across restarts and shareable between servers
+    Shared(PathBuf),
+    /// In memory, not touching the filesystem, evicting the least recently used ones above this many bytes
+    Memory(u64),
+}
+
+impl FromStr for EncodedStoreKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<EncodedStoreKind, String> {
+        if s == "temp" {
+            Ok(EncodedStoreKind::Temp)
+        } else if s.starts_with("capped:") {
+            Options::size_parse(s["capped:".len()..].into()).map(EncodedStoreKind::Capped)
+        } else if s.starts_with("shared:") && s.len() > "shared:".len() {
+            Ok(EncodedStoreKind::Shared(PathBuf::from(&s["shared:".len()..])))
+        } else if s.starts_with("memory:") {
+            Options::size_parse(s["memory:".len()..].into()).map(EncodedStoreKind::Memory)
+        } else {
+            Err(format!("{} is not a valid encoded store", s))
+        }
+    }
+}
+
+impl fmt::Display for EncodedStoreKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            EncodedStoreKind::Temp => f.write_str("temp"),
+            EncodedStoreKind::Capped(max_size) => write!(f, "capped:{}", max_size),
+            EncodedStoreKind::Shared(ref dir) => write!(f, "shared:{}", dir.display()),
+            EncodedStoreKind::Memory(max_size) => write!(f, "memory:{}", max_size),
+        }
+    }
+}
+
+
+/// Socket options for the listening sockets, inherited by accepted connections
+#[derive(Debug, Copy, Clone, Hash, PartialEq, Eq, Default)]
+pub struct TcpTuning {
+    /// Whether to disable Nagle's algorithm. Default: `false`
+    pub nodelay: bool,
+    /// `SO_SNDBUF`, if not the OS default. Default: `None`
+    pub send_buffer: Option<NonZeroU64>,
+    /// `SO_RCVBUF`, if not the OS default. Default: `None`
+    pub recv_buffer: Option<NonZeroU64>,
+    /// How many connections to queue to be accepted, if not `DEFAULT_LISTEN_BACKLOG`. Default: `None`
+    pub backlog: Option<NonZeroU32>,
+}
+
 
 /// Representation of the application's all configurable values.
 #[derive(Debug, Clone, Hash, PartialEq, Eq)]
 pub struct Options {
     /// The directory to host.
     pub hosted_directory: (String, PathBuf),
+    /// The only file in the hosted directory to serve, if a file was given. Default: `None`
+    pub single_file: Option<String>,
+    /// Whether the single file is what's piped to stdin, buffered in the temp dir, instead of a hosted one. Default: false
+    pub stdin: bool,
     /// The port to host on. Default: first free port from 8000 up
     pub port: Option<u16>,
     /// The address to bind to. Default: 0.0.0.0
     pub bind_address: IpAddr,
-    /// Whether to allow symlinks to be requested. Default: true
-    pub follow_symlinks: bool,
-    /// Whether to disallow going out of the descendants of the hosted directory (via symlinks)
-    ///
-    /// Can only be true if `follow_symlinks` is true.
-    ///
-    /// Default: false
-    pub sandbox_symlinks: bool,
+    /// How many `SO_REUSEPORT` sockets to accept connections on, instead of one ordinary one. Default: `None`
+    pub listeners: Option<NonZeroUsize>,
+    /// Socket options for the listening sockets. Default: OS defaults
+    pub tcp_tuning: TcpTuning,
+    /// Where requested symlinks can lead to. Default: `SymlinkPolicy::Always`
+    pub symlink_policy: SymlinkPolicy,
+    /// How many symlinks to resolve at most per requested path, so loops can't cause unbounded work. Default: `MAX_SYMLINKS`
+    pub max_symlink_depth: usize,
     /// The temp directory to write to before copying to hosted directory and to store encoded FS responses.
     /// Default: `"$TEMP/http-[FULL_PATH_TO_HOSTED_DIR]"`
     pub temp_directory: (String, PathBuf),
     /// Whether to generate directory listings at all. Default: true
     pub generate_listings: bool,
+    /// Whether to show file permissions and owners in listings and raw filesystem API responses. Default: false
+    pub listing_permissions: bool,
+    /// Whether to count downloads of each file, persisting them in the temp dir. Default: false
+    pub download_counts: bool,
+    /// Whether to show download counts in dir listings. Default: false
+    pub listing_downloads: bool,
     /// Whether to check for index files in served directories before serving a listing. Default: true
     pub check_indices: bool,
     /// Whether to allow requests to `/file` to return `/file.{INDEX_EXTENSIONS`. Default: false
     pub strip_extensions: bool,
+    /// Whether to match requested paths regardless of Unicode normalisation and emit NFC listing links. Default: false
+    pub normalise_unicode: bool,
+    /// Whether to stream block/character devices, FIFOs, and sockets instead of refusing them with a 403. Default: false
+    pub serve_devices: bool,
     /// Whether to allow write operations. Default: false
     pub allow_writes: bool,
+    /// Largest file that can be PUT. Default: `None`
+    pub max_upload_size: Option<u64>,
+    /// Directory to replicate uploads and deletions into, if any. Default: `None`
+    pub mirror_writes: Option<PathBuf>,
+    /// Whether to only accept uploads, never replacing files, and refuse everything else. Default: false
+    pub inbox: bool,
+    /// Whether POSTs can carry the actual method in an `X-HTTP-Method-Override` header. Default: false
+    pub method_override: bool,
     /// Whether to encode filesystem files. Default: true
     pub encode_fs: bool,
+    /// Size above which to encode filesystem files. Default: 1024
+    pub min_encoding_size: u64,
+    /// Size below which to encode filesystem files. Default: 100MiB
+    pub max_encoding_size: u64,
+    /// Minimal unencoded:encoded size ratio, in percent, at which to keep encoded filesystem files. Default: 110
+    pub min_encoding_gain: u64,
+    /// Globs of hosted paths to encode at startup. Default: empty
+    pub precompress: Vec<String>,
+    /// Whether to serve files' `.br`, `.gz`, and `.zst` siblings as their encoded forms. Default: false
+    pub serve_precompressed: bool,
+    /// How many filesystem files can be encoded at once, further requests wait. Default: amount of CPUs
+    pub max_encodings: usize,
+    /// Where encoded filesystem files are kept. Default: `EncodedStoreKind::Temp`
+    pub encoded_store: EncodedStoreKind,
+    /// How many encoded filesystem files to remember, deleting the least recently used ones. Default: 65536
+    pub encoded_cache_entries: usize,
+    /// Largest total size of cached encoded generated responses. Default: 64MiB
+    pub generated_cache_size: usize,
     /// How much to suppress output
     ///
     ///   * >= 1 – suppress serving status lines ("IP was served something")
@@ -95,22 +246,100 @@ pub struct Options {
     pub loglevel: LogLevel,
     /// Whether to colourise the log output. Default: `true`
     pub log_colour: bool,
+    /// Whether to prefix request log lines with `[METHOD STATUS]` instead of colouring them. Default: false
+    pub log_tags: bool,
+    /// Paths, without the leading slash, successful requests to which aren't logged or counted, like health checks
+    pub unlogged_paths: BTreeSet<String>,
+    /// Whether to log each request's headers and its response's, at first; switchable with the admin API. Default: false
+    pub log_headers: bool,
+    /// Whether to print the effective configuration as JSON once listening, regardless of `loglevel`. Default: false
+    pub print_config: bool,
+    /// Whether to serve the admin API under `ADMIN_PREFIX`, which is then required to need credentials. Default: false
+    pub admin: bool,
     /// Whether to handle WebDAV requests. Default: false
     pub webdav: bool,
+    /// Whether to handle requests from S3 clients. Default: false
+    pub s3: bool,
+    /// Remote instance to periodically mirror into the hosted directory. Default: `None`
+    pub mirror: Option<GenericUrl>,
+    /// Seconds between mirror passes. Default: 60
+    pub mirror_interval: u64,
+    /// Upstream to fetch files missing from the hosted directory from. Default: `None`
+    pub origin: Option<GenericUrl>,
+    /// Seconds after which files from the origin are revalidated against it. Default: `None`
+    pub origin_ttl: Option<u64>,
+    /// Whether to accept site deploys as tar archives POSTed to the root. Default: false
+    pub deploy: bool,
+    /// How many previous deploys to keep for rollback. Default: 3
+    pub deploy_keep: usize,
+    /// Whether to serve rsync-style deltas of files against POSTed block signatures. Default: false
+    pub delta_sync: bool,
+    /// Serve `.torrent`s for files at least this big when requested with `?torrent`. Default: `None`
+    pub torrent_min_size: Option<u64>,
+    /// Whether to generate offline bundles (a manifest and service worker) for directories with `?offline=`. Default: false
+    pub offline_bundle: bool,
+    /// Whether to expose and preserve extended attributes (alternate data streams on Windows). Default: false
+    pub xattrs: bool,
+    /// Whether to check and prune the temp dirs instead of serving. Default: false
+    pub check_cache: bool,
+    /// How often to check and prune the temp dirs while serving, in seconds. Default: `None`
+    pub check_cache_interval: Option<u64>,
+    /// Paths (relative to the hosted directory) -> age after which files under them are deleted
+    pub retention: BTreeMap<String, Duration>,
+    /// How often to enforce `retention`, in seconds. Default: 3600
+    pub retention_interval: u64,
+    /// Whether to only log what retention would delete. Default: false
+    pub retention_dry_run: bool,
+    /// How long to serve for before stopping on its own. Default: `None`
+    pub until: Option<Duration>,
+    /// How many downloads to serve before stopping on its own. Default: `None`
+    pub max_serves: Option<u64>,
+    /// How many bytes of response bodies to send before stopping on its own. Default: `None`
+    pub max_bytes: Option<u64>,
+    /// Minutes since midnight, local time, between which writes are accepted. Default: `None`
+    pub write_hours: Option<(u16, u16)>,
     /// Data for HTTPS, identity file and password. Default: `None`
     pub tls_data: Option<((String, PathBuf), String)>,
     /// Whether to generate a one-off certificate. Default: false
     pub generate_tls: bool,
+    /// Lowercased `Host` header names to accept requests for, with or without port; empty to accept any. Default: empty
+    pub allowed_hosts: BTreeSet<String>,
+    /// Lowercased hosts other than the requested one allowed to embed media files, if hotlink protection is enabled. Default: `None`
+    pub hotlink_protection: Option<BTreeSet<String>>,
     /// Data for per-path authentication, in the form `username[:password]`, or `None` to explicitly disable
     pub path_auth_data: BTreeMap<String, Option<String>>,
     /// Paths for which to generate auth data
     pub generate_path_auth: BTreeSet<String>,
+    /// htpasswd file with the users for global authentication, instead of a single `--auth` one, and what's in it. Default: `None`
+    pub auth_file: Option<(String, Htpasswd)>,
+    /// Whether files (and directory index files) can be downloaded without credentials, leaving them needed for everything else.
+    /// Default: false
+    pub public_files: bool,
     /// Header names and who we trust them from in `HEADER-NAME:CIDR` format
     pub proxies: BTreeMap<IpCidr, String>,
     /// Extension -> MIME type mapping overrides; empty string for no extension
     pub mime_type_overrides: BTreeMap<String, Mime>,
+    /// How long clients and caches may reuse served files for, if they're told at all. Default: `None`
+    pub cache_max_age: Option<Duration>,
+    /// Extension -> `cache_max_age` overrides; empty string for no extension
+    pub cache_max_age_overrides: BTreeMap<String, Duration>,
+    /// Whether files' ETags are their content hashes, also sent as X-Content-Hash, with `?v=<hash>` marking them immutable.
+    /// Default: `false`
+    pub content_hash_etags: bool,
+    /// URLs of assets to send `Link: rel=preload` for with HTML files
+    pub preloads: Vec<String>,
+    /// `TYPE/SUBTYPE` (or `TYPE/*`) -> whether to always (`true`) or never (`false`) encode files of that MIME type
+    pub compression_policy: BTreeMap<String, bool>,
     /// Max amount of data per second each request is allowed to return. Default: `None`
     pub request_bandwidth: Option<NonZeroU64>,
+    /// Size of the aligned windows small closed ranges are widened to, and of reads of ranges. Default: `None`
+    pub range_window: Option<NonZeroU64>,
+    /// How many served files to keep open, if any. Default: `None`
+    pub fd_cache: Option<NonZeroUsize>,
+    /// Smallest file or range to serve out of a memory mapping, if any are. Default: `None`
+    pub mmap_min_size: Option<NonZeroU64>,
+    /// Seconds over which to account bytes sent per client and top-level directory, if at all. Default: `None`
+    pub bandwidth_window: Option<NonZeroU64>,
     /// Additional headers to add to every response
     pub additional_headers: Vec<(String, Vec<u8>)>,
 }
@@ -118,53 +347,234 @@ pub struct Options {
 impl Options {
     /// Parse `env`-wide command-line arguments into an `Options` instance
     pub fn parse() -> Options {
+        Options::parse_from(env::args_os())
+    }
+
+    /// Parse the specified command line, starting with the program name, like `parse()`; invalid ones exit the process
+    pub fn parse_from<I: IntoIterator<Item = T>, T: Into<OsString> + Clone>(args: I) -> Options {
         let matches = App::new("http")
             .version(crate_version!())
-            .author(crate_authors!("\n"))
+            .author(&*env!("CARGO_PKG_AUTHORS").replace(':', "\n"))
             .about(crate_description!())
             .setting(AppSettings::ColoredHelp)
-            .arg(Arg::from_usage("[DIR] 'Directory to host. Default: current working directory'")
-                .validator(|s| Options::filesystem_dir_validator(s, "Directory to host")))
+            .arg(Arg::from_usage("[DIR] 'Directory, or single file, to host. Default: current working directory'")
+                .validator(|s| fs::canonicalize(&s).map(|_| ()).map_err(|_| format!("Directory or file to host \"{}\" not found", s))))
+            .arg(Arg::from_usage("--stdin [NAME] 'Serve what's piped to stdin as a single file called NAME'")
+                .conflicts_with("DIR")
+                .validator(Options::file_name_validator))
             .arg(Arg::from_usage("-p --port [port] 'Port to use. Default: first free port from 8000 up'").validator(Options::u16_validator))
             .arg(Arg::from_usage("-a --address [address] 'Address to bind to. Default: 0.0.0.0'").validator(Options::ipaddr_validator))
+            .arg(Arg::from_usage("--listeners [N] 'Accept connections on N SO_REUSEPORT sockets, each with its own accept loop. Linux-only'")
+                .validator(|s| NonZeroUsize::from_str(&s).map(|_| ()).map_err(|_| format!("{} is not a valid amount of listeners", s))))
+            .arg(Arg::from_usage("--tcp-nodelay 'Disable Nagle's algorithm on connections'"))
+            .arg(Arg::from_usage("--send-buffer [BYTES] 'Size connections' send buffers at BYTES, or 0 for the OS default. Default: 0'")
+                .validator(|s| Options::bandwidth_parse(s.into()).map(|_| ())))
+            .arg(Arg::from_usage("--recv-buffer [BYTES] 'Size connections' receive buffers at BYTES, or 0 for the OS default. Default: 0'")
+                .validator(|s| Options::bandwidth_parse(s.into()).map(|_| ())))
+            .arg(Arg::from_usage("--backlog [N] 'Queue up to N connections to be accepted. Default: 128'")
+                .validator(|s| NonZeroU32::from_str(&s).map(|_| ()).map_err(|_| format!("{} is not a valid backlog", s))))
             .arg(Arg::from_usage("-t --temp-dir [temp] 'Temporary directory. Default: $TEMP'")
                 .validator(|s| Options::filesystem_dir_validator(s, "Temporary directory")))
-            .arg(Arg::from_usage("-s --no-follow-symlinks 'Don't follow symlinks. Default: false'"))
+            .arg(Arg::from_usage("-s --no-follow-symlinks 'Don't follow symlinks. Equivalent to --follow-symlinks never'"))
             .arg(Arg::from_usage("-r --sandbox-symlinks 'Restrict/sandbox where symlinks lead to only the direct descendants of the hosted directory. \
-                                  Default: false'"))
+                                  Equivalent to --follow-symlinks within-root'"))
+            .arg(Arg::from_usage("--follow-symlinks [POLICY] 'Where symlinks can lead to. Default: always'")
+                .possible_values(&["never", "within-root", "always"])
+                .conflicts_with_all(&["no-follow-symlinks", "sandbox-symlinks"]))
+            .arg(Arg::from_usage("--max-symlink-depth [N] 'Resolve at most N symlinks per requested path. Default: 40'")
+                .validator(Options::usize_validator))
+            .arg(Arg::from_usage("--normalise-unicode 'Match paths regardless of Unicode normalisation form. Default: false'"))
+            .arg(Arg::from_usage("--serve-devices 'Stream devices and FIFOs instead of refusing them. Default: false'"))
             .arg(Arg::from_usage("-w --allow-write 'Allow for write operations. Default: false'"))
+            .arg(Arg::from_usage("--max-upload-size [BYTES] 'Refuse PUTs larger than BYTES. Default: unlimited'")
+                .requires("allow-write")
+                .validator(|s| Options::size_parse(s.into()).map(|_| ())))
+            .arg(Arg::from_usage("--mirror-writes [BACKUP_DIR] 'Replicate uploads and deletions into BACKUP_DIR in the background'")
+                .requires("allow-write")
+                .validator(|s| Options::filesystem_dir_validator(s, "Backup directory")))
+            .arg(Arg::from_usage("--write-hours [HH:MM-HH:MM] 'Only accept writes between these local times'")
+                .requires("allow-write")
+                .validator(|s| Options::hours_parse(&s).map(|_| ())))
+            .arg(Arg::from_usage("--inbox 'Only accept uploads, without replacing files, and refuse reading anything. Default: false'")
+                .requires("allow-write"))
+            .arg(Arg::from_usage("--method-override 'Treat POSTs with an X-HTTP-Method-Override header as that method. Default: false'"))
             .arg(Arg::from_usage("-l --no-listings 'Never generate dir listings. Default: false'"))
+            .arg(Arg::from_usage("--listing-permissions 'Show file permissions and owners in dir listings. Default: false'"))
+            .arg(Arg::from_usage("--download-counts 'Count downloads of each file, kept across restarts in the temp dir. Default: false'"))
+            .arg(Arg::from_usage("--listing-downloads 'Show download counts in dir listings. Default: false'").requires("download-counts"))
             .arg(Arg::from_usage("-i --no-indices 'Do not automatically use index files. Default: false'"))
             .arg(Arg::from_usage("-e --no-encode 'Do not encode filesystem files. Default: false'"))
+            .arg(Arg::from_usage("--min-encoding-size [BYTES] 'Only encode files larger than BYTES. Default: 1024'")
+                .validator(|s| Options::size_parse(s.into()).map(|_| ())))
+            .arg(Arg::from_usage("--max-encoding-size [BYTES] 'Only encode files smaller than BYTES. Default: 100M'")
+                .validator(|s| Options::size_parse(s.into()).map(|_| ())))
+            .arg(Arg::from_usage("--min-encoding-gain [PERCENT] 'Only keep encoded files if the original is at least PERCENT% their size. Default: 110'")
+                .validator(|s| u64::from_str(&s).map(|_| ()).map_err(|_| format!("{} is not a valid percentage", s))))
+            .arg(Arg::from_usage("--precompress [GLOB]... 'Encode files matching GLOB at startup'")
+                .use_delimiter(false)
+                .conflicts_with("no-encode")
+                .validator(|s| glob_regex(&s).map(|_| ())))
+            .arg(Arg::from_usage("--serve-precompressed 'Send FILE.br, FILE.gz, or FILE.zst as FILE, encoded, to clients accepting them'"))
+            .arg(Arg::from_usage("--max-encodings [N] 'Encode at most N files at once. Default: amount of CPUs'")
+                .validator(|s| match usize::from_str(&s) {
+                    Ok(0) => Err("0 encodings can't ever happen".to_string()),
+                    Ok(_) => Ok(()),
+                    Err(_) => Err(format!("{} is not a valid amount", s)),
+                }))
+            .arg(Arg::from_usage("--encoded-store [STORE] 'Keep encoded files in the temp dir, capped:BYTES of it, shared:DIR, or memory:BYTES. Default: temp'")
+                .conflicts_with("no-encode")
+                .validator(|s| match EncodedStoreKind::from_str(&s)? {
+                    EncodedStoreKind::Shared(dir) => Options::filesystem_dir_validator(dir.to_string_lossy().into_owned(), "Encoded store directory"),
+                    _ => Ok(()),
+                }))
+            .arg(Arg::from_usage("--encoded-cache-entries [N] 'Keep at most N encoded files, deleting the least recently used ones. Default: 65536'")
+                .conflicts_with("no-encode")
+                .validator(|s| match usize::from_str(&s) {
+                    Ok(0) => Err("0 encoded files can't be kept".to_string()),
+                    Ok(_) => Ok(()),
+                    Err(_) => Err(format!("{} is not a valid amount", s)),
+                }))
+            .arg(Arg::from_usage("--generated-cache-size [BYTES] 'Keep at most BYTES of encoded generated responses. Default: 64M'")
+                .validator(|s| Options::size_parse(s.into()).map(|_| ())))
             .arg(Arg::from_usage("-x --strip-extensions 'Allow stripping index extentions from served paths. Default: false'"))
             .arg(Arg::from_usage("-q --quiet... 'Suppress increasing amounts of output'"))
             .arg(Arg::from_usage("-c --no-colour 'Don't colourise the log output'"))
+            .arg(Arg::from_usage("--log-tags 'Tag request log lines with [METHOD STATUS] instead of colouring them. Default: false'"))
+            .arg(Arg::from_usage("--unlogged [PATH]... 'Don't log or count successful requests to PATH, like load balancer health checks'")
+                .use_delimiter(false))
+            .arg(Arg::from_usage("--log-headers 'Log request headers, with credentials redacted, and response headers. Default: false'"))
+            .arg(Arg::from_usage("--print-config 'Print the effective configuration as a line of JSON once listening, before any other output. Default: false'"))
+            .arg(Arg::from_usage("--admin 'Serve the admin API under /.http-admin/, which must need credentials. Default: false'"))
             .arg(Arg::from_usage("-d --webdav 'Handle WebDAV requests. Default: false'"))
+            .arg(Arg::from_usage("--mirror [URL] 'Periodically mirror the http instance at URL into the hosted directory'")
+                .validator(|s| Options::remote_url_parse(&s).map(|_| ())))
+            .arg(Arg::from_usage("--mirror-interval [SECONDS] 'Time between mirror passes. Default: 60'")
+                .requires("mirror")
+                .validator(|s| u64::from_str(&s).map(|_| ()).map_err(|_| format!("{} is not a valid amount of seconds", s))))
+            .arg(Arg::from_usage("--origin [URL] 'Fetch files missing from the hosted directory from URL and store them there'")
+                .validator(|s| Options::remote_url_parse(&s).map(|_| ())))
+            .arg(Arg::from_usage("--origin-ttl [SECONDS] 'Revalidate files against the origin once they're older than this, in the background'")
+                .requires("origin")
+                .validator(|s| u64::from_str(&s).map(|_| ()).map_err(|_| format!("{} is not a valid amount of seconds", s))))
+            .arg(Arg::from_usage("--deploy 'Accept tar archives POSTed to the root as site deploys replacing the hosted directory. Default: false'"))
+            .arg(Arg::from_usage("--deploy-keep [N] 'Amount of previous deploys to keep for rollback. Default: 3'")
+                .requires("deploy")
+                .validator(Options::usize_validator))
+            .arg(Arg::from_usage("--delta-sync 'Serve rsync-style deltas of files against block signatures POSTed to FILE?delta. Default: false'"))
+            .arg(Arg::from_usage("--torrent [MIN_SIZE] 'Serve .torrents seeded by this server for files at least MIN_SIZE big with FILE?torrent'")
+                .validator(|s| Options::size_parse(s.into()).map(|_| ())))
+            .arg(Arg::from_usage("--offline-bundle 'Generate a manifest and service worker caching DIR with DIR/?offline=manifest and DIR/?offline=sw. Default: false'"))
+            .arg(Arg::from_usage("--xattrs 'Expose extended attributes as WebDAV properties and preserve them across copies and deploys. Default: false'"))
+            .arg(Arg::from_usage("--check-cache 'Check the temp dirs, removing orphaned, corrupted, and stale files, then exit'"))
+            .arg(Arg::from_usage("--check-cache-interval [SECONDS] 'Check the temp dirs every SECONDS while serving'")
+                .conflicts_with("check-cache")
+                .validator(|s| NonZeroU64::from_str(&s).map(|_| ()).map_err(|_| format!("{} is not a valid amount of seconds", s))))
+            .arg(Arg::from_usage("--retention [PATH=AGE]... 'Delete files under PATH not modified in AGE, e.g. 7d, 12h, 30m, or 90s'")
+                .use_delimiter(false)
+                .validator(|s| Options::retention_parse(&s).map(|_| ())))
+            .arg(Arg::from_usage("--retention-interval [SECONDS] 'Enforce --retention every SECONDS. Default: 3600'")
+                .requires("retention")
+                .validator(|s| NonZeroU64::from_str(&s).map(|_| ()).map_err(|_| format!("{} is not a valid amount of seconds", s))))
+            .arg(Arg::from_usage("--retention-dry-run 'Only log what --retention would delete. Default: false'").requires("retention"))
+            .arg(Arg::from_usage("--until [DURATION] 'Stop serving after DURATION, e.g. 2h, 30m, or 90s'")
+                .validator(|s| Options::duration_parse(&s).map(|_| ())))
+            .arg(Arg::from_usage("--max-serves [N] 'Stop serving after N downloads'")
+                .validator(|s| NonZeroU64::from_str(&s).map(|_| ()).map_err(|_| format!("{} is not a valid amount of downloads", s))))
+            .arg(Arg::from_usage("--max-bytes [BYTES] 'Stop serving after sending BYTES'")
+                .validator(|s| Options::size_parse(s.into()).map(|_| ())))
+            .arg(Arg::from_usage("--s3 'Handle a subset of the S3 API, with top-level directories as buckets. Default: false'"))
             .arg(Arg::from_usage("--ssl [TLS_IDENTITY] 'Data for HTTPS, identity file. Password in HTTP_SSL_PASS env var, otherwise empty'")
                 .validator(Options::identity_validator))
             .arg(Arg::from_usage("--gen-ssl 'Generate a one-off TLS certificate'").conflicts_with("ssl"))
             .arg(Arg::from_usage("--auth [USERNAME[:PASSWORD]] 'Data for global authentication'").validator(Options::credentials_validator))
             .arg(Arg::from_usage("--gen-auth 'Generate a one-off username:password set for global authentication'").conflicts_with("auth"))
+            .arg(Arg::from_usage("--auth-file [HTPASSWD] 'Global authentication as any user in an htpasswd file'")
+                .conflicts_with_all(&["auth", "gen-auth"])
+                .validator(|s| Htpasswd::load(Path::new(&s)).map(|_| ())))
             .arg(Arg::from_usage("--path-auth [PATH=[USERNAME[:PASSWORD]]]... 'Data for authentication under PATH'")
                 .use_delimiter(false)
                 .validator(Options::path_credentials_validator))
             .arg(Arg::from_usage("--gen-path-auth [PATH]... 'Generate a one-off username:password set for authentication under PATH'").use_delimiter(false))
+            .arg(Arg::from_usage("--public-files 'Download files without credentials, but still need them for listings. Default: false'"))
+            .arg(Arg::from_usage("--allowed-host [HOST]... 'Only accept requests whose Host header is HOST or an IP address'")
+                .use_delimiter(false))
+            .arg(Arg::from_usage("--hotlink-protection 'Refuse media files to requests referred from other sites. Default: false'"))
+            .arg(Arg::from_usage("--allowed-referrer [HOST]... 'Allow HOST to embed media files despite --hotlink-protection'")
+                .use_delimiter(false)
+                .requires("hotlink-protection"))
             .arg(Arg::from_usage("--proxy [HEADER-NAME:CIDR]... 'Treat HEADER-NAME as proxy forwarded-for header when request comes from CIDR'")
                 .use_delimiter(false)
                 .validator(|s| Options::proxy_parse(s.into()).map(|_| ())))
             .arg(Arg::from_usage("-m --mime-type [EXTENSION:MIME-TYPE]... 'Always return MIME-TYPE for files with EXTENSION'")
                 .use_delimiter(false)
                 .validator(|s| Options::mime_type_override_parse(s.into()).map(|_| ())))
+            .arg(Arg::from_usage("--cache-max-age [DURATION] 'Let files be cached for DURATION, e.g. 7d, 12h, 30m, or 90s, with Cache-Control and Expires'")
+                .validator(|s| Options::duration_parse(&s).map(|_| ())))
+            .arg(Arg::from_usage("--cache-max-age-for [EXTENSION:DURATION]... 'Let files with EXTENSION be cached for DURATION instead'")
+                .use_delimiter(false)
+                .validator(|s| Options::cache_max_age_override_parse(&s).map(|_| ())))
+            .arg(Arg::from_usage("--content-hash-etags 'Use files' content hashes as ETags and X-Content-Hash, caching ?v=<hash> requests forever'"))
+            .arg(Arg::from_usage("--preload [URL]... 'Have browsers preload the asset at URL with every HTML file'")
+                .use_delimiter(false)
+                .validator(|s| Options::preload_parse(&s).map(|_| ())))
+            .arg(Arg::from_usage("--compress [MIME-TYPE:always|never]... 'Always or never encode files of MIME-TYPE, which may be TYPE/*'")
+                .use_delimiter(false)
+                .validator(|s| Options::compression_policy_parse(s.into()).map(|_| ())))
             .arg(Arg::from_usage("--request-bandwidth [BYTES] 'Limit each request to returning BYTES per second, or 0 for unlimited. Default: 0'")
                 .validator(|s| Options::bandwidth_parse(s.into()).map(|_| ())))
+            .arg(Arg::from_usage("--range-window [BYTES] 'Serve small byte ranges as the rest of their BYTES-aligned window, reading BYTES at once'")
+                .validator(|s| Options::bandwidth_parse(s.into()).map(|_| ())))
+            .arg(Arg::from_usage("--fd-cache [N] 'Keep up to N served files open between requests, closing ones idle for 10s'")
+                .validator(|s| NonZeroUsize::from_str(&s).map(|_| ()).map_err(|_| format!("{} is not a valid amount of files", s))))
+            .arg(Arg::from_usage("--mmap-min-size [BYTES] 'Serve files and ranges of at least BYTES from memory mappings, or 0 to never. Default: 0'")
+                .conflicts_with_all(&["allow-write", "deploy", "mirror"])
+                .validator(|s| Options::bandwidth_parse(s.into()).map(|_| ())))
+            .arg(Arg::from_usage("--bandwidth-window [SECONDS] 'Account bytes sent per client and top-level directory over the last SECONDS'")
+                .requires("admin")
+                .validator(|s| NonZeroU64::from_str(&s).map(|_| ()).map_err(|_| format!("{} is not a valid amount of seconds", s))))
             .arg(Arg::from_usage("-H --header [NAME: VALUE]... 'Headers to add to every response'")
                 .use_delimiter(false)
                 .validator(|s| Options::header_parse(&s).map(|_| ())))
-            .get_matches();
+            .get_matches_from(args);
 
         let dir = matches.value_of("DIR").unwrap_or(".");
-        let dir_pb = fs::canonicalize(dir).unwrap();
-        let follow_symlinks = !matches.is_present("no-follow-symlinks");
+        let mut dir_pb = verbatim_path(fs::canonicalize(dir).unwrap());
+        // A file is hosted on its own, from its directory
+        let single_file = if dir_pb.is_file() {
+            let file = dir_pb.file_name().expect("Hosted file has no name").to_string_lossy().into_owned();
+            dir_pb.pop();
+            Some(file)
+        } else {
+            None
+        };
+
+        let temp_directory = {
+            let (temp_s, temp_pb) = if let Some(tmpdir) = matches.value_of("temp-dir") {
+                (tmpdir.to_string(), verbatim_path(fs::canonicalize(tmpdir).unwrap()))
+            } else {
+                ("$TEMP".to_string(), temp_dir())
+            };
+            let suffix = dir_pb.to_str().unwrap().replace(r"\\?\", "").replace(':', "").replace('\\', "/").replace('/', "-");
+            let suffix = format!("http{}{}", if suffix.starts_with('-') { "" } else { "-" }, suffix);
+
+            (format!("{}{}{}",
+                     temp_s,
+                     if temp_s.ends_with('/') || temp_s.ends_with('\\') {
+                         ""
+                     } else {
+                         "/"
+                     },
+                     suffix),
+             temp_pb.join(suffix))
+        };
+
+        // Stdin's buffered into the temp dir and hosted from there like a single file
+        let stdin = matches.value_of("stdin");
+        let (hosted_directory, single_file) = match stdin {
+            Some(name) => (("<stdin>".to_string(), temp_directory.1.join("stdin")), Some(name.to_string())),
+            None => ((dir.to_string(), dir_pb), single_file),
+        };
+        let dir_pb = &hosted_directory.1;
 
         let mut path_auth_data = BTreeMap::new();
         if let Some(root_auth) = matches.value_of("auth").map(Options::normalise_credentials) {
@@ -180,6 +590,11 @@ impl Options {
             }
         }
 
+        let auth_file = matches.value_of("auth-file").map(|f| (f.to_string(), Htpasswd::load(Path::new(f)).unwrap()));
+        if auth_file.is_some() && path_auth_data.contains_key("") {
+            Options::path_credentials_dupe("");
+        }
+
         let mut generate_path_auth = BTreeSet::new();
         if matches.is_present("gen-auth") {
             generate_path_auth.insert("".to_string());
@@ -187,7 +602,7 @@ impl Options {
 
         if let Some(gen_path_auth) = matches.values_of("gen-path-auth") {
             for path in gen_path_auth.map(Options::normalise_path) {
-                if path_auth_data.contains_key(&path) {
+                if path_auth_data.contains_key(&path) || (path.is_empty() && auth_file.is_some()) {
                     Options::path_credentials_dupe(&path);
                 }
 
@@ -197,43 +612,145 @@ impl Options {
             }
         }
 
+        // The most specific credentials covering the admin API are the ones that apply
+        if matches.is_present("admin") &&
+           ![ADMIN_PREFIX, ""]
+            .iter()
+            .find(|p| generate_path_auth.contains(**p) || path_auth_data.contains_key(**p) || (p.is_empty() && auth_file.is_some()))
+            .map(|p| generate_path_auth.contains(*p) || path_auth_data.get(*p).map(Option::is_some).unwrap_or(true))
+            .unwrap_or(false) {
+            ClapError {
+                    message: format!("The admin API needs credentials: set them for \"/{}\" or globally", ADMIN_PREFIX),
+                    kind: ClapErrorKind::MissingRequiredArgument,
+                    info: None,
+                }
+                .exit()
+        }
+
+        let mirror_writes = matches.value_of("mirror-writes").map(|d| verbatim_path(fs::canonicalize(d).unwrap()));
+        if let Some(backup) = mirror_writes.as_ref().filter(|b| b.starts_with(dir_pb) || dir_pb.starts_with(b)) {
+            ClapError {
+                    message: format!("Backup directory \"{}\" overlaps the hosted directory", backup.display()),
+                    kind: ClapErrorKind::InvalidValue,
+                    info: None,
+                }
+                .exit()
+        }
+
+        let encoded_store = match matches.value_of("encoded-store").map(EncodedStoreKind::from_str).map(Result::unwrap) {
+            Some(EncodedStoreKind::Shared(dir)) => EncodedStoreKind::Shared(verbatim_path(fs::canonicalize(dir).unwrap())),
+            Some(kind) => kind,
+            None => EncodedStoreKind::Temp,
+        };
+        if let EncodedStoreKind::Shared(dir) = &encoded_store {
+            if dir.starts_with(dir_pb) || dir_pb.starts_with(dir) {
+                ClapError {
+                        message: format!("Encoded store directory \"{}\" overlaps the hosted directory", dir.display()),
+                        kind: ClapErrorKind::InvalidValue,
+                        info: None,
+                    }
+                    .exit()
+            }
+        }
+
         Options {
-            hosted_directory: (dir.to_string(), dir_pb.clone()),
+            hosted_directory,
+            single_file,
+            stdin: stdin.is_some(),
             port: matches.value_of("port").map(u16::from_str).map(Result::unwrap),
             bind_address: matches.value_of("address").map(IpAddr::from_str).map(Result::unwrap).unwrap_or_else(|| "0.0.0.0".parse().unwrap()),
-            follow_symlinks: follow_symlinks,
-            sandbox_symlinks: follow_symlinks && matches.is_present("sandbox-symlinks"),
-            temp_directory: {
-                let (temp_s, temp_pb) = if let Some(tmpdir) = matches.value_of("temp-dir") {
-                    (tmpdir.to_string(), fs::canonicalize(tmpdir).unwrap())
-                } else {
-                    ("$TEMP".to_string(), temp_dir())
-                };
-                let suffix = dir_pb.into_os_string().to_str().unwrap().replace(r"\\?\", "").replace(':', "").replace('\\', "/").replace('/', "-");
-                let suffix = format!("http{}{}", if suffix.starts_with('-') { "" } else { "-" }, suffix);
-
-                (format!("{}{}{}",
-                         temp_s,
-                         if temp_s.ends_with('/') || temp_s.ends_with('\\') {
-                             ""
-                         } else {
-                             "/"
-                         },
-                         suffix),
-                 temp_pb.join(suffix))
+            listeners: matches.value_of("listeners").map(NonZeroUsize::from_str).map(Result::unwrap),
+            tcp_tuning: TcpTuning {
+                nodelay: matches.is_present("tcp-nodelay"),
+                send_buffer: matches.value_of("send-buffer").map(Cow::from).map(Options::bandwidth_parse).map(Result::unwrap).unwrap_or_default(),
+                recv_buffer: matches.value_of("recv-buffer").map(Cow::from).map(Options::bandwidth_parse).map(Result::unwrap).unwrap_or_default(),
+                backlog: matches.value_of("backlog").map(NonZeroU32::from_str).map(Result::unwrap),
+            },
+            symlink_policy: if let Some(policy) = matches.value_of("follow-symlinks") {
+                SymlinkPolicy::from_str(policy).unwrap()
+            } else if matches.is_present("no-follow-symlinks") {
+                SymlinkPolicy::Never
+            } else if matches.is_present("sandbox-symlinks") {
+                SymlinkPolicy::WithinRoot
+            } else {
+                SymlinkPolicy::Always
             },
+            max_symlink_depth: matches.value_of("max-symlink-depth").map(usize::from_str).map(Result::unwrap).unwrap_or(MAX_SYMLINKS),
+            temp_directory,
+            max_upload_size: matches.value_of("max-upload-size").map(Cow::from).map(Options::size_parse).map(Result::unwrap),
             generate_listings: !matches.is_present("no-listings"),
+            listing_permissions: matches.is_present("listing-permissions"),
+            download_counts: matches.is_present("download-counts"),
+            listing_downloads: matches.is_present("listing-downloads"),
             check_indices: !matches.is_present("no-indices"),
             strip_extensions: matches.is_present("strip-extensions"),
+            normalise_unicode: matches.is_present("normalise-unicode"),
+            serve_devices: matches.is_present("serve-devices"),
             allow_writes: matches.is_present("allow-write"),
+            mirror_writes,
+            inbox: matches.is_present("inbox"),
+            method_override: matches.is_present("method-override"),
             encode_fs: !matches.is_present("no-encode"),
+            min_encoding_size: matches.value_of("min-encoding-size").map(Cow::from).map(Options::size_parse).map(Result::unwrap).unwrap_or(MIN_ENCODING_SIZE),
+            max_encoding_size: matches.value_of("max-encoding-size").map(Cow::from).map(Options::size_parse).map(Result::unwrap).unwrap_or(MAX_ENCODING_SIZE),
+            precompress: matches.values_of("precompress").unwrap_or_default().map(String::from).collect(),
+            serve_precompressed: matches.is_present("serve-precompressed"),
+            max_encodings: matches.value_of("max-encodings")
+                .map(usize::from_str)
+                .map(Result::unwrap)
+                .unwrap_or_else(|| thread::available_parallelism().map(|p| p.get()).unwrap_or(1)),
+            encoded_store,
+            encoded_cache_entries: matches.value_of("encoded-cache-entries").map(usize::from_str).map(Result::unwrap).unwrap_or(ENCODED_CACHE_ENTRIES),
+            generated_cache_size: matches.value_of("generated-cache-size")
+                .map(Cow::from)
+                .map(Options::size_parse)
+                .map(Result::unwrap)
+                .map(|s| s as usize)
+                .unwrap_or(GENERATED_CACHE_BUDGET),
+            min_encoding_gain: matches.value_of("min-encoding-gain")
+                .map(u64::from_str)
+                .map(Result::unwrap)
+                .unwrap_or((MIN_ENCODING_GAIN * 100f64).round() as u64),
             loglevel: matches.occurrences_of("quiet").into(),
-            log_colour: !matches.is_present("no-colour"),
+            log_colour: !matches.is_present("no-colour") && !matches.is_present("log-tags"),
+            log_tags: matches.is_present("log-tags"),
+            unlogged_paths: matches.values_of("unlogged").unwrap_or_default().map(|p| p.trim_start_matches('/').to_string()).collect(),
+            log_headers: matches.is_present("log-headers"),
+            print_config: matches.is_present("print-config"),
+            admin: matches.is_present("admin"),
             webdav: matches.is_present("webdav"),
+            s3: matches.is_present("s3"),
+            mirror: matches.value_of("mirror").map(Options::remote_url_parse).map(Result::unwrap),
+            mirror_interval: matches.value_of("mirror-interval").map(u64::from_str).map(Result::unwrap).unwrap_or(60),
+            origin: matches.value_of("origin").map(Options::remote_url_parse).map(Result::unwrap),
+            origin_ttl: matches.value_of("origin-ttl").map(u64::from_str).map(Result::unwrap),
+            deploy: matches.is_present("deploy"),
+            deploy_keep: matches.value_of("deploy-keep").map(usize::from_str).map(Result::unwrap).unwrap_or(3),
+            delta_sync: matches.is_present("delta-sync"),
+            torrent_min_size: matches.value_of("torrent").map(Cow::from).map(Options::size_parse).map(Result::unwrap),
+            offline_bundle: matches.is_present("offline-bundle"),
+            xattrs: matches.is_present("xattrs"),
+            check_cache: matches.is_present("check-cache"),
+            check_cache_interval: matches.value_of("check-cache-interval").map(u64::from_str).map(Result::unwrap),
+            retention: matches.values_of("retention").unwrap_or_default().map(Options::retention_parse).map(Result::unwrap).collect(),
+            retention_interval: matches.value_of("retention-interval").map(u64::from_str).map(Result::unwrap).unwrap_or(60 * 60),
+            retention_dry_run: matches.is_present("retention-dry-run"),
+            until: matches.value_of("until").map(Options::duration_parse).map(Result::unwrap),
+            max_serves: matches.value_of("max-serves").map(u64::from_str).map(Result::unwrap),
+            max_bytes: matches.value_of("max-bytes").map(Cow::from).map(Options::size_parse).map(Result::unwrap),
+            write_hours: matches.value_of("write-hours").map(Options::hours_parse).map(Result::unwrap),
             tls_data: matches.value_of("ssl").map(|id| ((id.to_string(), fs::canonicalize(id).unwrap()), env::var("HTTP_SSL_PASS").unwrap_or_default())),
             generate_tls: matches.is_present("gen-ssl"),
             path_auth_data: path_auth_data,
             generate_path_auth: generate_path_auth,
+            auth_file: auth_file,
+            public_files: matches.is_present("public-files"),
+            allowed_hosts: matches.values_of("allowed-host").unwrap_or_default().map(str::to_lowercase).collect(),
+            hotlink_protection: if matches.is_present("hotlink-protection") {
+                Some(matches.values_of("allowed-referrer").unwrap_or_default().map(str::to_lowercase).collect())
+            } else {
+                None
+            },
             proxies: matches.values_of("proxy").unwrap_or_default().map(Cow::from).map(Options::proxy_parse).map(Result::unwrap).collect(),
             mime_type_overrides: matches.values_of("mime-type")
                 .unwrap_or_default()
@@ -241,7 +758,25 @@ impl Options {
                 .map(Options::mime_type_override_parse)
                 .map(Result::unwrap)
                 .collect(),
+            cache_max_age: matches.value_of("cache-max-age").map(Options::duration_parse).map(Result::unwrap),
+            cache_max_age_overrides: matches.values_of("cache-max-age-for")
+                .unwrap_or_default()
+                .map(Options::cache_max_age_override_parse)
+                .map(Result::unwrap)
+                .collect(),
+            content_hash_etags: matches.is_present("content-hash-etags"),
+            preloads: matches.values_of("preload").unwrap_or_default().map(Options::preload_parse).map(Result::unwrap).collect(),
+            compression_policy: matches.values_of("compress")
+                .unwrap_or_default()
+                .map(Cow::from)
+                .map(Options::compression_policy_parse)
+                .map(Result::unwrap)
+                .collect(),
             request_bandwidth: matches.value_of("request-bandwidth").map(Cow::from).map(Options::bandwidth_parse).map(Result::unwrap).unwrap_or_default(),
+            bandwidth_window: matches.value_of("bandwidth-window").map(NonZeroU64::from_str).map(Result::unwrap),
+            fd_cache: matches.value_of("fd-cache").map(NonZeroUsize::from_str).map(Result::unwrap),
+            mmap_min_size: matches.value_of("mmap-min-size").map(Cow::from).map(Options::bandwidth_parse).map(Result::unwrap).unwrap_or_default(),
+            range_window: matches.value_of("range-window").map(Cow::from).map(Options::bandwidth_parse).map(Result::unwrap).unwrap_or_default(),
             additional_headers: matches.values_of("header")
                 .unwrap_or_default()
                 .map(Options::header_parse)
@@ -250,6 +785,85 @@ impl Options {
         }
     }
 
+    /// URL paths under which credentials are needed, like `/private`
+    pub fn authenticated_paths(&self) -> Vec<String> {
+        self.auth_file
+            .as_ref()
+            .map(|_| "".to_string())
+            .into_iter()
+            .chain(self.path_auth_data.iter().filter(|&(_, creds)| creds.is_some()).map(|(path, _)| path.clone()))
+            .map(|path| format!("/{}", path))
+            .collect()
+    }
+
+    /// The configuration in effect for a server listening on `socket` with the specified temp dirs, for `--print-config`
+    ///
+    /// Secrets (credentials, the TLS identity's password) are left out; only whether they're there is included.
+    pub fn effective_config(&self, socket: SocketAddr, temp_dirs: &BTreeMap<&'static str, PathBuf>) -> serde_json::Value {
+        let mut config = json!({
+            "root": self.hosted_directory.1.display().to_string(),
+            "single_file": self.single_file,
+            "stdin": self.stdin,
+            "address": socket.ip().to_string(),
+            "port": socket.port(),
+            "tls": self.tls_data.as_ref().map(|&((_, ref id), _)| id.display().to_string()),
+            "authenticated_paths": self.authenticated_paths(),
+            "auth_file": self.auth_file.as_ref().map(|&(ref f, _)| f),
+            "public_files": self.public_files,
+            "temp_directory": self.temp_directory.1.display().to_string(),
+            "temp_directories": temp_dirs.iter().map(|(name, dir)| (name.to_string(), dir.display().to_string())).collect::<BTreeMap<_, _>>(),
+            "symlinks": self.symlink_policy.to_string(),
+            "listings": self.generate_listings,
+            "download_counts": self.download_counts,
+            "indices": self.check_indices,
+            "strip_extensions": self.strip_extensions,
+            "writes": self.allow_writes,
+            "inbox": self.inbox,
+            "method_override": self.method_override,
+            "max_upload_size": self.max_upload_size,
+            "retention": self.retention.iter().map(|(path, age)| (format!("/{}", path), age.as_secs())).collect::<BTreeMap<_, _>>(),
+            "retention_dry_run": self.retention_dry_run,
+            "write_hours": self.write_hours.map(|(start, end)| format!("{:02}:{:02}-{:02}:{:02}", start / 60, start % 60, end / 60, end % 60)),
+            "until": self.until.map(|u| u.as_secs()),
+            "max_serves": self.max_serves,
+            "max_bytes": self.max_bytes,
+            "mirror_writes": self.mirror_writes.as_ref().map(|p| p.display().to_string()),
+            "encoding": self.encode_fs,
+            "encoded_store": self.encoded_store.to_string(),
+            "encoded_cache_entries": self.encoded_cache_entries,
+            "generated_cache_size": self.generated_cache_size,
+            "webdav": self.webdav,
+            "xattrs": self.xattrs,
+            "admin": self.admin,
+            "s3": self.s3,
+            "deploy": self.deploy,
+            "delta_sync": self.delta_sync,
+            "torrents": self.torrent_min_size.is_some(),
+            "offline_bundle": self.offline_bundle,
+            "mirror": self.mirror.as_ref().map(|u| u.to_string()),
+            "origin": self.origin.as_ref().map(|u| u.to_string()),
+            "allowed_hosts": self.allowed_hosts,
+            "cache_max_age": self.cache_max_age.map(|a| a.as_secs()),
+            "request_bandwidth": self.request_bandwidth.map(NonZeroU64::get),
+            "bandwidth_window": self.bandwidth_window.map(NonZeroU64::get),
+        });
+        // Past what one json!() can expand
+        config["cache_max_age_for"] = json!(self.cache_max_age_overrides.iter().map(|(ext, age)| (ext.clone(), age.as_secs())).collect::<BTreeMap<_, _>>());
+        config["content_hash_etags"] = json!(self.content_hash_etags);
+        config["preload"] = json!(self.preloads);
+        config["range_window"] = json!(self.range_window.map(NonZeroU64::get));
+        config["fd_cache"] = json!(self.fd_cache.map(NonZeroUsize::get));
+        config["mmap_min_size"] = json!(self.mmap_min_size.map(NonZeroU64::get));
+        config["serve_precompressed"] = json!(self.serve_precompressed);
+        config["unlogged"] = json!(self.unlogged_paths);
+        config["listeners"] = json!(self.listeners.map(NonZeroUsize::get));
+        config["tcp_nodelay"] = json!(self.tcp_tuning.nodelay);
+        config["send_buffer"] = json!(self.tcp_tuning.send_buffer.map(NonZeroU64::get));
+        config["recv_buffer"] = json!(self.tcp_tuning.recv_buffer.map(NonZeroU64::get));
+        config["backlog"] = json!(self.tcp_tuning.backlog.map(NonZeroU32::get));
+        config
+    }
+
     fn filesystem_dir_validator(s: String, prefix: &str) -> Result<(), String> {
         fs::canonicalize(&s).map_err(|_| format!("{} \"{}\" not found", prefix, s)).and_then(|f| if f.is_dir() {
             Ok(())
@@ -258,6 +872,14 @@ impl Options {
         })
     }
 
+    fn file_name_validator(s: String) -> Result<(), String> {
+        if s.is_empty() || s == "." || s == ".." || s.contains(['/', '\\', '\0']) {
+            Err(format!("\"{}\" is not a valid file name", s))
+        } else {
+            Ok(())
+        }
+    }
+
     fn identity_validator(s: String) -> Result<(), String> {
         fs::canonicalize(&s).map_err(|_| format!("TLS identity file \"{}\" not found", s)).and_then(|f| if f.is_file() {
             Ok(())
@@ -335,6 +957,10 @@ impl Options {
         u16::from_str(&s).map(|_| ()).map_err(|_| format!("{} is not a valid port number", s))
     }
 
+    fn usize_validator(s: String) -> Result<(), String> {
+        usize::from_str(&s).map(|_| ()).map_err(|_| format!("{} is not a valid amount", s))
+    }
+
     fn proxy_parse<'s>(s: Cow<'s, str>) -> Result<(IpCidr, String), String> {
         match s.find(":") {
             None => Err(format!("{} not in HEADER-NAME:CIDR format", s)),
@@ -349,9 +975,13 @@ impl Options {
         }
     }
 
-    fn bandwidth_parse<'s>(s_orig: Cow<'s, str>) -> Result<Option<NonZeroU64>, String> {
+    fn bandwidth_parse<'s>(s: Cow<'s, str>) -> Result<Option<NonZeroU64>, String> {
+        Options::size_parse(s).map(NonZeroU64::new)
+    }
+
+    fn size_parse<'s>(s_orig: Cow<'s, str>) -> Result<u64, String> {
         let s = s_orig.trim();
-        let multiplier_b = s.as_bytes().get(s.len() - 1).ok_or_else(|| format!("\"{}\" bandwidth specifier empty", s_orig))?;
+        let multiplier_b = s.as_bytes().get(s.len().wrapping_sub(1)).ok_or_else(|| format!("\"{}\" size specifier empty", s_orig))?;
         let multiplier_order = match multiplier_b {
             b'k' | b'K' => 1,
             b'm' | b'M' => 2,
@@ -373,8 +1003,49 @@ impl Options {
             }
         };
 
-        let number = u64::from_str(s).map_err(|e| format!("\"{}\" not band width size: {}", s, e))?;
-        Ok(NonZeroU64::new(number.checked_mul(multiplier).ok_or_else(|| format!("{} * {} too big", number, multiplier))?))
+        let number = u64::from_str(s).map_err(|e| format!("\"{}\" not a size: {}", s, e))?;
+        number.checked_mul(multiplier).ok_or_else(|| format!("{} * {} too big", number, multiplier))
+    }
+
+    /// `PATH=AGE`, with AGE as for `duration_parse()`
+    fn retention_parse(s: &str) -> Result<(String, Duration), String> {
+        let (path, age) = s.rsplit_once('=').ok_or_else(|| format!("{} not in PATH=AGE format", s))?;
+        Ok((Options::normalise_path(path), Options::duration_parse(age)?))
+    }
+
+    /// Seconds, or a number suffixed with `s`, `m`, `h`, `d`, or `w`
+    fn duration_parse(s_orig: &str) -> Result<Duration, String> {
+        let s = s_orig.trim();
+        let (s, unit) = match s.as_bytes().last() {
+            Some(b's') => (&s[..s.len() - 1], 1),
+            Some(b'm') => (&s[..s.len() - 1], 60),
+            Some(b'h') => (&s[..s.len() - 1], 60 * 60),
+            Some(b'd') => (&s[..s.len() - 1], 24 * 60 * 60),
+            Some(b'w') => (&s[..s.len() - 1], 7 * 24 * 60 * 60),
+            _ => (s, 1),
+        };
+        let number = u64::from_str(s).map_err(|e| format!("\"{}\" not a duration: {}", s_orig, e))?;
+        number.checked_mul(unit).map(Duration::from_secs).ok_or_else(|| format!("{} * {} too big", number, unit))
+    }
+
+    /// `HH:MM-HH:MM` as (start, end) minutes since midnight; the end can be before the start for windows spanning midnight
+    fn hours_parse(s: &str) -> Result<(u16, u16), String> {
+        let time = |t: &str| {
+            t.trim()
+                .split_once(':')
+                .and_then(|(h, m)| Some((u16::from_str(h).ok()?, u16::from_str(m).ok()?)))
+                .filter(|&(h, m)| h <= 24 && m < 60 && h * 60 + m <= 24 * 60)
+                .map(|(h, m)| h * 60 + m)
+                .ok_or_else(|| format!("\"{}\" not a time in HH:MM format", t))
+        };
+
+        let (start, end) = s.split_once('-').ok_or_else(|| format!("{} not in HH:MM-HH:MM format", s))?;
+        let (start, end) = (time(start)?, time(end)?);
+        if start == end {
+            Err(format!("{} is an empty window", s))
+        } else {
+            Ok((start, end))
+        }
     }
 
     fn mime_type_override_parse<'s>(s: Cow<'s, str>) -> Result<(String, Mime), String> {
@@ -390,6 +1061,51 @@ impl Options {
         }
     }
 
+    /// `EXTENSION:DURATION`, with DURATION as for `duration_parse()`
+    fn cache_max_age_override_parse(s: &str) -> Result<(String, Duration), String> {
+        let (ext, age) = s.split_once(':').ok_or_else(|| format!("{} not in EXTENSION:DURATION format", s))?;
+        Ok((ext.to_string(), Options::duration_parse(age)?))
+    }
+
+    fn compression_policy_parse<'s>(s: Cow<'s, str>) -> Result<(String, bool), String> {
+        match s.rfind(":") {
+            None => Err(format!("{} not in MIME-TYPE:always|never format", s)),
+            Some(col_idx) => {
+                let policy = match &s[col_idx + 1..] {
+                    "always" => true,
+                    "never" => false,
+                    p => return Err(format!("{} not always or never", p)),
+                };
+
+                let mt = s[..col_idx].to_lowercase();
+                match mt.find('/') {
+                    Some(sl_idx) if sl_idx != 0 && sl_idx != mt.len() - 1 && !mt[sl_idx + 1..].contains('/') => Ok((mt, policy)),
+                    _ => Err(format!("{} not a valid TYPE/SUBTYPE or TYPE/*", &s[..col_idx])),
+                }
+            }
+        }
+    }
+
+    fn remote_url_parse(s: &str) -> Result<GenericUrl, String> {
+        let mut url = GenericUrl::parse(s).map_err(|e| format!("{} is not a valid URL: {}", s, e))?;
+        if url.scheme() != "http" && url.scheme() != "https" {
+            return Err(format!("{} is not an HTTP(S) URL", s));
+        }
+        if !url.path().ends_with('/') {
+            let path = url.path().to_string() + "/";
+            url.set_path(&path);
+        }
+        Ok(url)
+    }
+
+    fn preload_parse(s: &str) -> Result<String, String> {
+        if s.is_empty() || s.contains(|c: char| c.is_whitespace() || c.is_control() || c == '<' || c == '>') {
+            Err(format!("\"{}\" not a URL", s))
+        } else {
+            Ok(s.to_string())
+        }
+    }
+
     fn header_parse(s: &str) -> Result<(String, Vec<u8>), String> {
         HEADER_REGEX.captures(s).map(|hdr| (hdr[1].to_string(), hdr[2].as_bytes().to_vec())).ok_or_else(|| format!("\"{}\" invalid format", s))
     }
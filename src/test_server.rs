@@ -0,0 +1,234 @@
+//! A fixture for other crates' integration tests: the server, in-process, hosting a throwaway directory on a local port.
+//!
+//! ```no_run
+//! # extern crate https;
+//! let server = https::TestServerBuilder::new()
+//!     .file("index.html", "<p>Hello</p>")
+//!     .file("assets/app.js", "alert(1);")
+//!     .arg("-w")
+//!     .start();
+//!
+//! // Point the code under test at server.url("/assets/app.js"), then
+//! server.assert_requested("GET", "/assets/app.js");
+//! ```
+
+
+use std::{env, fs, process};
+use std::net::SocketAddr;
+use std::path::{PathBuf, Path};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use iron::{IronResult, Listening, Response, Request, Handler, Iron};
+use self::super::ops::HttpHandler;
+use self::super::Options;
+
+
+static SERVERS_STARTED: AtomicUsize = AtomicUsize::new(0);
+
+
+/// A request the server answered, in the order they came in
+#[derive(Debug, Clone, Hash, PartialEq, Eq)]
+pub struct RecordedRequest {
+    pub method: String,
+    /// The path and query, still percent-encoded, like `/dir/file%20name.txt?format=json`
+    pub path: String,
+    /// `None` if handling it failed without a response
+    pub status: Option<u16>,
+}
+
+
+/// Sets up the tree to host and the command-line options to host it with, then `start()`s a `TestServer`
+#[derive(Debug, Clone, Default)]
+pub struct TestServerBuilder {
+    files: Vec<(String, Vec<u8>)>,
+    dirs: Vec<String>,
+    args: Vec<String>,
+}
+
+impl TestServerBuilder {
+    pub fn new() -> TestServerBuilder {
+        TestServerBuilder::default()
+    }
+
+    /// Add a file at `path`, relative to the hosted directory, creating its parents
+    pub fn file<P: Into<String>, C: Into<Vec<u8>>>(mut self, path: P, content: C) -> TestServerBuilder {
+        self.files.push((path.into(), content.into()));
+        self
+    }
+
+    /// Add an empty directory at `path`, relative to the hosted directory, creating its parents
+    pub fn dir<P: Into<String>>(mut self, path: P) -> TestServerBuilder {
+        self.dirs.push(path.into());
+        self
+    }
+
+    /// Pass an additional command-line argument, like `-w` or `--cache-max-age=60`
+    ///
+    /// The hosted directory, address, port, and temp directory are already taken care of.
+    pub fn arg<A: Into<String>>(mut self, arg: A) -> TestServerBuilder {
+        self.args.push(arg.into());
+        self
+    }
+
+    /// Pass several additional command-line arguments, like `arg()`
+    pub fn args<I: IntoIterator<Item = A>, A: Into<String>>(mut self, args: I) -> TestServerBuilder {
+        self.args.extend(args.into_iter().map(Into::into));
+        self
+    }
+
+    /// Write out the tree and start serving it on an OS-picked port on 127.0.0.1
+    ///
+    /// Panics if that fails, or if the arguments are invalid, which exits the process, like the binary does.
+    pub fn start(self) -> TestServer {
+        let root = env::temp_dir().join(format!("http-test-server-{}-{}", process::id(), SERVERS_STARTED.fetch_add(1, Ordering::Relaxed)));
+        let _ = fs::remove_dir_all(&root);
+        let site = root.join("site");
+        fs::create_dir_all(&site).expect("Failed to create hosted directory");
+        fs::create_dir_all(root.join("temp")).expect("Failed to create temp directory");
+        for dir in &self.dirs {
+            fs::create_dir_all(site.join(dir)).expect("Failed to create fixture directory");
+        }
+        for (path, content) in &self.files {
+            write_fixture(&site.join(path), content);
+        }
+
+        let opts = Options::parse_from(vec!["http".into(),
+                                            site.into_os_string(),
+                                            "-qqq".into(),
+                                            "-a".into(),
+                                            "127.0.0.1".into(),
+                                            "-p".into(),
+                                            "0".into(),
+                                            "--temp-dir".into(),
+                                            root.join("temp").into_os_string()]
+            .into_iter()
+            .chain(self.args.into_iter().map(Into::into)));
+
+        let requests = Arc::new(Mutex::new(vec![]));
+        let handler = RecordingHandler {
+            handler: HttpHandler::new(&opts),
+            requests: requests.clone(),
+        };
+        for td in handler.handler.temp_dirs().values() {
+            fs::create_dir_all(td).expect("Failed to create temp directory");
+        }
+        let listening = Iron::new(handler).http((opts.bind_address, 0)).expect("Failed to start server");
+
+        TestServer {
+            listening: listening,
+            requests: requests,
+            opts: opts,
+            root: root,
+        }
+    }
+}
+
+
+/// A running server hosting a temporary directory, stopped and cleaned up after on drop
+pub struct TestServer {
+    listening: Listening,
+    requests: Arc<Mutex<Vec<RecordedRequest>>>,
+    opts: Options,
+    root: PathBuf,
+}
+
+impl TestServer {
+    /// The address it's listening on
+    pub fn addr(&self) -> SocketAddr {
+        self.listening.socket
+    }
+
+    /// The port it's listening on
+    pub fn port(&self) -> u16 {
+        self.addr().port()
+    }
+
+    /// The URL of `path`, which should start with a slash, like `http://127.0.0.1:PORT/path`
+    pub fn url(&self, path: &str) -> String {
+        format!("http://{}{}", self.addr(), path)
+    }
+
+    /// The hosted directory, for checking on what was written to it
+    pub fn site(&self) -> PathBuf {
+        self.root.join("site")
+    }
+
+    /// Add or replace a file at `path`, relative to the hosted directory, creating its parents
+    pub fn add_file<P: AsRef<Path>, C: AsRef<[u8]>>(&self, path: P, content: C) {
+        write_fixture(&self.site().join(path), content.as_ref());
+    }
+
+    /// Remove the file at `path`, relative to the hosted directory
+    pub fn remove_file<P: AsRef<Path>>(&self, path: P) {
+        fs::remove_file(self.site().join(path)).expect("Failed to remove fixture file");
+    }
+
+    /// Every request answered so far, in the order they came in
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        self.requests.lock().unwrap_or_else(PoisonError::into_inner).clone()
+    }
+
+    /// Forget the requests answered so far
+    pub fn clear_requests(&self) {
+        self.requests.lock().unwrap_or_else(PoisonError::into_inner).clear();
+    }
+
+    /// The amount of `method` requests for `path` (with the query, if any) answered so far
+    pub fn request_count(&self, method: &str, path: &str) -> usize {
+        self.requests().iter().filter(|r| r.method == method && r.path == path).count()
+    }
+
+    /// Panic, listing what was, unless a `method` request for `path` (with the query, if any) was answered
+    pub fn assert_requested(&self, method: &str, path: &str) {
+        if self.request_count(method, path) == 0 {
+            panic!("No {} {} request, got {:?}", method, path, self.requests());
+        }
+    }
+
+    /// Panic, listing what was, if a `method` request for `path` (with the query, if any) was answered
+    pub fn assert_not_requested(&self, method: &str, path: &str) {
+        if self.request_count(method, path) != 0 {
+            panic!("Unexpected {} {} request, got {:?}", method, path, self.requests());
+        }
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        let _ = self.listening.close();
+        HttpHandler::clean_temp_dirs(&self.opts.temp_directory, self.opts.loglevel, self.opts.log_colour);
+        let _ = fs::remove_dir_all(&self.root);
+    }
+}
+
+
+fn write_fixture(path: &Path, content: &[u8]) {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).expect("Failed to create fixture directory");
+    }
+    fs::write(path, content).expect("Failed to write fixture file");
+}
+
+
+struct RecordingHandler {
+    handler: HttpHandler,
+    requests: Arc<Mutex<Vec<RecordedRequest>>>,
+}
+
+impl Handler for RecordingHandler {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let mut path = format!("/{}", req.url.path().join("/"));
+        if let Some(query) = req.url.query() {
+            path.push('?');
+            path.push_str(query);
+        }
+
+        let resp = self.handler.handle(req);
+        self.requests.lock().unwrap_or_else(PoisonError::into_inner).push(RecordedRequest {
+            method: req.method.to_string(),
+            path: path,
+            status: resp.as_ref().ok().and_then(|r| r.status).map(|s| s.to_u16()),
+        });
+        resp
+    }
+}
@@ -0,0 +1,155 @@
+use std::io::{self, ErrorKind as IoErrorKind, Result as IoResult, Error as IoError, Read};
+use std::path::{Component as PathComponent, PathBuf, Path};
+use self::super::set_file_xattr;
+use std::fs::{self, File};
+use std::str;
+
+
+/// Size of a tar header and the alignment of tar entry data
+const TAR_BLOCK: usize = 512;
+
+/// Largest GNU long name or pax extended header read into memory; bigger ones make the archive invalid
+const MAX_TAR_METADATA: u64 = 64 * 1024;
+
+
+/// Unpack a (ustar, GNU, or pax) tar archive into the specified directory, returning the amount of unpacked entries.
+///
+/// Only regular files and directories are unpacked, links and special files are skipped.
+/// Entries with absolute paths or paths containing `..` make the whole archive invalid.
+///
+/// If `xattrs`, extended attributes from pax `SCHILY.xattr.*` records are restored, where supported.
+pub fn unpack_tar<R: Read>(mut from: R, to: &Path, xattrs: bool) -> IoResult<usize> {
+    let mut header = [0u8; TAR_BLOCK];
+    let mut long_name = None;
+    let mut entry_xattrs = vec![];
+    let mut entries = 0;
+
+    loop {
+        if !read_block(&mut from, &mut header)? || header.iter().all(|&b| b == 0) {
+            return Ok(entries);
+        }
+
+        let size = tar_octal(&header[124..136])?;
+        let padded_size = (size + TAR_BLOCK as u64 - 1) / TAR_BLOCK as u64 * TAR_BLOCK as u64;
+        let mut data = (&mut from).take(padded_size);
+
+        let name = match long_name.take() {
+            Some(name) => name,
+            None => {
+                let mut name = tar_str(&header[0..100]).to_string();
+                if &header[257..262] == b"ustar" && header[345] != 0 {
+                    name = format!("{}/{}", tar_str(&header[345..500]), name);
+                }
+                name
+            }
+        };
+
+        match header[156] {
+            // GNU long name, the data is the name of the next entry
+            b'L' => {
+                let buf = read_tar_metadata(&mut data, size)?;
+                long_name = Some(tar_str(&buf).to_string());
+            }
+            // pax extended header, of which we only care about the path and extended attributes
+            b'x' => {
+                let buf = read_tar_metadata(&mut data, size)?;
+                let records = pax_records(&buf);
+                long_name = records.iter().find(|(k, _)| *k == "path").and_then(|(_, v)| str::from_utf8(v).ok()).map(str::to_string);
+                entry_xattrs = records.iter()
+                    .filter_map(|(k, v)| k.strip_prefix("SCHILY.xattr.").map(|n| (n.strip_prefix("user.").unwrap_or(n).to_string(), v.to_vec())))
+                    .collect();
+            }
+            b'0' | 0 | b'5' => {
+                let path = to.join(sanitise_tar_path(&name)?);
+                if header[156] == b'5' || name.ends_with('/') {
+                    fs::create_dir_all(&path)?;
+                } else {
+                    if let Some(parent) = path.parent() {
+                        fs::create_dir_all(parent)?;
+                    }
+                    io::copy(&mut (&mut data).take(size), &mut File::create(&path)?)?;
+                }
+                if xattrs {
+                    for (name, val) in &entry_xattrs {
+                        let _ = set_file_xattr(&path, name, Some(val));
+                    }
+                }
+                entry_xattrs.clear();
+                entries += 1;
+            }
+            _ => entry_xattrs.clear(),
+        }
+
+        io::copy(&mut data, &mut io::sink())?;
+    }
+}
+
+
+/// Read a metadata entry's data of `size`, which the client controls, so it's capped at `MAX_TAR_METADATA`
+fn read_tar_metadata<R: Read>(data: &mut R, size: u64) -> IoResult<Vec<u8>> {
+    if size > MAX_TAR_METADATA {
+        return Err(IoError::new(IoErrorKind::InvalidData, format!("tar metadata entry of {} bytes too big", size)));
+    }
+
+    let mut buf = Vec::with_capacity(size as usize);
+    data.take(size).read_to_end(&mut buf)?;
+    Ok(buf)
+}
+
+fn read_block<R: Read>(from: &mut R, into: &mut [u8]) -> IoResult<bool> {
+    let mut read = 0;
+    while read < into.len() {
+        match from.read(&mut into[read..])? {
+            0 if read == 0 => return Ok(false),
+            0 => return Err(IoError::new(IoErrorKind::UnexpectedEof, "truncated tar header")),
+            r => read += r,
+        }
+    }
+    Ok(true)
+}
+
+fn tar_str(field: &[u8]) -> &str {
+    let end = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    str::from_utf8(&field[..end]).unwrap_or("")
+}
+
+fn tar_octal(field: &[u8]) -> IoResult<u64> {
+    let s = tar_str(field).trim();
+    if s.is_empty() {
+        Ok(0)
+    } else {
+        u64::from_str_radix(s, 8).map_err(|e| IoError::new(IoErrorKind::InvalidData, format!("invalid tar size \"{}\": {}", s, e)))
+    }
+}
+
+/// Records are in the form `"LEN KEY=VALUE\n"`, LEN being the length of the whole record, since values can be binary
+fn pax_records(mut data: &[u8]) -> Vec<(&str, &[u8])> {
+    let mut ret = vec![];
+    while let Some(sp) = data.iter().position(|&b| b == b' ') {
+        let len = match str::from_utf8(&data[..sp]).ok().and_then(|l| l.parse::<usize>().ok()) {
+            Some(len) if len > sp + 1 && len <= data.len() => len,
+            _ => break,
+        };
+
+        let record = &data[sp + 1..len - 1];
+        if let Some(eq) = record.iter().position(|&b| b == b'=') {
+            if let Ok(key) = str::from_utf8(&record[..eq]) {
+                ret.push((key, &record[eq + 1..]));
+            }
+        }
+        data = &data[len..];
+    }
+    ret
+}
+
+fn sanitise_tar_path(name: &str) -> IoResult<PathBuf> {
+    let mut ret = PathBuf::new();
+    for comp in Path::new(name).components() {
+        match comp {
+            PathComponent::Normal(c) => ret.push(c),
+            PathComponent::CurDir => {}
+            _ => return Err(IoError::new(IoErrorKind::InvalidData, format!("illegal path \"{}\" in archive", name))),
+        }
+    }
+    Ok(ret)
+}
@@ -1,22 +1,35 @@
 use brotli::enc::backward_references::{BrotliEncoderParams, BrotliEncoderMode};
 use brotli::enc::BrotliCompress as brotli_compress;
+use brotli::CompressorWriter as BrotliEncoder;
+use brotli::Decompressor as BrotliDecompressor;
 use flate2::write::{DeflateEncoder, GzEncoder};
+use flate2::read::{DeflateDecoder, GzDecoder};
 use flate2::Compression as Flate2Compression;
-use std::io::{self, Error as IoError, Write};
-use iron::headers::{QualityItem, Encoding};
+use std::io::{self, ErrorKind as IoErrorKind, Result as IoResult, Error as IoError, Write, Read};
+use iron::headers::{QualityItem, Encoding, Quality};
+use iron::Response;
 use bzip2::Compression as BzCompression;
 use std::collections::BTreeSet;
 use bzip2::write::BzEncoder;
+use bzip2::read::BzDecoder;
+use zstd::stream::write::Encoder as ZstdEncoder;
+use zstd::stream::read::Decoder as ZstdDecoder;
 use unicase::UniCase;
 use std::path::Path;
-use std::fs::File;
+use std::str::FromStr;
+use std::fs::{self, File};
 use blake3;
+use zstd;
 
 
 lazy_static! {
     /// The list of content encodings we handle.
     pub static ref SUPPORTED_ENCODINGS: Vec<Encoding> = {
-        let es = vec![Encoding::Gzip, Encoding::Deflate, Encoding::EncodingExt("br".to_string()), Encoding::EncodingExt("bzip2".to_string())];
+        let es = vec![Encoding::Gzip,
+                      Encoding::Deflate,
+                      Encoding::EncodingExt("br".to_string()),
+                      Encoding::EncodingExt("bzip2".to_string()),
+                      Encoding::EncodingExt("zstd".to_string())];
         [es.clone(), es.into_iter().map(|e| Encoding::EncodingExt(format!("x-{}", e))).collect()].iter().flat_map(|e| e.clone()).collect()
     };
 
@@ -32,49 +45,164 @@ lazy_static! {
     };
 }
 
-/// The minimal size at which to encode filesystem files.
+/// The zstd compression level: its default, faster than any of the others', and compressing about as well as gzip's.
+pub const ZSTD_LEVEL: i32 = 3;
+
+/// The default minimal size at which to encode filesystem files.
 pub const MIN_ENCODING_SIZE: u64 = 1024;
 
-/// The maximal size at which to encode filesystem files.
+/// The default maximal size at which to encode filesystem files.
 pub const MAX_ENCODING_SIZE: u64 = 100 * 1024 * 1024;
 
-/// The minimal size gain at which to preserve encoded filesystem files.
+/// The default minimal size gain at which to preserve encoded filesystem files.
 pub const MIN_ENCODING_GAIN: f64 = 1.1;
 
+/// The maximal size of generated responses whose encoded forms are cached.
+pub const MAX_GENERATED_CACHE_ENTRY_SIZE: usize = 1024 * 1024;
+
+/// The default maximal total size of generated responses and their encoded forms to keep cached.
+pub const GENERATED_CACHE_BUDGET: usize = 64 * 1024 * 1024;
+
+/// The default maximal amount of encoded filesystem files to remember, and keep in the encoded store.
+pub const ENCODED_CACHE_ENTRIES: usize = 64 * 1024;
+
+
+/// Find best supported encoding to use, or `None` for identity, as per RFC 7231, section 5.3.4.
+///
+/// The client's ranking decides: the supported encoding with the highest quality wins, ties going to the one listed first,
+/// and ones not listed get the quality of `*`, if it's there.
+/// Identity is used instead if the client prefers it, or if nothing else is acceptable, even if identity isn't either.
+pub fn response_encoding(requested: &[QualityItem<Encoding>]) -> Option<Encoding> {
+    response_encoding_of(requested, &SUPPORTED_ENCODINGS)
+}
+
+/// Find the best of the `available` encodings to use, or `None` for identity, like `response_encoding()`.
+pub fn response_encoding_of(requested: &[QualityItem<Encoding>], available: &[Encoding]) -> Option<Encoding> {
+    let listed = requested.iter().map(|e| normalise_encoding(&e.item)).filter(|e| available.contains(e));
+    let best = listed.chain(available.iter().cloned())
+        .map(|e| (encoding_quality(requested, &e), e))
+        .filter(|&(q, _)| q.0 != 0)
+        .fold(None, |best: Option<(Quality, Encoding)>, (q, e)| match best {
+            Some((bq, _)) if bq >= q => best,
+            _ => Some((q, e)),
+        });
+
+    best.filter(|&(q, _)| q >= encoding_quality(requested, &Encoding::Identity)).map(|(_, e)| e)
+}
+
+/// Mark a response as chosen by the request's `Accept-Encoding`, encoded or not,
+/// so shared caches don't send one encoding to clients that asked for another.
+pub fn vary_encoding(mut resp: Response) -> Response {
+    resp.headers.set_raw("Vary", vec![b"Accept-Encoding".to_vec()]);
+    resp
+}
 
-/// Find best supported encoding to use, or `None` for identity.
-pub fn response_encoding(requested: &mut [QualityItem<Encoding>]) -> Option<Encoding> {
-    requested.sort_by_key(|e| e.quality);
-    requested.iter().filter(|e| e.quality.0 != 0).find(|e| SUPPORTED_ENCODINGS.contains(&e.item)).map(|e| e.item.clone())
+/// Whether a response with no content encoding is acceptable to a client accepting the specified encodings,
+/// which it is unless excluded with `identity;q=0`, or with `*;q=0` without a quality for identity
+pub fn identity_acceptable(requested: &[QualityItem<Encoding>]) -> bool {
+    encoding_quality(requested, &Encoding::Identity).0 != 0
+}
+
+/// The quality a client accepting `requested` gives `enc`; unlisted identity is acceptable, but as little as possible
+fn encoding_quality(requested: &[QualityItem<Encoding>], enc: &Encoding) -> Quality {
+    let wildcard = Encoding::EncodingExt("*".to_string());
+    requested.iter()
+        .find(|e| normalise_encoding(&e.item) == *enc)
+        .or_else(|| requested.iter().find(|e| e.item == wildcard))
+        .map(|e| e.quality)
+        .unwrap_or(if *enc == Encoding::Identity { Quality(1) } else { Quality(0) })
+}
+
+/// Content codings are case-insensitive, but only lower-case ones are parsed into their variants
+fn normalise_encoding(enc: &Encoding) -> Encoding {
+    match *enc {
+        Encoding::EncodingExt(ref e) => Encoding::from_str(&e.to_ascii_lowercase()).unwrap_or_else(|_| enc.clone()),
+        _ => enc.clone(),
+    }
 }
 
 /// Encode a string slice using a specified encoding or `None` if encoding failed or is not recognised.
 pub fn encode_str(dt: &str, enc: &Encoding) -> Option<Vec<u8>> {
     type EncodeT = fn(&str) -> Option<Vec<u8>>;
-    const STR_ENCODING_FNS: &[EncodeT] = &[encode_str_gzip, encode_str_deflate, encode_str_brotli, encode_str_bzip2];
+    const STR_ENCODING_FNS: &[EncodeT] = &[encode_str_gzip, encode_str_deflate, encode_str_brotli, encode_str_bzip2, encode_str_zstd];
 
     encoding_idx(enc).and_then(|fi| STR_ENCODING_FNS[fi](dt))
 }
 
-/// Encode the file denoted by the specified path into the file denoted by the specified path using a specified encoding or
-/// `false` if encoding failed, is not recognised or an I/O error occurred.
-pub fn encode_file(p: &Path, op: &Path, enc: &Encoding) -> bool {
-    type EncodeT = fn(File, File) -> bool;
-    const FILE_ENCODING_FNS: &[EncodeT] = &[encode_file_gzip, encode_file_deflate, encode_file_brotli, encode_file_bzip2];
+/// Encode the file denoted by the specified path into the file denoted by the specified path using a specified encoding,
+/// removing the partially-written output file if an I/O error occurred.
+pub fn encode_file(p: &Path, op: &Path, enc: &Encoding) -> IoResult<()> {
+    type EncodeT = fn(File, File) -> IoResult<()>;
+    const FILE_ENCODING_FNS: &[EncodeT] = &[encode_file_gzip, encode_file_deflate, encode_file_brotli, encode_file_bzip2, encode_file_zstd];
 
-    encoding_idx(enc)
-        .map(|fi| {
-            let inf = File::open(p);
-            let outf = File::create(op);
+    let fi = encoding_idx(enc).unwrap();
+    let inf = File::open(p)?;
+    // Don't leave partial files around to be served later
+    FILE_ENCODING_FNS[fi](inf, File::create(op)?).inspect_err(|_| {
+        let _ = fs::remove_file(op);
+    })
+}
 
-            inf.is_ok() && outf.is_ok() && FILE_ENCODING_FNS[fi](inf.unwrap(), outf.unwrap())
+/// Encoder writing the data written to it into another writer as it goes, for responses generated piecemeal.
+pub enum StreamEncoder<W: Write> {
+    Gzip(GzEncoder<W>),
+    Deflate(DeflateEncoder<W>),
+    Brotli(Box<BrotliEncoder<W>>),
+    Bzip2(BzEncoder<W>),
+    Zstd(ZstdEncoder<'static, W>),
+}
+
+impl<W: Write> StreamEncoder<W> {
+    /// Start encoding into the specified writer with the specified encoding, or `None` if it's not recognised.
+    pub fn new(into: W, enc: &Encoding) -> Option<StreamEncoder<W>> {
+        encoding_idx(enc).and_then(|ei| {
+            Some(match ei {
+                0 => StreamEncoder::Gzip(GzEncoder::new(into, Flate2Compression::default())),
+                1 => StreamEncoder::Deflate(DeflateEncoder::new(into, Flate2Compression::default())),
+                2 => StreamEncoder::Brotli(Box::new(BrotliEncoder::with_params(into, 4096, &BROTLI_PARAMS))),
+                3 => StreamEncoder::Bzip2(BzEncoder::new(into, BzCompression::Default)),
+                _ => StreamEncoder::Zstd(ZstdEncoder::new(into, ZSTD_LEVEL).ok()?),
+            })
         })
-        .unwrap()
+    }
+
+    /// Write out the rest of the encoded data.
+    pub fn finish(self) -> IoResult<W> {
+        match self {
+            StreamEncoder::Gzip(e) => e.finish(),
+            StreamEncoder::Deflate(e) => e.finish(),
+            StreamEncoder::Brotli(e) => Ok(e.into_inner()),
+            StreamEncoder::Bzip2(e) => e.finish(),
+            StreamEncoder::Zstd(e) => e.finish(),
+        }
+    }
+}
+
+impl<W: Write> Write for StreamEncoder<W> {
+    fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+        match *self {
+            StreamEncoder::Gzip(ref mut e) => e.write(buf),
+            StreamEncoder::Deflate(ref mut e) => e.write(buf),
+            StreamEncoder::Brotli(ref mut e) => e.write(buf),
+            StreamEncoder::Bzip2(ref mut e) => e.write(buf),
+            StreamEncoder::Zstd(ref mut e) => e.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> IoResult<()> {
+        match *self {
+            StreamEncoder::Gzip(ref mut e) => e.flush(),
+            StreamEncoder::Deflate(ref mut e) => e.flush(),
+            StreamEncoder::Brotli(ref mut e) => e.flush(),
+            StreamEncoder::Bzip2(ref mut e) => e.flush(),
+            StreamEncoder::Zstd(ref mut e) => e.flush(),
+        }
+    }
 }
 
 /// Encoding extension to use for encoded files, for example "gz" for gzip, or `None` if the encoding is not recognised.
 pub fn encoding_extension(enc: &Encoding) -> Option<&'static str> {
-    const ENCODING_EXTS: &[&str] = &["gz", "dflt", "br", "bz2"];
+    const ENCODING_EXTS: &[&str] = &["gz", "dflt", "br", "bz2", "zst"];
 
     encoding_idx(enc).map(|ei| ENCODING_EXTS[ei])
 }
@@ -86,6 +214,24 @@ pub fn file_hash(p: &Path) -> Result<blake3::Hash, IoError> {
     Ok(ctx.finalize())
 }
 
+/// Return the 256-bit BLAKE3 hash of the decoded contents of the file denoted by the specified path,
+/// encoded as denoted by the specified `encoding_extension()`.
+pub fn decoded_file_hash(p: &Path, ext: &str) -> Result<blake3::Hash, IoError> {
+    let f = File::open(p)?;
+    let mut decoder: Box<dyn Read> = match ext {
+        "gz" => Box::new(GzDecoder::new(f)),
+        "dflt" => Box::new(DeflateDecoder::new(f)),
+        "br" => Box::new(BrotliDecompressor::new(f, 4096)),
+        "bz2" => Box::new(BzDecoder::new(f)),
+        "zst" => Box::new(ZstdDecoder::new(f)?),
+        _ => return Err(IoError::new(IoErrorKind::InvalidInput, format!("unknown encoding extension {}", ext))),
+    };
+
+    let mut ctx = blake3::Hasher::new();
+    io::copy(&mut decoder, &mut ctx)?;
+    Ok(ctx.finalize())
+}
+
 
 fn encoding_idx(enc: &Encoding) -> Option<usize> {
     match *enc {
@@ -97,6 +243,7 @@ fn encoding_idx(enc: &Encoding) -> Option<usize> {
                 "x-deflate" => Some(1),
                 "br" | "x-br" => Some(2),
                 "bzip2" | "x-bzip2" => Some(3),
+                "zstd" | "x-zstd" => Some(4),
                 _ => None,
             }
         }
@@ -111,9 +258,9 @@ macro_rules! encode_fn {
             cmp.write_all(dt.as_bytes()).ok().and_then(|_| cmp.finish().ok())
         }
 
-        fn $file_fn_name(mut inf: File, outf: File) -> bool {
+        fn $file_fn_name(mut inf: File, outf: File) -> IoResult<()> {
             let mut cmp = $constructor(outf);
-            io::copy(&mut inf, &mut cmp).and_then(|_| cmp.finish()).is_ok()
+            io::copy(&mut inf, &mut cmp).and_then(|_| cmp.finish()).map(|_| ())
         }
     };
 
@@ -131,6 +278,14 @@ fn encode_str_brotli(dt: &str) -> Option<Vec<u8>> {
     brotli_compress(&mut dt.as_bytes(), &mut ret, &BROTLI_PARAMS).ok().map(|_| ret)
 }
 
-fn encode_file_brotli(mut inf: File, mut outf: File) -> bool {
-    brotli_compress(&mut inf, &mut outf, &BROTLI_PARAMS).is_ok()
+fn encode_file_brotli(mut inf: File, mut outf: File) -> IoResult<()> {
+    brotli_compress(&mut inf, &mut outf, &BROTLI_PARAMS).map(|_| ())
+}
+
+fn encode_str_zstd(dt: &str) -> Option<Vec<u8>> {
+    zstd::stream::encode_all(dt.as_bytes(), ZSTD_LEVEL).ok()
+}
+
+fn encode_file_zstd(inf: File, outf: File) -> IoResult<()> {
+    zstd::stream::copy_encode(inf, outf, ZSTD_LEVEL)
 }
@@ -0,0 +1,179 @@
+use std::io::{ErrorKind as IoErrorKind, Result as IoResult, Error as IoError, Write, Read};
+use std::collections::HashMap;
+use std::cmp;
+
+
+/// Smallest block size a signature can use
+pub const DELTA_MIN_BLOCK_SIZE: u32 = 64;
+
+/// Biggest block size a signature can use, 1MiB
+pub const DELTA_MAX_BLOCK_SIZE: u32 = 1024 * 1024;
+
+/// Most blocks a signature can describe, bounding its memory use to around 32MiB
+pub const DELTA_MAX_BLOCKS: usize = 1024 * 1024;
+
+/// Length of the truncated BLAKE3 hash of each block
+const STRONG_LEN: usize = 16;
+
+/// How much literal data to buffer before emitting it
+const LITERAL_CHUNK: usize = 64 * 1024;
+
+
+/// Block checksums of the client's copy of a file, against which a delta is computed.
+///
+/// Sent as a 32-bit big-endian block size, followed by, for each block in order,
+/// its 32-bit big-endian rolling checksum and the first 16 bytes of its BLAKE3 hash.
+/// The last block may be shorter than the block size.
+#[derive(Debug, Clone)]
+pub struct DeltaSignature {
+    block_size: usize,
+    blocks: HashMap<u32, Vec<(u32, [u8; STRONG_LEN])>>,
+}
+
+impl DeltaSignature {
+    /// Read a signature, failing with `InvalidData` if it's malformed or too big
+    pub fn read<R: Read>(mut from: R) -> IoResult<DeltaSignature> {
+        let mut word = [0u8; 4];
+        from.read_exact(&mut word)?;
+        let block_size = u32::from_be_bytes(word);
+        if block_size < DELTA_MIN_BLOCK_SIZE || block_size > DELTA_MAX_BLOCK_SIZE {
+            return Err(IoError::new(IoErrorKind::InvalidData, format!("block size {} out of range", block_size)));
+        }
+
+        let mut blocks: HashMap<_, Vec<_>> = HashMap::new();
+        let mut entry = [0u8; 4 + STRONG_LEN];
+        for idx in 0.. {
+            match read_entry(&mut from, &mut entry)? {
+                false => break,
+                true if idx as usize == DELTA_MAX_BLOCKS => return Err(IoError::new(IoErrorKind::InvalidData, "too many blocks")),
+                true => {
+                    let mut strong = [0u8; STRONG_LEN];
+                    strong.copy_from_slice(&entry[4..]);
+                    blocks.entry(u32::from_be_bytes([entry[0], entry[1], entry[2], entry[3]])).or_default().push((idx, strong));
+                }
+            }
+        }
+
+        Ok(DeltaSignature {
+            block_size: block_size as usize,
+            blocks: blocks,
+        })
+    }
+
+    fn find(&self, weak: u32, data: &[u8]) -> Option<u32> {
+        self.blocks.get(&weak).and_then(|candidates| {
+            let strong = strong_hash(data);
+            candidates.iter().find(|c| c.1 == strong).map(|c| c.0)
+        })
+    }
+}
+
+
+/// Write the delta turning the file described by `sig` into the one read from `from`.
+///
+/// The delta is a sequence of commands:
+///   * `C`, followed by a 32-bit big-endian block index - copy that block of the client's file,
+///   * `L`, followed by a 32-bit big-endian length and that many bytes - literal data,
+///   * `E`, followed by the 32-byte BLAKE3 hash of the whole new file - end.
+pub fn write_delta<R: Read, W: Write + ?Sized>(sig: &DeltaSignature, mut from: R, to: &mut W) -> IoResult<()> {
+    let bs = sig.block_size;
+    let mut whole = blake3::Hasher::new();
+
+    // Pending literal data is data[lit_start..pos], the window is data[pos..pos + bs]
+    let mut data = Vec::new();
+    let mut lit_start = 0;
+    let mut pos = 0;
+    let mut eof = false;
+    let mut rolling = None;
+
+    loop {
+        while !eof && data.len() - pos < bs + 1 {
+            let filled = data.len();
+            data.resize(filled + cmp::max(bs, LITERAL_CHUNK), 0);
+            let read = from.read(&mut data[filled..])?;
+            data.truncate(filled + read);
+            eof = read == 0;
+        }
+
+        let wlen = cmp::min(bs, data.len() - pos);
+        if wlen == 0 {
+            break;
+        }
+
+        let (a, b) = rolling.unwrap_or_else(|| weak_checksum(&data[pos..pos + wlen]));
+        if let Some(idx) = sig.find(a | (b << 16), &data[pos..pos + wlen]) {
+            write_literal(&data[lit_start..pos], &mut whole, to)?;
+            whole.update(&data[pos..pos + wlen]);
+            to.write_all(b"C")?;
+            to.write_all(&idx.to_be_bytes())?;
+
+            pos += wlen;
+            lit_start = pos;
+            rolling = None;
+        } else {
+            rolling = if wlen == bs && pos + bs < data.len() {
+                Some(roll(a, b, bs, data[pos], data[pos + bs]))
+            } else {
+                None
+            };
+            pos += 1;
+
+            if pos - lit_start >= LITERAL_CHUNK {
+                write_literal(&data[lit_start..pos], &mut whole, to)?;
+                lit_start = pos;
+            }
+        }
+
+        if lit_start >= LITERAL_CHUNK {
+            data.drain(..lit_start);
+            pos -= lit_start;
+            lit_start = 0;
+        }
+    }
+
+    write_literal(&data[lit_start..pos], &mut whole, to)?;
+    to.write_all(b"E")?;
+    to.write_all(whole.finalize().as_bytes())
+}
+
+
+/// rsync's rolling checksum, as its two 16-bit halves
+fn weak_checksum(block: &[u8]) -> (u32, u32) {
+    let len = block.len() as u32;
+    block.iter().enumerate().fold((0, 0), |(a, b), (i, &x)| ((a + x as u32) & 0xFFFF, (b + (len - i as u32) * x as u32) & 0xFFFF))
+}
+
+fn roll(a: u32, b: u32, len: usize, out: u8, inc: u8) -> (u32, u32) {
+    let a = a.wrapping_sub(out as u32).wrapping_add(inc as u32) & 0xFFFF;
+    let b = b.wrapping_sub((len as u32).wrapping_mul(out as u32)).wrapping_add(a) & 0xFFFF;
+    (a, b)
+}
+
+fn strong_hash(block: &[u8]) -> [u8; STRONG_LEN] {
+    let mut ret = [0u8; STRONG_LEN];
+    ret.copy_from_slice(&blake3::hash(block).as_bytes()[..STRONG_LEN]);
+    ret
+}
+
+fn write_literal<W: Write + ?Sized>(data: &[u8], whole: &mut blake3::Hasher, to: &mut W) -> IoResult<()> {
+    if !data.is_empty() {
+        whole.update(data);
+        to.write_all(b"L")?;
+        to.write_all(&(data.len() as u32).to_be_bytes())?;
+        to.write_all(data)?;
+    }
+    Ok(())
+}
+
+/// Fill the buffer with the next entry, returning `false` on a clean EOF
+fn read_entry<R: Read>(from: &mut R, buf: &mut [u8]) -> IoResult<bool> {
+    let mut read = 0;
+    while read < buf.len() {
+        match from.read(&mut buf[read..])? {
+            0 if read == 0 => return Ok(false),
+            0 => return Err(IoError::new(IoErrorKind::InvalidData, "truncated block entry")),
+            r => read += r,
+        }
+    }
+    Ok(true)
+}
@@ -0,0 +1,189 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+use sha1_smol;
+use std::fs;
+use bcrypt;
+use base64;
+use md5;
+
+
+/// Alphabet of the `crypt()`-style base64 in MD5-crypt hashes
+const CRYPT_BASE64: &[u8; 64] = b"./0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+
+/// Users and their password hashes, as read from an Apache htpasswd file.
+///
+/// bcrypt (`$2y$`, `$2a$`, `$2b$`), MD5-crypt (`$apr1$`), and SHA-1 (`{SHA}`) hashes are supported;
+/// lines with anything else, like `crypt()` hashes or plain-text passwords, make the file invalid.
+///
+/// # Examples
+///
+/// ```
+/// # use https::util::Htpasswd;
+/// let users = Htpasswd::parse("# Comrades\n\
+///                              karl:{SHA}VBPuJHI7uixaa6LQGWx4s+5GKNE=\n\
+///                              friedrich:$apr1$r31.....$HqJZimcKQFAMYayBlzkrA/\n").unwrap();
+/// assert!(users.verify("karl", "myPassword"));
+/// assert!(users.verify("friedrich", "myPassword"));
+/// assert!(!users.verify("karl", "capital"));
+/// assert!(!users.verify("vladimir", "myPassword"));
+///
+/// assert_eq!(Htpasswd::parse("vladimir:myPassword\n"),
+///            Err("line 1: unsupported password format, hash it with htpasswd -B, -m, or -s".to_string()));
+/// ```
+#[derive(Debug, Clone, Hash, PartialEq, Eq)]
+pub struct Htpasswd {
+    users: BTreeMap<String, PasswordHash>,
+}
+
+/// A password hash in one of the supported formats
+#[derive(Debug, Clone, Hash, PartialEq, Eq)]
+enum PasswordHash {
+    /// The whole `$2y$…` hash
+    Bcrypt(String),
+    /// The salt and the whole `$apr1$salt$…` hash
+    Apr1(String, String),
+    /// The decoded SHA-1 digest
+    Sha([u8; 20]),
+}
+
+impl Htpasswd {
+    /// Read the htpasswd file at `path`
+    pub fn load(path: &Path) -> Result<Htpasswd, String> {
+        let content = fs::read_to_string(path).map_err(|e| format!("Couldn't read htpasswd file \"{}\": {}", path.display(), e))?;
+        Htpasswd::parse(&content).map_err(|e| format!("htpasswd file \"{}\" invalid: {}", path.display(), e))
+    }
+
+    /// Parse `user:hash` lines, skipping blank ones and `#` comments, refusing hashes in unsupported formats
+    pub fn parse(content: &str) -> Result<Htpasswd, String> {
+        let mut users = BTreeMap::new();
+        for (i, line) in content.lines().enumerate().map(|(i, l)| (i + 1, l.trim_end_matches('\r'))) {
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            match line.find(':') {
+                Some(0) | None => return Err(format!("line {} not in format \"user:password\"", i)),
+                Some(colon) => {
+                    let hash = PasswordHash::parse(&line[colon + 1..])
+                        .ok_or_else(|| format!("line {}: unsupported password format, hash it with htpasswd -B, -m, or -s", i))?;
+                    if users.insert(line[..colon].to_string(), hash).is_some() {
+                        return Err(format!("user \"{}\" repeated on line {}", &line[..colon], i));
+                    }
+                }
+            }
+        }
+
+        if users.is_empty() {
+            Err("no users".to_string())
+        } else {
+            Ok(Htpasswd { users: users })
+        }
+    }
+
+    /// Whether there's a user called `username` whose password is `password`
+    pub fn verify(&self, username: &str, password: &str) -> bool {
+        match self.users.get(username) {
+            Some(&PasswordHash::Bcrypt(ref hash)) => bcrypt::verify(password, hash).unwrap_or(false),
+            Some(&PasswordHash::Apr1(ref salt, ref hash)) => constant_time_eq(md5_crypt(password.as_bytes(), salt.as_bytes()).as_bytes(), hash.as_bytes()),
+            Some(&PasswordHash::Sha(ref digest)) => constant_time_eq(&sha1_smol::Sha1::from(password).digest().bytes(), digest),
+            None => false,
+        }
+    }
+}
+
+impl PasswordHash {
+    fn parse(hash: &str) -> Option<PasswordHash> {
+        if hash.starts_with("$2y$") || hash.starts_with("$2a$") || hash.starts_with("$2b$") {
+            Some(PasswordHash::Bcrypt(hash.to_string()))
+        } else if hash.starts_with("$apr1$") {
+            let mut parts = hash["$apr1$".len()..].split('$');
+            match (parts.next(), parts.next(), parts.next()) {
+                (Some(salt), Some(_), None) => Some(PasswordHash::Apr1(salt.to_string(), hash.to_string())),
+                _ => None,
+            }
+        } else if hash.starts_with("{SHA}") {
+            let digest = base64::decode(&hash["{SHA}".len()..]).ok()?;
+            if digest.len() == 20 {
+                let mut ret = [0u8; 20];
+                ret.copy_from_slice(&digest);
+                Some(PasswordHash::Sha(ret))
+            } else {
+                None
+            }
+        } else {
+            None
+        }
+    }
+}
+
+
+/// Whether `lhs` and `rhs` are equal, taking as long to find out wherever they differ
+fn constant_time_eq(lhs: &[u8], rhs: &[u8]) -> bool {
+    lhs.len() == rhs.len() && lhs.iter().zip(rhs).fold(0u8, |acc, (l, r)| acc | (l ^ r)) == 0
+}
+
+
+/// Apache's MD5-crypt, as `$apr1$salt$hash`, with at most 8 characters of `salt` used
+fn md5_crypt(password: &[u8], salt: &[u8]) -> String {
+    let salt = &salt[..salt.len().min(8)];
+
+    let mut alternate = md5::Context::new();
+    alternate.consume(password);
+    alternate.consume(salt);
+    alternate.consume(password);
+    let alternate = alternate.compute();
+
+    let mut ctx = md5::Context::new();
+    ctx.consume(password);
+    ctx.consume(b"$apr1$");
+    ctx.consume(salt);
+    for chunk in password.chunks(16) {
+        ctx.consume(&alternate[..chunk.len()]);
+    }
+    let mut i = password.len();
+    while i != 0 {
+        if i & 1 != 0 {
+            ctx.consume([0u8]);
+        } else {
+            ctx.consume(&password[..1]);
+        }
+        i >>= 1;
+    }
+    let mut digest = ctx.compute();
+
+    for round in 0..1000 {
+        let mut ctx = md5::Context::new();
+        if round & 1 != 0 {
+            ctx.consume(password);
+        } else {
+            ctx.consume(&digest[..]);
+        }
+        if round % 3 != 0 {
+            ctx.consume(salt);
+        }
+        if round % 7 != 0 {
+            ctx.consume(password);
+        }
+        if round & 1 != 0 {
+            ctx.consume(&digest[..]);
+        } else {
+            ctx.consume(password);
+        }
+        digest = ctx.compute();
+    }
+
+    let mut ret = format!("$apr1${}$", String::from_utf8_lossy(salt));
+    for &(a, b, c) in &[(0, 6, 12), (1, 7, 13), (2, 8, 14), (3, 9, 15), (4, 10, 5)] {
+        crypt_base64(&mut ret, ((digest[a] as u32) << 16) | ((digest[b] as u32) << 8) | (digest[c] as u32), 4);
+    }
+    crypt_base64(&mut ret, digest[11] as u32, 2);
+    ret
+}
+
+fn crypt_base64(into: &mut String, mut val: u32, chars: usize) {
+    for _ in 0..chars {
+        into.push(CRYPT_BASE64[(val & 0x3F) as usize] as char);
+        val >>= 6;
+    }
+}
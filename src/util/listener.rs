@@ -0,0 +1,51 @@
+use socket2::{Domain, Socket, Type};
+use std::io::Result as IoResult;
+#[cfg(not(target_os = "linux"))]
+use std::io::{ErrorKind as IoErrorKind, Error as IoError};
+use std::net::{TcpListener, SocketAddr};
+use self::super::super::TcpTuning;
+
+
+/// Connections queued to be accepted, at most, if not specified; the same as `TcpListener::bind()` uses.
+pub const DEFAULT_LISTEN_BACKLOG: u32 = 128;
+
+
+/// Bind a listening socket to the specified address, tuned as specified, with `SO_REUSEPORT` if `reuseport`,
+/// so the kernel spreads connections between all sockets bound to it so.
+///
+/// The socket options are set on the listening socket, which accepted connections inherit.
+pub fn bind_listener(addr: SocketAddr, reuseport: bool, tuning: &TcpTuning) -> IoResult<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, None)?;
+    // Like TcpListener::bind(), so restarts can rebind right away
+    #[cfg(unix)]
+    socket.set_reuse_address(true)?;
+    if reuseport {
+        set_reuse_port(&socket)?;
+    }
+
+    if tuning.nodelay {
+        socket.set_nodelay(true)?;
+    }
+    if let Some(size) = tuning.send_buffer {
+        socket.set_send_buffer_size(size.get() as usize)?;
+    }
+    if let Some(size) = tuning.recv_buffer {
+        socket.set_recv_buffer_size(size.get() as usize)?;
+    }
+
+    socket.bind(&addr.into())?;
+    socket.listen(tuning.backlog.map(|b| b.get()).unwrap_or(DEFAULT_LISTEN_BACKLOG).min(i32::MAX as u32) as i32)?;
+    Ok(socket.into())
+}
+
+
+#[cfg(target_os = "linux")]
+fn set_reuse_port(socket: &Socket) -> IoResult<()> {
+    socket.set_reuse_port(true)
+}
+
+/// Elsewhere it doesn't spread connections, or isn't there at all
+#[cfg(not(target_os = "linux"))]
+fn set_reuse_port(_: &Socket) -> IoResult<()> {
+    Err(IoError::new(IoErrorKind::Unsupported, "SO_REUSEPORT listeners unsupported"))
+}
@@ -0,0 +1,86 @@
+use std::collections::hash_map::{DefaultHasher, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{PoisonError, RwLock};
+use std::hash::{Hasher, Hash};
+use std::borrow::Borrow;
+
+
+/// How many independently-locked parts an `LruMap` is split into
+const SHARDS: usize = 16;
+
+
+/// A `ShardedMap` holding at most some amount of entries, evicting the least recently used ones.
+///
+/// Recency is tracked per shard, so the evicted entries are the least recently used ones of the shard that got too full,
+/// which, with keys spread evenly, is close enough.
+#[derive(Debug)]
+pub struct LruMap<K, V> {
+    shards: Vec<RwLock<HashMap<K, (V, AtomicU64)>>>,
+    /// Bumped on every access, to stamp entries with
+    clock: AtomicU64,
+    shard_capacity: usize,
+}
+
+impl<K: Hash + Eq, V> LruMap<K, V> {
+    /// A map holding at most about `capacity` entries, at least one per shard
+    pub fn new(capacity: usize) -> LruMap<K, V> {
+        LruMap {
+            shards: (0..SHARDS).map(|_| Default::default()).collect(),
+            clock: AtomicU64::new(0),
+            shard_capacity: (capacity + SHARDS - 1) / SHARDS,
+        }
+    }
+
+    pub fn get<Q>(&self, key: &Q) -> Option<V>
+        where K: Borrow<Q>,
+              Q: Hash + Eq + ?Sized,
+              V: Clone
+    {
+        self.shard(key).read().unwrap_or_else(PoisonError::into_inner).get(key).map(|&(ref v, ref used)| {
+            used.store(self.clock.fetch_add(1, Ordering::Relaxed), Ordering::Relaxed);
+            v.clone()
+        })
+    }
+
+    /// Insert or replace the entry, returning the other ones evicted to make room for it
+    pub fn insert(&self, key: K, value: V) -> Vec<V>
+        where K: Clone
+    {
+        let mut shard = self.shard(&key).write().unwrap_or_else(PoisonError::into_inner);
+        shard.insert(key, (value, AtomicU64::new(self.clock.fetch_add(1, Ordering::Relaxed))));
+        let mut evicted = vec![];
+        while shard.len() > self.shard_capacity {
+            let oldest = shard.iter().min_by_key(|&(_, &(_, ref used))| used.load(Ordering::Relaxed)).map(|(k, _)| k.clone()).unwrap();
+            evicted.extend(shard.remove(&oldest).map(|(v, _)| v));
+        }
+        evicted
+    }
+
+    pub fn len(&self) -> usize {
+        self.shards.iter().map(|s| s.read().unwrap_or_else(PoisonError::into_inner).len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn clear(&self) {
+        for s in &self.shards {
+            s.write().unwrap_or_else(PoisonError::into_inner).clear();
+        }
+    }
+
+    /// Empty the shards a panic poisoned, since their entries may be half-updated, and unpoison them
+    pub fn recover(&self) {
+        for s in self.shards.iter().filter(|s| s.is_poisoned()) {
+            s.write().unwrap_or_else(PoisonError::into_inner).clear();
+            s.clear_poison();
+        }
+    }
+
+    fn shard<Q: Hash + ?Sized>(&self, key: &Q) -> &RwLock<HashMap<K, (V, AtomicU64)>> {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        &self.shards[hasher.finish() as usize % SHARDS]
+    }
+}
@@ -2,30 +2,51 @@
 
 
 mod os;
+mod s3;
+mod archive;
+mod delta;
 mod webdav;
 mod content_encoding;
+mod sharded_map;
+mod lru_map;
+mod listener;
+mod htpasswd;
 
 use base64;
+use regex::{self, Regex};
 use std::path::Path;
+use std::ffi::{OsString, OsStr};
+use unicode_normalization::UnicodeNormalization;
 use percent_encoding;
 use walkdir::WalkDir;
 use std::borrow::Cow;
 use rfsapi::RawFileData;
 use std::{cmp, f64, str};
-use std::time::SystemTime;
+use std::time::{Duration as StdDuration, SystemTime};
 use std::collections::HashMap;
 use time::{self, Duration, Tm};
-use iron::{mime, Headers, Url};
+use iron::{status, mime, Headers, Url};
+use iron::mime::Mime;
+use hyper::Client;
+use hyper::net::HttpsConnector;
+use hyper_native_tls::NativeTlsClient;
 use base64::display::Base64Display;
 use std::fmt::{self, Write as FmtWrite};
 use iron::error::HttpResult as HyperResult;
 use std::fs::{self, FileType, Metadata, File};
-use iron::headers::{HeaderFormat, UserAgent, Header};
+use iron::headers::{HeaderFormat, UserAgent, Quality, Accept, Header};
 use mime_guess::{guess_mime_type_opt, get_mime_type_str};
 use xml::name::{OwnedName as OwnedXmlName, Name as XmlName};
 use std::io::{ErrorKind as IoErrorKind, BufReader, BufRead, Result as IoResult, Error as IoError};
 
 pub use self::os::*;
+pub use self::s3::*;
+pub use self::archive::*;
+pub use self::delta::*;
+pub use self::sharded_map::*;
+pub use self::lru_map::*;
+pub use self::listener::*;
+pub use self::htpasswd::*;
 pub use self::webdav::*;
 pub use self::content_encoding::*;
 
@@ -39,6 +60,9 @@ pub const DIRECTORY_LISTING_HTML: &str = include_str!("../../assets/directory_li
 /// The HTML page to use as template for a requested directory's listing for mobile devices.
 pub const MOBILE_DIRECTORY_LISTING_HTML: &str = include_str!("../../assets/directory_listing_mobile.html");
 
+/// The service worker to use as template for offline bundles.
+pub const OFFLINE_SERVICE_WORKER_JS: &str = include_str!("../../assets/offline_sw.js");
+
 lazy_static! {
     /// Collection of data to be injected into generated responses.
     pub static ref ASSETS: HashMap<&'static str, Cow<'static, str>> = {
@@ -106,6 +130,11 @@ pub const PORT_SCAN_HIGHEST: u16 = 9999;
 /// The app name and version to use with User-Agent or Server response header.
 pub const USER_AGENT: &str = concat!("http/", env!("CARGO_PKG_VERSION"));
 
+/// Version of the format encoded files and cached torrent info are stored in.
+///
+/// Bump when either changes, so ones persisted by other versions are never served.
+pub const CACHE_FORMAT_VERSION: u32 = 1;
+
 /// Index file extensions to look for if `-i` was not specified and strippable extensions to look for if `-x` was specified.
 pub const INDEX_EXTENSIONS: &[&str] = &["html", "htm", "shtml"];
 
@@ -114,6 +143,38 @@ pub const INDEX_EXTENSIONS: &[&str] = &["html", "htm", "shtml"];
 /// `40` matches the linux `MAXSYMLINKS` macro in `include/linux/namei.h`.
 pub const MAX_SYMLINKS: usize = 40;
 
+/// Files bigger than this aren't hashed for raw filesystem API listings, 64MiB.
+pub const MAX_LISTING_HASH_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Hash algorithms raw filesystem API listings can include digests from, as specified in the `hashes` query parameter.
+pub const LISTING_HASH_ALGORITHMS: &[&str] = &["blake3"];
+
+/// First path segment reserved for the admin API, when enabled with `--admin`.
+pub const ADMIN_PREFIX: &str = ".http-admin";
+
+/// Header POSTs can carry the method they stand in for in, when enabled with `--method-override`.
+pub const METHOD_OVERRIDE_HEADER: &str = "X-HTTP-Method-Override";
+
+/// Header raw POST uploads carry the (percent-encoded) name of the file in.
+pub const FILE_NAME_HEADER: &str = "X-File-Name";
+
+/// Header clients can name uploads in, to look their progress up by in the admin API.
+pub const REQUEST_ID_HEADER: &str = "X-Request-Id";
+
+/// What browsers percent-encode in path segments, so generated URLs match the ones they request
+pub const PATH_SEGMENT_ENCODE_SET: &percent_encoding::AsciiSet = &percent_encoding::CONTROLS.add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'/')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'\\')
+    .add(b'`')
+    .add(b'{')
+    .add(b'}');
+
 
 /// The [WWW-Authenticate header](https://tools.ietf.org/html/rfc7235#section-4.1), without parsing.
 ///
@@ -221,6 +282,34 @@ pub fn uppercase_first(s: &str) -> String {
     }
 }
 
+/// %-escape a file name for an URL, like `escape_specials()`, %-escaping each byte past ASCII of ones that aren't UTF-8,
+/// so `percent_decode_non_utf8_path_segment()` gets them back
+///
+/// # Example
+///
+/// ```
+/// # use https::util::escape_file_name;
+/// # use std::ffi::OsStr;
+/// assert_eq!(escape_file_name(OsStr::new("капитал #1.txt")), "капитал %231.txt");
+/// ```
+pub fn escape_file_name(name: &OsStr) -> String {
+    match name.to_str() {
+        Some(name) => escape_specials(name),
+        None => escape_non_utf8(&file_name_bytes(name)),
+    }
+}
+
+fn escape_non_utf8(bytes: &[u8]) -> String {
+    let mut ret = String::with_capacity(bytes.len());
+    for &b in bytes {
+        match b {
+            b'%' | b'#' | b'?' | b'[' | b']' | b'"' | b'&' | b'<' | b'>' | 0x80..=0xFF => write!(ret, "%{:02X}", b).unwrap(),
+            _ => ret.push(b as char),
+        }
+    }
+    ret
+}
+
 /// Percent-encode the last character if it's white space
 ///
 /// Firefox treats, e.g. `href="http://henlo/menlo   "` as `href="http://henlo/menlo"`,
@@ -245,6 +334,65 @@ pub fn encode_tail_if_trimmed(mut s: String) -> String {
     }
 }
 
+/// Translate a shell-style glob into an anchored regex matching slash-separated relative paths.
+///
+/// `*` and `?` don't match slashes, `**` does, `[...]` is a character class.
+///
+/// # Examples
+///
+/// ```
+/// # use https::util::glob_regex;
+/// let rgx = glob_regex("docs/**.{css,js}").unwrap();
+/// assert!(rgx.is_match("docs/assets/main.js"));
+/// assert!(!rgx.is_match("src/main.js"));
+///
+/// assert!(glob_regex("*.html").unwrap().is_match("index.html"));
+/// assert!(!glob_regex("*.html").unwrap().is_match("sub/index.html"));
+/// ```
+pub fn glob_regex(glob: &str) -> Result<Regex, String> {
+    let mut rgx = String::with_capacity(glob.len() * 2 + 2);
+    rgx.push('^');
+
+    let mut chars = glob.chars().peekable();
+    let mut in_alt = false;
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                rgx.push_str(".*");
+            }
+            '*' => rgx.push_str("[^/]*"),
+            '?' => rgx.push_str("[^/]"),
+            '[' => {
+                rgx.push('[');
+                for c in &mut chars {
+                    if c == '\\' {
+                        rgx.push_str("\\\\");
+                    } else {
+                        rgx.push(c);
+                    }
+                    if c == ']' {
+                        break;
+                    }
+                }
+            }
+            '{' if !in_alt => {
+                in_alt = true;
+                rgx.push_str("(?:");
+            }
+            ',' if in_alt => rgx.push('|'),
+            '}' if in_alt => {
+                in_alt = false;
+                rgx.push(')');
+            }
+            c => rgx.push_str(&regex::escape(c.encode_utf8(&mut [0; 4]))),
+        }
+    }
+
+    rgx.push('$');
+    Regex::new(&rgx).map_err(|e| format!("{} not a valid glob: {}", glob, e))
+}
+
 /// %-escape special characters in an URL
 pub fn escape_specials<S: AsRef<str>>(s: S) -> String {
     let s = s.as_ref();
@@ -256,6 +404,11 @@ pub fn escape_specials<S: AsRef<str>>(s: S) -> String {
             b'?' => ret.extend(b"%3F"),
             b'[' => ret.extend(b"%5B"),
             b']' => ret.extend(b"%5D"),
+            // Not special in an URL, but they are in the HTML attributes these end up in
+            b'"' => ret.extend(b"%22"),
+            b'&' => ret.extend(b"%26"),
+            b'<' => ret.extend(b"%3C"),
+            b'>' => ret.extend(b"%3E"),
             _ => ret.push(b),
         }
     }
@@ -301,7 +454,7 @@ pub fn html_response<S: AsRef<str>>(data: &str, format_strings: &[S]) -> String
                        |d, (k, v)| d.replace(&format!("{{{}}}", k), v))
 }
 
-/// Return the path part of the URL.
+/// Return the path part of the URL, with segments that aren't UTF-8 decoded lossily.
 ///
 /// # Example
 ///
@@ -319,12 +472,40 @@ pub fn url_path(url: &Url) -> String {
         "/".to_string()
     } else {
         path.into_iter().fold("".to_string(),
-                              |cur, pp| format!("{}/{}", cur, percent_decode(pp).unwrap_or(Cow::Borrowed("<incorrect UTF8>"))))
+                              |cur, pp| format!("{}/{}", cur, percent_encoding::percent_decode(pp.as_bytes()).decode_utf8_lossy()))
             [1..]
             .to_string()
     }
 }
 
+/// Return the path part of the URL, %-escaped for links like `escape_file_name()`,
+/// so segments that aren't UTF-8 still lead to the same place.
+///
+/// # Example
+///
+/// ```
+/// # extern crate iron;
+/// # extern crate https;
+/// # use iron::Url;
+/// # use https::util::url_path_escaped;
+/// let url = Url::parse("http://127.0.0.1:8000/capitalism/%23%FF/").unwrap();
+/// assert_eq!(url_path_escaped(&url), "capitalism/%23%FF/");
+/// ```
+pub fn url_path_escaped(url: &Url) -> String {
+    let path = url.path();
+    if path == [""] {
+        "/".to_string()
+    } else {
+        path.into_iter()
+            .map(|pp| match String::from_utf8(percent_encoding::percent_decode(pp.as_bytes()).collect()) {
+                Ok(pp) => escape_specials(pp),
+                Err(err) => escape_non_utf8(err.as_bytes()),
+            })
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+}
+
 /// Decode a percent-encoded string (like a part of a URL).
 ///
 /// # Example
@@ -339,6 +520,46 @@ pub fn percent_decode(s: &str) -> Option<Cow<str>> {
     percent_encoding::percent_decode(s.as_bytes()).decode_utf8().ok()
 }
 
+/// Decode a percent-encoded URL path segment into a single filesystem path component.
+///
+/// Fails for what `percent_decode()` does, and for segments that wouldn't name a child when pushed onto a path:
+/// `.` and `..`, and anything with a path separator, drive prefix, or NUL encoded in.
+///
+/// # Example
+///
+/// ```
+/// # use https::util::percent_decode_path_segment;
+/// # use std::borrow::Cow;
+/// assert_eq!(percent_decode_path_segment("%D0%B0%20fdsa"), Some(Cow::Owned("а fdsa".to_string())));
+/// assert_eq!(percent_decode_path_segment("..%2F..%2Fetc"), None);
+/// assert_eq!(percent_decode_path_segment("%2e%2e"), None);
+/// ```
+pub fn percent_decode_path_segment(s: &str) -> Option<Cow<'_, str>> {
+    percent_decode(s).filter(|seg| {
+        seg != "." && seg != ".." && !seg.contains(|c| c == '/' || c == '\0' || (cfg!(windows) && (c == '\\' || c == ':')))
+    })
+}
+
+/// Decode a percent-encoded URL path segment into a file name that isn't UTF-8, as linked to by `escape_file_name()`.
+///
+/// Fails for segments that are UTF-8 once decoded, which are `percent_decode_path_segment()`'s,
+/// for ones with a path separator or NUL encoded in, and where file names can't be arbitrary bytes.
+///
+/// # Example
+///
+/// ```
+/// # use https::util::percent_decode_non_utf8_path_segment;
+/// assert_eq!(percent_decode_non_utf8_path_segment("%D0%B0%20fdsa"), None);
+/// assert_eq!(percent_decode_non_utf8_path_segment("%FF%2F"), None);
+/// ```
+pub fn percent_decode_non_utf8_path_segment(s: &str) -> Option<OsString> {
+    let bytes: Vec<u8> = percent_encoding::percent_decode(s.as_bytes()).collect();
+    if str::from_utf8(&bytes).is_ok() || bytes.contains(&b'/') || bytes.contains(&b'\0') {
+        return None;
+    }
+    file_name_from_bytes(bytes)
+}
+
 /// Get the timestamp of the file's last modification as a `time::Tm` in UTC.
 pub fn file_time_modified_p(f: &Path) -> Tm {
     file_time_modified(&f.metadata().expect("Failed to get file metadata"))
@@ -488,11 +709,131 @@ pub fn human_readable_size(s: u64) -> String {
     }
 }
 
+/// How long to stop encoding files for after running out of space.
+pub const DISK_FULL_ENCODING_PAUSE: StdDuration = StdDuration::from_secs(5 * 60);
+
+/// Check if the specified error means the disk (or the user's quota on it) is full.
+pub fn is_storage_full(err: &IoError) -> bool {
+    err.kind() == IoErrorKind::StorageFull || err.kind() == IoErrorKind::QuotaExceeded
+}
+
+/// How long to wait for remote servers to respond.
+pub const REMOTE_TIMEOUT: StdDuration = StdDuration::from_secs(30);
+
+/// Create an HTTP client for talking to other servers, with HTTPS support if TLS is available.
+///
+/// Reads and writes time out after `REMOTE_TIMEOUT`, so an unresponsive remote can't hang anything forever.
+pub fn http_client() -> Client {
+    let mut client = match NativeTlsClient::new() {
+        Ok(tls) => Client::with_connector(HttpsConnector::new(tls)),
+        Err(_) => Client::new(),
+    };
+    client.set_read_timeout(Some(REMOTE_TIMEOUT));
+    client.set_write_timeout(Some(REMOTE_TIMEOUT));
+    client
+}
+
+/// Find the entry in `dir` whose name is `name` in any Unicode normalisation form.
+///
+/// macOS clients send decomposed (NFD) paths, while most other systems store and send composed (NFC) ones.
+pub fn find_normalised(dir: &Path, name: &str) -> Option<OsString> {
+    let name: String = name.nfc().collect();
+    dir.read_dir().ok()?.flatten().map(|e| e.file_name()).find(|n| n.to_str().map(|n| n.nfc().eq(name.chars())).unwrap_or(false))
+}
+
+/// Check if, given the request headers, the request body is unambiguously delimited,
+/// returning the status and cause to refuse it with if it isn't.
+///
+/// Bodies need exactly one of a single non-negative `Content-Length` or a `Transfer-Encoding` ending in `chunked`,
+/// since intermediaries disagreeing on which one wins is what request smuggling relies on.
+pub fn body_framing_error(hdr: &Headers) -> Option<(status::Status, &'static str)> {
+    let lengths = hdr.get_raw("Content-Length");
+    let encodings = hdr.get_raw("Transfer-Encoding");
+
+    match (lengths, encodings) {
+        (Some(_), Some(_)) => Some((status::BadRequest, "Both Content-Length and Transfer-Encoding specified")),
+        (None, None) => Some((status::LengthRequired, "Neither Content-Length nor Transfer-Encoding specified")),
+        (Some(lengths), None) => {
+            let mut values = lengths.iter().flat_map(|l| l.split(|&b| b == b',')).map(|l| str::from_utf8(l).unwrap_or("").trim());
+            let first = values.next().unwrap_or("");
+            if first.is_empty() || !first.bytes().all(|b| b.is_ascii_digit()) || first.parse::<i64>().is_err() {
+                Some((status::BadRequest, "Content-Length not a valid length"))
+            } else if values.any(|l| l != first) {
+                Some((status::BadRequest, "Conflicting Content-Lengths specified"))
+            } else {
+                None
+            }
+        }
+        (None, Some(encodings)) => {
+            let last = encodings.iter().flat_map(|e| e.split(|&b| b == b',')).map(|e| str::from_utf8(e).unwrap_or("").trim()).last().unwrap_or("");
+            if last.eq_ignore_ascii_case("chunked") {
+                None
+            } else {
+                Some((status::BadRequest, "Transfer-Encoding not ending with chunked"))
+            }
+        }
+    }
+}
+
 /// Check if, given the request headers, the client should be considered a mobile device.
 pub fn client_mobile(hdr: &Headers) -> bool {
     hdr.get::<UserAgent>().map(|s| s.contains("Mobi") || s.contains("mobi")).unwrap_or(false)
 }
 
+/// Get the request's `X-Request-Id`, if it has exactly one of up to 128 visible ASCII characters.
+pub fn request_id(hdr: &Headers) -> Option<String> {
+    hdr.get_raw(REQUEST_ID_HEADER)
+        .filter(|vals| vals.len() == 1)
+        .map(|vals| &vals[0][..])
+        .filter(|id| !id.is_empty() && id.len() <= 128 && id.iter().all(|b| b.is_ascii_graphic()))
+        .map(|id| String::from_utf8_lossy(id).into_owned())
+}
+
+/// Check if, given the request headers, the client prefers `application/json` over `text/html`.
+///
+/// Each is weighed by the most specific `Accept` entry matching it; without an `Accept` header, HTML wins.
+pub fn client_prefers_json(hdr: &Headers) -> bool {
+    let accept = match hdr.get::<Accept>() {
+        Some(accept) => accept,
+        None => return false,
+    };
+
+    let quality = |tp: &str, sub: &str| {
+        let mut best = (0, Quality(0));
+        for qi in accept.iter() {
+            let Mime(ref qtp, ref qsub, _) = qi.item;
+            let specificity = match (qtp.as_str(), qsub.as_str()) {
+                (t, s) if t == tp && s == sub => 3,
+                (t, "*") if t == tp => 2,
+                ("*", "*") => 1,
+                _ => 0,
+            };
+            if specificity > best.0 {
+                best = (specificity, qi.quality);
+            }
+        }
+        best.1
+    };
+
+    let json = quality("application", "json");
+    json > Quality(0) && json > quality("text", "html")
+}
+
+/// Strip the tags from, and unescape the entities in, the HTML snippet
+pub fn html_to_text(s: &str) -> String {
+    let mut ret = String::with_capacity(s.len());
+    let mut in_tag = false;
+    for c in s.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' if in_tag => in_tag = false,
+            c if !in_tag => ret.push(c),
+            _ => {}
+        }
+    }
+    ret.replace("&lt;", "<").replace("&gt;", ">").replace("&quot;", "\"").replace("&#39;", "'").replace("&amp;", "&")
+}
+
 /// Check if, given the request headers, the client should be treated as Microsoft software.
 ///
 /// Based on https://github.com/miquels/webdav-handler-rs/blob/02433c1acfccd848a7de26889f6857cbad559076/src/handle_props.rs#L529
@@ -519,28 +860,45 @@ pub fn file_icon_suffix<P: AsRef<Path>>(f: P, is_file: bool) -> &'static str {
 /// Get the metadata of the specified file.
 ///
 /// The specified path must point to a file.
-pub fn get_raw_fs_metadata<P: AsRef<Path>>(f: P) -> RawFileData {
+pub fn get_raw_fs_metadata<P: AsRef<Path>>(f: P) -> IoResult<RawFileData> {
     get_raw_fs_metadata_impl(f.as_ref())
 }
 
-fn get_raw_fs_metadata_impl(f: &Path) -> RawFileData {
-    let meta = f.metadata().expect("Failed to get requested file metadata");
-    RawFileData {
+fn get_raw_fs_metadata_impl(f: &Path) -> IoResult<RawFileData> {
+    let meta = f.metadata()?;
+    Ok(RawFileData {
         mime_type: guess_mime_type_opt(f).unwrap_or_else(|| if file_binary(f) {
             "application/octet-stream".parse().unwrap()
         } else {
             "text/plain".parse().unwrap()
         }),
-        name: f.file_name().unwrap().to_str().expect("Failed to get requested file name").to_string(),
+        name: f.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default(),
         last_modified: file_time_modified(&meta),
         size: file_length(&meta, &f),
         is_file: true,
-    }
+    })
 }
 
 /// Recursively copy a directory
 ///
 /// Stolen from https://github.com/mdunsmuir/copy_dir/blob/0.1.2/src/lib.rs
+/// Copy the extended attributes of the specified file, or of everything in the specified directory, onto its copy
+///
+/// Best-effort, since not all filesystems support them.
+pub fn copy_xattrs(from: &Path, to: &Path) {
+    for entry in WalkDir::new(from).into_iter().flatten() {
+        let subp = entry.path().strip_prefix(from).expect("Walked outside copied directory");
+        let dest = if subp.as_os_str().is_empty() {
+            to.to_path_buf()
+        } else {
+            to.join(subp)
+        };
+        for (name, val) in file_xattrs(entry.path()) {
+            let _ = set_file_xattr(&dest, &name, Some(&val));
+        }
+    }
+}
+
 pub fn copy_dir(from: &Path, to: &Path) -> IoResult<Vec<(IoError, String)>> {
     macro_rules! push_error {
         ($vec:ident, $path:ident, $expr:expr) => {
@@ -1,7 +1,10 @@
 use self::super::super::is_actually_file;
 use os_str_generic::OsStrGenericExt;
+use std::os::unix::fs::{PermissionsExt, MetadataExt};
+use std::os::unix::ffi::{OsStringExt, OsStrExt};
+use std::path::{PathBuf, Path};
+use std::ffi::{OsString, OsStr};
 use std::fs::Metadata;
-use std::path::Path;
 
 
 const FILE_ATTRIBUTE_READONLY: u32 = 0x01;
@@ -35,3 +38,43 @@ pub fn win32_file_attributes(meta: &Metadata, path: &Path) -> u32 {
 
     attr
 }
+
+
+/// Convert an absolute path into a form not limited by `MAX_PATH`, which all paths already are here
+#[inline(always)]
+pub fn verbatim_path(p: PathBuf) -> PathBuf {
+    p
+}
+
+/// Push a relative path onto `to`; the OS resolves `.` and `..` physically
+#[inline(always)]
+pub fn push_relative(to: &mut PathBuf, rel: &Path) {
+    to.push(rel)
+}
+
+/// Get the `ls -l`-style permissions of the specified file, like `rwxr-xr-x`
+pub fn file_permissions(meta: &Metadata) -> String {
+    let mode = meta.permissions().mode();
+    "rwxrwxrwx".chars().enumerate().map(|(i, c)| if mode & (0o400 >> i) != 0 { c } else { '-' }).collect()
+}
+
+/// Get the owner of the specified file as `uid:gid`
+pub fn file_owner(meta: &Metadata) -> Option<String> {
+    Some(format!("{}:{}", meta.uid(), meta.gid()))
+}
+
+/// Get the raw bytes of the specified file name, which needn't be UTF-8
+pub fn file_name_bytes(name: &OsStr) -> Vec<u8> {
+    name.as_bytes().to_vec()
+}
+
+/// Make a file name of the specified raw bytes, which needn't be UTF-8
+pub fn file_name_from_bytes(bytes: Vec<u8>) -> Option<OsString> {
+    Some(OsString::from_vec(bytes))
+}
+
+/// Get the console ready for coloured UTF-8 output, which terminals here already are
+#[inline(always)]
+pub fn prepare_console() -> bool {
+    true
+}
@@ -1,8 +1,16 @@
 use std::os::unix::fs::{PermissionsExt, FileTypeExt};
-use libc::{O_RDONLY, c_ulong, close, ioctl, open};
+use libc::{O_RDONLY, c_ulong, close, ioctl, open, statvfs};
+#[cfg(target_os = "linux")]
+use libc::{AT_FDCWD, ENODATA, RENAME_EXCHANGE, c_char, c_void, getxattr, listxattr, removexattr, renameat2, setxattr};
+#[cfg(not(target_os = "linux"))]
+use std::io::ErrorKind as IoErrorKind;
+use std::io::{Result as IoResult, Error as IoError};
 use std::os::unix::ffi::OsStrExt;
 use std::fs::{FileType, Metadata};
 use std::ffi::CString;
+#[cfg(target_os = "linux")]
+use std::ptr;
+use std::mem;
 use std::path::Path;
 
 
@@ -52,3 +60,101 @@ fn file_length_impl(meta: &Metadata, path: &Path) -> u64 {
 pub fn file_executable(meta: &Metadata) -> bool {
     (meta.permissions().mode() & (S_IXUSR | S_IXGRP | S_IXOTH)) != 0
 }
+
+/// Atomically exchange the two specified paths, returning `false` if that's unsupported or failed
+#[cfg(target_os = "linux")]
+pub fn exchange_paths(lhs: &Path, rhs: &Path) -> bool {
+    let lhs_c = CString::new(lhs.as_os_str().as_bytes()).unwrap();
+    let rhs_c = CString::new(rhs.as_os_str().as_bytes()).unwrap();
+    unsafe { renameat2(AT_FDCWD, lhs_c.as_ptr(), AT_FDCWD, rhs_c.as_ptr(), RENAME_EXCHANGE) == 0 }
+}
+
+/// Atomically exchange the two specified paths, returning `false` if that's unsupported or failed
+#[cfg(not(target_os = "linux"))]
+#[inline(always)]
+pub fn exchange_paths(_: &Path, _: &Path) -> bool {
+    false
+}
+
+/// Get how many bytes can still be written to the filesystem containing the specified path, if known
+pub fn available_space(p: &Path) -> Option<u64> {
+    let p_c = CString::new(p.as_os_str().as_bytes()).ok()?;
+    let mut stat: statvfs = unsafe { mem::zeroed() };
+    if unsafe { statvfs(p_c.as_ptr(), &mut stat) } == 0 {
+        Some(stat.f_bavail as u64 * stat.f_frsize as u64)
+    } else {
+        None
+    }
+}
+
+/// Get the user extended attributes of the specified file, without the `user.` prefix
+#[cfg(target_os = "linux")]
+pub fn file_xattrs(p: &Path) -> Vec<(String, Vec<u8>)> {
+    let p_c = match CString::new(p.as_os_str().as_bytes()) {
+        Ok(p_c) => p_c,
+        Err(_) => return vec![],
+    };
+
+    let names_len = unsafe { listxattr(p_c.as_ptr(), ptr::null_mut(), 0) };
+    if names_len <= 0 {
+        return vec![];
+    }
+    let mut names = vec![0u8; names_len as usize];
+    let names_len = unsafe { listxattr(p_c.as_ptr(), names.as_mut_ptr() as *mut c_char, names.len()) };
+    if names_len < 0 {
+        return vec![];
+    }
+    names.truncate(names_len as usize);
+
+    names.split(|&b| b == 0)
+        .filter(|n| n.starts_with(b"user."))
+        .flat_map(|n| {
+            let name_c = CString::new(n).ok()?;
+            let val_len = unsafe { getxattr(p_c.as_ptr(), name_c.as_ptr(), ptr::null_mut(), 0) };
+            if val_len < 0 {
+                return None;
+            }
+            let mut val = vec![0u8; val_len as usize];
+            let val_len = unsafe { getxattr(p_c.as_ptr(), name_c.as_ptr(), val.as_mut_ptr() as *mut c_void, val.len()) };
+            if val_len < 0 {
+                return None;
+            }
+            val.truncate(val_len as usize);
+            Some((String::from_utf8_lossy(&n[5..]).into_owned(), val))
+        })
+        .collect()
+}
+
+/// Get the user extended attributes of the specified file, without the `user.` prefix
+#[cfg(not(target_os = "linux"))]
+#[inline(always)]
+pub fn file_xattrs(_: &Path) -> Vec<(String, Vec<u8>)> {
+    vec![]
+}
+
+/// Set, or remove if `None`, the specified user extended attribute (without the `user.` prefix) of the specified file
+///
+/// Removing a nonexistent attribute succeeds.
+#[cfg(target_os = "linux")]
+pub fn set_file_xattr(p: &Path, name: &str, value: Option<&[u8]>) -> IoResult<()> {
+    let p_c = CString::new(p.as_os_str().as_bytes())?;
+    let name_c = CString::new(format!("user.{}", name))?;
+    let ret = match value {
+        Some(val) => unsafe { setxattr(p_c.as_ptr(), name_c.as_ptr(), val.as_ptr() as *const c_void, val.len(), 0) },
+        None => unsafe { removexattr(p_c.as_ptr(), name_c.as_ptr()) },
+    };
+
+    let err = IoError::last_os_error();
+    if ret == 0 || (value.is_none() && err.raw_os_error() == Some(ENODATA)) {
+        Ok(())
+    } else {
+        Err(err)
+    }
+}
+
+/// Set, or remove if `None`, the specified user extended attribute (without the `user.` prefix) of the specified file
+#[cfg(not(target_os = "linux"))]
+#[inline(always)]
+pub fn set_file_xattr(_: &Path, _: &str, _: Option<&[u8]>) -> IoResult<()> {
+    Err(IoError::new(IoErrorKind::Unsupported, "extended attributes unsupported"))
+}
@@ -1,7 +1,27 @@
-use winapi::um::fileapi::GetFileAttributesW;
-use std::os::windows::ffi::OsStrExt;
-use std::fs::Metadata;
-use std::path::Path;
+use winapi::um::fileapi::{FindFirstStreamW, FindNextStreamW, FindClose, GetDiskFreeSpaceExW, GetFileAttributesW, FindStreamInfoStandard};
+use winapi::um::handleapi::INVALID_HANDLE_VALUE;
+use winapi::um::consoleapi::{GetConsoleMode, SetConsoleMode};
+use winapi::um::wincon::{ENABLE_VIRTUAL_TERMINAL_PROCESSING, SetConsoleOutputCP};
+use winapi::um::processenv::GetStdHandle;
+use winapi::um::winbase::STD_OUTPUT_HANDLE;
+use winapi::um::winnls::CP_UTF8;
+use winapi::um::winnt::ULARGE_INTEGER;
+use winapi::shared::minwindef::{LPVOID, MAX_PATH};
+use std::io::{ErrorKind as IoErrorKind, Result as IoResult};
+use std::os::windows::ffi::{OsStringExt, OsStrExt};
+use std::path::{Component as PathComponent, Prefix as PathPrefix, PathBuf, Path};
+use std::ffi::{OsString, OsStr};
+use std::{mem, ptr};
+use std::fs::{self, Metadata};
+
+
+/// [`WIN32_FIND_STREAM_DATA`](https://docs.microsoft.com/en-gb/windows/win32/api/fileapi/ns-fileapi-win32_find_stream_data),
+/// which winapi doesn't have
+#[repr(C)]
+struct Win32FindStreamData {
+    _stream_size: i64,
+    stream_name: [u16; MAX_PATH + 36],
+}
 
 
 /// Get windows-style attributes for the specified file
@@ -13,3 +33,140 @@ pub fn win32_file_attributes(_: &Metadata, path: &Path) -> u32 {
 
     unsafe { GetFileAttributesW(buf.as_ptr()) }
 }
+
+
+/// Convert an absolute path into its verbatim (`\\?\`-prefixed) form, which isn't limited to `MAX_PATH` characters
+///
+/// UNC paths (`\\server\share`) become `\\?\UNC\server\share`.
+pub fn verbatim_path(p: PathBuf) -> PathBuf {
+    let skip = match p.components().next() {
+        Some(PathComponent::Prefix(pc)) => {
+            match pc.kind() {
+                PathPrefix::Disk(_) => 0,
+                PathPrefix::UNC(..) => 2,
+                _ => return p, // Already verbatim, or a device path
+            }
+        }
+        _ => return p,
+    };
+
+    let mut ret: Vec<u16> = if skip == 0 { "\\\\?\\" } else { "\\\\?\\UNC\\" }.encode_utf16().collect();
+    ret.extend(p.as_os_str().encode_wide().skip(skip));
+    OsString::from_wide(&ret).into()
+}
+
+/// Push a relative path onto `to`, resolving `.` and `..` lexically,
+/// since verbatim paths are passed to the OS as-is and so can't contain them
+pub fn push_relative(to: &mut PathBuf, rel: &Path) {
+    for c in rel.components() {
+        match c {
+            PathComponent::ParentDir => {
+                to.pop();
+            }
+            PathComponent::CurDir => {}
+            c => to.push(c),
+        }
+    }
+}
+
+/// Get the permissions of the specified file, which here only mark whether it's read-only
+pub fn file_permissions(meta: &Metadata) -> String {
+    if meta.permissions().readonly() { "read-only" } else { "" }.to_string()
+}
+
+/// Get the owner of the specified file, which isn't exposed here
+#[inline(always)]
+pub fn file_owner(_: &Metadata) -> Option<String> {
+    None
+}
+
+/// Get how many bytes can still be written to the filesystem containing the specified path, if known
+///
+/// This takes the user's quota into account.
+pub fn available_space(p: &Path) -> Option<u64> {
+    let p_w: Vec<u16> = p.as_os_str().encode_wide().chain(Some(0)).collect();
+    let mut available: ULARGE_INTEGER = unsafe { mem::zeroed() };
+    if unsafe { GetDiskFreeSpaceExW(p_w.as_ptr(), &mut available, ptr::null_mut(), ptr::null_mut()) } != 0 {
+        Some(unsafe { *available.QuadPart() })
+    } else {
+        None
+    }
+}
+
+/// Get the named alternate data streams of the specified file, which are what Windows has for extended attributes
+pub fn file_xattrs(p: &Path) -> Vec<(String, Vec<u8>)> {
+    let p_w: Vec<u16> = p.as_os_str().encode_wide().chain(Some(0)).collect();
+    let mut data: Win32FindStreamData = unsafe { mem::zeroed() };
+    let find = unsafe { FindFirstStreamW(p_w.as_ptr(), FindStreamInfoStandard, &mut data as *mut _ as LPVOID, 0) };
+    if find == INVALID_HANDLE_VALUE {
+        return vec![];
+    }
+
+    let mut ret = vec![];
+    loop {
+        // Named streams are ":NAME:$DATA", the unnamed main one is "::$DATA"
+        let name = String::from_utf16_lossy(&data.stream_name[..data.stream_name.iter().position(|&c| c == 0).unwrap_or(data.stream_name.len())]);
+        if let Some(name) = name.strip_prefix(':').and_then(|n| n.strip_suffix(":$DATA")).filter(|n| !n.is_empty()) {
+            if let Ok(val) = fs::read(stream_path(p, name)) {
+                ret.push((name.to_string(), val));
+            }
+        }
+
+        if unsafe { FindNextStreamW(find, &mut data as *mut _ as LPVOID) } == 0 {
+            break;
+        }
+    }
+    unsafe { FindClose(find) };
+
+    ret
+}
+
+/// Set, or remove if `None`, the specified named alternate data stream of the specified file
+///
+/// Removing a nonexistent stream succeeds.
+pub fn set_file_xattr(p: &Path, name: &str, value: Option<&[u8]>) -> IoResult<()> {
+    match value {
+        Some(val) => fs::write(stream_path(p, name), val),
+        None => {
+            match fs::remove_file(stream_path(p, name)) {
+                Err(e) if e.kind() != IoErrorKind::NotFound => Err(e),
+                _ => Ok(()),
+            }
+        }
+    }
+}
+
+fn stream_path(p: &Path, name: &str) -> PathBuf {
+    let mut stream_p = p.as_os_str().to_os_string();
+    stream_p.push(":");
+    stream_p.push(name);
+    stream_p.into()
+}
+
+
+/// Get the bytes of the specified file name, which is UTF-16, and so lossily converted to UTF-8 if it's not valid
+pub fn file_name_bytes(name: &OsStr) -> Vec<u8> {
+    name.to_string_lossy().into_owned().into_bytes()
+}
+
+/// File names are UTF-16, so ones that aren't UTF-8 can't be made of bytes
+pub fn file_name_from_bytes(_: Vec<u8>) -> Option<OsString> {
+    None
+}
+
+/// Get the console ready for UTF-8 and escape-sequence-coloured output, returning whether colours will render
+///
+/// Consoles older than Windows 10 can't interpret escape sequences, so logs should be uncoloured there;
+/// output that isn't to a console is left alone, like on other platforms.
+pub fn prepare_console() -> bool {
+    unsafe {
+        SetConsoleOutputCP(CP_UTF8);
+
+        let out = GetStdHandle(STD_OUTPUT_HANDLE);
+        let mut mode = 0;
+        if out.is_null() || out == INVALID_HANDLE_VALUE || GetConsoleMode(out, &mut mode) == 0 {
+            return true;
+        }
+        mode & ENABLE_VIRTUAL_TERMINAL_PROCESSING != 0 || SetConsoleMode(out, mode | ENABLE_VIRTUAL_TERMINAL_PROCESSING) != 0
+    }
+}
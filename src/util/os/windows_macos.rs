@@ -1,13 +1,24 @@
+#[cfg(target_os = "macos")]
+use std::io::{ErrorKind as IoErrorKind, Result as IoResult, Error as IoError};
+#[cfg(target_os = "macos")]
+use std::os::unix::fs::FileTypeExt;
 use std::fs::{FileType, Metadata};
 use std::path::Path;
 
 
 /// OS-specific check for fileness
+#[cfg(target_os = "windows")]
 #[inline(always)]
 pub fn is_device(_: &FileType) -> bool {
     false
 }
 
+/// OS-specific check for fileness
+#[cfg(target_os = "macos")]
+pub fn is_device(tp: &FileType) -> bool {
+    tp.is_block_device() || tp.is_char_device() || tp.is_fifo() || tp.is_socket()
+}
+
 /// Check file length responsibly
 #[inline(always)]
 pub fn file_length<P: AsRef<Path>>(meta: &Metadata, _: &P) -> u64 {
@@ -19,3 +30,30 @@ pub fn file_length<P: AsRef<Path>>(meta: &Metadata, _: &P) -> u64 {
 pub fn file_executable(_: &Metadata) -> bool {
     true
 }
+
+/// Atomically exchange the two specified paths, returning `false` if that's unsupported or failed
+#[inline(always)]
+pub fn exchange_paths(_: &Path, _: &Path) -> bool {
+    false
+}
+
+/// Get how many bytes can still be written to the filesystem containing the specified path, if known
+#[cfg(target_os = "macos")]
+#[inline(always)]
+pub fn available_space(_: &Path) -> Option<u64> {
+    None
+}
+
+/// Get the user extended attributes of the specified file, without the `user.` prefix
+#[cfg(target_os = "macos")]
+#[inline(always)]
+pub fn file_xattrs(_: &Path) -> Vec<(String, Vec<u8>)> {
+    vec![]
+}
+
+/// Set, or remove if `None`, the specified user extended attribute (without the `user.` prefix) of the specified file
+#[cfg(target_os = "macos")]
+#[inline(always)]
+pub fn set_file_xattr(_: &Path, _: &str, _: Option<&[u8]>) -> IoResult<()> {
+    Err(IoError::new(IoErrorKind::Unsupported, "extended attributes unsupported"))
+}
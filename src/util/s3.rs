@@ -0,0 +1,32 @@
+use std::time::UNIX_EPOCH;
+use std::fs::Metadata;
+use iron::Headers;
+use blake3;
+
+
+/// Namespace URI of all S3 XML responses
+pub const S3_XML_NAMESPACE: &str = "http://s3.amazonaws.com/doc/2006-03-01/";
+
+/// Default and maximal amount of keys returned in one S3 object listing
+pub const S3_MAX_KEYS: usize = 1000;
+
+
+/// Check if, given the request headers and query, the request should be treated as coming from an S3 client.
+///
+/// S3 clients sign all of their requests with `x-amz-*` headers, and listings are requested with a `list-type` query.
+pub fn s3_request(hdr: &Headers, query: Option<&str>) -> bool {
+    hdr.iter().any(|h| h.name().len() > 6 && h.name()[..6].eq_ignore_ascii_case("x-amz-")) ||
+    query.map(|q| q.split('&').any(|kv| kv.starts_with("list-type="))).unwrap_or(false)
+}
+
+/// Get an S3-style ETag for the specified file, derived from its size and modification time.
+///
+/// This is *not* the MD5 of the content, since that'd require reading each file in full on every listing.
+pub fn s3_etag(meta: &Metadata) -> String {
+    let mtime = meta.modified().ok().and_then(|t| t.duration_since(UNIX_EPOCH).ok()).map(|d| d.as_nanos()).unwrap_or(0);
+
+    let mut ctx = blake3::Hasher::new();
+    ctx.update(&meta.len().to_le_bytes());
+    ctx.update(&mtime.to_le_bytes());
+    ctx.finalize().to_hex()[..32].to_string()
+}
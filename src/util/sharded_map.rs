@@ -0,0 +1,79 @@
+use std::collections::hash_map::{DefaultHasher, HashMap};
+use std::hash::{Hasher, Hash};
+use std::borrow::Borrow;
+use std::sync::{PoisonError, RwLock};
+
+
+/// How many independently-locked parts a `ShardedMap` is split into
+const SHARDS: usize = 16;
+
+
+/// A `HashMap` split into independently-locked shards by key, so that accesses to unrelated keys don't contend on one lock.
+///
+/// Values are returned by copy, so no lock is ever held by the caller, in particular across disk I/O.
+#[derive(Debug)]
+pub struct ShardedMap<K, V> {
+    shards: Vec<RwLock<HashMap<K, V>>>,
+}
+
+impl<K: Hash + Eq, V> ShardedMap<K, V> {
+    pub fn get<Q>(&self, key: &Q) -> Option<V>
+        where K: Borrow<Q>,
+              Q: Hash + Eq + ?Sized,
+              V: Clone
+    {
+        self.shard(key).read().unwrap_or_else(PoisonError::into_inner).get(key).cloned()
+    }
+
+    pub fn insert(&self, key: K, value: V) -> Option<V> {
+        self.shard(&key).write().unwrap_or_else(PoisonError::into_inner).insert(key, value)
+    }
+
+    pub fn remove<Q>(&self, key: &Q) -> Option<V>
+        where K: Borrow<Q>,
+              Q: Hash + Eq + ?Sized
+    {
+        self.shard(key).write().unwrap_or_else(PoisonError::into_inner).remove(key)
+    }
+
+    /// Keep only the entries `f` returns `true` for, shard by shard
+    pub fn retain<F: FnMut(&K, &mut V) -> bool>(&self, mut f: F) {
+        for s in &self.shards {
+            s.write().unwrap_or_else(PoisonError::into_inner).retain(&mut f);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.shards.iter().map(|s| s.read().unwrap_or_else(PoisonError::into_inner).len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn clear(&self) {
+        for s in &self.shards {
+            s.write().unwrap_or_else(PoisonError::into_inner).clear();
+        }
+    }
+
+    /// Empty the shards a panic poisoned, since their entries may be half-updated, and unpoison them
+    pub fn recover(&self) {
+        for s in self.shards.iter().filter(|s| s.is_poisoned()) {
+            s.write().unwrap_or_else(PoisonError::into_inner).clear();
+            s.clear_poison();
+        }
+    }
+
+    fn shard<Q: Hash + ?Sized>(&self, key: &Q) -> &RwLock<HashMap<K, V>> {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        &self.shards[hasher.finish() as usize % SHARDS]
+    }
+}
+
+impl<K: Hash + Eq, V> Default for ShardedMap<K, V> {
+    fn default() -> ShardedMap<K, V> {
+        ShardedMap { shards: (0..SHARDS).map(|_| Default::default()).collect() }
+    }
+}
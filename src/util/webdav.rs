@@ -18,9 +18,18 @@ macro_rules! xml_name {
 
 
 lazy_static! {
-    /// HTTP methods we support for WebDAV level 1, as specified in https://tools.ietf.org/html/rfc2518, without locks
-    pub static ref DAV_LEVEL_1_METHODS: Vec<method::Method> =
-        ["COPY", "MKCOL", "MOVE", "PROPFIND", "PROPPATCH"].iter().map(|m| method::Extension(m.to_string())).collect();
+    /// HTTP methods we support for WebDAV level 2, as specified in https://tools.ietf.org/html/rfc2518,
+    /// and for collection synchronisation, as specified in https://tools.ietf.org/html/rfc6578
+    pub static ref DAV_LEVEL_2_METHODS: Vec<method::Method> =
+        ["COPY", "LOCK", "MKCOL", "MOVE", "PROPFIND", "PROPPATCH", "REPORT", "UNLOCK"].iter().map(|m| method::Extension(m.to_string())).collect();
+
+    /// Other HTTP methods from the WebDAV family of RFCs (versioning, ACLs, bindings, search, CalDAV), which we recognise but don't support
+    pub static ref DAV_UNSUPPORTED_METHODS: Vec<method::Method> =
+        ["VERSION-CONTROL", "CHECKIN", "CHECKOUT", "UNCHECKOUT", "MKWORKSPACE", "UPDATE", "LABEL", "MERGE", "BASELINE-CONTROL",
+         "MKACTIVITY", "ACL", "BIND", "UNBIND", "REBIND", "ORDERPATCH", "SEARCH", "MKCALENDAR"]
+            .iter()
+            .map(|m| method::Extension(m.to_string()))
+            .collect();
 }
 
 /// Prefix and namespace URI for generic WebDAV elements
@@ -32,10 +41,14 @@ pub const WEBDAV_XML_NAMESPACE_MICROSOFT: (&str, &str) = ("Z", "urn:schemas-micr
 /// Prefix and namespace URI for elements for Apache emulation
 pub const WEBDAV_XML_NAMESPACE_APACHE: (&str, &str) = ("A", "http://apache.org/dav/props/");
 
+/// Prefix and namespace URI for properties mapping to files' extended attributes, with `--xattrs`
+pub const WEBDAV_XML_NAMESPACE_XATTR: (&str, &str) = ("X", "urn:xattr:");
+
 /// All first-class-recognised prefix/namespace pairs
 ///
 /// `WEBDAV_XML_NAMESPACE_DAV` needs to be the first here
-pub const WEBDAV_XML_NAMESPACES: &[&(&str, &str)] = &[&WEBDAV_XML_NAMESPACE_DAV, &WEBDAV_XML_NAMESPACE_MICROSOFT, &WEBDAV_XML_NAMESPACE_APACHE];
+pub const WEBDAV_XML_NAMESPACES: &[&(&str, &str)] =
+    &[&WEBDAV_XML_NAMESPACE_DAV, &WEBDAV_XML_NAMESPACE_MICROSOFT, &WEBDAV_XML_NAMESPACE_APACHE, &WEBDAV_XML_NAMESPACE_XATTR];
 
 /// Properties to return on empty body or [`<allprop />`](https://tools.ietf.org/html/rfc2518#section-12.14.1)
 /// for non-Windows clients
@@ -62,7 +75,9 @@ pub const WEBDAV_ALLPROP_PROPERTIES_WINDOWS: &[&[XmlName]] = &[&WEBDAV_ALLPROP_P
 /// Based on https://github.com/miquels/webdav-handler-rs/blob/02433c1acfccd848a7de26889f6857cbad559076/src/handle_props.rs#L34
 pub const WEBDAV_PROPNAME_PROPERTIES: &[&[XmlName]] = &[&WEBDAV_ALLPROP_PROPERTIES_NON_WINDOWS[0],
                                                         &[xml_name!(WEBDAV_XML_NAMESPACE_APACHE, "executable"),
-                                                          xml_name!(WEBDAV_XML_NAMESPACE_MICROSOFT, "Win32LastAccessTime")]];
+                                                          xml_name!(WEBDAV_XML_NAMESPACE_MICROSOFT, "Win32LastAccessTime"),
+                                                          xml_name!(WEBDAV_XML_NAMESPACE_DAV, "lockdiscovery"),
+                                                          xml_name!(WEBDAV_XML_NAMESPACE_DAV, "supportedlock")]];
 
 
 
@@ -73,7 +88,7 @@ pub const WEBDAV_PROPNAME_PROPERTIES: &[&[XmlName]] = &[&WEBDAV_ALLPROP_PROPERTI
 pub struct Dav(pub &'static [&'static str]);
 
 impl Dav {
-    pub const LEVEL_1: Dav = Dav(&["1"]);
+    pub const LEVEL_2: Dav = Dav(&["1", "2"]);
 }
 
 impl Header for Dav {
@@ -230,3 +245,116 @@ impl Default for Overwrite {
         Overwrite(true)
     }
 }
+
+/// The [Lock-Token header](https://tools.ietf.org/html/rfc4918#section-10.5), without the angle brackets.
+#[derive(Debug, Clone, Hash, PartialOrd, Ord, PartialEq, Eq)]
+pub struct LockToken(pub String);
+
+impl Header for LockToken {
+    fn header_name() -> &'static str {
+        "Lock-Token"
+    }
+
+    fn parse_header(raw: &[Vec<u8>]) -> HyperResult<LockToken> {
+        if raw.len() != 1 {
+            return Err(HyperError::Header);
+        }
+
+        let val = str::from_utf8(&unsafe { raw.get_unchecked(0) }).map_err(|_| HyperError::Header)?.trim();
+        if val.len() > 2 && val.starts_with('<') && val.ends_with('>') {
+            Ok(LockToken(val[1..val.len() - 1].to_string()))
+        } else {
+            Err(HyperError::Header)
+        }
+    }
+}
+
+impl HeaderFormat for LockToken {
+    fn fmt_header(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<{}>", self.0)
+    }
+}
+
+impl fmt::Display for LockToken {
+    #[inline(always)]
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.fmt_header(f)
+    }
+}
+
+/// The lock tokens submitted in an [If header](https://tools.ietf.org/html/rfc4918#section-10.4).
+///
+/// Only the `(<token>)` state tokens are kept; resource tags, `Not`, and entity tags aren't evaluated,
+/// so this only serves to prove the client knows the lock.
+#[derive(Debug, Clone, Hash, PartialOrd, Ord, PartialEq, Eq)]
+pub struct IfLockTokens(pub Vec<String>);
+
+impl Header for IfLockTokens {
+    fn header_name() -> &'static str {
+        "If"
+    }
+
+    fn parse_header(raw: &[Vec<u8>]) -> HyperResult<IfLockTokens> {
+        let mut tokens = vec![];
+        for val in raw {
+            let val = str::from_utf8(val).map_err(|_| HyperError::Header)?;
+            for list in val.split('(').skip(1) {
+                let list = list.split(')').next().unwrap_or("");
+                tokens.extend(list.split('<').skip(1).filter_map(|t| t.split('>').next()).filter(|t| !t.is_empty()).map(str::to_string));
+            }
+        }
+        Ok(IfLockTokens(tokens))
+    }
+}
+
+impl HeaderFormat for IfLockTokens {
+    fn fmt_header(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, token) in self.0.iter().enumerate() {
+            if i != 0 {
+                f.write_str(" ")?;
+            }
+            write!(f, "(<{}>)", token)?;
+        }
+        Ok(())
+    }
+}
+
+/// The [Timeout header](https://tools.ietf.org/html/rfc4918#section-10.7), as the first timeout requested,
+/// in seconds, or `None` for `Infinite`.
+#[derive(Debug, Copy, Clone, Hash, PartialOrd, Ord, PartialEq, Eq)]
+pub struct Timeout(pub Option<u64>);
+
+impl Header for Timeout {
+    fn header_name() -> &'static str {
+        "Timeout"
+    }
+
+    fn parse_header(raw: &[Vec<u8>]) -> HyperResult<Timeout> {
+        if raw.len() != 1 {
+            return Err(HyperError::Header);
+        }
+
+        let val = str::from_utf8(&unsafe { raw.get_unchecked(0) }).map_err(|_| HyperError::Header)?;
+        match val.split(',').next().map(str::trim) {
+            Some("Infinite") => Ok(Timeout(None)),
+            Some(secs) if secs.starts_with("Second-") => secs["Second-".len()..].parse().map(|s| Timeout(Some(s))).map_err(|_| HyperError::Header),
+            _ => Err(HyperError::Header),
+        }
+    }
+}
+
+impl HeaderFormat for Timeout {
+    fn fmt_header(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            Some(secs) => write!(f, "Second-{}", secs),
+            None => f.write_str("Infinite"),
+        }
+    }
+}
+
+impl fmt::Display for Timeout {
+    #[inline(always)]
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.fmt_header(f)
+    }
+}
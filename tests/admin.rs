@@ -0,0 +1,219 @@
+#[macro_use]
+extern crate serde_json;
+extern crate flate2;
+
+mod util;
+
+use self::util::Server;
+use std::io::{Write, Read};
+use std::time::Duration;
+use std::net::TcpStream;
+use std::path::PathBuf;
+use serde_json::Value;
+use std::thread;
+use std::fs;
+
+
+/// `adm:pw` as basic credentials
+const ADMIN_AUTH: &str = "Basic YWRtOnB3";
+
+
+fn admin_fixture() -> Server {
+    Server::start(&["--admin", "--path-auth", ".http-admin=adm:pw"],
+                  |site| fs::write(site.join("big.txt"), "Abolish the burgeoisie!\n".repeat(1000)).unwrap())
+}
+
+fn admin_json(server: &Server, path: &str) -> Value {
+    let resp = server.get(path).header("Authorization", ADMIN_AUTH).send();
+    assert_eq!(resp.status, 200);
+    assert_eq!(resp.header("Content-Type"), Some("application/json; charset=utf-8"));
+    serde_json::from_slice(&resp.body).unwrap()
+}
+
+
+#[test]
+fn needs_credentials() {
+    let server = admin_fixture();
+
+    assert_eq!(server.get("/.http-admin/").send().status, 401);
+    assert_eq!(server.get("/.http-admin/caches").send().status, 401);
+    assert_eq!(server.get("/big.txt").send().status, 200);
+}
+
+#[test]
+fn probes_need_no_credentials() {
+    let server = admin_fixture();
+
+    let resp = server.get("/.http-admin/healthz").send();
+    assert_eq!(resp.status, 200);
+    assert_eq!(serde_json::from_slice::<Value>(&resp.body).unwrap(), json!({ "healthy": true }));
+
+    let resp = server.get("/.http-admin/readyz").send();
+    assert_eq!(resp.status, 200);
+    assert_eq!(serde_json::from_slice::<Value>(&resp.body).unwrap()["ready"], true);
+
+    assert_eq!(server.request("DELETE", "/.http-admin/readyz").send().status, 401);
+}
+
+#[test]
+fn only_probes_skip_credentials() {
+    let server = Server::start(&["--admin", "--auth", "adm:pw"], |site| {
+        fs::create_dir(site.join(".http-admin")).unwrap();
+        fs::write(site.join(".http-admin").join("healthz"), "Means of production\n").unwrap();
+    });
+
+    assert_eq!(server.get("/.http-admin/healthz").send().status, 200);
+    assert_eq!(server.get("/.http-admin//healthz").send().status, 200);
+    let resp = server.get("//.http-admin/healthz").send();
+    assert_eq!(resp.status, 401);
+    assert!(!resp.text().contains("Means of production"));
+}
+
+#[test]
+fn not_ready_without_temp_dirs() {
+    let server = Server::start(&["--admin", "--path-auth", ".http-admin=adm:pw", "-w"], |_| {});
+    // Created at startup, then replaced with a file
+    let writes = PathBuf::from(server.config()["temp_directories"]["writes"].as_str().unwrap());
+    assert!(writes.is_dir());
+    fs::remove_dir(&writes).unwrap();
+    fs::write(&writes, "").unwrap();
+
+    let resp = server.get("/.http-admin/readyz").send();
+    assert_eq!(resp.status, 503);
+    assert_eq!(resp.header("Retry-After"), Some("5"));
+    let ready: Value = serde_json::from_slice(&resp.body).unwrap();
+    assert_eq!(ready["ready"], false);
+    assert_eq!(ready["temp_dirs"], false);
+    assert_eq!(ready["warm_up"], true);
+
+    assert_eq!(server.get("/.http-admin/healthz").send().status, 200);
+}
+
+#[test]
+fn not_served_by_default() {
+    let server = Server::start(&[], |site| {
+        fs::create_dir(site.join(".http-admin")).unwrap();
+        fs::write(site.join(".http-admin").join("caches"), "just a file").unwrap();
+    });
+
+    assert_eq!(server.get("/.http-admin/caches").send().text(), "just a file");
+}
+
+#[test]
+fn flush_caches() {
+    let server = admin_fixture();
+
+    server.get("/big.txt").header("Accept-Encoding", "gzip").send();
+    let caches = admin_json(&server, "/.http-admin/caches");
+    assert_eq!(caches["encoded"]["entries"].as_u64(), Some(1));
+    assert!(caches["encoded"]["bytes"].as_u64().unwrap() > 0);
+    assert_eq!(caches["hashes"]["entries"].as_u64(), Some(1));
+
+    assert_eq!(server.request("DELETE", "/.http-admin/caches/encoded").header("Authorization", ADMIN_AUTH).send().status, 204);
+    let caches = admin_json(&server, "/.http-admin/caches");
+    assert_eq!(caches["encoded"]["entries"].as_u64(), Some(0));
+    assert_eq!(caches["encoded"]["bytes"].as_u64(), Some(0));
+    assert_eq!(caches["hashes"]["entries"].as_u64(), Some(1));
+
+    assert_eq!(server.request("DELETE", "/.http-admin/caches").header("Authorization", ADMIN_AUTH).send().status, 204);
+    assert_eq!(admin_json(&server, "/.http-admin/caches")["hashes"]["entries"].as_u64(), Some(0));
+
+    // Still served right after
+    let resp = server.get("/big.txt").header("Accept-Encoding", "gzip").send();
+    assert_eq!(resp.decoded(), "Abolish the burgeoisie!\n".repeat(1000).into_bytes());
+}
+
+#[test]
+fn bounded_caches() {
+    let server = Server::start(&["--admin", "--path-auth", ".http-admin=adm:pw", "--encoded-cache-entries", "1", "--generated-cache-size", "0"],
+                               |site| {
+        for i in 0..40 {
+            fs::write(site.join(format!("{}.txt", i)), format!("Abolish the burgeoisie {}!\n", i).repeat(1000)).unwrap();
+        }
+    });
+
+    for i in 0..40 {
+        let resp = server.get(&format!("/{}.txt", i)).header("Accept-Encoding", "gzip").send();
+        assert_eq!(resp.decoded(), format!("Abolish the burgeoisie {}!\n", i).repeat(1000).into_bytes());
+    }
+    server.get("/").header("Accept-Encoding", "gzip, identity;q=0").send();
+
+    // At most one per shard, with the evicted ones' encoded files deleted
+    let caches = admin_json(&server, "/.http-admin/caches");
+    let entries = caches["encoded"]["entries"].as_u64().unwrap();
+    assert!(entries <= 16, "{}", entries);
+    assert_eq!(fs::read_dir(server.temp().join("encoded")).unwrap().count() as u64, entries);
+    assert_eq!(caches["generated"]["entries"].as_u64(), Some(0));
+}
+
+#[test]
+fn log_switch() {
+    let server = admin_fixture();
+
+    assert_eq!(admin_json(&server, "/.http-admin/log"), Value::Bool(false));
+    assert_eq!(server.request("PUT", "/.http-admin/log").header("Authorization", ADMIN_AUTH).body("true").send().status, 204);
+    assert_eq!(admin_json(&server, "/.http-admin/log"), Value::Bool(true));
+    assert_eq!(server.request("PUT", "/.http-admin/log").header("Authorization", ADMIN_AUTH).body("loud").send().status, 400);
+    assert_eq!(admin_json(&server, "/.http-admin/")["log"], Value::Bool(true));
+}
+
+#[test]
+fn header_log_switch() {
+    let server = admin_fixture();
+
+    assert_eq!(admin_json(&server, "/.http-admin/log-headers"), Value::Bool(false));
+    assert_eq!(server.request("PUT", "/.http-admin/log-headers").header("Authorization", ADMIN_AUTH).body("true").send().status, 204);
+    assert_eq!(admin_json(&server, "/.http-admin/log-headers"), Value::Bool(true));
+    assert_eq!(admin_json(&server, "/.http-admin/")["log_headers"], Value::Bool(true));
+    // Independent of serving status logging
+    assert_eq!(admin_json(&server, "/.http-admin/log"), Value::Bool(false));
+    assert_eq!(server.request("DELETE", "/.http-admin/log-headers").header("Authorization", ADMIN_AUTH).send().status, 405);
+}
+
+#[test]
+fn activity() {
+    let server = admin_fixture();
+
+    let activity = admin_json(&server, "/.http-admin/activity");
+    assert_eq!(activity["uploads"], Value::Array(vec![]));
+    assert_eq!(activity["encodings"].as_u64(), Some(0));
+    assert_eq!(activity["deploying"], Value::Bool(false));
+}
+
+#[test]
+fn upload_progress() {
+    let server = Server::start(&["-w", "--admin", "--path-auth", ".http-admin=adm:pw"], |_| {});
+    assert_eq!(admin_json(&server, "/.http-admin/uploads"), Value::Array(vec![]));
+    assert_eq!(server.get("/.http-admin/uploads/backup").header("Authorization", ADMIN_AUTH).send().status, 404);
+
+    let mut conn = TcpStream::connect(("127.0.0.1", server.port())).unwrap();
+    conn.write_all(b"PUT /backup.tar HTTP/1.1\r\nHost: localhost\r\nX-Request-Id: backup\r\nContent-Length: 16\r\n\r\nAbolish ").unwrap();
+    conn.flush().unwrap();
+    thread::sleep(Duration::from_millis(200));
+
+    let uploads = admin_json(&server, "/.http-admin/uploads/backup");
+    assert_eq!(uploads.as_array().map(Vec::len), Some(1));
+    assert_eq!(uploads[0]["id"].as_str(), Some("backup"));
+    assert_eq!(uploads[0]["path"].as_str(), Some("/backup.tar"));
+    assert_eq!(uploads[0]["bytes"].as_u64(), Some(8));
+    assert_eq!(uploads[0]["expected_bytes"].as_u64(), Some(16));
+    assert!(uploads[0]["bytes_per_second"].as_u64().is_some());
+    assert_eq!(admin_json(&server, "/.http-admin/activity")["uploads"], uploads);
+
+    conn.write_all(b"the rich").unwrap();
+    let mut resp = [0; 12];
+    conn.read_exact(&mut resp).unwrap();
+    assert_eq!(&resp, b"HTTP/1.1 201");
+    assert_eq!(admin_json(&server, "/.http-admin/uploads"), Value::Array(vec![]));
+}
+
+#[test]
+fn bad_requests() {
+    let server = admin_fixture();
+
+    let resp = server.request("POST", "/.http-admin/caches").header("Authorization", ADMIN_AUTH).send();
+    assert_eq!(resp.status, 405);
+    assert_eq!(resp.header("Allow"), Some("GET, DELETE"));
+    assert_eq!(server.get("/.http-admin/nonexistent").header("Authorization", ADMIN_AUTH).send().status, 404);
+    assert_eq!(server.request("DELETE", "/.http-admin/caches/nonexistent").header("Authorization", ADMIN_AUTH).send().status, 404);
+}
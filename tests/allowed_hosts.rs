@@ -0,0 +1,42 @@
+extern crate serde_json;
+extern crate flate2;
+
+mod util;
+
+use self::util::Server;
+use std::fs;
+
+
+fn hosts_fixture(args: &[&str]) -> Server {
+    Server::start(args, |site| fs::write(site.join("file.txt"), "Abolish the burgeoisie!\n").unwrap())
+}
+
+
+#[test]
+fn any_host_by_default() {
+    let server = hosts_fixture(&[]);
+
+    assert_eq!(server.get("/file.txt").header("Host", "rebound.example").send().status, 200);
+}
+
+#[test]
+fn disallowed_hosts_misdirected() {
+    let server = hosts_fixture(&["--allowed-host", "files.example", "--allowed-host", "other.example:8080"]);
+
+    assert_eq!(server.get("/file.txt").header("Host", "files.example").send().status, 200);
+    assert_eq!(server.get("/file.txt").header("Host", "FILES.example:1234").send().status, 200);
+    assert_eq!(server.get("/file.txt").header("Host", "other.example:8080").send().status, 200);
+
+    let resp = server.get("/file.txt").header("Host", "rebound.example").send();
+    assert_eq!(resp.status, 421);
+    assert!(!resp.text().contains("burgeoisie"));
+    assert_eq!(server.get("/file.txt").header("Host", "other.example:9090").send().status, 421);
+}
+
+#[test]
+fn ip_addresses_allowed() {
+    let server = hosts_fixture(&["--allowed-host", "files.example"]);
+
+    assert_eq!(server.get("/file.txt").send().status, 200);
+    assert_eq!(server.get("/file.txt").header("Host", "[::1]:80").send().status, 200);
+}
@@ -0,0 +1,57 @@
+extern crate serde_json;
+extern crate flate2;
+
+mod util;
+
+use self::util::Server;
+use serde_json::Value;
+use std::fs;
+
+
+/// `adm:pw` as basic credentials
+const ADMIN_AUTH: &str = "Basic YWRtOnB3";
+
+
+fn accounting_fixture() -> Server {
+    Server::start(&["--admin", "--path-auth", ".http-admin=adm:pw", "--bandwidth-window", "3600", "--proxy", "X-Forwarded-For:127.0.0.0/8", "-e"],
+                  |site| {
+                      fs::create_dir(site.join("big")).unwrap();
+                      fs::write(site.join("big").join("file.bin"), vec![b'x'; 100_000]).unwrap();
+                      fs::write(site.join("small.txt"), "tiny").unwrap();
+                  })
+}
+
+fn bandwidth(server: &Server) -> Value {
+    let resp = server.get("/.http-admin/bandwidth").header("Authorization", ADMIN_AUTH).send();
+    assert_eq!(resp.status, 200);
+    serde_json::from_slice(&resp.body).unwrap()
+}
+
+
+#[test]
+fn accounted() {
+    let server = accounting_fixture();
+
+    server.get("/big/file.bin").header("X-Forwarded-For", "203.0.113.7, 10.0.0.1").send();
+    server.get("/big/file.bin").header("X-Forwarded-For", "203.0.113.7").send();
+    server.get("/small.txt").header("X-Forwarded-For", "198.51.100.1").send();
+
+    let bw = bandwidth(&server);
+    assert_eq!(bw["window"].as_u64(), Some(3600));
+    assert_eq!(bw["clients"][0]["address"], Value::String("203.0.113.7".to_string()));
+    assert_eq!(bw["clients"][0]["bytes"].as_u64(), Some(200_000));
+    assert_eq!(bw["clients"][1]["address"], Value::String("198.51.100.1".to_string()));
+    assert_eq!(bw["clients"][1]["bytes"].as_u64(), Some(4));
+    assert_eq!(bw["paths"][0]["path"], Value::String("/big".to_string()));
+    assert_eq!(bw["paths"][0]["bytes"].as_u64(), Some(200_000));
+    assert_eq!(bw["paths"][1]["path"], Value::String("/".to_string()));
+}
+
+#[test]
+fn not_accounted_by_default() {
+    let server = Server::start(&["--admin", "--path-auth", ".http-admin=adm:pw"], |_| {});
+
+    assert_eq!(server.get("/.http-admin/bandwidth").header("Authorization", ADMIN_AUTH).send().status, 404);
+    assert_eq!(serde_json::from_slice::<Value>(&server.get("/.http-admin/").header("Authorization", ADMIN_AUTH).send().body).unwrap()["bandwidth"],
+               Value::Null);
+}
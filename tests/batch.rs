@@ -0,0 +1,117 @@
+extern crate serde_json;
+extern crate flate2;
+
+mod util;
+
+use self::util::Server;
+use serde_json::Value;
+use std::fs;
+
+
+/// `adm:pw` as basic credentials
+const ADMIN_AUTH: &str = "Basic YWRtOnB3";
+
+
+fn batch_fixture(args: &[&str]) -> Server {
+    Server::start(&[&["-w"], args].concat(), |site| {
+        fs::create_dir_all(site.join("pub").join("secret")).unwrap();
+        fs::write(site.join("pub").join("file.txt"), "Abolish the burgeoisie!\n").unwrap();
+        fs::write(site.join("pub").join("secret").join("file.txt"), "Workers of the world, unite!\n").unwrap();
+        fs::write(site.join("outside.txt"), "Means of production\n").unwrap();
+    })
+}
+
+fn batch(server: &Server, dir: &str, ops: &str, auth: Option<&str>) -> (u16, Vec<u64>) {
+    let mut req = server.request("POST", dir).header("Content-Type", "application/json");
+    if let Some(auth) = auth {
+        req = req.header("Authorization", auth);
+    }
+    let resp = req.body(ops).send();
+    let body: Value = serde_json::from_slice(&resp.body).unwrap();
+    (resp.status, body["results"].as_array().unwrap().iter().map(|r| r["status"].as_u64().unwrap()).collect())
+}
+
+
+#[test]
+fn operations() {
+    let server = batch_fixture(&[]);
+
+    let (st, results) = batch(&server,
+                              "/pub/",
+                              r#"[{"op": "mkdir", "path": "new"},
+                                  {"op": "copy", "from": "file.txt", "to": "new/copy.txt"},
+                                  {"op": "move", "from": "file.txt", "to": "new/moved.txt"},
+                                  {"op": "delete", "path": "new/copy.txt"}]"#,
+                              None);
+    assert_eq!(st, 200);
+    assert_eq!(results, [201, 201, 201, 204]);
+
+    let new = server.site().join("pub").join("new");
+    assert_eq!(fs::read_to_string(new.join("moved.txt")).unwrap(), "Abolish the burgeoisie!\n");
+    assert!(!new.join("copy.txt").exists());
+    assert!(!server.site().join("pub").join("file.txt").exists());
+}
+
+#[test]
+fn stops_at_failure() {
+    let server = batch_fixture(&[]);
+
+    let (st, results) = batch(&server,
+                              "/pub/",
+                              r#"[{"op": "delete", "path": "nonexistent"}, {"op": "delete", "path": "file.txt"}]"#,
+                              None);
+    assert_eq!(st, 207);
+    assert_eq!(results, [404, 424]);
+    assert!(server.site().join("pub").join("file.txt").exists());
+}
+
+#[test]
+fn confined_to_requested_directory() {
+    let server = batch_fixture(&[]);
+
+    let (st, results) = batch(&server,
+                              "/pub/",
+                              r#"[{"op": "delete", "path": "../outside.txt"}]"#,
+                              None);
+    assert_eq!((st, results), (207, vec![403]));
+    let (_, results) = batch(&server, "/pub/", r#"[{"op": "delete", "path": "/outside.txt"}]"#, None);
+    assert_eq!(results, [403]);
+    let (_, results) = batch(&server,
+                             "/pub/",
+                             r#"[{"op": "copy", "from": "/outside.txt", "to": "stolen.txt"}]"#,
+                             None);
+    assert_eq!(results, [403]);
+
+    assert!(server.site().join("outside.txt").exists());
+    assert!(!server.site().join("pub").join("stolen.txt").exists());
+}
+
+#[test]
+fn operands_need_their_credentials() {
+    let server = batch_fixture(&["--path-auth", "pub/secret=adm:pw"]);
+
+    let (_, results) = batch(&server, "/pub/", r#"[{"op": "delete", "path": "secret/file.txt"}]"#, None);
+    assert_eq!(results, [401]);
+    let (_, results) = batch(&server,
+                             "/pub/",
+                             r#"[{"op": "move", "from": "secret/file.txt", "to": "leaked.txt"}]"#,
+                             None);
+    assert_eq!(results, [401]);
+    assert!(server.site().join("pub").join("secret").join("file.txt").exists());
+
+    let (st, results) = batch(&server, "/pub/", r#"[{"op": "delete", "path": "secret/file.txt"}]"#, Some(ADMIN_AUTH));
+    assert_eq!((st, results), (200, vec![204]));
+}
+
+#[test]
+fn ambiguous_framing_refused() {
+    let server = batch_fixture(&[]);
+
+    let resp = server.request("POST", "/pub/")
+        .header("Content-Type", "application/json")
+        .header("Transfer-Encoding", "chunked")
+        .body(r#"[{"op": "delete", "path": "file.txt"}]"#)
+        .send();
+    assert_eq!(resp.status, 400);
+    assert!(server.site().join("pub").join("file.txt").exists());
+}
@@ -0,0 +1,108 @@
+extern crate serde_json;
+extern crate flate2;
+extern crate blake3;
+
+mod util;
+
+use self::util::Server;
+use std::fs;
+
+
+const BLOCK_SIZE: usize = 64;
+
+
+fn delta_fixture(args: &[&str]) -> Server {
+    Server::start(args, |site| fs::write(site.join("file.txt"), new_file()).unwrap())
+}
+
+fn old_file() -> Vec<u8> {
+    "Abolish the burgeoisie!\n".repeat(20).into_bytes()
+}
+
+fn new_file() -> Vec<u8> {
+    ["Workers of the world, unite!\n".repeat(3), "Abolish the burgeoisie!\n".repeat(20), "Seize the means!\n".to_string()].concat().into_bytes()
+}
+
+/// The signature of `data`, as the client would send it
+fn signature(data: &[u8]) -> Vec<u8> {
+    let mut sig = (BLOCK_SIZE as u32).to_be_bytes().to_vec();
+    for block in data.chunks(BLOCK_SIZE) {
+        let len = block.len() as u32;
+        let (a, b) = block.iter().enumerate().fold((0u32, 0u32), |(a, b), (i, &x)| {
+            ((a + x as u32) & 0xFFFF, (b + (len - i as u32) * x as u32) & 0xFFFF)
+        });
+        sig.extend_from_slice(&(a | (b << 16)).to_be_bytes());
+        sig.extend_from_slice(&blake3::hash(block).as_bytes()[..16]);
+    }
+    sig
+}
+
+/// Apply `delta` to `old`, checking the final hash
+fn apply(old: &[u8], mut delta: &[u8]) -> Vec<u8> {
+    let word = |d: &[u8]| u32::from_be_bytes([d[0], d[1], d[2], d[3]]) as usize;
+
+    let mut out = vec![];
+    loop {
+        match delta[0] {
+            b'C' => {
+                let idx = word(&delta[1..]);
+                out.extend_from_slice(&old[idx * BLOCK_SIZE..old.len().min((idx + 1) * BLOCK_SIZE)]);
+                delta = &delta[5..];
+            }
+            b'L' => {
+                let len = word(&delta[1..]);
+                out.extend_from_slice(&delta[5..5 + len]);
+                delta = &delta[5 + len..];
+            }
+            b'E' => {
+                assert_eq!(&delta[1..], blake3::hash(&out).as_bytes());
+                return out;
+            }
+            cmd => panic!("Unknown delta command {}", cmd),
+        }
+    }
+}
+
+
+#[test]
+fn reconstructs_file() {
+    let server = delta_fixture(&["--delta-sync"]);
+
+    let resp = server.request("POST", "/file.txt?delta").body(signature(&old_file())).send();
+    assert_eq!(resp.status, 200);
+    assert_eq!(resp.header("Content-Type").unwrap(), "application/octet-stream");
+    assert!(resp.body.len() < new_file().len());
+    assert_eq!(apply(&old_file(), &resp.body), new_file());
+}
+
+#[test]
+fn empty_copy() {
+    let server = delta_fixture(&["--delta-sync"]);
+
+    let resp = server.request("POST", "/file.txt?delta").body(signature(b"")).send();
+    assert_eq!(resp.status, 200);
+    assert_eq!(apply(b"", &resp.body), new_file());
+}
+
+#[test]
+fn invalid_signatures_refused() {
+    let server = delta_fixture(&["--delta-sync"]);
+
+    assert_eq!(server.request("POST", "/file.txt?delta").body(&b"\x00\x00\x00\x01"[..]).send().status, 400);
+    assert_eq!(server.request("POST", "/file.txt?delta").body(&b"\x00\x00"[..]).send().status, 400);
+    assert_eq!(server.request("POST", "/nonexistent?delta").body(signature(&old_file())).send().status, 404);
+}
+
+#[test]
+fn disabled_by_default() {
+    let server = delta_fixture(&[]);
+
+    assert_eq!(server.request("POST", "/file.txt?delta").body(signature(&old_file())).send().status, 403);
+}
+
+#[test]
+fn ambiguous_framing_refused() {
+    let server = delta_fixture(&["--delta-sync"]);
+
+    assert_eq!(server.request("POST", "/file.txt?delta").header("Transfer-Encoding", "chunked").body(signature(&old_file())).send().status, 400);
+}
@@ -0,0 +1,69 @@
+extern crate serde_json;
+extern crate flate2;
+
+mod util;
+
+use self::util::Server;
+use std::fs;
+
+
+/// A tar entry of the specified type, padded to whole blocks
+fn tar_entry(name: &str, kind: u8, data: &[u8]) -> Vec<u8> {
+    let mut header = [0u8; 512];
+    header[..name.len()].copy_from_slice(name.as_bytes());
+    header[100..107].copy_from_slice(b"0000644");
+    header[124..135].copy_from_slice(format!("{:011o}", data.len()).as_bytes());
+    header[156] = kind;
+    header[257..263].copy_from_slice(b"ustar\0");
+
+    let mut entry = header.to_vec();
+    entry.extend_from_slice(data);
+    entry.resize((entry.len() + 511) / 512 * 512, 0);
+    entry
+}
+
+fn deploy_fixture() -> Server {
+    Server::start(&["-w", "--deploy"], |site| fs::write(site.join("old.txt"), "Abolish the burgeoisie!\n").unwrap())
+}
+
+
+#[test]
+fn deployed() {
+    let server = deploy_fixture();
+
+    let tar = [tar_entry("new.txt", b'0', b"Workers of the world, unite!\n"), vec![0; 1024]].concat();
+    assert_eq!(server.request("POST", "/").header("Content-Type", "application/x-tar").body(tar).send().status, 201);
+    assert_eq!(server.get("/new.txt").send().text(), "Workers of the world, unite!\n");
+    assert_eq!(server.get("/old.txt").send().status, 404);
+}
+
+#[test]
+fn long_names() {
+    let server = deploy_fixture();
+
+    let name = "long/".repeat(40) + "new.txt";
+    let tar = [tar_entry("././@LongLink", b'L', name.as_bytes()), tar_entry("ignored", b'0', b"comrade"), vec![0; 1024]].concat();
+    assert_eq!(server.request("POST", "/").header("Content-Type", "application/x-tar").body(tar).send().status, 201);
+    assert_eq!(server.get(&format!("/{}", name)).send().text(), "comrade");
+}
+
+#[test]
+fn huge_metadata_refused() {
+    let server = deploy_fixture();
+
+    for &kind in &[b'L', b'x'] {
+        let tar = [tar_entry("././@LongLink", kind, &vec![b'a'; 100 * 1024]), tar_entry("new.txt", b'0', b"comrade"), vec![0; 1024]].concat();
+        assert_eq!(server.request("POST", "/").header("Content-Type", "application/x-tar").body(tar).send().status, 400);
+        assert_eq!(server.get("/old.txt").send().text(), "Abolish the burgeoisie!\n");
+    }
+}
+
+#[test]
+fn ambiguous_framing_refused() {
+    let server = deploy_fixture();
+
+    let tar = [tar_entry("new.txt", b'0', b"Workers of the world, unite!\n"), vec![0; 1024]].concat();
+    let resp = server.request("POST", "/").header("Content-Type", "application/x-tar").header("Transfer-Encoding", "chunked").body(tar).send();
+    assert_eq!(resp.status, 400);
+    assert_eq!(server.get("/old.txt").send().text(), "Abolish the burgeoisie!\n");
+}
@@ -0,0 +1,90 @@
+extern crate serde_json;
+extern crate flate2;
+
+mod util;
+
+use self::util::Server;
+use serde_json::Value;
+use std::fs;
+
+
+/// `adm:pw` as basic credentials
+const ADMIN_AUTH: &str = "Basic YWRtOnB3";
+
+
+fn counting_fixture(args: &[&str]) -> Server {
+    Server::start(args, |site| {
+        fs::create_dir(site.join("dir")).unwrap();
+        fs::write(site.join("dir").join("popular.txt"), "Abolish the burgeoisie!\n").unwrap();
+        fs::write(site.join("dir").join("niche.txt"), "Workers of the world, unite!\n").unwrap();
+    })
+}
+
+fn listing_downloads(server: &Server) -> Value {
+    let listing: Value = serde_json::from_slice(&server.get("/dir/?format=json").send().body).unwrap();
+    listing["downloads"].clone()
+}
+
+
+#[test]
+fn counted() {
+    let server = counting_fixture(&["--download-counts"]);
+
+    for _ in 0..3 {
+        assert_eq!(server.get("/dir/popular.txt").send().status, 200);
+    }
+    server.get("/dir/popular.txt").header("Range", "bytes=0-4").send();
+    server.get("/dir/popular.txt").header("Range", "bytes=5-").send();
+    server.request("HEAD", "/dir/niche.txt").send();
+
+    let downloads = listing_downloads(&server);
+    assert_eq!(downloads["popular.txt"].as_u64(), Some(4));
+    assert_eq!(downloads["niche.txt"].as_u64(), Some(0));
+}
+
+#[test]
+fn unlogged_not_counted() {
+    let server = counting_fixture(&["--download-counts", "--unlogged", "/dir/popular.txt"]);
+
+    server.get("/dir/popular.txt").send();
+    server.get("/dir/niche.txt").send();
+
+    let downloads = listing_downloads(&server);
+    assert_eq!(downloads["popular.txt"].as_u64(), Some(0));
+    assert_eq!(downloads["niche.txt"].as_u64(), Some(1));
+}
+
+#[test]
+fn not_counted_by_default() {
+    let server = counting_fixture(&[]);
+
+    server.get("/dir/popular.txt").send();
+    assert_eq!(listing_downloads(&server), Value::Null);
+    assert!(!server.temp().join("downloads").exists());
+}
+
+#[test]
+fn listing_column() {
+    let server = counting_fixture(&["--download-counts", "--listing-downloads"]);
+
+    server.get("/dir/popular.txt").send();
+    let listing = server.get("/dir/").send().text();
+    assert!(listing.contains("<th>Downloads</th>"));
+    assert!(listing.contains("<td><a href=\"/dir/popular.txt\">1</a></td>"));
+    assert!(listing.contains("<td><a href=\"/dir/niche.txt\">0</a></td>"));
+}
+
+#[test]
+fn admin_popularity() {
+    let server = counting_fixture(&["--download-counts", "--admin", "--path-auth", ".http-admin=adm:pw"]);
+
+    server.get("/dir/niche.txt").send();
+    server.get("/dir/popular.txt").send();
+    server.get("/dir/popular.txt").send();
+
+    let resp = server.get("/.http-admin/downloads").header("Authorization", ADMIN_AUTH).send();
+    assert_eq!(resp.status, 200);
+    let downloads: Value = serde_json::from_slice(&resp.body).unwrap();
+    assert_eq!(downloads,
+               serde_json::from_str::<Value>(r#"[{"path": "/dir/popular.txt", "downloads": 2}, {"path": "/dir/niche.txt", "downloads": 1}]"#).unwrap());
+}
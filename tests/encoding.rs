@@ -0,0 +1,325 @@
+extern crate serde_json;
+extern crate flate2;
+extern crate blake3;
+extern crate brotli;
+extern crate zstd;
+
+mod util;
+
+use self::util::Server;
+use std::{env, fs, process, thread};
+use std::io::Read;
+
+
+fn encoding_fixture() -> Server {
+    Server::start(&[], |site| {
+        fs::write(site.join("big.txt"), "Abolish the burgeoisie!\n".repeat(1000)).unwrap();
+        fs::write(site.join("small.txt"), "tiny").unwrap();
+    })
+}
+
+/// The content encoding `big.txt` is served in when accepting the specified encodings, `None` for identity
+fn negotiated(server: &Server, accept: &str) -> Option<String> {
+    let resp = server.get("/big.txt").header("Accept-Encoding", accept).send();
+    assert_eq!(resp.status, 200, "Accept-Encoding: {}", accept);
+    resp.header("Content-Encoding").map(str::to_string)
+}
+
+
+#[test]
+fn client_ranking() {
+    let server = encoding_fixture();
+
+    assert_eq!(negotiated(&server, "gzip;q=0.5, br").as_deref(), Some("br"));
+    assert_eq!(negotiated(&server, "br;q=0.2, deflate;q=0.9, gzip;q=0.5").as_deref(), Some("deflate"));
+    assert_eq!(negotiated(&server, "deflate, gzip").as_deref(), Some("deflate"));
+    assert_eq!(negotiated(&server, "gzip, deflate").as_deref(), Some("gzip"));
+    assert_eq!(negotiated(&server, "compress, gzip;q=0.1").as_deref(), Some("gzip"));
+    assert_eq!(negotiated(&server, "GZIP").as_deref(), Some("gzip"));
+}
+
+#[test]
+fn vary() {
+    let server = Server::start(&[], |site| {
+        fs::write(site.join("big.txt"), "Abolish the burgeoisie!\n".repeat(1000)).unwrap();
+        fs::write(site.join("small.txt"), "Abolish").unwrap();
+    });
+
+    // Whatever was picked, since a different Accept-Encoding might've gotten something else
+    assert_eq!(server.get("/big.txt").header("Accept-Encoding", "gzip").send().header("Vary"), Some("Accept-Encoding"));
+    assert_eq!(server.get("/big.txt").send().header("Vary"), Some("Accept-Encoding"));
+    assert_eq!(server.get("/big.txt").header("Accept-Encoding", "gzip").header("Range", "bytes=5-").send().header("Vary"),
+               Some("Accept-Encoding"));
+    assert_eq!(server.get("/").send().header("Vary"), Some("Accept-Encoding"));
+    assert_eq!(server.get("/nonexistent").send().header("Vary"), Some("Accept-Encoding"));
+
+    // Never encoded
+    assert_eq!(server.get("/small.txt").header("Accept-Encoding", "gzip").send().header("Vary"), None);
+}
+
+#[test]
+fn wildcard() {
+    let server = encoding_fixture();
+
+    assert_eq!(negotiated(&server, "*").as_deref(), Some("gzip"));
+    assert_eq!(negotiated(&server, "gzip;q=0.1, *;q=0.5").as_deref(), Some("deflate"));
+    assert_eq!(negotiated(&server, "gzip, *;q=0").as_deref(), Some("gzip"));
+    assert_eq!(negotiated(&server, "*;q=0, identity"), None);
+}
+
+#[test]
+fn identity() {
+    let server = encoding_fixture();
+
+    assert_eq!(negotiated(&server, ""), None);
+    assert_eq!(negotiated(&server, "identity"), None);
+    assert_eq!(negotiated(&server, "gzip;q=0"), None);
+    assert_eq!(negotiated(&server, "identity, gzip;q=0.5"), None);
+    assert_eq!(negotiated(&server, "identity;q=0.5, gzip").as_deref(), Some("gzip"));
+    assert_eq!(negotiated(&server, "identity;q=0, gzip").as_deref(), Some("gzip"));
+
+    let resp = server.get("/big.txt").header("Accept-Encoding", "gzip").send();
+    assert_eq!(resp.decoded(), "Abolish the burgeoisie!\n".repeat(1000).into_bytes());
+}
+
+#[test]
+fn nothing_acceptable() {
+    let server = encoding_fixture();
+
+    assert_eq!(server.get("/big.txt").header("Accept-Encoding", "identity;q=0").send().status, 406);
+    assert_eq!(server.get("/big.txt").header("Accept-Encoding", "*;q=0").send().status, 406);
+    assert_eq!(server.get("/big.txt").header("Accept-Encoding", "compress, identity;q=0").send().status, 406);
+    assert_eq!(server.request("HEAD", "/big.txt").header("Accept-Encoding", "*;q=0").send().status, 406);
+
+    // Only identity is available for files too small to encode
+    assert_eq!(server.get("/small.txt").header("Accept-Encoding", "gzip, identity;q=0").send().status, 406);
+    assert_eq!(server.get("/small.txt").header("Accept-Encoding", "gzip").send().text(), "tiny");
+
+    // Errors stay errors
+    assert_eq!(server.get("/nonexistent.txt").header("Accept-Encoding", "*;q=0").send().status, 404);
+}
+
+#[test]
+fn generated_small_responses() {
+    let server = encoding_fixture();
+
+    // Listings under the encoding threshold are encoded if the client won't take them as-is
+    let resp = server.get("/").header("Accept-Encoding", "gzip, identity;q=0").send();
+    assert_eq!(resp.status, 200);
+    assert_eq!(resp.header("Content-Encoding"), Some("gzip"));
+}
+
+#[test]
+fn brotli() {
+    let server = encoding_fixture();
+    let unbrotli = |body: &[u8]| {
+        let mut out = vec![];
+        brotli::Decompressor::new(body, 4096).read_to_end(&mut out).expect("Failed to unbrotli body");
+        out
+    };
+
+    let resp = server.get("/big.txt").header("Accept-Encoding", "br").send();
+    assert_eq!(resp.header("Content-Encoding"), Some("br"));
+    assert_eq!(unbrotli(&resp.body), "Abolish the burgeoisie!\n".repeat(1000).into_bytes());
+    let encoded: Vec<_> = fs::read_dir(server.temp().join("encoded")).unwrap().flatten().map(|f| f.file_name().into_string().unwrap()).collect();
+    assert_eq!(encoded.len(), 1);
+    assert!(encoded[0].ends_with(".txt.br"), "{}", encoded[0]);
+
+    let resp = server.get("/").header("Accept-Encoding", "br, identity;q=0").send();
+    assert_eq!(resp.header("Content-Encoding"), Some("br"));
+    assert!(String::from_utf8(unbrotli(&resp.body)).unwrap().contains("big.txt"));
+}
+
+#[test]
+fn zstd() {
+    let server = encoding_fixture();
+
+    assert_eq!(negotiated(&server, "gzip;q=0.5, zstd").as_deref(), Some("zstd"));
+
+    let resp = server.get("/big.txt").header("Accept-Encoding", "zstd").send();
+    assert_eq!(resp.header("Content-Encoding"), Some("zstd"));
+    assert_eq!(zstd::stream::decode_all(&resp.body[..]).unwrap(), "Abolish the burgeoisie!\n".repeat(1000).into_bytes());
+    let encoded: Vec<_> = fs::read_dir(server.temp().join("encoded")).unwrap().flatten().map(|f| f.file_name().into_string().unwrap()).collect();
+    assert!(encoded.iter().any(|f| f.ends_with(".txt.zst")), "{:?}", encoded);
+
+    let resp = server.get("/").header("Accept-Encoding", "zstd, identity;q=0").send();
+    assert_eq!(resp.header("Content-Encoding"), Some("zstd"));
+    assert!(String::from_utf8(zstd::stream::decode_all(&resp.body[..]).unwrap()).unwrap().contains("big.txt"));
+}
+
+#[test]
+fn precompressed_siblings() {
+    let server = Server::start(&["-e", "--serve-precompressed"], |site| {
+        fs::write(site.join("app.js"), "alert(1);").unwrap();
+        fs::write(site.join("app.js.gz"), "pretend gzip").unwrap();
+        fs::write(site.join("app.js.br"), "pretend brotli").unwrap();
+    });
+    assert_eq!(server.config()["serve_precompressed"].as_bool(), Some(true));
+
+    let resp = server.get("/app.js").header("Accept-Encoding", "gzip").send();
+    assert_eq!(resp.header("Content-Encoding"), Some("gzip"));
+    assert!(resp.header("Content-Type").unwrap().contains("javascript"));
+    assert_eq!(resp.text(), "pretend gzip");
+
+    let resp = server.get("/app.js").header("Accept-Encoding", "gzip;q=0.5, br").send();
+    assert_eq!(resp.header("Content-Encoding"), Some("br"));
+    assert_eq!(resp.text(), "pretend brotli");
+
+    let resp = server.get("/app.js").header("Accept-Encoding", "gzip").header("Range", "bytes=8-").send();
+    assert_eq!(resp.status, 206);
+    assert_eq!(resp.header("Content-Encoding"), Some("gzip"));
+    assert_eq!(resp.text(), "gzip");
+
+    // Not accepted, or not there
+    let resp = server.get("/app.js").header("Accept-Encoding", "zstd").send();
+    assert_eq!(resp.header("Content-Encoding"), None);
+    assert_eq!(resp.text(), "alert(1);");
+    assert_eq!(server.get("/app.js").send().text(), "alert(1);");
+    assert_eq!(server.get("/app.js.gz").header("Accept-Encoding", "gzip").send().header("Content-Encoding"), None);
+}
+
+#[test]
+fn capped_store() {
+    let server = Server::start(&["--encoded-store", "capped:100"], |site| {
+        fs::write(site.join("big.txt"), "Abolish the burgeoisie!\n".repeat(1000)).unwrap();
+    });
+    assert_eq!(server.config()["encoded_store"].as_str(), Some("capped:100"));
+
+    // Doesn't fit, so it's never kept
+    assert_eq!(negotiated(&server, "gzip"), None);
+    assert_eq!(negotiated(&server, "gzip"), None);
+    assert_eq!(fs::read_dir(server.temp().join("encoded")).unwrap().count(), 0);
+}
+
+#[test]
+fn memory_store() {
+    let server = Server::start(&["--encoded-store", "memory:1M"], |site| {
+        fs::write(site.join("big.txt"), "Abolish the burgeoisie!\n".repeat(1000)).unwrap();
+    });
+    assert_eq!(server.config()["encoded_store"].as_str(), Some("memory:1048576"));
+
+    let first = server.get("/big.txt").header("Accept-Encoding", "gzip").send();
+    let second = server.get("/big.txt").header("Accept-Encoding", "gzip").send();
+    assert_eq!(second.header("Content-Encoding"), Some("gzip"));
+    assert_eq!(first.body, second.body);
+    assert_eq!(second.decoded(), "Abolish the burgeoisie!\n".repeat(1000).into_bytes());
+    assert!(!server.temp().join("encoded").exists());
+
+    // Ranges come out of the file instead
+    let resp = server.get("/big.txt").header("Accept-Encoding", "gzip").header("Range", "bytes=0-6").send();
+    assert_eq!(resp.header("Content-Encoding"), None);
+    assert_eq!(resp.text(), "Abolish");
+
+    // Doesn't fit, so it's never kept
+    let server = Server::start(&["--encoded-store", "memory:100"], |site| {
+        fs::write(site.join("big.txt"), "Abolish the burgeoisie!\n".repeat(1000)).unwrap();
+    });
+    assert_eq!(negotiated(&server, "gzip"), None);
+}
+
+#[test]
+fn shared_store() {
+    let shared = env::temp_dir().join(format!("http-test-shared-{}", process::id()));
+    fs::create_dir_all(&shared).unwrap();
+    let arg = format!("shared:{}", shared.display());
+
+    {
+        let server = Server::start(&["--encoded-store", &arg], |site| {
+            fs::write(site.join("big.txt"), "Abolish the burgeoisie!\n".repeat(1000)).unwrap();
+        });
+        assert_eq!(negotiated(&server, "gzip").as_ref().map(|s| &s[..]), Some("gzip"));
+        assert!(!server.temp().join("encoded").exists());
+    }
+
+    // In the shared directory, rather than the temp dir
+    assert_eq!(fs::read_dir(&shared).unwrap().count(), 1);
+    fs::remove_dir_all(&shared).unwrap();
+}
+
+#[test]
+fn other_versions_encodings_ignored() {
+    let content = "Abolish the burgeoisie!\n".repeat(1000);
+    let shared = env::temp_dir().join(format!("http-test-shared-old-{}", process::id()));
+    fs::create_dir_all(&shared).unwrap();
+    // Named like before encoded files had cache fingerprints
+    fs::write(shared.join(format!("{}.txt.gz", blake3::hash(content.as_bytes()).to_hex())), "not even gzip").unwrap();
+
+    {
+        let server = Server::start(&["--encoded-store", &format!("shared:{}", shared.display())], |site| {
+            fs::write(site.join("big.txt"), &content).unwrap();
+        });
+        let resp = server.get("/big.txt").header("Accept-Encoding", "gzip").send();
+        assert_eq!(resp.header("Content-Encoding"), Some("gzip"));
+        assert_eq!(resp.decoded(), content.as_bytes());
+    }
+
+    assert_eq!(fs::read_dir(&shared).unwrap().count(), 2);
+    fs::remove_dir_all(&shared).unwrap();
+}
+
+#[test]
+fn not_worth_encoding_per_file() {
+    // Doesn't compress, so it's remembered as not worth encoding
+    let mut state = 12345u32;
+    let noise: Vec<u8> = (0..8192)
+        .map(|_| {
+            state = state.wrapping_mul(1103515245).wrapping_add(12345);
+            (state >> 16) as u8
+        })
+        .collect();
+    let server = Server::start(&[], |site| {
+        fs::write(site.join("a.txt"), &noise).unwrap();
+        fs::write(site.join("b.txt"), &noise).unwrap();
+    });
+
+    let resp = server.get("/a.txt").header("Accept-Encoding", "gzip").send();
+    assert_eq!(resp.header("Content-Encoding"), None);
+    assert_eq!(resp.body, noise);
+
+    // b.txt had the same contents, but is still served itself once a.txt changes
+    fs::write(server.site().join("a.txt"), "Abolish the burgeoisie!\n".repeat(1000)).unwrap();
+    let resp = server.get("/b.txt").header("Accept-Encoding", "gzip").send();
+    assert_eq!(resp.header("Content-Encoding"), None);
+    assert_eq!(resp.body, noise);
+
+    let resp = server.get("/a.txt").header("Accept-Encoding", "gzip").send();
+    assert_eq!(resp.decoded(), "Abolish the burgeoisie!\n".repeat(1000).into_bytes());
+}
+
+#[test]
+fn concurrent_population() {
+    let content = |i: usize| format!("{} Abolish the burgeoisie!\n", i).repeat(100_000);
+    let mut server = Server::start_logging(&["--no-colour"], |site| {
+        for i in 0..4 {
+            fs::write(site.join(format!("big{}.txt", i)), content(i)).unwrap();
+        }
+    });
+
+    // Every clone of the handler on every thread sees the same cache, so each file's encoded once
+    thread::scope(|s| {
+        for i in 0..4 {
+            for _ in 0..8 {
+                let server = &server;
+                s.spawn(move || {
+                    let resp = server.get(&format!("/big{}.txt", i)).header("Accept-Encoding", "gzip").send();
+                    assert_eq!(resp.status, 200);
+                    assert_eq!(resp.decoded(), content(i).into_bytes());
+                });
+            }
+        }
+    });
+    assert_eq!(server.get("/marker").send().status, 404);
+
+    let mut encoded = 0;
+    loop {
+        let line = server.log_line();
+        if line.contains("marker") {
+            break;
+        }
+        if line.contains("encoded as gzip for") && line.ends_with("% ratio") {
+            encoded += 1;
+        }
+    }
+    assert_eq!(encoded, 4);
+    assert_eq!(fs::read_dir(server.temp().join("encoded")).unwrap().count(), 4);
+}
@@ -0,0 +1,145 @@
+extern crate serde_json;
+extern crate flate2;
+
+mod util;
+
+use self::util::Server;
+use std::path::PathBuf;
+use std::fs;
+
+
+#[test]
+fn not_found_is_html_by_default() {
+    let server = Server::start(&[], |_| {});
+
+    let resp = server.get("/nonexistent").header("Accept", "text/html,application/xhtml+xml,*/*;q=0.8").send();
+    assert_eq!(resp.status, 404);
+    assert!(resp.header("Content-Type").unwrap().starts_with("text/html"));
+    assert!(resp.text().contains("404 Not Found"));
+}
+
+#[test]
+fn not_found_is_json_when_preferred() {
+    let server = Server::start(&[], |_| {});
+
+    let resp = server.get("/nonexistent").header("Accept", "application/json").send();
+    assert_eq!(resp.status, 404);
+    assert!(resp.header("Content-Type").unwrap().starts_with("application/json"));
+
+    let body: serde_json::Value = serde_json::from_str(&resp.text()).unwrap();
+    assert_eq!(body["status"], 404);
+    assert_eq!(body["error"], "404 Not Found");
+    assert!(body["message"].as_str().unwrap().contains("nonexistent"));
+    assert!(!body["message"].as_str().unwrap().contains('<'));
+}
+
+#[test]
+fn html_wins_ties_and_low_json_quality() {
+    let server = Server::start(&[], |_| {});
+
+    for accept in &["*/*", "application/json, text/html", "application/json;q=0.5, text/*"] {
+        let resp = server.get("/nonexistent").header("Accept", accept).send();
+        assert_eq!(resp.status, 404);
+        assert!(resp.header("Content-Type").unwrap().starts_with("text/html"), "{}", accept);
+    }
+}
+
+#[test]
+fn method_not_allowed_keeps_allow_in_json() {
+    let server = Server::start(&["-w"], |site| {
+        std::fs::create_dir(site.join("dir")).unwrap();
+    });
+
+    let resp = server.request("PUT", "/dir/").header("Accept", "application/json").body("x").send();
+    assert_eq!(resp.status, 405);
+    assert!(resp.header("Allow").is_some());
+
+    let body: serde_json::Value = serde_json::from_str(&resp.text()).unwrap();
+    assert_eq!(body["status"], 405);
+    assert!(body["detail"].as_str().unwrap().starts_with("Allowed methods: "));
+}
+
+#[test]
+fn filesystem_errors_are_internal_errors() {
+    let server = Server::start(&["-w"], |site| fs::write(site.join("file.txt"), "Abolish the burgeoisie!\n").unwrap());
+    // With a file where the writes temp dir goes, writing the upload to it fails
+    let writes = PathBuf::from(server.config()["temp_directories"]["writes"].as_str().unwrap());
+    fs::create_dir_all(writes.parent().unwrap()).unwrap();
+    fs::write(&writes, "").unwrap();
+
+    let resp = server.request("PUT", "/upload.txt").header("Accept", "application/json").body("upload").send();
+    assert_eq!(resp.status, 500);
+    let body: serde_json::Value = serde_json::from_str(&resp.text()).unwrap();
+    assert_eq!(body["error"], "500 Internal Server Error");
+
+    for _ in 0..3 {
+        assert_eq!(server.get("/file.txt").send().status, 200);
+    }
+}
+
+#[test]
+fn filesystem_errors_are_logged() {
+    let mut server = Server::start_logging(&["-w", "--no-colour"], |_| {});
+    let writes = PathBuf::from(server.config()["temp_directories"]["writes"].as_str().unwrap());
+    fs::create_dir_all(writes.parent().unwrap()).unwrap();
+    fs::write(&writes, "").unwrap();
+
+    assert_eq!(server.request("PUT", "/upload.txt").body("upload").send().status, 500);
+    assert!(server.log_line().contains("requested to PUT upload.txt but writing it to the temp dir failed"));
+}
+
+#[cfg(unix)]
+#[test]
+fn unreadable_is_forbidden() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mut server = Server::start_logging(&["--no-colour"], |site| {
+        fs::write(site.join("secret.txt"), "Abolish the burgeoisie!\n").unwrap();
+        fs::set_permissions(site.join("secret.txt"), fs::Permissions::from_mode(0o000)).unwrap();
+        fs::create_dir(site.join("secret")).unwrap();
+        fs::set_permissions(site.join("secret"), fs::Permissions::from_mode(0o100)).unwrap();
+    });
+    // Permissions don't apply to root
+    if fs::File::open(server.site().join("secret.txt")).is_ok() {
+        return;
+    }
+
+    assert_eq!(server.get("/secret.txt").send().status, 403);
+    assert!(server.log_line().contains("was served file"));
+    assert!(server.log_line().contains("requested to GET secret.txt but opening the file failed"));
+
+    let resp = server.get("/secret/").header("Accept", "application/json").send();
+    assert_eq!(resp.status, 403);
+    let body: serde_json::Value = serde_json::from_str(&resp.text()).unwrap();
+    assert_eq!(body["error"], "403 Forbidden");
+
+    // So it can be cleaned up
+    fs::set_permissions(server.site().join("secret"), fs::Permissions::from_mode(0o755)).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn fifos_refused() {
+    use std::process::Command;
+
+    let server = Server::start(&["--delta-sync"], |site| {
+        fs::write(site.join("file.txt"), "Abolish the burgeoisie!\n").unwrap();
+        let _ = Command::new("mkfifo").arg(site.join("pipe")).status();
+    });
+    if !server.site().join("pipe").exists() {
+        return;
+    }
+
+    // Opening the FIFO would block until a writer shows up, so these not timing out is the point
+    assert_eq!(server.get("/pipe").send().status, 403);
+    assert_eq!(server.request("HEAD", "/pipe").send().status, 403);
+    assert_eq!(server.get("/pipe").header("Range", "bytes=0-10").send().status, 403);
+    assert_eq!(server.get("/pipe?format=json").send().status, 403);
+    assert_eq!(server.request("POST", "/pipe?delta").body(&b"\0\0\0\x40"[..]).send().status, 403);
+
+    let listing = server.get("/?format=json&hashes=blake3").send();
+    assert_eq!(listing.status, 200);
+    let listing: serde_json::Value = serde_json::from_slice(&listing.body).unwrap();
+    assert!(listing["hashes"]["blake3"].get("pipe").is_none(), "{}", listing);
+    assert!(listing["hashes"]["blake3"]["file.txt"].is_string(), "{}", listing);
+}
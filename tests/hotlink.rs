@@ -0,0 +1,47 @@
+extern crate serde_json;
+extern crate flate2;
+
+mod util;
+
+use self::util::Server;
+use std::fs;
+
+
+fn hotlink_fixture(args: &[&str]) -> Server {
+    Server::start(args, |site| {
+        fs::write(site.join("cat.png"), "not really a cat").unwrap();
+        fs::write(site.join("notes.txt"), "Abolish the burgeoisie!\n").unwrap();
+    })
+}
+
+
+#[test]
+fn media_refused_to_other_sites() {
+    let server = hotlink_fixture(&["--hotlink-protection"]);
+
+    assert_eq!(server.get("/cat.png").header("Referer", "https://leech.example/page.html").send().status, 403);
+    assert_eq!(server.get("/notes.txt").header("Referer", "https://leech.example/page.html").send().status, 200);
+}
+
+#[test]
+fn same_site_and_direct_allowed() {
+    let server = hotlink_fixture(&["--hotlink-protection"]);
+
+    assert_eq!(server.get("/cat.png").send().status, 200);
+    assert_eq!(server.get("/cat.png").header("Referer", "http://127.0.0.1/gallery/").send().status, 200);
+}
+
+#[test]
+fn allowed_referrers() {
+    let server = hotlink_fixture(&["--hotlink-protection", "--allowed-referrer", "friend.example"]);
+
+    assert_eq!(server.get("/cat.png").header("Referer", "https://FRIEND.example/").send().status, 200);
+    assert_eq!(server.get("/cat.png").header("Referer", "https://leech.example/").send().status, 403);
+}
+
+#[test]
+fn not_protected_by_default() {
+    let server = hotlink_fixture(&[]);
+
+    assert_eq!(server.get("/cat.png").header("Referer", "https://leech.example/page.html").send().status, 200);
+}
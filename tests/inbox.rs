@@ -0,0 +1,87 @@
+extern crate serde_json;
+extern crate flate2;
+
+mod util;
+
+use self::util::Server;
+use std::{fs, thread};
+
+
+fn inbox_fixture() -> Server {
+    Server::start(&["-w", "--inbox"], |site| fs::write(site.join("secret.txt"), "classified").unwrap())
+}
+
+
+#[test]
+fn nothing_readable() {
+    let server = inbox_fixture();
+
+    assert_eq!(server.get("/secret.txt").send().status, 405);
+    assert_eq!(server.get("/").send().status, 405);
+    assert_eq!(server.request("DELETE", "/secret.txt").send().status, 405);
+
+    let resp = server.request("OPTIONS", "/").send();
+    assert_eq!(resp.status, 204);
+    assert_eq!(resp.header("Allow"), Some("OPTIONS, POST, PUT"));
+}
+
+#[test]
+fn upload() {
+    let server = inbox_fixture();
+
+    let resp = server.request("PUT", "/sub/new.txt").body("put").send();
+    assert_eq!(resp.status, 201);
+    assert_eq!(resp.header("Location"), Some("/sub/new.txt"));
+    assert_eq!(fs::read_to_string(server.site().join("sub").join("new.txt")).unwrap(), "put");
+
+    assert_eq!(server.request("POST", "/posted.txt").body("post").send().status, 201);
+    assert_eq!(fs::read_to_string(server.site().join("posted.txt")).unwrap(), "post");
+}
+
+#[test]
+fn never_replaces() {
+    let server = inbox_fixture();
+
+    let resp = server.request("PUT", "/secret.txt").body("overwritten").send();
+    assert_eq!(resp.status, 201);
+    assert_eq!(resp.header("Location"), Some("/secret%20(1).txt"));
+    let resp = server.request("PUT", "/secret.txt").body("again").send();
+    assert_eq!(resp.header("Location"), Some("/secret%20(2).txt"));
+
+    assert_eq!(fs::read_to_string(server.site().join("secret.txt")).unwrap(), "classified");
+    assert_eq!(fs::read_to_string(server.site().join("secret (1).txt")).unwrap(), "overwritten");
+    assert_eq!(fs::read_to_string(server.site().join("secret (2).txt")).unwrap(), "again");
+}
+
+#[test]
+fn needs_name() {
+    let server = inbox_fixture();
+
+    assert_eq!(server.request("PUT", "/").body("nameless").send().status, 400);
+}
+
+#[test]
+fn post_with_file_name() {
+    let server = inbox_fixture();
+
+    let resp = server.request("POST", "/").header("X-File-Name", "secret.txt").body("more").send();
+    assert_eq!(resp.status, 201);
+    assert_eq!(resp.header("Location"), Some("/secret%20(1).txt"));
+}
+
+#[test]
+fn concurrent_uploads_get_own_names() {
+    let server = inbox_fixture();
+
+    thread::scope(|s| {
+        for i in 0..8 {
+            let server = &server;
+            s.spawn(move || assert_eq!(server.request("PUT", "/secret.txt").body(format!("upload {}", i)).send().status, 201));
+        }
+    });
+
+    let mut uploaded: Vec<_> = (1..9).map(|i| fs::read_to_string(server.site().join(format!("secret ({}).txt", i))).unwrap()).collect();
+    uploaded.sort();
+    assert_eq!(uploaded, (0..8).map(|i| format!("upload {}", i)).collect::<Vec<_>>());
+    assert_eq!(fs::read_to_string(server.site().join("secret.txt")).unwrap(), "classified");
+}
@@ -0,0 +1,136 @@
+extern crate serde_json;
+extern crate flate2;
+
+mod util;
+
+use self::util::Server;
+use std::fs;
+
+
+fn logging_fixture(args: &[&str]) -> Server {
+    Server::start_logging(args, |site| fs::write(site.join("file.txt"), "Abolish the burgeoisie!\n").unwrap())
+}
+
+
+#[test]
+fn tagged_with_method_and_status() {
+    let mut server = logging_fixture(&["--log-tags"]);
+
+    assert_eq!(server.get("/file.txt").send().status, 200);
+    let line = server.log_line();
+    assert!(line.starts_with("[GET 200] "), "{}", line);
+    assert!(line.contains("file.txt"), "{}", line);
+
+    assert_eq!(server.request("PUT", "/file.txt").body("nope").send().status, 403);
+    assert!(server.log_line().starts_with("[PUT 403] "));
+
+    assert_eq!(server.get("/nonexistent").send().status, 404);
+    assert!(server.log_line().starts_with("[GET 404] "));
+}
+
+#[test]
+fn tags_replace_colours() {
+    let mut server = logging_fixture(&["--log-tags"]);
+
+    assert_eq!(server.get("/file.txt").send().status, 200);
+    assert!(!server.log_line().contains('\x1b'));
+}
+
+#[test]
+fn coloured_by_default() {
+    let mut server = logging_fixture(&[]);
+
+    assert_eq!(server.get("/file.txt").send().status, 200);
+    let line = server.log_line();
+    assert!(line.contains('\x1b'));
+    assert!(!line.contains("[GET 200]"));
+}
+
+#[test]
+fn unlogged_successes_skipped() {
+    let mut server = logging_fixture(&["--unlogged", "/file.txt"]);
+
+    assert_eq!(server.get("/file.txt").send().status, 200);
+    assert_eq!(server.get("/nonexistent").send().status, 404);
+    assert!(server.log_line().contains("nonexistent"));
+}
+
+#[test]
+fn unlogged_failures_logged() {
+    let mut server = logging_fixture(&["--unlogged", "/healthz", "--log-tags"]);
+
+    assert_eq!(server.get("/healthz").send().status, 404);
+    assert!(server.log_line().starts_with("[GET 404] "));
+}
+
+#[test]
+fn startup_banner() {
+    let mut server = logging_fixture(&["--no-colour"]);
+    let lines = server.startup_lines();
+    let url = format!("http://127.0.0.1:{}/", server.port());
+
+    assert!(lines[0].starts_with("Hosting \""), "{:?}", lines);
+    let field = |name: &str| {
+        lines.iter()
+            .find(|l| l.trim_start().starts_with(name))
+            .map(|l| l.trim_start()[name.len()..].trim().to_string())
+            .unwrap_or_else(|| panic!("No {} in {:?}", name, lines))
+    };
+    assert_eq!(field("Root"), server.site().display().to_string());
+    assert_eq!(field("URL"), url);
+    assert_eq!(field("Writes"), "disabled");
+    assert_eq!(field("TLS"), "disabled");
+    assert_eq!(field("Authentication"), "none");
+    assert_eq!(field("Download"), format!("curl -O {}FILE", url));
+    assert!(!lines.iter().any(|l| l.contains("Upload")), "{:?}", lines);
+}
+
+#[test]
+fn startup_banner_writes_and_auth() {
+    let mut server = logging_fixture(&["--no-colour", "-w", "--auth", "user:pass"]);
+    let lines = server.startup_lines();
+    let url = format!("http://127.0.0.1:{}/", server.port());
+
+    assert!(lines.iter().any(|l| l.trim() == "Writes          enabled"), "{:?}", lines);
+    assert!(lines.iter().any(|l| l.trim() == "Authentication  basic, under /"), "{:?}", lines);
+    assert!(lines.iter().any(|l| l.trim() == format!("Upload    curl -u user -T FILE {}FILE", url)), "{:?}", lines);
+    // The password's only in the credentials table
+    assert_eq!(lines.iter().filter(|l| l.contains("pass")).count(), 1, "{:?}", lines);
+}
+
+#[test]
+fn headers_logged() {
+    let mut server = logging_fixture(&["--log-headers", "--no-colour"]);
+
+    assert_eq!(server.get("/file.txt").header("X-Debug", "yes").header("Authorization", "Basic dXNlcjpwYXNz").send().status, 200);
+    let mut lines = vec![];
+    while !lines.last().map(|l: &String| l.starts_with("and was answered")).unwrap_or(false) {
+        lines.push(server.log_line());
+    }
+    assert!(lines.iter().any(|l| l == "sent GET file.txt with headers"), "{:?}", lines);
+    assert!(lines.iter().any(|l| l == "X-Debug: yes"), "{:?}", lines);
+    assert!(lines.iter().any(|l| l == "Authorization: Basic [redacted]"), "{:?}", lines);
+    assert!(!lines.iter().any(|l| l.contains("dXNlcjpwYXNz")), "{:?}", lines);
+    assert_eq!(lines.last().unwrap(), "and was answered 200 OK with headers");
+    assert!(server.log_line().starts_with("Server: "));
+}
+
+#[test]
+fn schemeless_credentials_redacted() {
+    let mut server = logging_fixture(&["--log-headers", "--no-colour"]);
+
+    let resp = server.get("/file.txt")
+        .header("Authorization", "dXNlcjpwYXNz")
+        .header("Proxy-Authorization", "Basic cHJveHk6cGFzcw==")
+        .header("Cookie", "theme=red; session=c2VjcmV0")
+        .send();
+    assert_eq!(resp.status, 200);
+    let mut lines = vec![];
+    while !lines.last().map(|l: &String| l.starts_with("and was answered")).unwrap_or(false) {
+        lines.push(server.log_line());
+    }
+    assert!(lines.iter().any(|l| l == "Authorization: [redacted]"), "{:?}", lines);
+    assert!(lines.iter().any(|l| l == "Proxy-Authorization: Basic [redacted]"), "{:?}", lines);
+    assert!(lines.iter().any(|l| l == "Cookie: [redacted]"), "{:?}", lines);
+    assert!(!lines.iter().any(|l| l.contains("dXNlcjpwYXNz") || l.contains("cHJveHk6cGFzcw") || l.contains("session")), "{:?}", lines);
+}
@@ -0,0 +1,50 @@
+extern crate serde_json;
+extern crate flate2;
+
+mod util;
+
+use self::util::Server;
+
+
+#[test]
+fn post_becomes_delete() {
+    let server = Server::start(&["-w", "--method-override"], |site| {
+        std::fs::write(site.join("file.txt"), "data").unwrap();
+    });
+
+    let resp = server.request("POST", "/file.txt").header("X-HTTP-Method-Override", "DELETE").send();
+    assert_eq!(resp.status, 204);
+    assert_eq!(server.get("/file.txt").send().status, 404);
+}
+
+#[test]
+fn post_becomes_put() {
+    let server = Server::start(&["-w", "--method-override"], |_| {});
+
+    let resp = server.request("POST", "/new.txt").header("X-HTTP-Method-Override", "put").body("new data").send();
+    assert_eq!(resp.status, 201);
+    assert_eq!(server.get("/new.txt").send().text(), "new data");
+}
+
+#[test]
+fn invalid_override_refused() {
+    let server = Server::start(&["-w", "--method-override"], |site| {
+        std::fs::write(site.join("file.txt"), "data").unwrap();
+    });
+
+    for method in &["CONNECT", "POST", "DE LETE"] {
+        let resp = server.request("POST", "/file.txt").header("X-HTTP-Method-Override", method).send();
+        assert_eq!(resp.status, 400, "{}", method);
+    }
+    assert_eq!(server.get("/file.txt").send().text(), "data");
+}
+
+#[test]
+fn ignored_when_disabled() {
+    let server = Server::start(&["-w"], |site| {
+        std::fs::write(site.join("file.txt"), "data").unwrap();
+    });
+
+    server.request("POST", "/file.txt").header("X-HTTP-Method-Override", "DELETE").send();
+    assert_eq!(server.get("/file.txt").send().text(), "data");
+}
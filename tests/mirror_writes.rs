@@ -0,0 +1,67 @@
+extern crate serde_json;
+extern crate flate2;
+
+mod util;
+
+use std::time::{Duration, Instant};
+use std::path::{PathBuf, Path};
+use self::util::Server;
+use std::{env, fs, process, thread};
+
+
+fn backup_dir(name: &str) -> PathBuf {
+    let backup = env::temp_dir().join(format!("http-test-backup-{}-{}", process::id(), name));
+    let _ = fs::remove_dir_all(&backup);
+    fs::create_dir_all(&backup).unwrap();
+    backup
+}
+
+/// Replication happens in the background, so give it a bit
+fn eventually<F: Fn() -> bool>(cond: F) -> bool {
+    let start = Instant::now();
+    while start.elapsed() < Duration::from_secs(5) {
+        if cond() {
+            return true;
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+    false
+}
+
+fn read(p: &Path) -> Option<String> {
+    fs::read_to_string(p).ok()
+}
+
+
+#[test]
+fn uploads_and_deletions_replicated() {
+    let backup = backup_dir("writes");
+    let server = Server::start(&["-w", "--mirror-writes", backup.to_str().unwrap()], |_| {});
+    assert_eq!(server.config()["mirror_writes"].as_str(), backup.canonicalize().unwrap().to_str());
+
+    assert_eq!(server.request("PUT", "/dir/file.txt").body("data").send().status, 201);
+    assert!(eventually(|| read(&backup.join("dir").join("file.txt")).as_deref() == Some("data")));
+
+    assert_eq!(server.request("PUT", "/dir/file.txt").body("newer data").send().status, 204);
+    assert!(eventually(|| read(&backup.join("dir").join("file.txt")).as_deref() == Some("newer data")));
+
+    assert_eq!(server.request("DELETE", "/dir").send().status, 204);
+    assert!(eventually(|| !backup.join("dir").exists()));
+
+    let _ = fs::remove_dir_all(&backup);
+}
+
+#[test]
+fn moves_replicated() {
+    let backup = backup_dir("moves");
+    let server = Server::start(&["-w", "--webdav", "--mirror-writes", backup.to_str().unwrap()], |_| {});
+
+    assert_eq!(server.request("PUT", "/from.txt").body("moved").send().status, 201);
+    assert!(eventually(|| backup.join("from.txt").exists()));
+
+    let destination = format!("http://127.0.0.1:{}/to.txt", server.config()["port"]);
+    assert_eq!(server.request("MOVE", "/from.txt").header("Destination", &destination).send().status, 201);
+    assert!(eventually(|| !backup.join("from.txt").exists() && read(&backup.join("to.txt")).as_deref() == Some("moved")));
+
+    let _ = fs::remove_dir_all(&backup);
+}
@@ -0,0 +1,81 @@
+extern crate serde_json;
+extern crate flate2;
+
+mod util;
+
+use self::util::Server;
+use serde_json::Value;
+use std::fs;
+
+
+fn offline_fixture(args: &[&str]) -> Server {
+    Server::start(args, |site| {
+        fs::create_dir_all(site.join("app").join("img")).unwrap();
+        fs::write(site.join("app").join("index.html"), "<p>Abolish the burgeoisie!</p>\n").unwrap();
+        fs::write(site.join("app").join("img").join("fist.png"), "not really a fist").unwrap();
+        fs::write(site.join("app").join("with space.txt"), "Workers of the world, unite!\n").unwrap();
+        fs::write(site.join("outside.txt"), "Means of production\n").unwrap();
+    })
+}
+
+fn manifest(server: &Server) -> Value {
+    let resp = server.get("/app/?offline=manifest").send();
+    assert_eq!(resp.status, 200);
+    assert_eq!(resp.header("Cache-Control"), Some("no-cache"));
+    serde_json::from_slice(&resp.body).unwrap()
+}
+
+
+#[test]
+fn manifest_lists_directory() {
+    let server = offline_fixture(&["--offline-bundle"]);
+
+    let manifest = manifest(&server);
+    assert_eq!(manifest["scope"], "/app/");
+    assert_eq!(manifest["files"], json_files(&["/app/", "/app/img/", "/app/img/fist.png", "/app/index.html", "/app/with%20space.txt"]));
+}
+
+#[test]
+fn version_follows_files() {
+    let server = offline_fixture(&["--offline-bundle"]);
+
+    let version = manifest(&server)["version"].clone();
+    assert_eq!(manifest(&server)["version"], version);
+
+    fs::write(server.site().join("app").join("index.html"), "<p>Abolish the burgeoisie, now!</p>\n").unwrap();
+    assert_ne!(manifest(&server)["version"], version);
+}
+
+#[test]
+fn service_worker() {
+    let server = offline_fixture(&["--offline-bundle"]);
+
+    let version = manifest(&server)["version"].as_str().unwrap().to_string();
+    let resp = server.get("/app/?offline=sw").send();
+    assert_eq!(resp.status, 200);
+    assert_eq!(resp.header("Content-Type"), Some("application/javascript;charset=utf-8"));
+    let sw = resp.text();
+    assert!(sw.contains(&version), "{}", sw);
+    assert!(sw.contains("\"/app/with%20space.txt\""), "{}", sw);
+    assert!(!sw.contains("outside.txt"), "{}", sw);
+    assert!(!sw.contains("{files}") && !sw.contains("{scope}"), "{}", sw);
+}
+
+#[test]
+fn invalid_part() {
+    let server = offline_fixture(&["--offline-bundle"]);
+
+    assert_eq!(server.get("/app/?offline=everything").send().status, 400);
+}
+
+#[test]
+fn disabled_by_default() {
+    let server = offline_fixture(&[]);
+
+    assert_eq!(server.get("/app/?offline=manifest").send().status, 403);
+}
+
+
+fn json_files(files: &[&str]) -> Value {
+    Value::Array(files.iter().map(|&f| Value::from(f)).collect())
+}
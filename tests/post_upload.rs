@@ -0,0 +1,57 @@
+extern crate serde_json;
+extern crate flate2;
+
+mod util;
+
+use self::util::Server;
+use std::fs;
+
+
+#[test]
+fn creates_file() {
+    let server = Server::start(&["-w"], |site| fs::create_dir(site.join("dir")).unwrap());
+
+    let resp = server.request("POST", "/dir/").header("X-File-Name", "na%C3%AFve%20notes.txt").body("contents").send();
+    assert_eq!(resp.status, 201);
+    assert_eq!(resp.header("Location"), Some("/dir/na%C3%AFve%20notes.txt"));
+    assert_eq!(fs::read_to_string(server.site().join("dir").join("naïve notes.txt")).unwrap(), "contents");
+}
+
+#[test]
+fn replaces_like_put() {
+    let server = Server::start(&["-w"], |site| fs::write(site.join("file.txt"), "old").unwrap());
+
+    let resp = server.request("POST", "/").header("X-File-Name", "file.txt").body("new").send();
+    assert!(resp.status == 200 || resp.status == 204, "{}", resp.status);
+    assert_eq!(server.get("/file.txt").send().text(), "new");
+}
+
+#[test]
+fn invalid_names_refused() {
+    let server = Server::start(&["-w"], |_| {});
+
+    for name in &["", "..", "a/b", "a%2Fb", "%2E%2E"] {
+        let resp = server.request("POST", "/").header("X-File-Name", name).body("x").send();
+        assert_eq!(resp.status, 400, "{}", name);
+    }
+    assert_eq!(server.request("POST", "/file.txt").header("X-File-Name", "file.txt").body("x").send().status, 400);
+}
+
+#[test]
+fn needs_writes() {
+    let server = Server::start(&[], |_| {});
+
+    assert_eq!(server.request("POST", "/").header("X-File-Name", "file.txt").body("x").send().status, 403);
+}
+
+#[test]
+fn any_content_type() {
+    let server = Server::start(&["-w", "--deploy"], |site| fs::write(site.join("old.txt"), "kept").unwrap());
+
+    for &(name, content_type) in &[("data.json", "application/json"), ("site.tar", "application/x-tar"), ("site.tar.gz", "application/gzip")] {
+        let resp = server.request("POST", "/").header("X-File-Name", name).header("Content-Type", content_type).body("not a batch or archive").send();
+        assert_eq!(resp.status, 201, "{}", name);
+        assert_eq!(fs::read_to_string(server.site().join(name)).unwrap(), "not a batch or archive");
+    }
+    assert_eq!(server.get("/old.txt").send().text(), "kept");
+}
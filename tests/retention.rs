@@ -0,0 +1,56 @@
+extern crate serde_json;
+extern crate flate2;
+
+mod util;
+
+use std::time::{SystemTime, Duration, Instant};
+use self::util::Server;
+use std::path::Path;
+use std::{fs, thread};
+
+
+fn age(p: &Path, days: u64) {
+    fs::File::options().write(true).open(p).unwrap().set_modified(SystemTime::now() - Duration::from_secs(days * 24 * 60 * 60)).unwrap();
+}
+
+fn retention_fixture(extra: &[&str]) -> Server {
+    let mut args = vec!["--retention", "incoming=7d", "--retention-interval", "1"];
+    args.extend(extra);
+    Server::start(&args, |site| {
+        fs::create_dir_all(site.join("incoming").join("old")).unwrap();
+        for f in &["incoming/stale.txt", "incoming/old/stale.txt", "incoming/fresh.txt", "kept.txt"] {
+            fs::write(site.join(f), "data").unwrap();
+        }
+        for f in &["incoming/stale.txt", "incoming/old/stale.txt", "kept.txt"] {
+            age(&site.join(f), 10);
+        }
+    })
+}
+
+
+#[test]
+fn old_files_deleted() {
+    let server = retention_fixture(&[]);
+    let site = server.site();
+
+    let start = Instant::now();
+    while site.join("incoming").join("old").exists() && start.elapsed() < Duration::from_secs(5) {
+        thread::sleep(Duration::from_millis(20));
+    }
+    assert!(!site.join("incoming").join("stale.txt").exists());
+    assert!(!site.join("incoming").join("old").exists());
+    assert!(site.join("incoming").join("fresh.txt").exists());
+    assert!(site.join("kept.txt").exists());
+    assert_eq!(server.get("/incoming/stale.txt").send().status, 404);
+}
+
+#[test]
+fn dry_run_deletes_nothing() {
+    let server = retention_fixture(&["--retention-dry-run"]);
+    assert_eq!(server.config()["retention"]["/incoming"].as_u64(), Some(7 * 24 * 60 * 60));
+    assert_eq!(server.config()["retention_dry_run"].as_bool(), Some(true));
+
+    thread::sleep(Duration::from_millis(1500));
+    assert!(server.site().join("incoming").join("stale.txt").exists());
+    assert!(server.site().join("incoming").join("old").join("stale.txt").exists());
+}
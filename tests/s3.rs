@@ -0,0 +1,53 @@
+extern crate serde_json;
+extern crate flate2;
+
+mod util;
+
+use self::util::Server;
+use std::fs;
+
+
+/// `user:pw` as basic credentials
+const AUTH: &str = "Basic dXNlcjpwdw==";
+
+/// Any `x-amz-*` header marks a request as an S3 one
+const AMZ_DATE: &str = "20260101T000000Z";
+
+
+fn s3_fixture(args: &[&str]) -> Server {
+    Server::start(&[&["--s3"], args].concat(), |site| {
+        fs::create_dir_all(site.join("bucket").join("private")).unwrap();
+        fs::create_dir_all(site.join("bucket").join("dir")).unwrap();
+        fs::write(site.join("bucket").join("open.txt"), "Workers of the world, unite!\n").unwrap();
+        fs::write(site.join("bucket").join("dir").join("file.txt"), "Abolish the burgeoisie!\n").unwrap();
+        fs::write(site.join("bucket").join("private").join("secret.txt"), "Seize the means of production\n").unwrap();
+    })
+}
+
+
+#[test]
+fn list_objects_needs_their_credentials() {
+    let server = s3_fixture(&["--path-auth", "bucket/private=user:pw"]);
+
+    let listing = server.get("/bucket/?list-type=2").send().text();
+    assert!(listing.contains("<Key>open.txt</Key>"), "{}", listing);
+    assert!(listing.contains("<Key>dir/file.txt</Key>"), "{}", listing);
+    assert!(!listing.contains("secret"), "{}", listing);
+
+    let listing = server.get("/bucket/?list-type=2").header("Authorization", AUTH).send().text();
+    assert!(listing.contains("<Key>private/secret.txt</Key>"), "{}", listing);
+}
+
+#[test]
+fn delete_object_never_removes_directories() {
+    let server = s3_fixture(&["-w"]);
+
+    let resp = server.request("DELETE", "/bucket/dir").header("X-Amz-Date", AMZ_DATE).send();
+    assert_eq!(resp.status, 404);
+    assert!(resp.text().contains("<Code>NoSuchKey</Code>"));
+    assert_eq!(server.request("DELETE", "/bucket/dir/").header("X-Amz-Date", AMZ_DATE).send().status, 404);
+    assert!(server.site().join("bucket").join("dir").join("file.txt").exists());
+
+    assert_eq!(server.request("DELETE", "/bucket/dir/file.txt").header("X-Amz-Date", AMZ_DATE).send().status, 204);
+    assert!(!server.site().join("bucket").join("dir").join("file.txt").exists());
+}
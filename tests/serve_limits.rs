@@ -0,0 +1,43 @@
+extern crate serde_json;
+extern crate flate2;
+
+mod util;
+
+use std::time::{Duration, Instant};
+use self::util::Server;
+use std::{fs, thread};
+
+
+fn wait_exit(server: &mut Server) -> bool {
+    let start = Instant::now();
+    while !server.exited() && start.elapsed() < Duration::from_secs(5) {
+        thread::sleep(Duration::from_millis(20));
+    }
+    server.exited()
+}
+
+
+#[test]
+fn stops_after_serves() {
+    let mut server = Server::start(&["--max-serves", "2"], |site| fs::write(site.join("file.txt"), "data").unwrap());
+    assert_eq!(server.config()["max_serves"].as_u64(), Some(2));
+
+    // Listings and partial downloads don't count
+    assert_eq!(server.get("/").send().status, 200);
+    assert_eq!(server.get("/file.txt").header("Range", "bytes=1-").send().status, 206);
+    assert_eq!(server.get("/file.txt").send().text(), "data");
+    assert!(!server.exited());
+
+    assert_eq!(server.get("/file.txt").send().text(), "data");
+    assert!(wait_exit(&mut server));
+}
+
+#[test]
+fn stops_after_bytes() {
+    let mut server = Server::start(&["--max-bytes", "10K"], |site| fs::write(site.join("file.txt"), "0123456789".repeat(600)).unwrap());
+
+    assert_eq!(server.get("/file.txt").send().body.len(), 6000);
+    assert!(!server.exited());
+    assert_eq!(server.get("/file.txt").send().body.len(), 6000);
+    assert!(wait_exit(&mut server));
+}
@@ -0,0 +1,448 @@
+extern crate serde_json;
+extern crate flate2;
+extern crate blake3;
+
+mod util;
+
+use self::util::Server;
+use std::fs;
+use std::time::{SystemTime, Duration};
+
+
+fn text_fixture() -> Server {
+    Server::start(&[], |site| {
+        fs::write(site.join("digits.txt"), "0123456789").unwrap();
+        fs::write(site.join("big.txt"), "Abolish the burgeoisie!\n".repeat(1000)).unwrap();
+        fs::write(site.join("empty.txt"), "").unwrap();
+    })
+}
+
+
+#[test]
+fn whole_file() {
+    let server = text_fixture();
+    let resp = server.get("/digits.txt").send();
+    assert_eq!(resp.status, 200);
+    assert_eq!(resp.header("Accept-Ranges"), Some("bytes"));
+    assert_eq!(resp.text(), "0123456789");
+
+    assert_eq!(server.get("/nonexistent.txt").send().status, 404);
+}
+
+#[test]
+fn closed_range() {
+    let server = text_fixture();
+    let resp = server.get("/digits.txt").header("Range", "bytes=2-5").send();
+    assert_eq!(resp.status, 206);
+    assert_eq!(resp.header("Content-Range"), Some("bytes 2-5/10"));
+    assert_eq!(resp.text(), "2345");
+}
+
+#[test]
+fn closed_range_past_end() {
+    let server = text_fixture();
+    let resp = server.get("/digits.txt").header("Range", "bytes=7-100").send();
+    assert_eq!(resp.status, 206);
+    assert_eq!(resp.header("Content-Range"), Some("bytes 7-9/10"));
+    assert_eq!(resp.text(), "789");
+
+    // Nowhere near 4GiB is allocated for this
+    let resp = server.get("/big.txt").header("Range", "bytes=24-4294967295").send();
+    assert_eq!(resp.status, 206);
+    assert_eq!(resp.header("Content-Range"), Some("bytes 24-23999/24000"));
+    assert_eq!(resp.header("Content-Length"), Some("23976"));
+    assert_eq!(resp.text(), "Abolish the burgeoisie!\n".repeat(999));
+}
+
+#[test]
+fn open_ranges() {
+    let server = text_fixture();
+
+    let resp = server.get("/digits.txt").header("Range", "bytes=6-").send();
+    assert_eq!(resp.status, 206);
+    assert_eq!(resp.header("Content-Range"), Some("bytes 6-9/10"));
+    assert_eq!(resp.text(), "6789");
+
+    let resp = server.get("/digits.txt").header("Range", "bytes=-3").send();
+    assert_eq!(resp.status, 206);
+    assert_eq!(resp.header("Content-Range"), Some("bytes 7-9/10"));
+    assert_eq!(resp.text(), "789");
+
+    let resp = server.get("/digits.txt").header("Range", "bytes=-30").send();
+    assert_eq!(resp.status, 206);
+    assert_eq!(resp.text(), "0123456789");
+}
+
+#[test]
+fn unsatisfiable_ranges() {
+    let server = text_fixture();
+
+    let resp = server.get("/digits.txt").header("Range", "bytes=10-").send();
+    assert_eq!(resp.status, 416);
+    assert_eq!(resp.header("Content-Range"), Some("bytes */10"));
+
+    assert_eq!(server.get("/digits.txt").header("Range", "bytes=-0").send().status, 416);
+    assert_eq!(server.get("/empty.txt").header("Range", "bytes=-5").send().status, 416);
+    assert_eq!(server.get("/digits.txt").header("Range", "bytes=10-12,20-").send().status, 416);
+}
+
+#[test]
+fn multiple_ranges() {
+    let server = text_fixture();
+
+    let resp = server.get("/digits.txt").header("Range", "bytes=6-7,0-1,-1").send();
+    assert_eq!(resp.status, 206);
+    assert_eq!(resp.header("Content-Range"), None);
+    let content_type = resp.header("Content-Type").unwrap();
+    assert!(content_type.starts_with("multipart/byteranges; boundary="), "{}", content_type);
+    let boundary = &content_type["multipart/byteranges; boundary=".len()..];
+
+    let body = resp.text();
+    assert_eq!(resp.header("Content-Length"), Some(&body.len().to_string()[..]));
+    let parts: Vec<_> = body.split(&format!("--{}", boundary)).collect();
+    assert_eq!(parts.len(), 5);
+    assert_eq!(parts[1], "\r\nContent-Type: text/plain\r\nContent-Range: bytes 6-7/10\r\n\r\n67\r\n");
+    assert_eq!(parts[2], "\r\nContent-Type: text/plain\r\nContent-Range: bytes 0-1/10\r\n\r\n01\r\n");
+    assert_eq!(parts[3], "\r\nContent-Type: text/plain\r\nContent-Range: bytes 9-9/10\r\n\r\n9\r\n");
+    assert_eq!(parts[4], "--\r\n");
+}
+
+#[test]
+fn multiple_ranges_coalesced() {
+    let server = text_fixture();
+
+    // Overlapping ranges are merged, in file order
+    let resp = server.get("/digits.txt").header("Range", "bytes=5-7,0-1,6-8").send();
+    assert_eq!(resp.status, 206);
+    let body = resp.text();
+    assert!(body.contains("Content-Range: bytes 0-1/10\r\n\r\n01\r\n"));
+    assert!(body.contains("Content-Range: bytes 5-8/10\r\n\r\n5678\r\n"));
+    assert!(body.find("bytes 0-1").unwrap() < body.find("bytes 5-8").unwrap());
+
+    // Only one left satisfiable, or after merging, is a plain range
+    let resp = server.get("/digits.txt").header("Range", "bytes=2-3,50-").send();
+    assert_eq!(resp.status, 206);
+    assert_eq!(resp.header("Content-Range"), Some("bytes 2-3/10"));
+    assert_eq!(resp.text(), "23");
+    let resp = server.get("/digits.txt").header("Range", "bytes=2-5,4-6").send();
+    assert_eq!(resp.header("Content-Range"), Some("bytes 2-6/10"));
+    assert_eq!(resp.text(), "23456");
+
+    let many = (0..65).map(|i| format!("{}-{}", i, i)).collect::<Vec<_>>().join(",");
+    assert_eq!(server.get("/big.txt").header("Range", &format!("bytes={}", many)).send().status, 416);
+}
+
+#[test]
+fn gzip_encoding() {
+    let server = text_fixture();
+    let expected = "Abolish the burgeoisie!\n".repeat(1000).into_bytes();
+
+    let resp = server.get("/big.txt").header("Accept-Encoding", "gzip").send();
+    assert_eq!(resp.status, 200);
+    assert_eq!(resp.header("Content-Encoding"), Some("gzip"));
+    assert!(resp.body.len() < expected.len());
+    assert_eq!(resp.decoded(), expected);
+
+    let resp = server.get("/big.txt").send();
+    assert_eq!(resp.header("Content-Encoding"), None);
+    assert_eq!(resp.body, expected);
+
+    // Too small to bother
+    let resp = server.get("/digits.txt").header("Accept-Encoding", "gzip").send();
+    assert_eq!(resp.header("Content-Encoding"), None);
+    assert_eq!(resp.text(), "0123456789");
+}
+
+#[test]
+fn encoding_cache() {
+    let server = text_fixture();
+
+    let first = server.get("/big.txt").header("Accept-Encoding", "gzip").send();
+    let second = server.get("/big.txt").header("Accept-Encoding", "gzip").send();
+    assert_eq!(first.body, second.body);
+
+    // Cached by content, so changes are picked up
+    fs::write(server.site().join("big.txt"), "Abolish the monarchy!\n".repeat(1000)).unwrap();
+    let resp = server.get("/big.txt").header("Accept-Encoding", "gzip").send();
+    assert_eq!(resp.header("Content-Encoding"), Some("gzip"));
+    assert_eq!(resp.decoded(), "Abolish the monarchy!\n".repeat(1000).into_bytes());
+}
+
+#[test]
+fn encoded_range() {
+    let server = text_fixture();
+
+    let whole = server.get("/big.txt").header("Accept-Encoding", "gzip").send();
+    let resp = server.get("/big.txt").header("Accept-Encoding", "gzip").header("Range", "bytes=10-").send();
+    assert_eq!(resp.status, 206);
+    assert_eq!(resp.header("Content-Encoding"), Some("gzip"));
+    assert_eq!(resp.body, &whole.body[10..]);
+}
+
+#[test]
+fn no_encode() {
+    let server = Server::start(&["-e"], |site| fs::write(site.join("big.txt"), "Abolish the burgeoisie!\n".repeat(1000)).unwrap());
+
+    let resp = server.get("/big.txt").header("Accept-Encoding", "gzip").send();
+    assert_eq!(resp.header("Content-Encoding"), None);
+    assert_eq!(resp.body.len(), 24 * 1000);
+}
+
+#[test]
+fn listing() {
+    let server = text_fixture();
+
+    let resp = server.get("/").send();
+    assert_eq!(resp.status, 200);
+    let text = resp.text();
+    assert!(text.contains("href=\"/digits.txt\""));
+    assert!(text.contains("href=\"/big.txt\""));
+}
+
+#[test]
+fn escaped_paths() {
+    let server = Server::start(&[], |site| {
+        fs::create_dir(site.join("a b")).unwrap();
+        fs::write(site.join("a b").join("ж.txt"), "comrade").unwrap();
+    });
+
+    assert_eq!(server.get("/a%20b/%D0%B6.txt").send().text(), "comrade");
+    // Names that aren't UTF-8 can exist on Unices
+    assert_eq!(server.get("/a%20b/%D0.txt").send().status, if cfg!(unix) { 404 } else { 400 });
+    assert_eq!(server.get("/a%20b/..%2F..%2Fetc%2Fpasswd").send().status, 400);
+    assert_eq!(server.get("/%2Fetc%2Fpasswd").send().status, 400);
+}
+
+#[cfg(unix)]
+#[test]
+fn non_utf8_names() {
+    use std::os::unix::ffi::OsStrExt;
+    use std::ffi::OsStr;
+
+    let server = Server::start(&[], |site| {
+        fs::write(site.join(OsStr::from_bytes(b"caf\xE9 #1.txt")), "latin-1").unwrap();
+        fs::create_dir(site.join(OsStr::from_bytes(b"d\xFCr"))).unwrap();
+        fs::write(site.join(OsStr::from_bytes(b"d\xFCr")).join("inside.txt"), "comrade").unwrap();
+    });
+
+    let text = server.get("/").send().text();
+    assert!(text.contains("href=\"/caf%E9 %231.txt\""), "{}", text);
+    assert!(text.contains("caf\u{FFFD} #1.txt"), "{}", text);
+    assert!(text.contains("href=\"/d%FCr\""), "{}", text);
+
+    assert_eq!(server.get("/caf%E9%20%231.txt").send().text(), "latin-1");
+    let text = server.get("/d%FCr/").send().text();
+    assert!(text.contains("href=\"/d%FCr/inside.txt\""), "{}", text);
+    assert_eq!(server.get("/d%FCr/inside.txt").send().text(), "comrade");
+}
+
+#[test]
+fn unicode_normalisation() {
+    let fixture = |site: &std::path::Path| fs::write(site.join("e\u{301}te\u{301}.txt"), "decomposed").unwrap();
+
+    let server = Server::start(&[], fixture);
+    assert_eq!(server.get("/%C3%A9t%C3%A9.txt").send().status, 404);
+
+    let server = Server::start(&["--normalise-unicode"], fixture);
+    assert_eq!(server.get("/%C3%A9t%C3%A9.txt").send().text(), "decomposed");
+    assert_eq!(server.get("/e%CC%81te%CC%81.txt").send().text(), "decomposed");
+    let listing = server.get("/").send().text();
+    assert!(listing.contains("href=\"/\u{e9}t\u{e9}.txt\""), "{}", listing);
+}
+
+#[test]
+fn listing_hashes() {
+    let server = Server::start(&[], |site| {
+        fs::write(site.join("file.txt"), "Abolish the burgeoisie!\n").unwrap();
+        fs::create_dir(site.join("dir")).unwrap();
+    });
+
+    let listing: serde_json::Value = serde_json::from_slice(&server.get("/?format=json&hashes=blake3").send().body).unwrap();
+    assert_eq!(listing["hashes"]["blake3"]["file.txt"], blake3::hash(b"Abolish the burgeoisie!\n").to_hex().to_string());
+    assert!(listing["hashes"]["blake3"].get("dir").is_none());
+
+    let listing: serde_json::Value = serde_json::from_slice(&server.get("/?format=json").send().body).unwrap();
+    assert!(listing.get("hashes").is_none());
+    assert_eq!(server.get("/?format=json&hashes=md5").send().status, 400);
+}
+
+#[test]
+fn cache_headers() {
+    let server = Server::start(&["--cache-max-age", "1h", "--cache-max-age-for", "html:0", "--cache-max-age-for", ":30s"], |site| {
+        fs::write(site.join("digits.txt"), "0123456789").unwrap();
+        fs::write(site.join("index.html"), "<p>Abolish the burgeoisie!</p>").unwrap();
+        fs::write(site.join("README"), "Read me").unwrap();
+    });
+    assert_eq!(server.config()["cache_max_age"].as_u64(), Some(60 * 60));
+    assert_eq!(server.config()["cache_max_age_for"]["html"].as_u64(), Some(0));
+
+    let resp = server.get("/digits.txt").send();
+    assert_eq!(resp.header("Cache-Control"), Some("max-age=3600"));
+    assert!(resp.header("Expires").is_some());
+
+    let resp = server.get("/digits.txt").header("Range", "bytes=2-3").send();
+    assert_eq!(resp.status, 206);
+    assert_eq!(resp.header("Cache-Control"), Some("max-age=3600"));
+
+    assert_eq!(server.get("/").send().header("Cache-Control"), Some("max-age=0"));
+    assert_eq!(server.get("/index.html").send().header("Cache-Control"), Some("max-age=0"));
+    assert_eq!(server.get("/README").send().header("Cache-Control"), Some("max-age=30"));
+
+    // Only successes are cacheable
+    let resp = server.get("/digits.txt").header("Range", "bytes=20-").send();
+    assert_eq!(resp.status, 416);
+    assert_eq!(resp.header("Cache-Control"), None);
+    assert_eq!(server.get("/nonexistent").send().header("Cache-Control"), None);
+}
+
+#[test]
+fn no_cache_headers_by_default() {
+    let server = text_fixture();
+
+    let resp = server.get("/digits.txt").send();
+    assert_eq!(resp.header("Cache-Control"), None);
+    assert_eq!(resp.header("Expires"), None);
+}
+
+#[test]
+fn content_hash_etags() {
+    let server = Server::start(&["--content-hash-etags", "--cache-max-age", "1h"],
+                               |site| fs::write(site.join("big.txt"), "Abolish the burgeoisie!\n".repeat(1000)).unwrap());
+    assert_eq!(server.config()["content_hash_etags"].as_bool(), Some(true));
+    let hash = blake3::hash("Abolish the burgeoisie!\n".repeat(1000).as_bytes()).to_hex().to_string();
+
+    let resp = server.get("/big.txt").send();
+    assert_eq!(resp.header("ETag"), Some(&format!("\"{}\"", hash)[..]));
+    assert_eq!(resp.header("X-Content-Hash"), Some(&hash[..]));
+    assert_eq!(resp.header("Cache-Control"), Some("max-age=3600"));
+
+    // Of the file, so encoded responses have it too
+    let resp = server.get("/big.txt").header("Accept-Encoding", "gzip").send();
+    assert_eq!(resp.header("Content-Encoding"), Some("gzip"));
+    assert_eq!(resp.header("X-Content-Hash"), Some(&hash[..]));
+
+    let resp = server.get(&format!("/big.txt?v={}", &hash[..8])).send();
+    assert_eq!(resp.header("Cache-Control"), Some("public, max-age=31536000, immutable"));
+    let resp = server.get(&format!("/big.txt?v={}", hash)).header("Range", "bytes=0-6").send();
+    assert_eq!(resp.status, 206);
+    assert_eq!(resp.header("Cache-Control"), Some("public, max-age=31536000, immutable"));
+
+    // Stale or too short to be sure of
+    assert_eq!(server.get("/big.txt?v=00000000").send().header("Cache-Control"), Some("max-age=3600"));
+    assert_eq!(server.get(&format!("/big.txt?v={}", &hash[..4])).send().header("Cache-Control"), Some("max-age=3600"));
+    fs::write(server.site().join("big.txt"), "Abolish the monarchy!\n".repeat(1000)).unwrap();
+    let resp = server.get(&format!("/big.txt?v={}", &hash[..8])).send();
+    assert_eq!(resp.header("Cache-Control"), Some("max-age=3600"));
+    assert_ne!(resp.header("X-Content-Hash"), Some(&hash[..]));
+}
+
+#[test]
+fn metadata_etags_by_default() {
+    let server = text_fixture();
+
+    let resp = server.get("/digits.txt?v=00000000").send();
+    assert!(resp.header("ETag").is_some());
+    assert_eq!(resp.header("X-Content-Hash"), None);
+    assert_eq!(resp.header("Cache-Control"), None);
+}
+
+#[test]
+fn preload_links() {
+    let server = Server::start(&["--preload", "/style.css", "--preload", "/fonts/serif.woff2?v=2"], |site| {
+        fs::write(site.join("index.html"), "<p>Abolish the burgeoisie!</p>").unwrap();
+        fs::write(site.join("page.htm"), "<p>Abolish the monarchy!</p>").unwrap();
+        fs::write(site.join("style.css"), "p {}").unwrap();
+    });
+    assert_eq!(server.config()["preload"][1].as_str(), Some("/fonts/serif.woff2?v=2"));
+
+    for page in &["/", "/index.html", "/page.htm"] {
+        let resp = server.get(page).send();
+        assert_eq!(resp.headers("Link"),
+                   vec!["</style.css>; rel=preload; as=style", "</fonts/serif.woff2?v=2>; rel=preload; as=font; crossorigin"],
+                   "{}",
+                   page);
+    }
+
+    assert!(server.get("/style.css").send().headers("Link").is_empty());
+    assert!(server.get("/index.html").header("Range", "bytes=0-2").send().headers("Link").is_empty());
+}
+
+#[test]
+fn range_window() {
+    let server = Server::start(&["--range-window", "1k"], |site| fs::write(site.join("big.txt"), "Abolish the burgeoisie!\n".repeat(1000)).unwrap());
+    assert_eq!(server.config()["range_window"].as_u64(), Some(1024));
+
+    // Widened to the end of the window it starts in
+    let resp = server.get("/big.txt").header("Range", "bytes=1030-1040").send();
+    assert_eq!(resp.status, 206);
+    assert_eq!(resp.header("Content-Range"), Some("bytes 1030-2047/24000"));
+    assert_eq!(resp.body.len(), 2048 - 1030);
+    assert!(resp.text().starts_with(&"Abolish the burgeoisie!\n".repeat(1000)[1030..1041]));
+
+    // Not past the end of the file, or shrunk
+    let resp = server.get("/big.txt").header("Range", "bytes=23900-23910").send();
+    assert_eq!(resp.header("Content-Range"), Some("bytes 23900-23999/24000"));
+    let resp = server.get("/big.txt").header("Range", "bytes=0-5000").send();
+    assert_eq!(resp.header("Content-Range"), Some("bytes 0-5000/24000"));
+    assert_eq!(resp.text(), "Abolish the burgeoisie!\n".repeat(1000)[..5001]);
+
+    let resp = server.get("/big.txt").header("Range", "bytes=20000-").send();
+    assert_eq!(resp.text(), "Abolish the burgeoisie!\n".repeat(1000)[20000..]);
+}
+
+#[test]
+fn fd_cache() {
+    let server = Server::start(&["--fd-cache", "1"], |site| {
+        fs::write(site.join("digits.txt"), "0123456789").unwrap();
+        fs::write(site.join("big.txt"), "Abolish the burgeoisie!\n".repeat(1000)).unwrap();
+    });
+    assert_eq!(server.config()["fd_cache"].as_u64(), Some(1));
+
+    // Shared between responses, each reading at its own position
+    assert_eq!(server.get("/digits.txt").header("Range", "bytes=2-5").send().text(), "2345");
+    assert_eq!(server.get("/digits.txt").send().text(), "0123456789");
+    assert_eq!(server.get("/digits.txt").header("Range", "bytes=6-7,0-1").send().status, 206);
+    assert_eq!(server.get("/digits.txt").header("Range", "bytes=-3").send().text(), "789");
+
+    // Replaced files are reopened
+    fs::write(server.site().join("digits.txt.new"), "9876543210").unwrap();
+    fs::rename(server.site().join("digits.txt.new"), server.site().join("digits.txt")).unwrap();
+    assert_eq!(server.get("/digits.txt").send().text(), "9876543210");
+    fs::write(server.site().join("digits.txt"), "01234").unwrap();
+    assert_eq!(server.get("/digits.txt").send().text(), "01234");
+
+    // Evicted to make room for others
+    assert_eq!(server.get("/big.txt").send().body.len(), 24 * 1000);
+    assert_eq!(server.get("/big.txt").header("Accept-Encoding", "gzip").send().decoded().len(), 24 * 1000);
+    assert_eq!(server.get("/digits.txt").send().text(), "01234");
+
+    fs::remove_file(server.site().join("digits.txt")).unwrap();
+    assert_eq!(server.get("/digits.txt").send().status, 404);
+}
+
+#[test]
+fn mmap() {
+    let server = Server::start(&["--mmap-min-size", "1k"], |site| {
+        fs::write(site.join("big.txt"), "Abolish the burgeoisie!\n".repeat(1000)).unwrap();
+        fs::write(site.join("new.txt"), "Abolish the monarchy!\n".repeat(1000)).unwrap();
+        fs::OpenOptions::new()
+            .write(true)
+            .open(site.join("big.txt"))
+            .unwrap()
+            .set_modified(SystemTime::now() - Duration::from_secs(5 * 60))
+            .unwrap();
+    });
+    assert_eq!(server.config()["mmap_min_size"].as_u64(), Some(1024));
+    let big = "Abolish the burgeoisie!\n".repeat(1000);
+
+    assert_eq!(server.get("/big.txt").send().text(), big);
+    let resp = server.get("/big.txt").header("Range", "bytes=1000-2999").send();
+    assert_eq!(resp.header("Content-Range"), Some("bytes 1000-2999/24000"));
+    assert_eq!(resp.text(), big[1000..3000]);
+    assert_eq!(server.get("/big.txt").header("Range", "bytes=-5000").send().text(), big[19000..]);
+    // Too small
+    assert_eq!(server.get("/big.txt").header("Range", "bytes=10-20").send().text(), big[10..21]);
+
+    // Too recently modified, so read as usual
+    assert_eq!(server.get("/new.txt").send().text(), "Abolish the monarchy!\n".repeat(1000));
+}
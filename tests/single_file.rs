@@ -0,0 +1,53 @@
+extern crate serde_json;
+extern crate flate2;
+
+mod util;
+
+use self::util::Server;
+use std::fs;
+
+
+fn single_file_fixture(args: &[&str]) -> Server {
+    Server::start_hosting("hand off.txt", args, |site| {
+        fs::write(site.join("hand off.txt"), "Abolish the burgeoisie!\n".repeat(1000)).unwrap();
+        fs::write(site.join("other.txt"), "not for you").unwrap();
+    })
+}
+
+
+#[test]
+fn served_at_root_and_name() {
+    let server = single_file_fixture(&[]);
+    assert_eq!(server.config()["single_file"].as_str(), Some("hand off.txt"));
+
+    let resp = server.get("/").send();
+    assert_eq!(resp.status, 200);
+    assert_eq!(resp.text(), "Abolish the burgeoisie!\n".repeat(1000));
+    assert_eq!(resp.header("Content-Disposition"), Some("inline; filename=\"hand off.txt\""));
+
+    let resp = server.get("/hand%20off.txt").send();
+    assert_eq!(resp.status, 200);
+    assert_eq!(resp.text(), "Abolish the burgeoisie!\n".repeat(1000));
+}
+
+#[test]
+fn nothing_else_served() {
+    let server = single_file_fixture(&[]);
+
+    assert_eq!(server.get("/other.txt").send().status, 404);
+    assert_eq!(server.get("/hand%20off.txt/").send().status, 404);
+    assert_eq!(server.get("/nonexistent").send().status, 404);
+}
+
+#[test]
+fn ranges_and_encoding() {
+    let server = single_file_fixture(&[]);
+
+    let resp = server.get("/").header("Range", "bytes=0-6").send();
+    assert_eq!(resp.status, 206);
+    assert_eq!(resp.text(), "Abolish");
+
+    let resp = server.get("/").header("Accept-Encoding", "gzip").send();
+    assert_eq!(resp.header("Content-Encoding"), Some("gzip"));
+    assert_eq!(resp.decoded(), "Abolish the burgeoisie!\n".repeat(1000).into_bytes());
+}
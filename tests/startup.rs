@@ -0,0 +1,60 @@
+#[macro_use]
+extern crate serde_json;
+extern crate flate2;
+
+mod util;
+
+use self::util::Server;
+use std::fs;
+
+
+#[test]
+fn print_config() {
+    let server = Server::start(&["-w", "-d", "-s"], |_| {});
+    let config = server.config();
+
+    assert_eq!(config["address"], "127.0.0.1");
+    assert_ne!(config["port"], 0);
+    assert_eq!(fs::canonicalize(config["root"].as_str().unwrap()).unwrap(), fs::canonicalize(server.site()).unwrap());
+    assert_eq!(config["tls"], serde_json::Value::Null);
+    assert_eq!(config["symlinks"], "never");
+    assert_eq!(config["writes"].as_bool(), Some(true));
+    assert_eq!(config["webdav"].as_bool(), Some(true));
+    assert_eq!(config["s3"].as_bool(), Some(false));
+    assert!(config["temp_directories"]["writes"].as_str().unwrap().starts_with(config["temp_directory"].as_str().unwrap()));
+    assert_eq!(config["temp_directories"]["origin"], serde_json::Value::Null);
+}
+
+#[test]
+fn print_config_hides_credentials() {
+    let server = Server::start(&["--path-auth", "/private=user:hunter2"], |site| fs::create_dir(site.join("private")).unwrap());
+
+    assert_eq!(server.config()["authenticated_paths"], json!(["/private"]));
+    assert!(!server.config().to_string().contains("hunter2"));
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn reuseport_listeners() {
+    let server = Server::start(&["--listeners", "4", "--download-counts"], |site| fs::write(site.join("hello.txt"), "Hello").unwrap());
+    assert_eq!(server.config()["listeners"].as_u64(), Some(4));
+
+    // Connections spread between the listeners all hit the same handler state
+    for _ in 0..16 {
+        assert_eq!(server.get("/hello.txt").send().text(), "Hello");
+    }
+    let listing: serde_json::Value = serde_json::from_slice(&server.get("/?format=json").send().body).unwrap();
+    assert_eq!(listing["downloads"]["hello.txt"].as_u64(), Some(16));
+}
+
+#[test]
+fn tcp_tuning() {
+    let server = Server::start(&["--tcp-nodelay", "--send-buffer", "256K", "--recv-buffer", "0", "--backlog", "1024"],
+                               |site| fs::write(site.join("hello.txt"), "Hello").unwrap());
+    assert_eq!(server.config()["tcp_nodelay"].as_bool(), Some(true));
+    assert_eq!(server.config()["send_buffer"].as_u64(), Some(256 * 1024));
+    assert_eq!(server.config()["recv_buffer"], serde_json::Value::Null);
+    assert_eq!(server.config()["backlog"].as_u64(), Some(1024));
+
+    assert_eq!(server.get("/hello.txt").send().text(), "Hello");
+}
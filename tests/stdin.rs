@@ -0,0 +1,53 @@
+extern crate serde_json;
+extern crate flate2;
+
+mod util;
+
+use std::time::Duration;
+use self::util::Server;
+use std::io::Write;
+use std::thread;
+
+
+#[test]
+fn streamed_while_received() {
+    let (server, mut stdin) = Server::start_stdin("greeting.txt", &[]);
+    assert_eq!(server.config()["stdin"].as_bool(), Some(true));
+    assert_eq!(server.config()["single_file"].as_str(), Some("greeting.txt"));
+
+    stdin.write_all(b"Abolish ").unwrap();
+    stdin.flush().unwrap();
+    thread::sleep(Duration::from_millis(200));
+
+    assert_eq!(server.request("PUT", "/greeting.txt").body("nope").send().status, 405);
+    assert_eq!(server.get("/other.txt").send().status, 404);
+
+    let resp = thread::scope(|s| {
+        let resp = s.spawn(|| server.get("/").send());
+        thread::sleep(Duration::from_millis(200));
+        stdin.write_all(b"the burgeoisie!\n").unwrap();
+        drop(stdin);
+        resp.join().unwrap()
+    });
+    assert_eq!(resp.status, 200);
+    assert_eq!(resp.header("Content-Length"), None);
+    assert_eq!(resp.header("Content-Disposition"), Some("inline; filename=\"greeting.txt\""));
+    assert_eq!(resp.text(), "Abolish the burgeoisie!\n");
+}
+
+#[test]
+fn served_as_file_once_received() {
+    let (server, mut stdin) = Server::start_stdin("greeting.txt", &[]);
+    stdin.write_all(b"Abolish the burgeoisie!\n").unwrap();
+    drop(stdin);
+    thread::sleep(Duration::from_millis(200));
+
+    let resp = server.get("/greeting.txt").send();
+    assert_eq!(resp.status, 200);
+    assert_eq!(resp.header("Content-Length"), Some("24"));
+    assert_eq!(resp.text(), "Abolish the burgeoisie!\n");
+
+    let resp = server.get("/").header("Range", "bytes=0-6").send();
+    assert_eq!(resp.status, 206);
+    assert_eq!(resp.text(), "Abolish");
+}
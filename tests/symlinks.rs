@@ -0,0 +1,71 @@
+#![cfg(unix)]
+
+extern crate serde_json;
+extern crate flate2;
+
+mod util;
+
+use std::os::unix::fs::symlink;
+use self::util::Server;
+use std::path::Path;
+use std::fs;
+
+
+/// `inside` links to a file in the hosted directory, `outside` to one next to it, and `dangling` to nothing
+fn symlink_fixture(site: &Path) {
+    fs::create_dir(site.join("dir")).unwrap();
+    fs::write(site.join("dir").join("target.txt"), "inside").unwrap();
+    fs::write(site.parent().unwrap().join("outside.txt"), "outside").unwrap();
+
+    symlink("dir/target.txt", site.join("inside")).unwrap();
+    symlink("dir", site.join("dir_link")).unwrap();
+    symlink(site.parent().unwrap().join("outside.txt"), site.join("outside")).unwrap();
+    symlink("nonexistent", site.join("dangling")).unwrap();
+}
+
+
+#[test]
+fn followed_by_default() {
+    let server = Server::start(&[], symlink_fixture);
+
+    assert_eq!(server.get("/inside").send().text(), "inside");
+    assert_eq!(server.get("/dir_link/target.txt").send().text(), "inside");
+    assert_eq!(server.get("/outside").send().text(), "outside");
+    assert_eq!(server.get("/dangling").send().status, 404);
+}
+
+#[test]
+fn not_followed() {
+    let server = Server::start(&["-s"], symlink_fixture);
+
+    assert_eq!(server.get("/inside").send().status, 404);
+    assert_eq!(server.get("/dir_link/target.txt").send().status, 404);
+    assert_eq!(server.get("/outside").send().status, 404);
+    assert_eq!(server.get("/dir/target.txt").send().text(), "inside");
+
+    let listing = server.get("/").send().text();
+    assert!(listing.contains("href=\"/dir\""));
+    assert!(!listing.contains("href=\"/inside\""));
+}
+
+#[test]
+fn sandboxed() {
+    let server = Server::start(&["-r"], symlink_fixture);
+
+    assert_eq!(server.get("/inside").send().text(), "inside");
+    assert_eq!(server.get("/dir_link/target.txt").send().text(), "inside");
+    assert_eq!(server.get("/outside").send().status, 404);
+
+    let listing = server.get("/").send().text();
+    assert!(listing.contains("href=\"/inside\""));
+    assert!(!listing.contains("href=\"/outside\""));
+}
+
+#[test]
+fn deleting_link_keeps_target() {
+    let server = Server::start(&["-w"], symlink_fixture);
+
+    assert_eq!(server.request("DELETE", "/outside").send().status, 204);
+    assert!(!server.site().join("outside").exists());
+    assert!(server.site().parent().unwrap().join("outside.txt").exists());
+}
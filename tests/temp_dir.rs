@@ -0,0 +1,49 @@
+extern crate serde_json;
+extern crate flate2;
+
+mod util;
+
+use self::util::Server;
+use std::fs;
+
+
+/// Hosts the directory holding both the site and the temp directory, like `--temp-dir` pointed inside the hosted one
+fn enclosing_fixture(args: &[&str]) -> Server {
+    Server::start_hosting("..", args, |site| fs::write(site.join("big.txt"), "Abolish the burgeoisie!\n".repeat(1000)).unwrap())
+}
+
+
+#[test]
+fn not_listed() {
+    let server = enclosing_fixture(&[]);
+    assert_eq!(server.get("/site/big.txt").header("Accept-Encoding", "gzip").send().status, 200);
+    assert!(server.temp().read_dir().unwrap().next().is_some());
+
+    let resp = server.get("/").send();
+    assert_eq!(resp.status, 200);
+    assert!(resp.text().contains("site/"));
+    assert!(!resp.text().contains("temp/"));
+}
+
+#[test]
+fn not_served() {
+    let server = enclosing_fixture(&[]);
+    assert_eq!(server.get("/site/big.txt").header("Accept-Encoding", "gzip").send().status, 200);
+    let encoded_dir = server.temp().read_dir().unwrap().next().unwrap().unwrap().path().join("encoded");
+    let encoded = encoded_dir.read_dir().unwrap().next().unwrap().unwrap().path();
+    let encoded = encoded.strip_prefix(server.site().parent().unwrap()).unwrap().to_str().unwrap().replace('\\', "/");
+
+    assert_eq!(server.get("/temp/").send().status, 404);
+    assert_eq!(server.get("/temp").send().status, 404);
+    assert_eq!(server.get(&format!("/{}", encoded)).send().status, 404);
+    assert_eq!(server.request("HEAD", &format!("/{}", encoded)).send().status, 404);
+}
+
+#[test]
+fn not_writable() {
+    let server = enclosing_fixture(&["-w"]);
+
+    assert_eq!(server.request("PUT", "/temp/planted.txt").body("gotcha").send().status, 404);
+    assert!(!server.temp().join("planted.txt").exists());
+    assert_eq!(server.request("PUT", "/site/fine.txt").body("fine").send().status, 201);
+}
@@ -0,0 +1,79 @@
+//! The `TestServer` fixture, only built with `--features test-server`
+
+
+#![cfg(feature = "test-server")]
+
+
+extern crate https;
+
+use https::{TestServerBuilder, RecordedRequest, TestServer};
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::fs;
+
+
+fn get(server: &TestServer, path: &str) -> String {
+    let mut conn = TcpStream::connect(server.addr()).unwrap();
+    write!(conn, "GET {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n", path, server.addr()).unwrap();
+    let mut resp = String::new();
+    conn.read_to_string(&mut resp).unwrap();
+    resp
+}
+
+
+#[test]
+fn serves_tree() {
+    let server = TestServerBuilder::new().file("index.txt", "root").file("sub dir/nested.txt", "nested").dir("empty").start();
+    assert_eq!(server.url("/index.txt"), format!("http://127.0.0.1:{}/index.txt", server.port()));
+
+    let resp = get(&server, "/index.txt");
+    assert!(resp.starts_with("HTTP/1.1 200"), "{}", resp);
+    assert!(resp.ends_with("\r\n\r\nroot"), "{}", resp);
+    assert!(get(&server, "/sub%20dir/nested.txt").ends_with("\r\n\r\nnested"));
+    assert!(get(&server, "/empty/").starts_with("HTTP/1.1 200"));
+
+    server.add_file("late.txt", "added");
+    assert!(get(&server, "/late.txt").ends_with("\r\n\r\nadded"));
+    server.remove_file("late.txt");
+    assert!(get(&server, "/late.txt").starts_with("HTTP/1.1 404"));
+}
+
+#[test]
+fn records_requests() {
+    let server = TestServerBuilder::new().file("a.txt", "a").start();
+    get(&server, "/a.txt");
+    get(&server, "/missing?format=json");
+
+    assert_eq!(server.requests(),
+               vec![RecordedRequest {
+                        method: "GET".to_string(),
+                        path: "/a.txt".to_string(),
+                        status: Some(200),
+                    },
+                    RecordedRequest {
+                        method: "GET".to_string(),
+                        path: "/missing?format=json".to_string(),
+                        status: Some(404),
+                    }]);
+    server.assert_requested("GET", "/a.txt");
+    server.assert_not_requested("PUT", "/a.txt");
+    assert_eq!(server.request_count("GET", "/a.txt"), 1);
+
+    server.clear_requests();
+    assert_eq!(server.requests(), vec![]);
+}
+
+#[test]
+fn args_and_cleanup() {
+    let server = TestServerBuilder::new().args(vec!["-w"]).start();
+    let mut conn = TcpStream::connect(server.addr()).unwrap();
+    write!(conn, "PUT /up.txt HTTP/1.1\r\nHost: {}\r\nConnection: close\r\nContent-Length: 8\r\n\r\nuploaded", server.addr()).unwrap();
+    let mut resp = String::new();
+    conn.read_to_string(&mut resp).unwrap();
+    assert!(resp.starts_with("HTTP/1.1 201"), "{}", resp);
+    assert_eq!(fs::read_to_string(server.site().join("up.txt")).unwrap(), "uploaded");
+
+    let site = server.site();
+    drop(server);
+    assert!(!site.exists());
+}
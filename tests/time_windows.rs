@@ -0,0 +1,52 @@
+extern crate serde_json;
+extern crate flate2;
+extern crate time;
+
+mod util;
+
+use std::time::{Duration, Instant};
+use self::util::Server;
+use std::{fs, thread};
+
+
+/// `HH:MM-HH:MM` from `from` to `to` minutes from now, local time
+fn window(from: i32, to: i32) -> String {
+    let now = time::now();
+    let minute = |offset: i32| (now.tm_hour * 60 + now.tm_min + offset).rem_euclid(24 * 60);
+    let (start, end) = (minute(from), minute(to));
+    format!("{:02}:{:02}-{:02}:{:02}", start / 60, start % 60, end / 60, end % 60)
+}
+
+
+#[test]
+fn until_stops() {
+    let mut server = Server::start(&["--until", "1s"], |_| {});
+    assert_eq!(server.config()["until"].as_u64(), Some(1));
+    assert_eq!(server.get("/").send().status, 200);
+
+    let start = Instant::now();
+    while !server.exited() && start.elapsed() < Duration::from_secs(5) {
+        thread::sleep(Duration::from_millis(20));
+    }
+    assert!(server.exited());
+}
+
+#[test]
+fn writes_refused_outside_hours() {
+    let hours = window(120, 180);
+    let server = Server::start(&["-w", "--write-hours", &hours], |site| fs::write(site.join("file.txt"), "data").unwrap());
+    assert_eq!(server.config()["write_hours"].as_str(), Some(&hours[..]));
+
+    assert_eq!(server.request("PUT", "/new.txt").body("new").send().status, 403);
+    assert_eq!(server.request("DELETE", "/file.txt").send().status, 403);
+    assert!(!server.site().join("new.txt").exists());
+    assert_eq!(server.get("/file.txt").send().text(), "data");
+}
+
+#[test]
+fn writes_accepted_within_hours() {
+    let hours = window(-60, 60);
+    let server = Server::start(&["-w", "--write-hours", &hours], |_| {});
+
+    assert_eq!(server.request("PUT", "/new.txt").body("new").send().status, 201);
+}
@@ -0,0 +1,66 @@
+extern crate serde_json;
+extern crate sha1_smol;
+extern crate flate2;
+
+mod util;
+
+use self::util::Server;
+use std::fs;
+
+
+/// Over one piece, which is at least 256KiB
+fn big_file() -> Vec<u8> {
+    (0..300 * 1024).map(|i| (i % 251) as u8).collect()
+}
+
+fn torrent_fixture(args: &[&str]) -> Server {
+    Server::start(args, |site| {
+        fs::write(site.join("big.bin"), big_file()).unwrap();
+        fs::write(site.join("small.txt"), "Abolish the burgeoisie!\n").unwrap();
+    })
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack.windows(needle.len()).any(|w| w == needle)
+}
+
+
+#[test]
+fn served_for_big_files() {
+    let server = torrent_fixture(&["--torrent", "100K"]);
+
+    let resp = server.get("/big.bin?torrent").send();
+    assert_eq!(resp.status, 200);
+    assert_eq!(resp.header("Content-Type").unwrap(), "application/x-bittorrent");
+    assert!(resp.header("Content-Disposition").unwrap().contains("big.bin.torrent"));
+
+    let data = big_file();
+    let mut pieces = sha1_smol::Sha1::from(&data[..256 * 1024]).digest().bytes().to_vec();
+    pieces.extend_from_slice(&sha1_smol::Sha1::from(&data[256 * 1024..]).digest().bytes());
+    let info = [&format!("4:infod6:lengthi{}e4:name7:big.bin12:piece lengthi262144e6:pieces40:", data.len()).into_bytes()[..], &pieces, b"e"].concat();
+    assert!(resp.body.starts_with(b"d10:created by"));
+    assert!(contains(&resp.body, &info));
+    let seed = format!("http://127.0.0.1:{}/big.bin", server.port());
+    assert!(resp.body.ends_with(format!("8:url-list{}:{}e", seed.len(), seed).as_bytes()));
+
+    // Second time from the cache
+    assert_eq!(server.get("/big.bin?torrent").send().body, resp.body);
+}
+
+#[test]
+fn small_files_served_directly() {
+    let server = torrent_fixture(&["--torrent", "100K"]);
+
+    let resp = server.get("/small.txt?torrent").send();
+    assert_eq!(resp.status, 200);
+    assert_eq!(resp.text(), "Abolish the burgeoisie!\n");
+}
+
+#[test]
+fn disabled_by_default() {
+    let server = torrent_fixture(&[]);
+
+    let resp = server.get("/big.bin?torrent").send();
+    assert_eq!(resp.status, 200);
+    assert_eq!(resp.body, big_file());
+}
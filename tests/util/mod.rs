@@ -0,0 +1,287 @@
+//! Test harness: runs the server against a fixture tree on an OS-picked local port, and a scriptable client to talk to it with.
+//!
+//! Every test file, and the benchmarks, include this with `mod util;`, so not everything is used by all of them.
+
+
+#![allow(dead_code)]
+
+
+use std::io::{Result as IoResult, BufReader, BufRead, Write, Read};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::net::TcpStream;
+use std::process::{Command, ChildStdout, ChildStdin, Child, Stdio};
+use std::time::Duration;
+use std::path::{PathBuf, Path};
+use std::{env, fs, process};
+use flate2::read::GzDecoder;
+use serde_json::{self, Value};
+
+
+static SERVERS_STARTED: AtomicUsize = AtomicUsize::new(0);
+
+
+/// A server process hosting `site()`, killed and cleaned up after on drop
+pub struct Server {
+    child: Child,
+    /// Kept open so later output doesn't hit a closed pipe, and read by `log_line()`
+    stdout: BufReader<ChildStdout>,
+    port: u16,
+    root: PathBuf,
+    config: Value,
+}
+
+impl Server {
+    /// Start the server with the specified additional arguments, after filling its hosted directory with `fixture()`
+    pub fn start<F: FnOnce(&Path)>(args: &[&str], fixture: F) -> Server {
+        Server::start_hosting("", args, fixture)
+    }
+
+    /// Like `start()`, but hosting `hosted`, relative to `site()`, which can be a file
+    pub fn start_hosting<F: FnOnce(&Path)>(hosted: &str, args: &[&str], fixture: F) -> Server {
+        Server::spawn(Some(hosted), args, Stdio::null(), true, fixture)
+    }
+
+    /// Like `start()`, but logging serving status, for `log_line()`
+    pub fn start_logging<F: FnOnce(&Path)>(args: &[&str], fixture: F) -> Server {
+        Server::spawn(Some(""), args, Stdio::null(), false, fixture)
+    }
+
+    /// Like `start()`, but with `--stdin NAME` instead of a hosted directory, and what to pipe to it
+    pub fn start_stdin(name: &str, args: &[&str]) -> (Server, ChildStdin) {
+        let mut server = Server::spawn(None, &[&["--stdin", name][..], args].concat(), Stdio::piped(), true, |_| {});
+        let stdin = server.child.stdin.take().unwrap();
+        (server, stdin)
+    }
+
+    fn spawn<F: FnOnce(&Path)>(hosted: Option<&str>, args: &[&str], stdin: Stdio, quiet: bool, fixture: F) -> Server {
+        let root = env::temp_dir().join(format!("http-test-{}-{}", process::id(), SERVERS_STARTED.fetch_add(1, Ordering::Relaxed)));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("site")).expect("Failed to create fixture directory");
+        fs::create_dir_all(root.join("temp")).expect("Failed to create temp directory");
+        fixture(&root.join("site"));
+
+        // Port 0 gets one picked by the OS, and the first line --print-config outputs is what it ended up being
+        let mut child = Command::new(env!("CARGO_BIN_EXE_http"))
+            .args(hosted.map(|h| root.join("site").join(h)))
+            .args(if quiet { &["-qqq"][..] } else { &[][..] })
+            .args(["-a", "127.0.0.1", "-p", "0", "--print-config", "--temp-dir"])
+            .arg(root.join("temp"))
+            .args(args)
+            .stdin(stdin)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("Failed to start server");
+
+        let mut stdout = BufReader::new(child.stdout.take().unwrap());
+        let mut config = String::new();
+        stdout.read_line(&mut config).expect("Failed to read server configuration");
+        let config: Value = serde_json::from_str(&config).unwrap_or_else(|e| panic!("Server didn't print configuration ({}): {:?}", e, config));
+        let port = config["port"].as_u64().expect("No port in server configuration") as u16;
+
+        Server {
+            child,
+            stdout,
+            port,
+            root,
+            config,
+        }
+    }
+
+    /// The configuration printed by `--print-config`
+    pub fn config(&self) -> &Value {
+        &self.config
+    }
+
+    /// The hosted directory
+    pub fn site(&self) -> PathBuf {
+        self.root.join("site")
+    }
+
+    /// The port it's listening on, for talking to it without `request()`
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// The directory passed as `--temp-dir`
+    pub fn temp(&self) -> PathBuf {
+        self.root.join("temp")
+    }
+
+    /// The next timestamped log line, without the (maybe coloured) timestamp, skipping startup messages
+    pub fn log_line(&mut self) -> String {
+        loop {
+            let mut line = String::new();
+            assert_ne!(self.stdout.read_line(&mut line).expect("Failed to read server log"), 0, "Server log ended");
+            if line.trim_start_matches("\x1b[36m").starts_with('[') {
+                let after = &line[line.find(']').unwrap() + 1..];
+                return after.trim_start_matches("\x1b[00m").trim_start_matches("\x1b[0m").trim().to_string();
+            }
+        }
+    }
+
+    /// The startup messages, up to and excluding "Ctrl-C to stop."
+    pub fn startup_lines(&mut self) -> Vec<String> {
+        let mut lines = vec![];
+        loop {
+            let mut line = String::new();
+            assert_ne!(self.stdout.read_line(&mut line).expect("Failed to read server log"), 0, "Server log ended");
+            let line = line.trim_end().trim_start_matches("\x1b[0m").to_string();
+            if line == "Ctrl-C to stop." {
+                return lines;
+            }
+            lines.push(line);
+        }
+    }
+
+    /// Whether the server process has stopped on its own
+    pub fn exited(&mut self) -> bool {
+        self.child.try_wait().expect("Failed to check on server").is_some()
+    }
+
+    pub fn get(&self, path: &str) -> Request<'_> {
+        self.request("GET", path)
+    }
+
+    pub fn request(&self, method: &str, path: &str) -> Request<'_> {
+        Request {
+            server: self,
+            method: method.to_string(),
+            path: path.to_string(),
+            headers: vec![],
+            body: vec![],
+        }
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        let _ = fs::remove_dir_all(&self.root);
+    }
+}
+
+
+/// A request to be sent with `send()`, built up call-by-call
+pub struct Request<'s> {
+    server: &'s Server,
+    method: String,
+    path: String,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl<'s> Request<'s> {
+    pub fn header(mut self, name: &str, value: &str) -> Request<'s> {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn body<B: Into<Vec<u8>>>(mut self, body: B) -> Request<'s> {
+        self.body = body.into();
+        self
+    }
+
+    /// Send the request on a fresh connection and read the whole response, panicking if that fails
+    pub fn send(self) -> Response {
+        let desc = format!("{} {}", self.method, self.path);
+        self.send_impl().unwrap_or_else(|e| panic!("{}: {}", desc, e))
+    }
+
+    fn send_impl(self) -> IoResult<Response> {
+        let mut conn = TcpStream::connect(("127.0.0.1", self.server.port))?;
+        conn.set_read_timeout(Some(Duration::from_secs(30)))?;
+
+        let mut head = format!("{} {} HTTP/1.1\r\nConnection: close\r\nContent-Length: {}\r\n", self.method, self.path, self.body.len());
+        // Overridable for testing Host validation
+        if !self.headers.iter().any(|(n, _)| n.eq_ignore_ascii_case("Host")) {
+            head.push_str(&format!("Host: 127.0.0.1:{}\r\n", self.server.port));
+        }
+        for (name, value) in &self.headers {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        head.push_str("\r\n");
+        conn.write_all(head.as_bytes())?;
+        conn.write_all(&self.body)?;
+
+        let mut conn = BufReader::new(conn);
+        let mut line = String::new();
+        conn.read_line(&mut line)?;
+        let status = line.split(' ').nth(1).and_then(|s| s.parse().ok()).unwrap_or(0);
+
+        let mut headers = vec![];
+        loop {
+            line.clear();
+            conn.read_line(&mut line)?;
+            let line = line.trim_end();
+            if line.is_empty() {
+                break;
+            }
+            if let Some(colon) = line.find(':') {
+                headers.push((line[..colon].to_string(), line[colon + 1..].trim().to_string()));
+            }
+        }
+
+        let mut body = vec![];
+        let chunked = headers.iter().any(|(n, v)| n.eq_ignore_ascii_case("Transfer-Encoding") && v.eq_ignore_ascii_case("chunked"));
+        if self.method == "HEAD" || status == 204 || status == 304 {
+        } else if chunked {
+            loop {
+                line.clear();
+                conn.read_line(&mut line)?;
+                let size = usize::from_str_radix(line.trim().split(';').next().unwrap_or(""), 16).unwrap_or(0);
+                if size == 0 {
+                    break;
+                }
+                let start = body.len();
+                body.resize(start + size, 0);
+                conn.read_exact(&mut body[start..])?;
+                conn.read_line(&mut line)?;
+            }
+        } else {
+            conn.read_to_end(&mut body)?;
+        }
+
+        Ok(Response {
+            status,
+            headers,
+            body,
+        })
+    }
+}
+
+
+/// A response as read off the wire, with the chunked transfer encoding undone
+#[derive(Debug)]
+pub struct Response {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    /// The value of the first header with the specified name, if any
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(n, _)| n.eq_ignore_ascii_case(name)).map(|(_, v)| &v[..])
+    }
+
+    pub fn headers(&self, name: &str) -> Vec<&str> {
+        self.headers.iter().filter(|(n, _)| n.eq_ignore_ascii_case(name)).map(|(_, v)| &v[..]).collect()
+    }
+
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+
+    /// The body with the gzip content encoding undone, if it was applied
+    pub fn decoded(&self) -> Vec<u8> {
+        if self.header("Content-Encoding") == Some("gzip") {
+            let mut out = vec![];
+            GzDecoder::new(&self.body[..]).read_to_end(&mut out).expect("Failed to gunzip body");
+            out
+        } else {
+            self.body.clone()
+        }
+    }
+}
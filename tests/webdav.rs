@@ -0,0 +1,281 @@
+extern crate serde_json;
+extern crate flate2;
+
+mod util;
+
+use self::util::Server;
+use std::time::UNIX_EPOCH;
+use std::fs;
+
+
+fn webdav_fixture(args: &[&str]) -> Server {
+    Server::start(&[&["-d"], args].concat(), |site| {
+        fs::create_dir_all(site.join("dir").join("sub")).unwrap();
+        fs::write(site.join("dir").join("file.txt"), "Abolish the burgeoisie!\n").unwrap();
+        fs::write(site.join("dir").join("sub").join("deep.txt"), "Workers of the world, unite!\n").unwrap();
+    })
+}
+
+/// The text of all `tag` elements in `xml`, in order
+fn elements(xml: &str, tag: &str) -> Vec<String> {
+    let (open, close) = (format!("<{}>", tag), format!("</{}>", tag));
+    xml.split(&open[..]).skip(1).map(|s| s[..s.find(&close[..]).unwrap_or_else(|| panic!("Unclosed {} in {}", tag, xml))].to_string()).collect()
+}
+
+fn sync_collection(server: &Server, dir: &str, token: &str, level: &str) -> (u16, String) {
+    let body = format!(r#"<?xml version="1.0" encoding="utf-8"?>
+                          <D:sync-collection xmlns:D="DAV:">
+                            <D:sync-token>{}</D:sync-token>
+                            <D:sync-level>{}</D:sync-level>
+                            <D:prop><D:getcontentlength/></D:prop>
+                          </D:sync-collection>"#,
+                       token,
+                       level);
+    let resp = server.request("REPORT", dir).header("Content-Type", "text/xml").body(body).send();
+    (resp.status, resp.text())
+}
+
+
+#[test]
+fn sync_collection_initial() {
+    let server = webdav_fixture(&[]);
+    let base = format!("http://127.0.0.1:{}/dir/", server.port());
+
+    let (st, xml) = sync_collection(&server, "/dir/", "", "1");
+    assert_eq!(st, 207);
+    let mut hrefs = elements(&xml, "D:href");
+    hrefs.sort();
+    assert_eq!(hrefs, [format!("{}file.txt", base), format!("{}sub/", base)]);
+    assert!(elements(&xml, "D:sync-token")[0].starts_with("data:,1-"), "{}", xml);
+
+    let (_, xml) = sync_collection(&server, "/dir/", "", "infinite");
+    assert_eq!(elements(&xml, "D:href").len(), 3, "{}", xml);
+    assert!(xml.contains(&format!("{}sub/deep.txt", base)), "{}", xml);
+}
+
+#[test]
+fn sync_collection_changes() {
+    let server = webdav_fixture(&[]);
+    let base = format!("http://127.0.0.1:{}/dir/", server.port());
+
+    let (_, xml) = sync_collection(&server, "/dir/", "", "1");
+    let token = elements(&xml, "D:sync-token").remove(0);
+
+    let (st, xml) = sync_collection(&server, "/dir/", &token, "1");
+    assert_eq!(st, 207);
+    assert!(elements(&xml, "D:href").is_empty(), "{}", xml);
+    assert_eq!(elements(&xml, "D:sync-token"), [token.clone()]);
+
+    fs::write(server.site().join("dir").join("file.txt"), "Abolish the burgeoisie, now!\n").unwrap();
+    fs::write(server.site().join("dir").join("new.txt"), "Seize the means!\n").unwrap();
+    fs::remove_dir_all(server.site().join("dir").join("sub")).unwrap();
+
+    let (st, xml) = sync_collection(&server, "/dir/", &token, "1");
+    assert_eq!(st, 207);
+    let responses = elements(&xml, "D:response");
+    assert_eq!(responses.len(), 3, "{}", xml);
+    assert!(responses.iter().any(|r| r.contains(&format!("{}file.txt<", base)) && r.contains("29")), "{}", xml);
+    assert!(responses.iter().any(|r| r.contains(&format!("{}new.txt<", base))), "{}", xml);
+    assert!(responses.iter().any(|r| r.contains(&format!("{}sub/<", base)) && r.contains("404 Not Found")), "{}", xml);
+    assert_ne!(elements(&xml, "D:sync-token"), [token]);
+}
+
+#[test]
+fn sync_collection_unknown_token() {
+    let server = webdav_fixture(&[]);
+
+    let (st, xml) = sync_collection(&server, "/dir/", "data:,1-nonexistent", "1");
+    assert_eq!(st, 403);
+    assert!(xml.contains("valid-sync-token"), "{}", xml);
+
+    // Tokens are for a specific level
+    let (_, xml) = sync_collection(&server, "/dir/", "", "1");
+    let token = elements(&xml, "D:sync-token").remove(0);
+    assert_eq!(sync_collection(&server, "/dir/", &token, "infinite").0, 403);
+}
+
+#[test]
+fn sync_collection_only_report() {
+    let server = webdav_fixture(&[]);
+
+    let resp = server.request("REPORT", "/dir/")
+        .body(r#"<?xml version="1.0"?><D:expand-property xmlns:D="DAV:"/>"#)
+        .send();
+    assert_eq!(resp.status, 403);
+    assert!(resp.text().contains("supported-report"));
+    assert_eq!(sync_collection(&server, "/dir/file.txt", "", "1").0, 403);
+    assert_eq!(server.request("REPORT", "/dir/").body("<not xml").send().status, 400);
+}
+
+fn proppatch(server: &Server, path: &str, updates: &str) -> (u16, String) {
+    let body = format!(r#"<?xml version="1.0" encoding="utf-8"?>
+                          <D:propertyupdate xmlns:D="DAV:" xmlns:X="urn:example">{}</D:propertyupdate>"#,
+                       updates);
+    let resp = server.request("PROPPATCH", path).header("Content-Type", "text/xml").body(body).send();
+    (resp.status, resp.text())
+}
+
+fn propfind(server: &Server, path: &str, props: &str) -> String {
+    let body = format!(r#"<?xml version="1.0" encoding="utf-8"?>
+                          <D:propfind xmlns:D="DAV:" xmlns:X="urn:example"><D:prop>{}</D:prop></D:propfind>"#,
+                       props);
+    let resp = server.request("PROPFIND", path).header("Depth", "0").header("Content-Type", "text/xml").body(body).send();
+    assert_eq!(resp.status, 207);
+    resp.text()
+}
+
+
+#[test]
+fn proppatch_modification_time() {
+    let server = webdav_fixture(&["-w"]);
+
+    let (st, xml) = proppatch(&server,
+                              "/dir/file.txt",
+                              "<D:set><D:prop><D:getlastmodified>Wed, 01 Jan 2020 00:00:00 GMT</D:getlastmodified></D:prop></D:set>");
+    assert_eq!(st, 207);
+    assert_eq!(elements(&xml, "D:status"), ["HTTP/1.1 200 OK"]);
+    let modified = fs::metadata(server.site().join("dir").join("file.txt")).unwrap().modified().unwrap();
+    assert_eq!(modified.duration_since(UNIX_EPOCH).unwrap().as_secs(), 1577836800);
+
+    let (_, xml) = proppatch(&server,
+                             "/dir/file.txt",
+                             "<D:set><D:prop><D:getlastmodified>2021-02-03T04:05:06Z</D:getlastmodified></D:prop></D:set>");
+    assert_eq!(elements(&xml, "D:status"), ["HTTP/1.1 200 OK"]);
+    assert!(propfind(&server, "/dir/file.txt", "<D:getlastmodified/>").contains("2021-02-03T04:05:06"));
+}
+
+#[test]
+fn proppatch_dead_properties() {
+    let server = webdav_fixture(&["-w"]);
+
+    let (st, xml) = proppatch(&server, "/dir/file.txt", "<D:set><D:prop><X:colour>red</X:colour></D:prop></D:set>");
+    assert_eq!(st, 207);
+    assert_eq!(elements(&xml, "D:status"), ["HTTP/1.1 200 OK"]);
+    assert!(propfind(&server, "/dir/file.txt", "<X:colour/>").contains(">red<"));
+
+    let (_, xml) = proppatch(&server, "/dir/file.txt", "<D:remove><D:prop><X:colour/></D:prop></D:remove>");
+    assert_eq!(elements(&xml, "D:status"), ["HTTP/1.1 200 OK"]);
+    let xml = propfind(&server, "/dir/file.txt", "<X:colour/>");
+    assert!(!xml.contains(">red<"), "{}", xml);
+    assert!(xml.contains("404 Not Found"), "{}", xml);
+}
+
+#[test]
+fn proppatch_atomic() {
+    let server = webdav_fixture(&["-w"]);
+    let before = fs::metadata(server.site().join("dir").join("file.txt")).unwrap().modified().unwrap();
+
+    let (st, xml) = proppatch(&server,
+                              "/dir/file.txt",
+                              "<D:set><D:prop><X:colour>red</X:colour><D:getlastmodified>yesterday</D:getlastmodified></D:prop></D:set>");
+    assert_eq!(st, 207);
+    let mut statuses = elements(&xml, "D:status");
+    statuses.sort();
+    assert_eq!(statuses, ["HTTP/1.1 409 Conflict", "HTTP/1.1 424 Failed Dependency"]);
+    assert!(!propfind(&server, "/dir/file.txt", "<X:colour/>").contains(">red<"));
+
+    let (_, xml) = proppatch(&server, "/dir/file.txt", "<D:set><D:prop><D:getcontentlength>1</D:getcontentlength></D:prop></D:set>");
+    assert_eq!(elements(&xml, "D:status"), ["HTTP/1.1 403 Forbidden"]);
+    assert_eq!(fs::metadata(server.site().join("dir").join("file.txt")).unwrap().modified().unwrap(), before);
+}
+
+#[test]
+fn proppatch_needs_writes() {
+    let server = webdav_fixture(&[]);
+
+    assert_eq!(proppatch(&server, "/dir/file.txt", "<D:set><D:prop><X:colour>red</X:colour></D:prop></D:set>").0, 403);
+    assert_eq!(server.request("PROPPATCH", "/dir/nonexistent").body("").send().status, 403);
+}
+
+fn lock(server: &Server, path: &str, scope: &str) -> (u16, Option<String>) {
+    let body = format!(r#"<?xml version="1.0" encoding="utf-8"?>
+                          <D:lockinfo xmlns:D="DAV:">
+                            <D:lockscope><D:{}/></D:lockscope>
+                            <D:locktype><D:write/></D:locktype>
+                            <D:owner><D:href>karl</D:href></D:owner>
+                          </D:lockinfo>"#,
+                       scope);
+    let resp = server.request("LOCK", path).header("Content-Type", "text/xml").header("Timeout", "Second-600").body(body).send();
+    (resp.status, resp.header("Lock-Token").map(|t| t.trim_start_matches('<').trim_end_matches('>').to_string()))
+}
+
+#[test]
+fn locked_writes_need_token() {
+    let server = webdav_fixture(&["-w"]);
+
+    let (st, token) = lock(&server, "/dir/file.txt", "exclusive");
+    assert_eq!(st, 200);
+    let token = token.unwrap();
+    assert!(token.starts_with("opaquelocktoken:"), "{}", token);
+
+    assert_eq!(server.request("PUT", "/dir/file.txt").body("Stolen").send().status, 423);
+    assert_eq!(server.request("DELETE", "/dir/file.txt").send().status, 423);
+    assert_eq!(server.request("DELETE", "/dir/").send().status, 423);
+    let dest = format!("http://127.0.0.1:{}/moved.txt", server.port());
+    assert_eq!(server.request("MOVE", "/dir/file.txt").header("Destination", &dest).send().status, 423);
+    assert_eq!(fs::read_to_string(server.site().join("dir").join("file.txt")).unwrap(), "Abolish the burgeoisie!\n");
+
+    let resp = server.request("PUT", "/dir/file.txt").header("If", &format!("(<{}>)", token)).body("Seized").send();
+    assert_eq!(resp.status, 204);
+    assert_eq!(fs::read_to_string(server.site().join("dir").join("file.txt")).unwrap(), "Seized");
+}
+
+#[test]
+fn locked_batch_ops_refused() {
+    let server = webdav_fixture(&["-w"]);
+
+    let token = lock(&server, "/dir/file.txt", "exclusive").1.unwrap();
+    let batch = |ops: &str, lock_token: Option<&str>| {
+        let mut req = server.request("POST", "/dir/").header("Content-Type", "application/json");
+        if let Some(t) = lock_token {
+            req = req.header("If", &format!("(<{}>)", t));
+        }
+        let results: serde_json::Value = serde_json::from_str(&req.body(ops).send().text()).unwrap();
+        results["results"].as_array().unwrap().iter().map(|r| r["status"].as_u64().unwrap()).collect::<Vec<_>>()
+    };
+
+    assert_eq!(batch(r#"[{"op": "delete", "path": "file.txt"}]"#, None), [423]);
+    assert_eq!(batch(r#"[{"op": "move", "from": "file.txt", "to": "moved.txt"}]"#, None), [423]);
+    assert_eq!(batch(r#"[{"op": "copy", "from": "sub/deep.txt", "to": "file.txt", "overwrite": true}]"#, None), [423]);
+    assert_eq!(fs::read_to_string(server.site().join("dir").join("file.txt")).unwrap(), "Abolish the burgeoisie!\n");
+
+    assert_eq!(batch(r#"[{"op": "delete", "path": "file.txt"}]"#, Some(&token)), [204]);
+    assert!(!server.site().join("dir").join("file.txt").exists());
+}
+
+#[test]
+fn unlocked() {
+    let server = webdav_fixture(&["-w"]);
+
+    let token = lock(&server, "/dir/file.txt", "exclusive").1.unwrap();
+    assert_eq!(server.request("UNLOCK", "/dir/file.txt").header("Lock-Token", "<opaquelocktoken:nope>").send().status, 409);
+    assert_eq!(server.request("UNLOCK", "/dir/file.txt").header("Lock-Token", &format!("<{}>", token)).send().status, 204);
+    assert_eq!(server.request("PUT", "/dir/file.txt").body("Seized").send().status, 204);
+}
+
+#[test]
+fn lock_conflicts() {
+    let server = webdav_fixture(&["-w"]);
+
+    let (st, token) = lock(&server, "/dir/new.txt", "shared");
+    assert_eq!((st, token.is_some()), (201, true));
+    assert_eq!(fs::read(server.site().join("dir").join("new.txt")).unwrap(), b"");
+
+    assert_eq!(lock(&server, "/dir/", "exclusive").0, 423);
+    assert_eq!(lock(&server, "/dir/", "shared").0, 200);
+    assert_eq!(lock(&server, "/dir/file.txt", "exclusive").0, 423);
+    assert_eq!(lock(&server, "/dir/other.txt", "shared").0, 423);
+
+    let xml = propfind(&server, "/dir/new.txt", "<D:lockdiscovery/>");
+    assert!(xml.contains(&token.unwrap()), "{}", xml);
+    assert_eq!(elements(&xml, "D:depth"), ["infinity", "infinity"]);
+}
+
+#[test]
+fn level_2_advertised() {
+    let server = webdav_fixture(&[]);
+
+    let resp = server.request("OPTIONS", "/").send();
+    assert_eq!(resp.header("DAV"), Some("1, 2"));
+    assert!(resp.header("Allow").unwrap().contains("LOCK"));
+}
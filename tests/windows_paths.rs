@@ -0,0 +1,94 @@
+//! Hosting directories past `MAX_PATH` and on UNC shares, which only mean anything on Windows.
+#![cfg(windows)]
+
+
+extern crate serde_json;
+extern crate flate2;
+
+mod util;
+
+use std::path::{PathBuf, Path, Component, Prefix};
+use self::util::Server;
+use std::{env, fs, process};
+
+
+/// Nested deep enough for the whole path to be well over 260 characters
+fn long_subdir() -> PathBuf {
+    (0..8).map(|i| format!("long-directory-name-number-{}-to-get-past-max-path", i)).collect()
+}
+
+/// `C:\dir` as `\\localhost\C$\dir`, through the administrative share
+fn unc_path(p: &Path) -> Option<PathBuf> {
+    let mut comps = p.components();
+    let drive = match comps.next() {
+        Some(Component::Prefix(pc)) => {
+            match pc.kind() {
+                Prefix::Disk(d) | Prefix::VerbatimDisk(d) => d as char,
+                _ => return None,
+            }
+        }
+        _ => return None,
+    };
+    Some(PathBuf::from(format!(r"\\localhost\{}$", drive)).join(comps.as_path().strip_prefix(r"\").unwrap_or(comps.as_path())))
+}
+
+
+#[test]
+fn long_paths() {
+    let server = Server::start_hosting(long_subdir().to_str().unwrap(), &["-w"], |site| {
+        let dir = site.join(long_subdir());
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("file.txt"), "Abolish the burgeoisie!\n").unwrap();
+    });
+    assert!(server.site().join(long_subdir()).as_os_str().len() > 260);
+
+    let resp = server.get("/file.txt").send();
+    assert_eq!(resp.status, 200);
+    assert_eq!(resp.text(), "Abolish the burgeoisie!\n");
+    assert!(server.get("/").send().text().contains("file.txt"));
+
+    assert_eq!(server.request("PUT", "/new.txt").body("Workers of the world, unite!\n").send().status, 201);
+    assert_eq!(fs::read_to_string(server.site().join(long_subdir()).join("new.txt")).unwrap(), "Workers of the world, unite!\n");
+}
+
+#[test]
+fn verbatim_root_confined() {
+    let server = Server::start_hosting("hosted", &[], |site| {
+        fs::create_dir(site.join("hosted")).unwrap();
+        fs::write(site.join("hosted").join("file.txt"), "Abolish the burgeoisie!\n").unwrap();
+        fs::write(site.join("outside.txt"), "Means of production\n").unwrap();
+    });
+
+    assert_eq!(server.get("/file.txt").send().status, 200);
+    for path in &["/../outside.txt", "/..%5Coutside.txt", "/%2E%2E/outside.txt", "/C:/Windows/win.ini"] {
+        let resp = server.get(path).send();
+        assert!(!resp.text().contains("Means of production"), "{}: {}", path, resp.text());
+        assert_ne!(resp.status, 200, "{}", path);
+    }
+}
+
+#[test]
+fn unc_shares() {
+    let dir = env::temp_dir().join(format!("http-test-unc-{}", process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("file.txt"), "Abolish the burgeoisie!\n").unwrap();
+
+    // Not every machine has the administrative shares available
+    let unc = match unc_path(&fs::canonicalize(&dir).unwrap()).filter(|u| u.join("file.txt").is_file()) {
+        Some(unc) => unc,
+        None => {
+            let _ = fs::remove_dir_all(&dir);
+            return;
+        }
+    };
+
+    {
+        // An absolute path replaces the site directory
+        let server = Server::start_hosting(unc.to_str().unwrap(), &[], |_| {});
+        let resp = server.get("/file.txt").send();
+        assert_eq!(resp.status, 200);
+        assert_eq!(resp.text(), "Abolish the burgeoisie!\n");
+        assert!(server.get("/").send().text().contains("file.txt"));
+    }
+    let _ = fs::remove_dir_all(&dir);
+}
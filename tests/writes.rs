@@ -0,0 +1,186 @@
+extern crate serde_json;
+extern crate flate2;
+
+mod util;
+
+use self::util::Server;
+use std::fs;
+use std::time::{SystemTime, Duration};
+
+
+fn writable_fixture() -> Server {
+    Server::start(&["-w"], |site| {
+        fs::create_dir(site.join("dir")).unwrap();
+        fs::write(site.join("dir").join("file.txt"), "old").unwrap();
+    })
+}
+
+
+#[test]
+fn writes_forbidden_by_default() {
+    let server = Server::start(&[], |site| fs::write(site.join("file.txt"), "old").unwrap());
+
+    assert_eq!(server.request("PUT", "/file.txt").body("new").send().status, 403);
+    assert_eq!(server.request("DELETE", "/file.txt").send().status, 403);
+    assert_eq!(fs::read_to_string(server.site().join("file.txt")).unwrap(), "old");
+}
+
+#[test]
+fn upload_new() {
+    let server = writable_fixture();
+
+    let resp = server.request("PUT", "/dir/new.txt").body("Workers of the world, unite!").send();
+    assert_eq!(resp.status, 201);
+    assert_eq!(fs::read_to_string(server.site().join("dir").join("new.txt")).unwrap(), "Workers of the world, unite!");
+    assert_eq!(server.get("/dir/new.txt").send().text(), "Workers of the world, unite!");
+}
+
+#[test]
+fn upload_creates_parents() {
+    let server = writable_fixture();
+
+    assert_eq!(server.request("PUT", "/dir/sub/deeper/new.txt").body("nested").send().status, 201);
+    assert_eq!(fs::read_to_string(server.site().join("dir").join("sub").join("deeper").join("new.txt")).unwrap(), "nested");
+}
+
+#[test]
+fn upload_replace() {
+    let server = writable_fixture();
+
+    assert_eq!(server.request("PUT", "/dir/file.txt").body("new").send().status, 204);
+    assert_eq!(fs::read_to_string(server.site().join("dir").join("file.txt")).unwrap(), "new");
+}
+
+#[test]
+fn upload_onto_directory() {
+    let server = writable_fixture();
+
+    let resp = server.request("PUT", "/dir").body("not a directory").send();
+    assert_eq!(resp.status, 405);
+    assert!(server.site().join("dir").is_dir());
+}
+
+#[test]
+fn upload_escaping() {
+    let server = writable_fixture();
+
+    assert_eq!(server.request("PUT", "/..%2Fescaped.txt").body("out").send().status, 400);
+    assert!(!server.site().parent().unwrap().join("escaped.txt").exists());
+}
+
+#[test]
+fn delete_file() {
+    let server = writable_fixture();
+
+    assert_eq!(server.request("DELETE", "/dir/file.txt").send().status, 204);
+    assert!(!server.site().join("dir").join("file.txt").exists());
+    assert_eq!(server.get("/dir/file.txt").send().status, 404);
+
+    assert_eq!(server.request("DELETE", "/dir/file.txt").send().status, 404);
+}
+
+#[test]
+fn delete_directory() {
+    let server = writable_fixture();
+
+    assert_eq!(server.request("DELETE", "/dir").send().status, 204);
+    assert!(!server.site().join("dir").exists());
+}
+
+#[test]
+fn replace_bumps_directory() {
+    let server = writable_fixture();
+    let dir = server.site().join("dir");
+    let long_ago = SystemTime::now() - Duration::from_secs(24 * 60 * 60);
+    fs::File::open(&dir).unwrap().set_modified(long_ago).unwrap();
+
+    assert_eq!(server.request("PUT", "/dir/file.txt").body("new").send().status, 204);
+    assert!(fs::metadata(&dir).unwrap().modified().unwrap() > long_ago + Duration::from_secs(60));
+}
+
+#[test]
+fn writes_forget_hashes() {
+    let server = Server::start(&["-w", "--admin", "--path-auth", ".http-admin=adm:pw"], |site| {
+        fs::create_dir(site.join("dir")).unwrap();
+        fs::write(site.join("dir").join("file.txt"), "old".repeat(1000)).unwrap();
+    });
+    let hashes = || {
+        let resp = server.get("/.http-admin/caches").header("Authorization", "Basic YWRtOnB3").send();
+        serde_json::from_slice::<serde_json::Value>(&resp.body).unwrap()["hashes"]["entries"].as_u64()
+    };
+
+    server.get("/dir/file.txt").header("Accept-Encoding", "gzip").send();
+    assert_eq!(hashes(), Some(1));
+    assert_eq!(server.request("PUT", "/dir/file.txt").body("new".repeat(1000)).send().status, 204);
+    assert_eq!(hashes(), Some(0));
+    assert_eq!(server.get("/dir/file.txt").header("Accept-Encoding", "gzip").send().decoded(), "new".repeat(1000).into_bytes());
+
+    assert_eq!(hashes(), Some(1));
+    assert_eq!(server.request("DELETE", "/dir").send().status, 204);
+    assert_eq!(hashes(), Some(0));
+}
+
+#[test]
+fn conditional_create() {
+    let server = writable_fixture();
+
+    assert_eq!(server.request("PUT", "/dir/file.txt").header("If-None-Match", "*").body("new").send().status, 412);
+    assert_eq!(fs::read_to_string(server.site().join("dir").join("file.txt")).unwrap(), "old");
+
+    let resp = server.request("PUT", "/dir/new.txt").header("If-None-Match", "*").body("new").send();
+    assert_eq!(resp.status, 201);
+    assert!(resp.header("ETag").is_some());
+    assert_eq!(server.request("PUT", "/dir/nonexistent.txt").header("If-Match", "*").body("new").send().status, 412);
+    assert!(!server.site().join("dir").join("nonexistent.txt").exists());
+}
+
+#[test]
+fn conditional_replace() {
+    let server = writable_fixture();
+
+    let etag = server.get("/dir/file.txt").send().header("ETag").unwrap().to_string();
+    assert_eq!(server.request("PUT", "/dir/file.txt").header("If-Match", "\"stale\"").body("new").send().status, 412);
+    assert_eq!(server.request("PUT", "/dir/file.txt").header("If-Match", &format!("W/{}", etag)).body("new").send().status, 412);
+
+    let resp = server.request("PUT", "/dir/file.txt").header("If-Match", &etag).body("newer").send();
+    assert_eq!(resp.status, 204);
+    let new_etag = resp.header("ETag").unwrap().to_string();
+    assert_ne!(new_etag, etag);
+    assert_eq!(server.get("/dir/file.txt").send().header("ETag"), Some(&new_etag[..]));
+
+    // Someone else's already replaced it
+    assert_eq!(server.request("DELETE", "/dir/file.txt").header("If-Match", &etag).send().status, 412);
+    assert_eq!(server.request("DELETE", "/dir/file.txt").header("If-None-Match", &new_etag).send().status, 412);
+    assert_eq!(fs::read_to_string(server.site().join("dir").join("file.txt")).unwrap(), "newer");
+    assert_eq!(server.request("DELETE", "/dir/file.txt").header("If-Match", &new_etag).send().status, 204);
+    assert!(!server.site().join("dir").join("file.txt").exists());
+}
+
+#[test]
+fn conditional_unmodified_since() {
+    let server = writable_fixture();
+    let file = fs::File::options().write(true).open(server.site().join("dir").join("file.txt")).unwrap();
+    file.set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000)).unwrap(); // 2001-09-09T01:46:40Z
+    drop(file);
+
+    assert_eq!(server.request("PUT", "/dir/file.txt").header("If-Unmodified-Since", "Sat, 01 Jan 2000 00:00:00 GMT").body("new").send().status,
+               412);
+    assert_eq!(server.request("DELETE", "/dir/file.txt").header("If-Unmodified-Since", "Sun, 09 Sep 2001 01:46:39 GMT").send().status, 412);
+    assert_eq!(server.request("PUT", "/dir/file.txt").header("If-Unmodified-Since", "Sun, 09 Sep 2001 01:46:40 GMT").body("new").send().status,
+               204);
+    // Only If-Match counts when both are sent
+    let etag = server.get("/dir/file.txt").send().header("ETag").unwrap().to_string();
+    assert_eq!(server.request("DELETE", "/dir/file.txt")
+                   .header("If-Match", &etag)
+                   .header("If-Unmodified-Since", "Sat, 01 Jan 2000 00:00:00 GMT")
+                   .send()
+                   .status,
+               204);
+}
+
+#[test]
+fn conditional_missing_delete() {
+    let server = writable_fixture();
+
+    assert_eq!(server.request("DELETE", "/dir/nonexistent.txt").header("If-Match", "*").send().status, 404);
+}
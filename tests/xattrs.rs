@@ -0,0 +1,161 @@
+//! Extended attributes are only supported on Linux, and not by every filesystem there,
+//! so each test bails out if it can't set one on the fixture.
+#![cfg(target_os = "linux")]
+
+
+extern crate serde_json;
+extern crate flate2;
+extern crate base64;
+extern crate libc;
+
+mod util;
+
+use std::os::unix::ffi::OsStrExt;
+use self::util::Server;
+use libc::c_void;
+use std::ffi::CString;
+use std::path::Path;
+use std::{fs, ptr};
+
+
+fn xattrs_fixture(args: &[&str]) -> Option<Server> {
+    let server = Server::start(&[&["-d"], args].concat(), |site| fs::write(site.join("file.txt"), "Abolish the burgeoisie!\n").unwrap());
+    if set_xattr(&server.site().join("file.txt"), "colour", b"red") {
+        Some(server)
+    } else {
+        None
+    }
+}
+
+fn xattr_c_names(p: &Path, name: &str) -> (CString, CString) {
+    (CString::new(p.as_os_str().as_bytes()).unwrap(), CString::new(format!("user.{}", name)).unwrap())
+}
+
+/// Set the specified user extended attribute, without the `user.` prefix, returning whether that worked
+fn set_xattr(p: &Path, name: &str, val: &[u8]) -> bool {
+    let (p_c, name_c) = xattr_c_names(p, name);
+    unsafe { libc::setxattr(p_c.as_ptr(), name_c.as_ptr(), val.as_ptr() as *const c_void, val.len(), 0) == 0 }
+}
+
+/// The specified user extended attribute, without the `user.` prefix, if the file has it
+fn xattr(p: &Path, name: &str) -> Option<Vec<u8>> {
+    let (p_c, name_c) = xattr_c_names(p, name);
+    let len = unsafe { libc::getxattr(p_c.as_ptr(), name_c.as_ptr(), ptr::null_mut(), 0) };
+    if len < 0 {
+        return None;
+    }
+    let mut val = vec![0u8; len as usize];
+    let len = unsafe { libc::getxattr(p_c.as_ptr(), name_c.as_ptr(), val.as_mut_ptr() as *mut c_void, val.len()) };
+    if len < 0 {
+        return None;
+    }
+    val.truncate(len as usize);
+    Some(val)
+}
+
+fn propfind_colour(server: &Server, path: &str) -> String {
+    let resp = server.request("PROPFIND", path)
+        .header("Depth", "0")
+        .body(r#"<?xml version="1.0"?><D:propfind xmlns:D="DAV:" xmlns:X="urn:xattr:"><D:prop><X:colour/></D:prop></D:propfind>"#)
+        .send();
+    assert_eq!(resp.status, 207);
+    resp.text()
+}
+
+/// A tar entry of the specified type, padded to whole blocks
+fn tar_entry(name: &str, kind: u8, data: &[u8]) -> Vec<u8> {
+    let mut header = [0u8; 512];
+    header[..name.len()].copy_from_slice(name.as_bytes());
+    header[100..107].copy_from_slice(b"0000644");
+    header[124..135].copy_from_slice(format!("{:011o}", data.len()).as_bytes());
+    header[156] = kind;
+    header[257..263].copy_from_slice(b"ustar\0");
+
+    let mut entry = header.to_vec();
+    entry.extend_from_slice(data);
+    entry.resize((entry.len() + 511) / 512 * 512, 0);
+    entry
+}
+
+/// A pax extended header record, prefixed with its own length
+fn pax_record(key: &str, val: &str) -> String {
+    let rest = format!(" {}={}\n", key, val);
+    let mut len = rest.len() + 1;
+    while (len.to_string().len() + rest.len()) != len {
+        len += 1;
+    }
+    format!("{}{}", len, rest)
+}
+
+
+#[test]
+fn exposed_as_properties() {
+    let server = match xattrs_fixture(&["--xattrs"]) {
+        Some(server) => server,
+        None => return,
+    };
+
+    assert!(propfind_colour(&server, "/file.txt").contains(&format!(">{}<", base64::encode("red"))));
+}
+
+#[test]
+fn set_with_proppatch() {
+    let server = match xattrs_fixture(&["--xattrs", "-w"]) {
+        Some(server) => server,
+        None => return,
+    };
+
+    let resp = server.request("PROPPATCH", "/file.txt")
+        .body(format!(r#"<?xml version="1.0"?>
+                         <D:propertyupdate xmlns:D="DAV:" xmlns:X="urn:xattr:">
+                           <D:set><D:prop><X:shape>{}</X:shape></D:prop></D:set>
+                           <D:remove><D:prop><X:colour/></D:prop></D:remove>
+                         </D:propertyupdate>"#,
+                      base64::encode("round")))
+        .send();
+    assert_eq!(resp.status, 207);
+    assert!(resp.text().contains("200 OK") && !resp.text().contains("409"), "{}", resp.text());
+
+    let file = server.site().join("file.txt");
+    assert_eq!(xattr(&file, "shape"), Some(b"round".to_vec()));
+    assert_eq!(xattr(&file, "colour"), None);
+}
+
+#[test]
+fn preserved_across_copies() {
+    let server = match xattrs_fixture(&["--xattrs", "-w"]) {
+        Some(server) => server,
+        None => return,
+    };
+
+    let resp = server.request("COPY", "/file.txt")
+        .header("Destination", &format!("http://127.0.0.1:{}/copy.txt", server.port()))
+        .send();
+    assert_eq!(resp.status, 201);
+    assert_eq!(xattr(&server.site().join("copy.txt"), "colour"), Some(b"red".to_vec()));
+}
+
+#[test]
+fn restored_from_deploys() {
+    let server = match xattrs_fixture(&["--xattrs", "-w", "--deploy"]) {
+        Some(server) => server,
+        None => return,
+    };
+
+    let tar = [tar_entry("PaxHeaders/new.txt", b'x', pax_record("SCHILY.xattr.user.colour", "blue").as_bytes()),
+               tar_entry("new.txt", b'0', b"Workers of the world, unite!\n"),
+               vec![0; 1024]]
+        .concat();
+    assert_eq!(server.request("POST", "/").header("Content-Type", "application/x-tar").body(tar).send().status, 201);
+    assert_eq!(xattr(&server.site().join("new.txt"), "colour"), Some(b"blue".to_vec()));
+}
+
+#[test]
+fn dead_properties_without_switch() {
+    let server = match xattrs_fixture(&[]) {
+        Some(server) => server,
+        None => return,
+    };
+
+    assert!(!propfind_colour(&server, "/file.txt").contains(&base64::encode("red")));
+}